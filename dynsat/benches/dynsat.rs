@@ -0,0 +1,132 @@
+use criterion::{black_box, criterion_group, criterion_main, Criterion, Throughput};
+
+use plugin_common::bench::{bench_plugin, model_with, noise};
+use plugin_common::svf::{SVFCoefficients, SVFSimd, Type, SVF};
+
+use dynsat::comp::{Comp, CompSimd};
+use dynsat::DynSat;
+
+fn svf_run(c: &mut Criterion) {
+    let input: Vec<f64> = noise(1024).iter().map(|x| *x as f64).collect();
+    let coeffs = SVFCoefficients::<f64>::from_params(Type::BandPass, 48000.0, 1000.0, 1.0).unwrap();
+    let mut svf = SVF::<f64>::new(coeffs);
+
+    let mut group = c.benchmark_group("svf_run");
+    group.throughput(Throughput::Elements(input.len() as u64));
+    group.bench_function("band_pass", |b| {
+        b.iter(|| {
+            for x in input.iter() {
+                black_box(svf.run(*x));
+            }
+        })
+    });
+    group.finish();
+}
+
+// N scalar filters against one SVFSimd<N> on the same frames, in one group so criterion reports
+// the speedup of the lanes directly
+fn svf_lanes<const N: usize>(c: &mut Criterion, input: &[f64]) {
+    let coeffs = SVFCoefficients::<f64>::from_params(Type::BandPass, 48000.0, 1000.0, 1.0).unwrap();
+    let mut scalar = [SVF::<f64>::new(coeffs); N];
+    let mut simd = SVFSimd::<N>::new([coeffs; N]);
+
+    let mut group = c.benchmark_group(format!("svf_lanes_{}", N));
+    group.throughput(Throughput::Elements(input.len() as u64));
+    group.bench_function("scalar", |b| {
+        b.iter(|| {
+            for x in input.chunks_exact(N) {
+                for (svf, x) in scalar.iter_mut().zip(x) {
+                    black_box(svf.run(*x));
+                }
+            }
+        })
+    });
+    group.bench_function("simd", |b| {
+        b.iter(|| {
+            for x in input.chunks_exact(N) {
+                let mut lanes = [0.0; N];
+                lanes.copy_from_slice(x);
+                black_box(simd.run(lanes));
+            }
+        })
+    });
+    group.finish();
+}
+
+fn svf_simd_run(c: &mut Criterion) {
+    let input: Vec<f64> = noise(1024).iter().map(|x| *x as f64).collect();
+    svf_lanes::<2>(c, &input);
+    svf_lanes::<4>(c, &input);
+    svf_lanes::<8>(c, &input);
+}
+
+// N scalar compressors against one CompSimd<N>, as svf_lanes
+fn comp_lanes<const N: usize>(c: &mut Criterion, input: &[f64]) {
+    let mut scalar = [Comp::new(0.0, 10.0, 20.0, 48000.0, 5.0); N];
+    let mut simd = CompSimd::<N>::new(0.0, 10.0, 20.0, 48000.0, 5.0);
+
+    let mut group = c.benchmark_group(format!("comp_lanes_{}", N));
+    group.throughput(Throughput::Elements(input.len() as u64));
+    group.bench_function("scalar", |b| {
+        b.iter(|| {
+            for x in input.chunks_exact(N) {
+                for (comp, x) in scalar.iter_mut().zip(x) {
+                    black_box(comp.process(*x * 4.0));
+                }
+            }
+        })
+    });
+    group.bench_function("simd", |b| {
+        b.iter(|| {
+            for x in input.chunks_exact(N) {
+                let mut lanes = [0.0; N];
+                for (lane, x) in lanes.iter_mut().zip(x) {
+                    *lane = x * 4.0;
+                }
+                black_box(simd.process(lanes));
+            }
+        })
+    });
+    group.finish();
+}
+
+fn comp_simd_process(c: &mut Criterion) {
+    let input: Vec<f64> = noise(1024).iter().map(|x| x.abs() as f64).collect();
+    comp_lanes::<2>(c, &input);
+    comp_lanes::<4>(c, &input);
+    comp_lanes::<8>(c, &input);
+}
+
+fn comp_process(c: &mut Criterion) {
+    let input: Vec<f64> = noise(1024).iter().map(|x| x.abs() as f64).collect();
+    let mut comp = Comp::new(0.0, 10.0, 20.0, 48000.0, 5.0);
+
+    let mut group = c.benchmark_group("comp_process");
+    group.throughput(Throughput::Elements(input.len() as u64));
+    group.bench_function("detector", |b| {
+        b.iter(|| {
+            for x in input.iter() {
+                black_box(comp.process(*x * 4.0));
+            }
+        })
+    });
+    group.finish();
+}
+
+fn dynsat_process(c: &mut Criterion) {
+    for &mode in [1.0, 3.0, 4.0].iter() {
+        bench_plugin::<DynSat>(c, &format!("dynsat_process_mode_{}", mode), || {
+            model_with::<DynSat>(&[("Mode", mode)])
+        });
+    }
+}
+
+criterion_group!(
+    benches,
+    svf_run,
+    svf_simd_run,
+    comp_process,
+    comp_simd_process,
+    dynsat_process
+);
+criterion_main!(benches);
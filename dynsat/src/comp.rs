@@ -1,4 +1,5 @@
-use crate::units::Units;
+use plugin_common::lanes::Lanes;
+use plugin_common::units::Units;
 use std::f64::consts::PI;
 
 #[derive(Debug, Clone, Copy)]
@@ -53,3 +54,139 @@ impl Comp {
         }
     }
 }
+
+/// N compressor detectors run side by side, one per lane, see SVFSimd.
+/// All lanes share the same settings. Each lane gives the same result as a scalar Comp.
+#[derive(Debug, Clone, Copy)]
+pub struct CompSimd<const N: usize> {
+    prev_env: Lanes<N>,
+    cte_attack: f64,
+    cte_release: f64,
+    thrlin: f64,
+    ratio: f64,
+}
+
+impl<const N: usize> CompSimd<N> {
+    pub fn new(
+        threshold: f64,
+        attack: f64,
+        release: f64,
+        sample_rate: f64,
+        ratio: f64,
+    ) -> CompSimd<N> {
+        let mut new_comp = CompSimd {
+            prev_env: Lanes::splat(1.0),
+            cte_attack: 0.0,
+            cte_release: 0.0,
+            thrlin: 0.0,
+            ratio: 0.0,
+        };
+        new_comp.update(threshold, attack, release, sample_rate, ratio);
+        new_comp
+    }
+
+    pub fn update(
+        &mut self,
+        threshold: f64,
+        attack: f64,
+        release: f64,
+        sample_rate: f64,
+        ratio: f64,
+    ) {
+        self.thrlin = threshold.db_to_lin();
+        self.cte_attack = (-2.0 * PI * 1000.0 / attack / sample_rate).exp();
+        self.cte_release = (-2.0 * PI * 1000.0 / release / sample_rate).exp();
+        self.ratio = ratio;
+    }
+
+    /// Runs one lane on its own, as a scalar Comp, the other lanes hold their envelopes
+    pub fn process_lane(&mut self, lane: usize, detector_input: f64) -> f64 {
+        let prev_env = self.prev_env[lane];
+        let cte = if detector_input >= prev_env {
+            self.cte_attack
+        } else {
+            self.cte_release
+        };
+        let env = detector_input + cte * (prev_env - detector_input);
+        self.prev_env[lane] = env;
+        if env <= self.thrlin {
+            1.0
+        } else {
+            (env / self.thrlin).powf(1.0 / self.ratio - 1.0)
+        }
+    }
+
+    #[inline]
+    pub fn process(&mut self, detector_input: [f64; N]) -> [f64; N] {
+        // The envelopes run in the lanes, the transfer function is per lane
+        let input = Lanes::from_array(detector_input);
+        let cte = input.select_ge(
+            self.prev_env,
+            Lanes::splat(self.cte_attack),
+            Lanes::splat(self.cte_release),
+        );
+        self.prev_env = input + cte * (self.prev_env - input);
+        let mut output = [1.0; N];
+        for (out, env) in output.iter_mut().zip(self.prev_env.to_array().iter()) {
+            if *env > self.thrlin {
+                *out = (env / self.thrlin).powf(1.0 / self.ratio - 1.0);
+            }
+        }
+        output
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn check_lanes<const N: usize>() {
+        let mut simd = CompSimd::<N>::new(-12.0, 10.0, 20.0, 48000.0, 4.0);
+        let mut scalar = [Comp::new(-12.0, 10.0, 20.0, 48000.0, 4.0); N];
+
+        let mut seed = 1u32;
+        for n in 0..10000 {
+            let mut input = [0.0; N];
+            for (lane, x) in input.iter_mut().enumerate() {
+                seed = seed.wrapping_mul(1664525).wrapping_add(1013904223);
+                // Bursts at different levels per lane so lanes cross the threshold at different times
+                let level = if (n / 1000 + lane) % 2 == 0 {
+                    0.05
+                } else {
+                    1.0
+                };
+                *x = (seed as f64 / u32::MAX as f64) * level;
+            }
+            let output = simd.process(input);
+            for lane in 0..N {
+                assert_eq!(output[lane], scalar[lane].process(input[lane]));
+            }
+        }
+    }
+
+    #[test]
+    fn test_simd_matches_scalar() {
+        check_lanes::<1>();
+        check_lanes::<2>();
+        check_lanes::<4>();
+        check_lanes::<8>();
+    }
+
+    // A lane run on its own keeps in step with its scalar Comp, and doesn't move the others
+    #[test]
+    fn test_process_lane_matches_scalar() {
+        let mut simd = CompSimd::<4>::new(-12.0, 10.0, 20.0, 48000.0, 4.0);
+        let mut scalar = [Comp::new(-12.0, 10.0, 20.0, 48000.0, 4.0); 4];
+        for n in 0..4000 {
+            let x = if (n / 500) % 2 == 0 { 1.0 } else { 0.05 };
+            if n % 3 == 0 {
+                let output = simd.process([x; 4]);
+                for lane in 0..4 {
+                    assert_eq!(output[lane], scalar[lane].process(x));
+                }
+            } else {
+                assert_eq!(simd.process_lane(1, x), scalar[1].process(x));
+            }
+        }
+    }
+}
@@ -1,42 +1,43 @@
 #![allow(incomplete_features)]
 #![feature(generic_associated_types)]
 
+use std::ffi::CStr;
+
 use serde::{Deserialize, Serialize};
+use serde_json::{Map, Value};
 
 use baseplug::{Plugin, ProcessContext};
-use units::map_to_freq;
-
-mod comp;
-mod svf;
-mod units;
-
-use crate::svf::{SVFCoefficients, Type, SVF};
-
-use crate::comp::Comp;
-
-fn setup_logging() {
-    let log_folder = ::dirs::home_dir().unwrap().join("tmp");
+use clap_sys::id::clap_id;
+use clap_sys::plugin_features::{
+    CLAP_PLUGIN_FEATURE_AUDIO_EFFECT, CLAP_PLUGIN_FEATURE_COMPRESSOR,
+    CLAP_PLUGIN_FEATURE_DISTORTION, CLAP_PLUGIN_FEATURE_STEREO,
+};
+use log::Level;
+use plugin_common::clap::{ClapExport, ClapParam};
+use plugin_common::logging;
+use plugin_common::mix::{MixLaw, MixStage};
+use plugin_common::presets::{FactoryPresets, Preset};
+use plugin_common::protect::{DenormalGuard, Sanitizer};
+use plugin_common::rtlog::RtLog;
+use plugin_common::state::VersionedState;
+use plugin_common::svf::{SVFCoefficients, SVFSimd, Type};
+use plugin_common::units::{map_to_freq, Units};
+use plugin_common::vst3::sys::{uid, Tuid};
+use plugin_common::vst3::Vst3Export;
 
-    let _ = ::std::fs::create_dir(log_folder.clone());
+pub mod comp;
 
-    let log_file = ::std::fs::File::create(log_folder.join("DynSat.log")).unwrap();
-
-    let log_config = ::simplelog::ConfigBuilder::new()
-        .set_time_to_local(true)
-        .build();
-
-    let _ = ::simplelog::WriteLogger::init(simplelog::LevelFilter::Info, log_config, log_file);
-
-    ::log_panics::init();
-
-    ::log::info!("init");
-}
+use crate::comp::CompSimd;
 
 const FILTER_COUNT: usize = 16;
+// Each SIMD block holds the left and right of 4 bands, interleaved
+const LANES: usize = 8;
+const BLOCKS: usize = FILTER_COUNT * 2 / LANES;
 
-baseplug::model! {
-    #[derive(Debug, Serialize, Deserialize)]
-    struct DynSatModel {
+plugin_common::model! {
+    #[derive(Debug, Clone, Serialize, Deserialize)]
+    #[serde(default)]
+    pub struct DynSatModel {
         #[model(min = -12.0, max = 96.0)]
         #[parameter(name = "Gain", unit = "Decibels",
             gradient = "Power(1.0)")]
@@ -48,7 +49,31 @@ baseplug::model! {
         #[model(min = 1.0, max = 10.0)]
         #[parameter(name = "Mode", unit = "Generic",
             gradient = "Linear")]
-        mode: f32
+        #[export(stepped)]
+        mode: f32,
+        #[model(min = 0.0, max = 1.0)]
+        #[parameter(name = "Mix", unit = "Generic",
+            gradient = "Linear")]
+        mix: f32,
+        #[model(min = -12.0, max = 12.0)]
+        #[parameter(name = "Trim", unit = "Decibels",
+            gradient = "Power(1.0)")]
+        trim: f32,
+        #[model(min = 0.0, max = 1.0)]
+        #[parameter(name = "Mix Law", unit = "Generic",
+            gradient = "Linear")]
+        #[export(stepped)]
+        mix_law: f32,
+        #[model(min = 0.0, max = 1.0)]
+        #[parameter(name = "Bypass", unit = "Generic",
+            gradient = "Linear")]
+        #[export(stepped)]
+        bypass: f32,
+        #[model(min = 0.0, max = 1.0)]
+        #[parameter(name = "Bypass Tails", unit = "Generic",
+            gradient = "Linear")]
+        #[export(stepped)]
+        bypass_tails: f32
     }
 }
 
@@ -61,13 +86,55 @@ impl Default for DynSatModel {
             gain: 1.0,
             out_gain: 1.0,
             mode: 1.0,
+            mix: 1.0,
+            trim: 1.0,
+            mix_law: 0.0,
+            bypass: 0.0,
+            bypass_tails: 0.0,
         }
     }
 }
 
-struct DynSat {
-    svfs: [[SVF<f64>; 2]; FILTER_COUNT],
-    comps: [[Comp; 2]; FILTER_COUNT],
+impl FactoryPresets for DynSatModel {
+    fn factory_presets() -> Vec<Preset<Self>> {
+        // name, gain dB, out gain dB, mode
+        let presets: [(&str, f32, f32, f32); 5] = [
+            ("Default", 0.0, 0.0, 1.0),
+            ("Gentle Glue", 3.0, -1.0, 3.0),
+            ("Multiband Warmth", 12.0, -9.0, 1.0),
+            ("Band Squash", 18.0, -12.0, 2.0),
+            ("Tape Drive", 6.0, -4.0, 4.0),
+        ];
+        presets
+            .iter()
+            .map(|&(name, gain, out_gain, mode)| Preset {
+                name: name.to_string(),
+                model: DynSatModel {
+                    gain: gain.db_to_lin(),
+                    out_gain: out_gain.db_to_lin(),
+                    mode,
+                    ..DynSatModel::default()
+                },
+            })
+            .collect()
+    }
+}
+
+impl VersionedState for DynSatModel {
+    const STATE_VERSION: u32 = 1;
+
+    fn migrate(_from_version: u32, _model: &mut Map<String, Value>) {
+        // Version 0 is the bare model, the parameters haven't changed since
+    }
+}
+
+pub struct DynSat {
+    svfs: [SVFSimd<LANES>; BLOCKS],
+    comps: [CompSimd<LANES>; BLOCKS],
+    mix: MixStage,
+    sanitizer: Sanitizer,
+    rt_log: RtLog,
+    clipping: bool,
 }
 
 impl Plugin for DynSat {
@@ -81,16 +148,16 @@ impl Plugin for DynSat {
     type Model = DynSatModel;
 
     #[inline]
-    fn new(sample_rate: f32, _model: &DynSatModel) -> Self {
-        setup_logging();
+    fn new(sample_rate: f32, model: &DynSatModel) -> Self {
+        let log_tag = logging::init(Self::NAME);
+        ::log::info!(target: &log_tag, "init");
         let coeffs =
             SVFCoefficients::<f64>::from_params(Type::BandPass, sample_rate as f64, 100.0, 1.0)
                 .unwrap();
-        let mut svfs = [[SVF::<f64>::new(coeffs); 2]; FILTER_COUNT];
-        let len = svfs.len();
-        for (i, svf) in svfs.iter_mut().enumerate() {
-            let hz = map_to_freq(i as f32 / (len - 1) as f32);
-            ::log::info!("{}", hz);
+        let mut svfs = [SVFSimd::<LANES>::new([coeffs; LANES]); BLOCKS];
+        for i in 0..FILTER_COUNT {
+            let hz = map_to_freq(i as f32 / (FILTER_COUNT - 1) as f32);
+            ::log::info!(target: &log_tag, "{}", hz);
             let coeffs2 = SVFCoefficients::<f64>::from_params(
                 Type::BandPass,
                 sample_rate as f64,
@@ -98,55 +165,97 @@ impl Plugin for DynSat {
                 0.70710678118654757f64 * 2.0,
             )
             .unwrap();
-            svf[0].update_coefficients(coeffs2);
-            svf[1].update_coefficients(coeffs2);
+            let svf = &mut svfs[i * 2 / LANES];
+            let lane = i * 2 % LANES;
+            svf.update_coefficients(lane, coeffs2);
+            svf.update_coefficients(lane + 1, coeffs2);
         }
 
-        let comps = [[Comp::new(0.0, 10.0, 20.0, 48000.0, 5.0); 2]; FILTER_COUNT];
-        DynSat { svfs, comps }
+        let comps = [CompSimd::<LANES>::new(0.0, 10.0, 20.0, 48000.0, 5.0); BLOCKS];
+        let mut mix = MixStage::new(sample_rate);
+        mix.set_latency(Self::latency(model, sample_rate));
+        DynSat {
+            svfs,
+            comps,
+            mix,
+            sanitizer: Sanitizer::new(),
+            rt_log: RtLog::new(log_tag),
+            clipping: false,
+        }
     }
 
     #[inline]
     fn process(&mut self, model: &DynSatModelProcess, ctx: &mut ProcessContext<Self>) {
+        let _denormals = DenormalGuard::new();
         let input = &ctx.inputs[0].buffers;
         let output = &mut ctx.outputs[0].buffers;
+        let mut peak = 0.0f64;
         for i in 0..ctx.nframes {
             let mode = model.mode[i] as u8;
             let gain = model.gain[i] as f64;
             let out_gain = model.out_gain[i] as f64;
+            let mix = model.mix[i] as f64;
+            let trim = model.trim[i] as f64;
+            let mix_law = MixLaw::from_param(model.mix_law[i]);
+            let bypass = model.bypass[i] >= 0.5;
+            let bypass_tails = model.bypass_tails[i] >= 0.5;
+            // Non-finite input would stay in the filter and envelope states for good
             let l = input[0][i] as f64;
             let r = input[1][i] as f64;
+            let l = if l.is_finite() { l } else { 0.0 };
+            let r = if r.is_finite() { r } else { 0.0 };
+            let dry = [l, r];
+
+            self.mix.set_bypass(bypass, bypass_tails);
+            if !self.mix.processing() {
+                // Bypassed without tails, the filters and compressors hold their state
+                let [l_out, r_out] = self.mix.process(dry, [0.0; 2], mix, mix_law, trim);
+                output[0][i] = l_out as f32;
+                output[1][i] = r_out as f32;
+                continue;
+            }
+            let l = l * self.mix.input_gain();
+            let r = r * self.mix.input_gain();
             let mut l_out = 0.0 as f64;
             let mut r_out = 0.0 as f64;
 
             if mode == 1 || mode == 2 {
                 let l_a = l * gain;
                 let r_a = r * gain;
+                let mut input = [0.0; LANES];
+                for pair in input.chunks_exact_mut(2) {
+                    pair[0] = l_a;
+                    pair[1] = r_a;
+                }
                 for (svf, comp) in self.svfs.iter_mut().zip(&mut self.comps) {
-                    let mut l_band = svf[0].run(l_a);
-                    let mut r_band = svf[1].run(r_a);
-                    let cv_l = comp[0].process(l_band.abs() as f64);
-                    let cv_r = comp[1].process(r_band.abs() as f64);
-                    l_band *= cv_l;
-                    r_band *= cv_r;
-                    if mode == 1 {
-                        l_band = (l_band * gain).tanh();
-                        r_band = (r_band * gain).tanh();
-                        l_band /= cv_l;
-                        r_band /= cv_r;
+                    let mut bands = svf.run(input);
+                    let mut detector = [0.0; LANES];
+                    for (d, band) in detector.iter_mut().zip(bands.iter()) {
+                        *d = band.abs();
+                    }
+                    let cv = comp.process(detector);
+                    for (band, cv) in bands.iter_mut().zip(cv.iter()) {
+                        *band *= cv;
+                        if mode == 1 {
+                            *band = (*band * gain).tanh();
+                            *band /= cv;
+                        }
+                    }
+                    for pair in bands.chunks_exact(2) {
+                        l_out += pair[0];
+                        r_out += pair[1];
                     }
-                    l_out += l_band;
-                    r_out += r_band;
                 }
-                l_out /= (self.svfs.len() as f64) * 0.25;
-                r_out /= (self.svfs.len() as f64) * 0.25;
+                l_out /= (FILTER_COUNT as f64) * 0.25;
+                r_out /= (FILTER_COUNT as f64) * 0.25;
                 l_out *= out_gain;
                 r_out *= out_gain;
             } else if mode == 3 {
                 let l_a = l * gain;
                 let r_a = r * gain;
-                let cv_l = self.comps[0][0].process(l_a.abs() as f64);
-                let cv_r = self.comps[0][1].process(r_a.abs() as f64);
+                // The lowest band's compressors, so their envelopes carry over from modes 1 and 2
+                let cv_l = self.comps[0].process_lane(0, l_a.abs());
+                let cv_r = self.comps[0].process_lane(1, r_a.abs());
                 l_out = l_a * cv_l * out_gain;
                 r_out = r_a * cv_r * out_gain;
             } else {
@@ -154,10 +263,221 @@ impl Plugin for DynSat {
                 r_out = (r * gain).tanh() * out_gain;
             }
 
+            // Out Gain is the wet level, Trim comes after the blend with the dry input
+            let [l_out, r_out] = self.mix.process(dry, [l_out, r_out], mix, mix_law, trim);
+
+            peak = peak.max(l_out.abs()).max(r_out.abs());
             output[0][i] = l_out as f32;
             output[1][i] = r_out as f32;
         }
+
+        // Only logged when clipping starts, not on every clipped block
+        let clipping = peak > 1.0;
+        if clipping && !self.clipping {
+            self.rt_log.push(Level::Warn, "Output clipping. Peak", peak);
+        }
+        self.clipping = clipping;
+        if self.sanitizer.process_buffers(output, ctx.nframes) {
+            self.rt_log.push(
+                Level::Warn,
+                "Replaced non-finite output samples. Total",
+                self.sanitizer.count as f64,
+            );
+        }
     }
 }
 
-baseplug::vst2!(DynSat, b"tAnE");
+impl ClapExport for DynSat {
+    const CLAP_ID: &'static str = "net.dgdigital.dynsat";
+    const VERSION: &'static str = env!("CARGO_PKG_VERSION");
+    const CLAP_FEATURES: &'static [&'static CStr] = &[
+        CLAP_PLUGIN_FEATURE_AUDIO_EFFECT,
+        CLAP_PLUGIN_FEATURE_COMPRESSOR,
+        CLAP_PLUGIN_FEATURE_DISTORTION,
+        CLAP_PLUGIN_FEATURE_STEREO,
+    ];
+    const BYPASS_PARAM: Option<clap_id> = Some(6);
+
+    fn clap_params() -> Vec<ClapParam<DynSatModel>> {
+        DynSatModel::clap_params()
+    }
+}
+
+impl Vst3Export for DynSat {
+    const VST3_CLASS_ID: Tuid = uid(0x2FECBAF2, 0x99334C52, 0x9AA36733, 0x4BA1CE92);
+    const VST3_CATEGORIES: &'static str = "Fx|Dynamics|Distortion";
+}
+
+plugin_common::vst2_export!(DynSat, b"tAnE");
+plugin_common::clap_export!(DynSat);
+plugin_common::vst3_export!(DynSat);
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::comp::Comp;
+    use plugin_common::harness::Harness;
+    #[cfg(feature = "rt-check")]
+    use plugin_common::rt_check;
+    use plugin_common::{clap, fuzz, state, vst2, vst3};
+    use proptest::prelude::*;
+
+    // The band filters' output peaks at a few times their input on transients
+    const BAND_OVERSHOOT: f32 = 4.0;
+
+    // The compressors only ever turn down. Modes 1 and 2 sum the bands, each at most the
+    // overshoot on the driven input, and mode 1 drives each band again before its tanh, which
+    // can't make it louder than that. Mode 3 is the driven input and the others tanh.
+    fn max_output(models: &[DynSatModel]) -> f32 {
+        let gain = fuzz::peak(models, |model| model.gain);
+        let bands = BAND_OVERSHOOT * gain * gain.max(1.0) / 0.25;
+        let wet = fuzz::peak(models, |model| model.out_gain) * bands.max(gain).max(1.0);
+        fuzz::mixed_max_output(wet, fuzz::peak(models, |model| model.trim))
+    }
+
+    fn model() -> impl Strategy<Value = DynSatModel> {
+        (
+            (-12.0f32..=96.0, -96.0f32..=12.0, 1.0f32..=10.0),
+            (0.0f32..=1.0, -12.0f32..=12.0, 0.0f32..=1.0),
+            (0.0f32..=1.0, 0.0f32..=1.0),
+        )
+            .prop_map(
+                |((gain, out_gain, mode), (mix, trim, mix_law), (bypass, bypass_tails))| {
+                    DynSatModel {
+                        gain: gain.db_to_lin(),
+                        out_gain: out_gain.db_to_lin(),
+                        mode,
+                        mix,
+                        trim: trim.db_to_lin(),
+                        mix_law,
+                        bypass,
+                        bypass_tails,
+                    }
+                },
+            )
+    }
+
+    #[test]
+    fn test_state_v0_fixture() {
+        let model: DynSatModel =
+            state::from_json(include_str!("../fixtures/state_v0.json")).unwrap();
+        assert_eq!(model.gain, 3.981072);
+        assert_eq!(model.out_gain, 0.5011872);
+        assert_eq!(model.mode, 3.0);
+        // Saved before the mix stage existed
+        assert_eq!(model.mix, 1.0);
+        assert_eq!(model.trim, 1.0);
+    }
+
+    #[cfg(feature = "rt-check")]
+    #[test]
+    fn test_process_is_realtime_safe() {
+        for preset in DynSatModel::factory_presets() {
+            rt_check::check_process::<DynSat>(|| preset.model.clone(), 48000.0, 256, 64);
+        }
+    }
+
+    #[cfg(feature = "rt-check")]
+    #[test]
+    fn test_process_is_realtime_safe_with_full_log_queue() {
+        use std::sync::mpsc;
+
+        // All dry, so the over range input clips and process logs it
+        let model = DynSatModel {
+            mix: 0.0,
+            ..DynSatModel::default()
+        };
+        let mut harness = Harness::<DynSat>::new(|| model.clone(), 48000.0, 256);
+        // The sink holds its writer thread up, so the queue stays full
+        let (release, wait) = mpsc::channel::<()>();
+        harness.plugin.rt_log = RtLog::with_sink(move |_, _| {
+            let _ = wait.recv();
+        });
+        while harness.plugin.rt_log.push(Level::Info, "filler", 0.0) {}
+
+        let input = vec![4.0; 256];
+        let violations = rt_check::check(|| {
+            harness.process([&input, &input]);
+        });
+        assert_eq!(violations, rt_check::Violations::default());
+        assert!(harness.plugin.clipping);
+        assert!(!harness.plugin.rt_log.push(Level::Info, "filler", 0.0));
+        drop(release);
+    }
+
+    proptest! {
+        #![proptest_config(ProptestConfig::with_cases(64))]
+
+        #[test]
+        fn test_fuzz_process(
+            blocks in fuzz::blocks(model(), 1..16),
+            sample_rate in fuzz::sample_rate(),
+        ) {
+            fuzz::fuzz_process::<DynSat>(
+                &blocks,
+                DynSatModel::default(),
+                sample_rate,
+                256,
+                max_output,
+            )?;
+        }
+    }
+
+    // Mode 3 compresses each channel through a compressor of its own, as it always has
+    #[test]
+    fn test_mode_3_matches_scalar_comps() {
+        let model = DynSatModel {
+            gain: 4.0,
+            out_gain: 0.5,
+            mode: 3.0,
+            ..DynSatModel::default()
+        };
+        let mut harness = Harness::<DynSat>::new(|| model.clone(), 48000.0, 256);
+        let mut comps = [Comp::new(0.0, 10.0, 20.0, 48000.0, 5.0); 2];
+        let noise = plugin_common::bench::noise(256);
+        for block in 0..20 {
+            // Loud and quiet blocks so the envelopes attack and release
+            let level = if block % 4 < 2 { 1.0 } else { 0.1 };
+            let l: Vec<f32> = noise.iter().map(|x| x * level).collect();
+            let r: Vec<f32> = noise.iter().rev().map(|x| x * level * 0.5).collect();
+            let output = harness.process([&l, &r]);
+            for (channel, input) in [&l, &r].iter().enumerate() {
+                for (x, y) in input.iter().zip(output[channel]) {
+                    let driven = *x as f64 * 4.0;
+                    let expected = driven * comps[channel].process(driven.abs()) * 0.5;
+                    assert!((*y as f64 - expected).abs() < 1e-6, "{} != {}", y, expected);
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn test_clap_export() {
+        clap::host::validate::<DynSat>(&crate::clap_entry);
+    }
+
+    #[test]
+    fn test_vst2_export() {
+        vst2::host::validate::<DynSat>(crate::VSTPluginMain);
+    }
+
+    #[test]
+    fn test_vst2_chunk_v0_fixture() {
+        // The chunk baseplug's VST2 wrapper saved, the bare model before the mix stage existed
+        let instance = vst2::host::Instance::new(crate::VSTPluginMain);
+        assert!(instance.load(include_bytes!("../fixtures/vst2_chunk_v0.json")));
+        let saved = String::from_utf8(instance.save()).unwrap();
+        let model: DynSatModel = state::from_json(&saved).unwrap();
+        assert!((model.gain - 2.5118864).abs() < 1e-5);
+        assert!((model.out_gain - 0.70794576).abs() < 1e-6);
+        assert_eq!(model.mode, 2.0);
+        assert_eq!(model.mix, 1.0);
+        assert_eq!(model.trim, 1.0);
+        assert_eq!(model.bypass, 0.0);
+    }
+
+    #[test]
+    fn test_vst3_export() {
+        vst3::host::validate::<DynSat>(crate::GetPluginFactory);
+    }
+}
@@ -0,0 +1,12 @@
+use criterion::{criterion_group, criterion_main, Criterion};
+
+use plugin_common::bench::bench_plugin;
+
+use gain::{Gain, GainModel};
+
+fn gain_process(c: &mut Criterion) {
+    bench_plugin::<Gain>(c, "gain_process", GainModel::default);
+}
+
+criterion_group!(benches, gain_process);
+criterion_main!(benches);
@@ -1,21 +1,58 @@
 #![allow(incomplete_features)]
 #![feature(generic_associated_types)]
 
+use std::ffi::CStr;
+
 use serde::{Serialize, Deserialize};
+use serde_json::{Map, Value};
 
 use baseplug::{
     ProcessContext,
     Plugin,
 };
+use clap_sys::id::clap_id;
+use clap_sys::plugin_features::{
+    CLAP_PLUGIN_FEATURE_AUDIO_EFFECT,
+    CLAP_PLUGIN_FEATURE_UTILITY,
+    CLAP_PLUGIN_FEATURE_MIXING,
+    CLAP_PLUGIN_FEATURE_STEREO,
+};
 
+use plugin_common::clap::{ClapExport, ClapParam};
+use plugin_common::mix::{MixLaw, MixStage};
+use plugin_common::presets::{FactoryPresets, Preset};
+use plugin_common::protect::{DenormalGuard, Sanitizer};
+use plugin_common::state::VersionedState;
+use plugin_common::units::Units;
+use plugin_common::vst3::sys::{uid, Tuid};
+use plugin_common::vst3::Vst3Export;
 
-baseplug::model! {
-    #[derive(Debug, Serialize, Deserialize)]
-    struct GainModel {
+plugin_common::model! {
+    #[derive(Debug, Clone, Serialize, Deserialize)]
+    #[serde(default)]
+    pub struct GainModel {
         #[model(min = -90.0, max = 3.0)]
-        #[parameter(name = "gain", unit = "Decibels",
+        #[parameter(name = "Gain", unit = "Decibels",
             gradient = "Power(0.15)")]
-        gain: f32
+        gain: f32,
+        #[model(min = 0.0, max = 1.0)]
+        #[parameter(name = "Mix", unit = "Generic",
+            gradient = "Linear")]
+        mix: f32,
+        #[model(min = -12.0, max = 12.0)]
+        #[parameter(name = "Trim", unit = "Decibels",
+            gradient = "Power(1.0)")]
+        trim: f32,
+        #[model(min = 0.0, max = 1.0)]
+        #[parameter(name = "Mix Law", unit = "Generic",
+            gradient = "Linear")]
+        #[export(stepped)]
+        mix_law: f32,
+        #[model(min = 0.0, max = 1.0)]
+        #[parameter(name = "Bypass", unit = "Generic",
+            gradient = "Linear")]
+        #[export(stepped)]
+        bypass: f32
     }
 }
 
@@ -25,12 +62,48 @@ impl Default for GainModel {
             // "gain" is converted from dB to coefficient in the parameter handling code,
             // so in the model here it's a coeff.
             // -0dB == 1.0
-            gain: 1.0
+            gain: 1.0,
+            mix: 1.0,
+            trim: 1.0,
+            mix_law: 0.0,
+            bypass: 0.0,
         }
     }
 }
 
-struct Gain;
+impl FactoryPresets for GainModel {
+    fn factory_presets() -> Vec<Preset<Self>> {
+        let presets: [(&str, f32); 4] = [
+            ("Unity", 0.0),
+            ("-6 dB", -6.0),
+            ("-12 dB", -12.0),
+            ("+3 dB", 3.0),
+        ];
+        presets
+            .iter()
+            .map(|&(name, db)| Preset {
+                name: name.to_string(),
+                model: GainModel {
+                    gain: db.db_to_lin(),
+                    ..GainModel::default()
+                },
+            })
+            .collect()
+    }
+}
+
+impl VersionedState for GainModel {
+    const STATE_VERSION: u32 = 1;
+
+    fn migrate(_from_version: u32, _model: &mut Map<String, Value>) {
+        // Version 0 is the bare model, the parameters haven't changed since
+    }
+}
+
+pub struct Gain {
+    mix: MixStage,
+    sanitizer: Sanitizer,
+}
 
 impl Plugin for Gain {
     const NAME: &'static str = "basic gain plug";
@@ -43,20 +116,195 @@ impl Plugin for Gain {
     type Model = GainModel;
 
     #[inline]
-    fn new(_sample_rate: f32, _model: &GainModel) -> Self {
-        Self
+    fn new(sample_rate: f32, model: &GainModel) -> Self {
+        let mut mix = MixStage::new(sample_rate);
+        mix.set_latency(Self::latency(model, sample_rate));
+        Self {
+            mix,
+            sanitizer: Sanitizer::new(),
+        }
     }
 
     #[inline]
     fn process(&mut self, model: &GainModelProcess, ctx: &mut ProcessContext<Self>) {
+        let _denormals = DenormalGuard::new();
         let input = &ctx.inputs[0].buffers;
         let output = &mut ctx.outputs[0].buffers;
 
         for i in 0..ctx.nframes {
-            output[0][i] = input[0][i] * model.gain[i];
-            output[1][i] = input[1][i] * model.gain[i];
+            let gain = model.gain[i] as f64;
+            let dry = [input[0][i] as f64, input[1][i] as f64];
+            let wet = [dry[0] * gain, dry[1] * gain];
+            let mix = model.mix[i] as f64;
+            let trim = model.trim[i] as f64;
+            let mix_law = MixLaw::from_param(model.mix_law[i]);
+            // There's no state to keep running, so no tails
+            self.mix.set_bypass(model.bypass[i] >= 0.5, false);
+            let [l, r] = self.mix.process(dry, wet, mix, mix_law, trim);
+            output[0][i] = l as f32;
+            output[1][i] = r as f32;
         }
+
+        self.sanitizer.process_buffers(output, ctx.nframes);
+    }
+}
+
+impl ClapExport for Gain {
+    const CLAP_ID: &'static str = "net.dgdigital.gain";
+    const VERSION: &'static str = env!("CARGO_PKG_VERSION");
+    const CLAP_FEATURES: &'static [&'static CStr] = &[
+        CLAP_PLUGIN_FEATURE_AUDIO_EFFECT,
+        CLAP_PLUGIN_FEATURE_UTILITY,
+        CLAP_PLUGIN_FEATURE_MIXING,
+        CLAP_PLUGIN_FEATURE_STEREO,
+    ];
+    const BYPASS_PARAM: Option<clap_id> = Some(4);
+
+    fn clap_params() -> Vec<ClapParam<GainModel>> {
+        GainModel::clap_params()
     }
 }
 
-baseplug::vst2!(Gain, b"tAnE");
\ No newline at end of file
+impl Vst3Export for Gain {
+    const VST3_CLASS_ID: Tuid = uid(0x9E87D41B, 0xE73349EC, 0xAA05DCB5, 0xA5742CB2);
+    const VST3_CATEGORIES: &'static str = "Fx|Tools";
+}
+
+plugin_common::vst2_export!(Gain, b"tAnE");
+plugin_common::clap_export!(Gain);
+plugin_common::vst3_export!(Gain);
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use plugin_common::clap;
+    use plugin_common::clap::host::ParamEvent;
+    use plugin_common::fuzz;
+    #[cfg(feature = "rt-check")]
+    use plugin_common::rt_check;
+    use plugin_common::state;
+    use plugin_common::vst2;
+    use plugin_common::vst3;
+    use proptest::prelude::*;
+
+    // The wet signal is the full scale input at the most gain the models ramp through
+    fn max_output(models: &[GainModel]) -> f32 {
+        let wet = fuzz::peak(models, |model| model.gain);
+        fuzz::mixed_max_output(wet, fuzz::peak(models, |model| model.trim))
+    }
+
+    fn model() -> impl Strategy<Value = GainModel> {
+        (
+            -90.0f32..=3.0,
+            0.0f32..=1.0,
+            -12.0f32..=12.0,
+            0.0f32..=1.0,
+            0.0f32..=1.0,
+        )
+            .prop_map(|(db, mix, trim, mix_law, bypass)| GainModel {
+                gain: db.db_to_lin(),
+                mix,
+                trim: trim.db_to_lin(),
+                mix_law,
+                bypass,
+            })
+    }
+
+    #[cfg(feature = "rt-check")]
+    #[test]
+    fn test_process_is_realtime_safe() {
+        for preset in GainModel::factory_presets() {
+            rt_check::check_process::<Gain>(|| preset.model.clone(), 48000.0, 256, 64);
+        }
+    }
+
+    proptest! {
+        #[test]
+        fn test_fuzz_process(
+            blocks in fuzz::blocks(model(), 1..16),
+            sample_rate in fuzz::sample_rate(),
+        ) {
+            fuzz::fuzz_process::<Gain>(
+                &blocks,
+                GainModel::default(),
+                sample_rate,
+                256,
+                max_output,
+            )?;
+        }
+    }
+
+    #[test]
+    fn test_clap_export() {
+        let host = clap::host::validate::<Gain>(&crate::clap_entry);
+
+        // Automation starts at the frame it's timestamped with
+        let instance = host.create();
+        instance.activate();
+        let mut buffers = [vec![1.0; 512], vec![1.0; 512]];
+        let event = ParamEvent {
+            time: 300,
+            id: 0,
+            value: -90.0,
+        };
+        instance.process(&mut buffers, &[event]);
+        assert!(buffers[0][..300].iter().all(|x| *x == 1.0));
+        assert!(buffers[0][300..310].iter().any(|x| *x < 1.0));
+
+        // Bypass fades back to the input, without a jump
+        let mut buffers = [vec![1.0; 1024], vec![1.0; 1024]];
+        instance.process(&mut buffers, &[]);
+        let mut buffers = [vec![1.0; 1024], vec![1.0; 1024]];
+        let event = ParamEvent {
+            time: 0,
+            id: 4,
+            value: 1.0,
+        };
+        instance.process(&mut buffers, &[event]);
+        let mut faded = buffers[0].clone();
+        let mut buffers = [vec![1.0; 1024], vec![1.0; 1024]];
+        instance.process(&mut buffers, &[]);
+        faded.extend_from_slice(&buffers[0]);
+        assert!(faded[0] < 0.01);
+        assert!(faded.windows(2).all(|x| (x[1] - x[0]).abs() < 0.01));
+        assert!(faded[1536..].iter().all(|x| *x == 1.0));
+        instance.deactivate();
+    }
+
+    #[test]
+    fn test_vst2_export() {
+        vst2::host::validate::<Gain>(crate::VSTPluginMain);
+    }
+
+    #[test]
+    fn test_vst2_chunk_v0_fixture() {
+        // The chunk baseplug's VST2 wrapper saved, the bare model before the mix stage existed
+        let instance = vst2::host::Instance::new(crate::VSTPluginMain);
+        assert!(instance.load(include_bytes!("../fixtures/vst2_chunk_v0.json")));
+        let saved = String::from_utf8(instance.save()).unwrap();
+        let model: GainModel = state::from_json(&saved).unwrap();
+        assert!((model.gain - 0.3548134).abs() < 1e-6);
+        assert_eq!(model.mix, 1.0);
+        assert_eq!(model.trim, 1.0);
+        assert_eq!(model.bypass, 0.0);
+    }
+
+    #[test]
+    fn test_vst3_export() {
+        let host = vst3::host::validate::<Gain>(crate::GetPluginFactory);
+
+        // Automation starts at the sample offset of its point
+        let instance = host.create();
+        instance.activate();
+        let mut buffers = [vec![1.0; 512], vec![1.0; 512]];
+        let event = vst3::host::ParamEvent {
+            offset: 300,
+            id: 0,
+            normalized: 0.0,
+        };
+        instance.process(&mut buffers, &[event]);
+        assert!(buffers[0][..300].iter().all(|x| *x == 1.0));
+        assert!(buffers[0][300..310].iter().any(|x| *x < 1.0));
+        instance.deactivate();
+    }
+}
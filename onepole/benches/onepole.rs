@@ -0,0 +1,67 @@
+use criterion::{black_box, criterion_group, criterion_main, BenchmarkId, Criterion, Throughput};
+
+use plugin_common::bench::{bench_plugin, model_with, noise};
+
+use plugin_common::lanes::Lanes;
+
+use onepole::{OnePole, OnePoleFilter, OnePoleLanes, OnePoleModel};
+
+fn one_pole_filter_process(c: &mut Criterion) {
+    let input: Vec<f64> = noise(1024).iter().map(|x| *x as f64).collect();
+
+    let mut group = c.benchmark_group("one_pole_filter_process");
+    group.throughput(Throughput::Elements(input.len() as u64));
+    // Low pass, high pass, low shelf, high shelf, all pass
+    for &kind in [1u8, 2, 3, 4, 5].iter() {
+        let mut filter = OnePoleFilter::new(kind, 48000.0, 1000.0, 3.0);
+        group.bench_function(BenchmarkId::from_parameter(kind), |b| {
+            b.iter(|| {
+                for x in input.iter() {
+                    black_box(filter.process(*x));
+                }
+            })
+        });
+    }
+    group.finish();
+}
+
+// Two scalar filters, one per channel, against the lane-paired pair the plugin runs
+fn one_pole_lanes_process(c: &mut Criterion) {
+    let input: Vec<f64> = noise(1024).iter().map(|x| *x as f64).collect();
+    let mut scalar = [OnePoleFilter::new(1, 48000.0, 1000.0, 3.0); 2];
+    let mut lanes = OnePoleLanes::<2>::new(1, 48000.0, 1000.0, 3.0);
+
+    let mut group = c.benchmark_group("one_pole_lanes_2");
+    group.throughput(Throughput::Elements(input.len() as u64));
+    group.bench_function("scalar", |b| {
+        b.iter(|| {
+            for x in input.chunks_exact(2) {
+                black_box([scalar[0].process(x[0]), scalar[1].process(x[1])]);
+            }
+        })
+    });
+    group.bench_function("simd", |b| {
+        b.iter(|| {
+            for x in input.chunks_exact(2) {
+                black_box(lanes.process(Lanes::from_array([x[0], x[1]])));
+            }
+        })
+    });
+    group.finish();
+}
+
+fn onepole_process(c: &mut Criterion) {
+    bench_plugin::<OnePole>(c, "onepole_process", OnePoleModel::default);
+    // 48dB/oct Linkwitz-Riley low pass, the most sections
+    bench_plugin::<OnePole>(c, "onepole_process_48db", || {
+        model_with::<OnePole>(&[("Slope", 6.0), ("Alignment", 2.0)])
+    });
+}
+
+criterion_group!(
+    benches,
+    one_pole_filter_process,
+    one_pole_lanes_process,
+    onepole_process
+);
+criterion_main!(benches);
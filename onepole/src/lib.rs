@@ -33,28 +33,92 @@ http://www.willpirkle.com/Downloads/AN-4VirtualAnalogFilters.pdf (Page 6)
 */
 
 use std::f64::consts::PI;
+use std::ffi::CStr;
 
 use serde::{Deserialize, Serialize};
+use serde_json::{Map, Value};
 
 use baseplug::{Plugin, ProcessContext};
+use clap_sys::id::clap_id;
+use clap_sys::plugin_features::{
+    CLAP_PLUGIN_FEATURE_AUDIO_EFFECT, CLAP_PLUGIN_FEATURE_EQUALIZER, CLAP_PLUGIN_FEATURE_FILTER,
+    CLAP_PLUGIN_FEATURE_STEREO,
+};
+use plugin_common::clap::{ClapExport, ClapParam};
+use plugin_common::lanes::Lanes;
+use plugin_common::mix::{MixLaw, MixStage};
+use plugin_common::presets::{FactoryPresets, Preset};
+use plugin_common::protect::{DenormalGuard, Sanitizer};
+use plugin_common::ramp::{CoeffRamp, Interpolate};
+use plugin_common::state::VersionedState;
+use plugin_common::svf::{SVFCoefficients, SVFSimd, Type};
+use plugin_common::units::butterworth_cascade_q;
+use plugin_common::vst3::sys::{uid, Tuid};
+use plugin_common::vst3::Vst3Export;
 
-baseplug::model! {
-    #[derive(Debug, Serialize, Deserialize)]
-    struct OnePoleModel {
+pub use plugin_common::ramp::UpdateRate;
+
+plugin_common::model! {
+    #[derive(Debug, Clone, Serialize, Deserialize)]
+    #[serde(default)]
+    pub struct OnePoleModel {
         #[model(min = -6.0, max = 6.0)]
-        #[parameter(name = "gain", unit = "Generic",
+        #[parameter(name = "Gain", unit = "Generic",
             gradient = "Linear")]
+        #[export(label = "dB")]
         gain: f32,
 
         #[model(min = 20.0, max = 20000.0)]
-        #[parameter(name = "freq", unit = "Generic",
+        #[parameter(name = "Freq", unit = "Generic",
             gradient = "Power(10.0)")]
+        #[export(label = "Hz")]
         freq: f32,
 
         #[model(min = 1.0, max = 10.0)]
-        #[parameter(name = "kind", unit = "Generic",
+        #[parameter(name = "Kind", unit = "Generic",
             gradient = "Linear")]
+        #[export(stepped)]
         kind: f32,
+
+        #[model(min = 1.0, max = 6.0)]
+        #[parameter(name = "Slope", unit = "Generic",
+            gradient = "Linear")]
+        #[export(stepped)]
+        slope: f32,
+
+        #[model(min = 1.0, max = 2.0)]
+        #[parameter(name = "Alignment", unit = "Generic",
+            gradient = "Linear")]
+        #[export(stepped)]
+        alignment: f32,
+
+        #[model(min = 0.0, max = 1.0)]
+        #[parameter(name = "Mix", unit = "Generic",
+            gradient = "Linear")]
+        mix: f32,
+
+        #[model(min = -12.0, max = 12.0)]
+        #[parameter(name = "Trim", unit = "Decibels",
+            gradient = "Power(1.0)")]
+        trim: f32,
+
+        #[model(min = 0.0, max = 1.0)]
+        #[parameter(name = "Mix Law", unit = "Generic",
+            gradient = "Linear")]
+        #[export(stepped)]
+        mix_law: f32,
+
+        #[model(min = 0.0, max = 1.0)]
+        #[parameter(name = "Bypass", unit = "Generic",
+            gradient = "Linear")]
+        #[export(stepped)]
+        bypass: f32,
+
+        #[model(min = 0.0, max = 1.0)]
+        #[parameter(name = "Bypass Tails", unit = "Generic",
+            gradient = "Linear")]
+        #[export(stepped)]
+        bypass_tails: f32,
     }
 }
 
@@ -64,13 +128,54 @@ impl Default for OnePoleModel {
             gain: 1.0,
             kind: 1.0,
             freq: 1000.0,
+            slope: 1.0,
+            alignment: 1.0,
+            mix: 1.0,
+            trim: 1.0,
+            mix_law: 0.0,
+            bypass: 0.0,
+            bypass_tails: 0.0,
         }
     }
 }
 
+impl FactoryPresets for OnePoleModel {
+    fn factory_presets() -> Vec<Preset<Self>> {
+        // name, gain dB, freq, kind, slope, alignment
+        let presets: [(&str, f32, f32, f32, f32, f32); 5] = [
+            ("Default", 1.0, 1000.0, 1.0, 1.0, 1.0),
+            ("Rumble Cut", 0.0, 80.0, 2.0, 2.0, 1.0),
+            ("Warmth", 2.0, 200.0, 3.0, 1.0, 1.0),
+            ("Air", 3.0, 8000.0, 4.0, 1.0, 1.0),
+            ("Steep Low Pass", 0.0, 8000.0, 1.0, 6.0, 2.0),
+        ];
+        presets
+            .iter()
+            .map(|&(name, gain, freq, kind, slope, alignment)| Preset {
+                name: name.to_string(),
+                model: OnePoleModel {
+                    gain,
+                    freq,
+                    kind,
+                    slope,
+                    alignment,
+                    ..OnePoleModel::default()
+                },
+            })
+            .collect()
+    }
+}
+
+impl VersionedState for OnePoleModel {
+    const STATE_VERSION: u32 = 1;
+
+    fn migrate(_from_version: u32, _model: &mut Map<String, Value>) {
+        // Version 0 is the bare model, slope and alignment were added after it and take their defaults
+    }
+}
 
 #[derive(Clone, Copy, Debug)]
-struct OnePoleCoeffs {
+pub struct OnePoleCoeffs {
     a: f64,
     g: f64,
     a1: f64,
@@ -83,12 +188,6 @@ impl OnePoleCoeffs {
         let mut a1 = 0.0;
 
         match kind {
-            1 | 2 | 5 | _ => {
-                // Low pass | High pass | All Pass
-                a = 1.0;
-                g = (PI * f0 / fs).tan();
-                a1 = g / (1.0 + g);
-            }
             3 => {
                 // Low Shelf
                 a = 10.0f64.powf(db_gain / 20.0);
@@ -101,6 +200,12 @@ impl OnePoleCoeffs {
                 g = (PI * f0 / fs).tan() * (a).sqrt();
                 a1 = g / (1.0 + g);
             }
+            _ => {
+                // Low pass | High pass | All Pass
+                a = 1.0;
+                g = (PI * f0 / fs).tan();
+                a1 = g / (1.0 + g);
+            }
         }
 
         OnePoleCoeffs { a, g, a1 }
@@ -108,14 +213,14 @@ impl OnePoleCoeffs {
 }
 
 #[derive(Clone, Copy, Debug)]
-struct OnePoleFilter {
+pub struct OnePoleFilter {
     pub kind: u8,
     ic1eq: f64,
     pub coeffs: OnePoleCoeffs,
 }
 
 impl OnePoleFilter {
-    fn new(kind: u8, fs: f64, f0: f64, db_gain: f64) -> OnePoleFilter {
+    pub fn new(kind: u8, fs: f64, f0: f64, db_gain: f64) -> OnePoleFilter {
         OnePoleFilter {
             kind,
             ic1eq: 0.0,
@@ -123,53 +228,270 @@ impl OnePoleFilter {
         }
     }
 
-    fn process(&mut self, input: f64) -> f64 {
+    pub fn process(&mut self, input: f64) -> f64 {
         //http://www.willpirkle.com/Downloads/AN-4VirtualAnalogFilters.pdf (page 5)
         let v1 = self.coeffs.a1 * (input - self.ic1eq);
         let v2 = v1 + self.ic1eq;
         self.ic1eq = v2 + v1;
 
-        // Low pass
-        let mut m0 = 0.0;
-        let mut m1 = 1.0;
+        let (m0, m1) = output_mix(self.kind, &self.coeffs);
+        m0 * input + m1 * v2
+    }
+}
 
-        match self.kind {
-            1 => {
-                // Low pass
-                m0 = 0.0;
-                m1 = 1.0;
-            }
-            2 => {
-                // High pass
-                m0 = 1.0;
-                m1 = -1.0;
-            }
-            3 => {
-                // Low Shelf
-                m0 = 1.0;
-                m1 = self.coeffs.a - 1.0;
-            }
-            4 => {
-                // High Shelf
-                m0 = self.coeffs.a;
-                m1 = 1.0 - self.coeffs.a;
-            }
-            5 => {
-                // All pass
-                m0 = 1.0;
-                m1 = -2.0;
+// How much of the input and of the filtered signal each kind of one pole outputs
+fn output_mix(kind: u8, coeffs: &OnePoleCoeffs) -> (f64, f64) {
+    // Low pass
+    let mut m0 = 0.0;
+    let mut m1 = 1.0;
+
+    match kind {
+        1 => {
+            // Low pass
+            m0 = 0.0;
+            m1 = 1.0;
+        }
+        2 => {
+            // High pass
+            m0 = 1.0;
+            m1 = -1.0;
+        }
+        3 => {
+            // Low Shelf
+            m0 = 1.0;
+            m1 = coeffs.a - 1.0;
+        }
+        4 => {
+            // High Shelf
+            m0 = coeffs.a;
+            m1 = 1.0 - coeffs.a;
+        }
+        5 => {
+            // All pass
+            m0 = 1.0;
+            m1 = -2.0;
+        }
+        _ => {}
+    }
+
+    (m0, m1)
+}
+
+// A OnePoleFilter in each lane, all of the same kind and with the same coefficients
+#[derive(Clone, Copy, Debug)]
+pub struct OnePoleLanes<const N: usize> {
+    pub kind: u8,
+    ic1eq: Lanes<N>,
+    pub coeffs: OnePoleCoeffs,
+}
+
+impl<const N: usize> OnePoleLanes<N> {
+    pub fn new(kind: u8, fs: f64, f0: f64, db_gain: f64) -> OnePoleLanes<N> {
+        OnePoleLanes {
+            kind,
+            ic1eq: Lanes::splat(0.0),
+            coeffs: OnePoleCoeffs::new(kind, fs, f0, db_gain),
+        }
+    }
+
+    pub fn process(&mut self, input: Lanes<N>) -> Lanes<N> {
+        let v1 = Lanes::splat(self.coeffs.a1) * (input - self.ic1eq);
+        let v2 = v1 + self.ic1eq;
+        self.ic1eq = v2 + v1;
+
+        let (m0, m1) = output_mix(self.kind, &self.coeffs);
+        Lanes::splat(m0) * input + Lanes::splat(m1) * v2
+    }
+}
+
+// Sections needed for the steepest slope, 48dB/oct
+const MAX_ONE_POLES: usize = 2;
+const MAX_SVFS: usize = 4;
+
+// How often coefficients are recalculated while parameters are changing, unless it's set with
+// OnePole::set_update_rate. They're interpolated in between, and nothing is recalculated while
+// the parameters are static.
+const DEFAULT_UPDATE_RATE: UpdateRate = UpdateRate::Samples(16);
+
+// Slope 1-6 to filter order: 6, 12, 18, 24, 36, 48 dB/oct
+fn slope_order(slope: u8) -> u32 {
+    match slope {
+        0 | 1 => 1,
+        2 => 2,
+        3 => 3,
+        4 => 4,
+        5 => 6,
+        _ => 8,
+    }
+}
+
+/// Coefficients for every section of a CascadeFilter.
+/// Kinds 1 and 2 (low pass and high pass) are built from one pole and SVF sections in series,
+/// alignment 1 is Butterworth, 2 is Linkwitz-Riley. Other kinds use a single one pole section.
+#[derive(Clone, Copy, Debug)]
+struct CascadeCoeffs {
+    kind: u8,
+    one_pole: OnePoleCoeffs,
+    svfs: [SVFCoefficients<f64>; MAX_SVFS],
+    one_pole_count: usize,
+    svf_count: usize,
+}
+
+impl CascadeCoeffs {
+    fn new(kind: u8, slope: u8, alignment: u8, fs: f64, f0: f64, db_gain: f64) -> CascadeCoeffs {
+        // Keep clear of nyquist, where tan() blows up and the SVF coefficients are rejected
+        let f0 = f0.min(fs * 0.49);
+        // Unused sections are left passing their input straight through
+        let pass_through =
+            SVFCoefficients::<f64>::from_params(Type::PeakingEQ(0.0), fs, f0, 1.0).unwrap();
+        let mut coeffs = CascadeCoeffs {
+            kind,
+            one_pole: OnePoleCoeffs::new(kind, fs, f0, db_gain),
+            svfs: [pass_through; MAX_SVFS],
+            one_pole_count: 1,
+            svf_count: 0,
+        };
+
+        let svf_type = match kind {
+            1 => Type::LowPass,
+            2 => Type::HighPass,
+            _ => return coeffs,
+        };
+
+        let order = slope_order(slope);
+        // Linkwitz-Riley is two of the half order Butterworth in series,
+        // odd orders don't have a Linkwitz-Riley version so they stay Butterworth.
+        let (order, passes) = if alignment == 2 && order % 2 == 0 {
+            (order / 2, 2)
+        } else {
+            (order, 1)
+        };
+
+        coeffs.one_pole_count = 0;
+        for _ in 0..passes {
+            // Odd orders start with a first order section, which is pole 0
+            let first_pole = if order % 2 == 1 {
+                coeffs.one_pole_count += 1;
+                1
+            } else {
+                0
+            };
+            for pole in first_pole..(order + 1) / 2 {
+                let q = butterworth_cascade_q(order, pole);
+                coeffs.svfs[coeffs.svf_count] =
+                    SVFCoefficients::<f64>::from_params(svf_type, fs, f0, q).unwrap();
+                coeffs.svf_count += 1;
             }
-            _ => {}
         }
+        coeffs
+    }
+}
 
-        m0 * input + m1 * v2
+impl Interpolate for OnePoleCoeffs {
+    fn step_to(&self, target: &Self, n: usize) -> Self {
+        let n = n as f64;
+        OnePoleCoeffs {
+            a: (target.a - self.a) / n,
+            g: (target.g - self.g) / n,
+            a1: (target.a1 - self.a1) / n,
+        }
+    }
+
+    fn add_step(&mut self, step: &Self) {
+        self.a += step.a;
+        self.g += step.g;
+        self.a1 += step.a1;
     }
 }
 
-struct OnePole {
-    filter_l: OnePoleFilter,
-    filter_r: OnePoleFilter,
+// The section layout switches straight to the target's, only the coefficients are ramped
+impl Interpolate for CascadeCoeffs {
+    fn step_to(&self, target: &Self, n: usize) -> Self {
+        let mut step = *target;
+        step.one_pole = self.one_pole.step_to(&target.one_pole, n);
+        for (step, (svf, target)) in step.svfs.iter_mut().zip(self.svfs.iter().zip(&target.svfs)) {
+            *step = svf.step_to(target, n);
+        }
+        step
+    }
+
+    fn add_step(&mut self, step: &Self) {
+        self.kind = step.kind;
+        self.one_pole_count = step.one_pole_count;
+        self.svf_count = step.svf_count;
+        self.one_pole.add_step(&step.one_pole);
+        for (svf, step) in self.svfs.iter_mut().zip(&step.svfs) {
+            svf.add_step(step);
+        }
+    }
+}
+
+// The left and right channels' cascades, paired in lanes
+#[derive(Clone, Copy, Debug)]
+struct CascadeFilter {
+    one_poles: [OnePoleLanes<2>; MAX_ONE_POLES],
+    svfs: [SVFSimd<2>; MAX_SVFS],
+    one_pole_count: usize,
+    svf_count: usize,
+}
+
+impl CascadeFilter {
+    fn new(coeffs: &CascadeCoeffs) -> CascadeFilter {
+        let mut filter = CascadeFilter {
+            one_poles: [OnePoleLanes {
+                kind: coeffs.kind,
+                ic1eq: Lanes::splat(0.0),
+                coeffs: coeffs.one_pole,
+            }; MAX_ONE_POLES],
+            svfs: [SVFSimd::new([coeffs.svfs[0]; 2]); MAX_SVFS],
+            one_pole_count: 0,
+            svf_count: 0,
+        };
+        filter.set_coefficients(coeffs);
+        filter
+    }
+
+    fn set_coefficients(&mut self, coeffs: &CascadeCoeffs) {
+        for one_pole in self.one_poles.iter_mut() {
+            one_pole.kind = coeffs.kind;
+            one_pole.coeffs = coeffs.one_pole;
+        }
+        for (svf, coeffs) in self.svfs.iter_mut().zip(&coeffs.svfs) {
+            svf.set_coefficients(*coeffs);
+        }
+        self.one_pole_count = coeffs.one_pole_count;
+        self.svf_count = coeffs.svf_count;
+    }
+
+    fn process(&mut self, input: [f64; 2]) -> [f64; 2] {
+        let mut x = Lanes::from_array(input);
+        for one_pole in self.one_poles[..self.one_pole_count].iter_mut() {
+            x = one_pole.process(x);
+        }
+        for svf in self.svfs[..self.svf_count].iter_mut() {
+            x = svf.run_lanes(x);
+        }
+        x.to_array()
+    }
+}
+
+// kind, slope, alignment, freq, gain
+type CascadeParams = (u8, u8, u8, f32, f32);
+
+pub struct OnePole {
+    filter: CascadeFilter,
+    coeffs: CoeffRamp<CascadeParams, CascadeCoeffs>,
+    mix: MixStage,
     sample_rate: f64,
+    sanitizer: Sanitizer,
+}
+
+impl OnePole {
+    /// How often coefficients are recalculated while parameters are changing, every 16 samples
+    /// by default. Per sample follows automation exactly, per block costs the least.
+    pub fn set_update_rate(&mut self, rate: UpdateRate) {
+        self.coeffs.rate = rate;
+    }
 }
 
 impl Plugin for OnePole {
@@ -184,50 +506,535 @@ impl Plugin for OnePole {
 
     #[inline]
     fn new(sample_rate: f32, model: &OnePoleModel) -> Self {
+        let params = (
+            model.kind as u8,
+            model.slope as u8,
+            model.alignment as u8,
+            model.freq,
+            model.gain,
+        );
+        let coeffs = CascadeCoeffs::new(
+            params.0,
+            params.1,
+            params.2,
+            sample_rate as f64,
+            params.3 as f64,
+            params.4 as f64,
+        );
+        let filter = CascadeFilter::new(&coeffs);
+        let mut mix = MixStage::new(sample_rate);
+        mix.set_latency(Self::latency(model, sample_rate));
         OnePole {
-            filter_l: OnePoleFilter::new(
-                model.kind as u8,
-                sample_rate as f64,
-                model.freq as f64,
-                model.gain as f64,
-            ),
-            filter_r: OnePoleFilter::new(
-                model.kind as u8,
-                sample_rate as f64,
-                model.freq as f64,
-                model.gain as f64,
-            ),
+            filter,
+            coeffs: CoeffRamp::new(DEFAULT_UPDATE_RATE, params, coeffs),
+            mix,
             sample_rate: sample_rate as f64,
+            sanitizer: Sanitizer::new(),
         }
     }
 
     #[inline]
     fn process(&mut self, model: &OnePoleModelProcess, ctx: &mut ProcessContext<Self>) {
+        let _denormals = DenormalGuard::new();
         let input = &ctx.inputs[0].buffers;
         let output = &mut ctx.outputs[0].buffers;
 
+        let sample_rate = self.sample_rate;
         for i in 0..ctx.nframes {
-            let kind = model.kind[i] as u8;
-            self.filter_l.coeffs = OnePoleCoeffs::new(
-                kind,
-                self.sample_rate,
-                model.freq[i] as f64,
-                model.gain[i] as f64,
+            let coeffs = self.coeffs.next(
+                i,
+                ctx.nframes,
+                |j| {
+                    (
+                        model.kind[j] as u8,
+                        model.slope[j] as u8,
+                        model.alignment[j] as u8,
+                        model.freq[j],
+                        model.gain[j],
+                    )
+                },
+                |(kind, slope, alignment, freq, gain)| {
+                    CascadeCoeffs::new(
+                        kind,
+                        slope,
+                        alignment,
+                        sample_rate,
+                        freq as f64,
+                        gain as f64,
+                    )
+                },
             );
-            self.filter_l.kind = kind;
-            self.filter_r.coeffs = self.filter_l.coeffs;
-            self.filter_r.kind = kind;
+            self.filter.set_coefficients(&coeffs);
 
+            // Non-finite input would stay in the filter states for good
             let l = input[0][i] as f64;
             let r = input[1][i] as f64;
+            let l = if l.is_finite() { l } else { 0.0 };
+            let r = if r.is_finite() { r } else { 0.0 };
 
-            let l = self.filter_l.process(l);
-            let r = self.filter_r.process(r);
+            let bypass = model.bypass[i] >= 0.5;
+            let bypass_tails = model.bypass_tails[i] >= 0.5;
+            self.mix.set_bypass(bypass, bypass_tails);
+            let wet = if self.mix.processing() {
+                let gain = self.mix.input_gain();
+                self.filter.process([l * gain, r * gain])
+            } else {
+                // Bypassed without tails, the filters hold their state
+                [0.0; 2]
+            };
+            let mix = model.mix[i] as f64;
+            let trim = model.trim[i] as f64;
+            let mix_law = MixLaw::from_param(model.mix_law[i]);
+            let [l, r] = self.mix.process([l, r], wet, mix, mix_law, trim);
 
             output[0][i] = l as f32;
             output[1][i] = r as f32;
         }
+
+        self.sanitizer.process_buffers(output, ctx.nframes);
     }
 }
 
-baseplug::vst2!(OnePole, b"tAbE");
+impl ClapExport for OnePole {
+    const CLAP_ID: &'static str = "net.dgdigital.onepole";
+    const VERSION: &'static str = env!("CARGO_PKG_VERSION");
+    const CLAP_FEATURES: &'static [&'static CStr] = &[
+        CLAP_PLUGIN_FEATURE_AUDIO_EFFECT,
+        CLAP_PLUGIN_FEATURE_FILTER,
+        CLAP_PLUGIN_FEATURE_EQUALIZER,
+        CLAP_PLUGIN_FEATURE_STEREO,
+    ];
+    const BYPASS_PARAM: Option<clap_id> = Some(8);
+
+    fn clap_params() -> Vec<ClapParam<OnePoleModel>> {
+        OnePoleModel::clap_params()
+    }
+}
+
+impl Vst3Export for OnePole {
+    const VST3_CLASS_ID: Tuid = uid(0xFB6D02F0, 0xDD0745DC, 0x8D04A3C0, 0xB1C99561);
+    const VST3_CATEGORIES: &'static str = "Fx|Filter|EQ";
+}
+
+plugin_common::vst2_export!(OnePole, b"tAbE");
+plugin_common::clap_export!(OnePole);
+plugin_common::vst3_export!(OnePole);
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use plugin_common::harness::Harness;
+    #[cfg(feature = "rt-check")]
+    use plugin_common::rt_check;
+    use plugin_common::svf::SVF;
+    use plugin_common::units::Units;
+    use plugin_common::{clap, fuzz, state, vst2, vst3};
+    use proptest::prelude::*;
+
+    const FS: f64 = 48000.0;
+    const F0: f64 = 1000.0;
+    const PROBES: [f64; 6] = [20.0, 200.0, 900.0, 1000.0, 5000.0, 18000.0];
+    // The steepest cascades overshoot by a few times on square waves under heavy automation,
+    // this is well past that but catches any instability
+    const OVERSHOOT: f32 = 8.0;
+
+    // Complex response at hz, measured by demodulating a sine probe once the filter has settled.
+    // One second of samples holds a whole number of periods of any integer hz.
+    fn measure<F: FnMut(f64) -> f64>(mut process: F, hz: f64) -> (f64, f64) {
+        let n = FS as usize;
+        let mut re = 0.0;
+        let mut im = 0.0;
+        for i in 0..n * 2 {
+            let w = 2.0 * PI * hz * i as f64 / FS;
+            let y = process(w.sin());
+            if i >= n {
+                re += y * w.sin();
+                im += y * w.cos();
+            }
+        }
+        (re * 2.0 / n as f64, im * 2.0 / n as f64)
+    }
+
+    // Bilinear transform of the analog prototype (b1 * s + b0) / (s + wc), with f0 prewarped
+    fn analytic(kind: u8, db_gain: f64, hz: f64) -> (f64, f64) {
+        let a = 10.0f64.powf(db_gain / 20.0);
+        let t0 = (PI * F0 / FS).tan();
+        let w = (PI * hz / FS).tan();
+        let (b1, b0, wc) = match kind {
+            1 => (0.0, t0, t0),
+            2 => (1.0, 0.0, t0),
+            3 => (1.0, t0 * a.sqrt(), t0 / a.sqrt()),
+            4 => (a, t0 * a.sqrt(), t0 * a.sqrt()),
+            _ => (1.0, -t0, t0),
+        };
+        let den = wc * wc + w * w;
+        ((b0 * wc + b1 * w * w) / den, (b1 * w * wc - b0 * w) / den)
+    }
+
+    fn check_kind(kind: u8, db_gain: f64) {
+        for &hz in PROBES.iter() {
+            let mut filter = OnePoleFilter::new(kind, FS, F0, db_gain);
+            let (re, im) = measure(|x| filter.process(x), hz);
+            let (a_re, a_im) = analytic(kind, db_gain, hz);
+            let error = ((re - a_re).powi(2) + (im - a_im).powi(2)).sqrt();
+            assert!(
+                error < 1e-6,
+                "kind {} gain {}dB at {}Hz: measured ({}, {}) expected ({}, {})",
+                kind,
+                db_gain,
+                hz,
+                re,
+                im,
+                a_re,
+                a_im
+            );
+        }
+    }
+
+    fn magnitude_db(kind: u8, db_gain: f64, hz: f64) -> f64 {
+        let mut filter = OnePoleFilter::new(kind, FS, F0, db_gain);
+        let (re, im) = measure(|x| filter.process(x), hz);
+        10.0 * (re * re + im * im).log10()
+    }
+
+    #[test]
+    fn test_low_pass() {
+        check_kind(1, 0.0);
+        assert!((magnitude_db(1, 0.0, F0) + 3.0103).abs() < 0.001);
+    }
+
+    #[test]
+    fn test_high_pass() {
+        check_kind(2, 0.0);
+        assert!((magnitude_db(2, 0.0, F0) + 3.0103).abs() < 0.001);
+    }
+
+    #[test]
+    fn test_low_shelf() {
+        for &db_gain in [-6.0, -3.0, 3.0, 6.0].iter() {
+            check_kind(3, db_gain);
+            // Full gain at the bottom, half of it at f0
+            assert!((magnitude_db(3, db_gain, 20.0) - db_gain).abs() < 0.01);
+            assert!((magnitude_db(3, db_gain, F0) - db_gain / 2.0).abs() < 0.001);
+        }
+    }
+
+    #[test]
+    fn test_high_shelf() {
+        for &db_gain in [-6.0, -3.0, 3.0, 6.0].iter() {
+            check_kind(4, db_gain);
+            assert!((magnitude_db(4, db_gain, 18000.0) - db_gain).abs() < 0.1);
+            assert!((magnitude_db(4, db_gain, F0) - db_gain / 2.0).abs() < 0.001);
+        }
+    }
+
+    #[test]
+    fn test_all_pass() {
+        check_kind(5, 0.0);
+        for &hz in PROBES.iter() {
+            assert!(magnitude_db(5, 0.0, hz).abs() < 0.001);
+        }
+    }
+
+    // Slopes 1-6 and their filter orders
+    const SLOPES: [(u8, u32); 6] = [(1, 1), (2, 2), (3, 3), (4, 4), (5, 6), (6, 8)];
+
+    fn cascade(kind: u8, slope: u8, alignment: u8, f0: f64, hz: f64) -> (f64, f64) {
+        let coeffs = CascadeCoeffs::new(kind, slope, alignment, FS, f0, 0.0);
+        let mut filter = CascadeFilter::new(&coeffs);
+        measure(|x| filter.process([x, x])[0], hz)
+    }
+
+    fn cascade_db(kind: u8, slope: u8, alignment: u8, f0: f64, hz: f64) -> f64 {
+        let (re, im) = cascade(kind, slope, alignment, f0, hz);
+        10.0 * (re * re + im * im).log10()
+    }
+
+    #[test]
+    fn test_butterworth_cascade() {
+        for &(slope, order) in SLOPES.iter() {
+            for &kind in [1, 2].iter() {
+                let db = cascade_db(kind, slope, 1, F0, F0);
+                assert!(
+                    (db + 3.0103).abs() < 0.001,
+                    "kind {} order {}: {}dB at f0",
+                    kind,
+                    order,
+                    db
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn test_linkwitz_riley_cascade() {
+        for &(slope, order) in SLOPES.iter().filter(|(_, order)| order % 2 == 0) {
+            for &kind in [1, 2].iter() {
+                let db = cascade_db(kind, slope, 2, F0, F0);
+                assert!(
+                    (db + 6.0206).abs() < 0.001,
+                    "kind {} order {}: {}dB at f0",
+                    kind,
+                    order,
+                    db
+                );
+            }
+        }
+    }
+
+    // The stopband is measured 3 and 4 octaves away from f0, where it's within a fraction of a dB
+    // of the asymptote. The low pass is measured with a low f0, so the bilinear transform's
+    // warping near nyquist barely steepens it.
+    #[test]
+    fn test_cascade_stopband_slope() {
+        for &(slope, order) in SLOPES.iter() {
+            let expected = 6.0206 * order as f64;
+            for &alignment in [1, 2].iter() {
+                let high_pass = cascade_db(2, slope, alignment, F0, 125.0)
+                    - cascade_db(2, slope, alignment, F0, 62.5);
+                let low_pass = cascade_db(1, slope, alignment, 100.0, 800.0)
+                    - cascade_db(1, slope, alignment, 100.0, 1600.0);
+                for &(kind, db_per_oct) in [(1, low_pass), (2, high_pass)].iter() {
+                    assert!(
+                        (db_per_oct - expected).abs() < 0.02 * expected + 0.1,
+                        "kind {} order {} alignment {}: {}dB/oct",
+                        kind,
+                        order,
+                        alignment,
+                        db_per_oct
+                    );
+                }
+            }
+        }
+    }
+
+    // Each Linkwitz-Riley low and high pass pair adds up to an all pass. When the order is twice
+    // an odd Butterworth order, 2 and 6, the high pass is inverted to sum flat, like crossovers
+    // built from them do.
+    #[test]
+    fn test_linkwitz_riley_sums_flat() {
+        for &(slope, order) in SLOPES.iter().filter(|(_, order)| order % 2 == 0) {
+            let sign = if order % 4 == 0 { 1.0 } else { -1.0 };
+            for &hz in PROBES.iter() {
+                let (lp_re, lp_im) = cascade(1, slope, 2, F0, hz);
+                let (hp_re, hp_im) = cascade(2, slope, 2, F0, hz);
+                let (re, im) = (lp_re + sign * hp_re, lp_im + sign * hp_im);
+                let db = 10.0 * (re * re + im * im).log10();
+                assert!(db.abs() < 0.001, "order {} at {}Hz: {}dB", order, hz, db);
+            }
+        }
+    }
+
+    // Each channel's lane gives the same as a scalar cascade of the same sections
+    #[test]
+    fn test_stereo_matches_scalar() {
+        let noise = plugin_common::bench::noise(4096);
+        for &(kind, slope, alignment) in [(1, 6, 2), (2, 5, 1), (1, 3, 1), (3, 1, 1)].iter() {
+            let coeffs = CascadeCoeffs::new(kind, slope, alignment, FS, F0, 3.0);
+            let mut stereo = CascadeFilter::new(&coeffs);
+            let mut scalar = [(); 2].map(|_| {
+                let one_poles = vec![
+                    OnePoleFilter {
+                        kind,
+                        ic1eq: 0.0,
+                        coeffs: coeffs.one_pole,
+                    };
+                    coeffs.one_pole_count
+                ];
+                let svfs: Vec<SVF<f64>> = coeffs.svfs[..coeffs.svf_count]
+                    .iter()
+                    .map(|coeffs| SVF::<f64>::new(*coeffs))
+                    .collect();
+                (one_poles, svfs)
+            });
+            for (i, x) in noise.iter().enumerate() {
+                let input = [*x as f64, noise[noise.len() - 1 - i] as f64 * 0.5];
+                let output = stereo.process(input);
+                for (channel, (one_poles, svfs)) in scalar.iter_mut().enumerate() {
+                    let mut y = input[channel];
+                    for one_pole in one_poles.iter_mut() {
+                        y = one_pole.process(y);
+                    }
+                    for svf in svfs.iter_mut() {
+                        y = svf.run(y);
+                    }
+                    assert_eq!(output[channel], y);
+                }
+            }
+        }
+    }
+
+    // A cutoff sweep during each block at every update rate. Per sample is the reference, the
+    // others lag it while the cutoff moves, stay stable and end up at the same filter once it
+    // stops.
+    #[test]
+    fn test_update_rates() {
+        let frames = 256;
+        let rates = [
+            UpdateRate::Sample,
+            UpdateRate::Samples(16),
+            UpdateRate::Block,
+        ];
+        let outputs: Vec<Vec<f32>> = rates
+            .iter()
+            .map(|&rate| {
+                let mut model = OnePoleModel {
+                    freq: 200.0,
+                    kind: 1.0,
+                    slope: 4.0,
+                    ..OnePoleModel::default()
+                };
+                let mut harness = Harness::<OnePole>::new(|| model.clone(), FS as f32, frames);
+                harness.plugin.set_update_rate(rate);
+                let mut seed = 1u32;
+                let mut output = Vec::new();
+                for block in 0..32 {
+                    if block < 8 {
+                        model.freq = 200.0 * 2.0f32.powi(block + 1);
+                        harness.set_model(&model);
+                    }
+                    let input: Vec<f32> = (0..frames)
+                        .map(|_| {
+                            seed = seed.wrapping_mul(1664525).wrapping_add(1013904223);
+                            (seed as f32 / u32::MAX as f32) * 2.0 - 1.0
+                        })
+                        .collect();
+                    output.extend_from_slice(harness.process([&input, &input])[0]);
+                }
+                output
+            })
+            .collect();
+
+        let settled = frames * 24;
+        for (rate, output) in rates.iter().zip(outputs.iter()).skip(1) {
+            let diff = |range: std::ops::Range<usize>| {
+                output[range.clone()]
+                    .iter()
+                    .zip(&outputs[0][range])
+                    .map(|(y, reference)| (y - reference).abs())
+                    .fold(0.0, f32::max)
+            };
+            let sweep = diff(0..settled);
+            assert!(sweep > 0.0, "{:?} didn't change how often it updates", rate);
+            for y in output.iter() {
+                assert!(y.abs() <= OVERSHOOT, "{:?} output {}", rate, y);
+            }
+            let after = diff(settled..output.len());
+            assert!(
+                after < 1e-5,
+                "{:?} is off by {} after the sweep",
+                rate,
+                after
+            );
+        }
+    }
+
+    #[test]
+    fn test_state_v0_fixture() {
+        let model: OnePoleModel =
+            state::from_json(include_str!("../fixtures/state_v0.json")).unwrap();
+        assert_eq!(model.gain, 3.0);
+        assert_eq!(model.freq, 8000.0);
+        assert_eq!(model.kind, 4.0);
+        // Saved before slope and alignment existed
+        assert_eq!(model.slope, 1.0);
+        assert_eq!(model.alignment, 1.0);
+        assert_eq!(model.mix, 1.0);
+        assert_eq!(model.trim, 1.0);
+    }
+
+    #[cfg(feature = "rt-check")]
+    #[test]
+    fn test_process_is_realtime_safe() {
+        for preset in OnePoleModel::factory_presets() {
+            rt_check::check_process::<OnePole>(|| preset.model.clone(), 48000.0, 256, 64);
+        }
+    }
+
+    // Shelves boost by at most their gain, everything else passes at unity gain at most
+    fn max_output(models: &[OnePoleModel]) -> f32 {
+        let boost = fuzz::peak(models, |model| model.gain).db_to_lin().max(1.0);
+        fuzz::mixed_max_output(OVERSHOOT * boost, fuzz::peak(models, |model| model.trim))
+    }
+
+    fn model() -> impl Strategy<Value = OnePoleModel> {
+        (
+            -6.0f32..=6.0,
+            20.0f32..=20000.0,
+            1.0f32..=10.0,
+            1.0f32..=6.0,
+            1.0f32..=2.0,
+            0.0f32..=1.0,
+            -12.0f32..=12.0,
+            0.0f32..=1.0,
+            0.0f32..=1.0,
+            0.0f32..=1.0,
+        )
+            .prop_map(
+                |(gain, freq, kind, slope, alignment, mix, trim, mix_law, bypass, bypass_tails)| {
+                    OnePoleModel {
+                        gain,
+                        freq,
+                        kind,
+                        slope,
+                        alignment,
+                        mix,
+                        trim: trim.db_to_lin(),
+                        mix_law,
+                        bypass,
+                        bypass_tails,
+                    }
+                },
+            )
+    }
+
+    proptest! {
+        #![proptest_config(ProptestConfig::with_cases(64))]
+
+        #[test]
+        fn test_fuzz_process(
+            blocks in fuzz::blocks(model(), 1..16),
+            sample_rate in fuzz::sample_rate(),
+        ) {
+            fuzz::fuzz_process::<OnePole>(
+                &blocks,
+                OnePoleModel::default(),
+                sample_rate,
+                256,
+                max_output,
+            )?;
+        }
+    }
+
+    #[test]
+    fn test_clap_export() {
+        clap::host::validate::<OnePole>(&crate::clap_entry);
+    }
+
+    #[test]
+    fn test_vst2_export() {
+        vst2::host::validate::<OnePole>(crate::VSTPluginMain);
+    }
+
+    #[test]
+    fn test_vst2_chunk_v0_fixture() {
+        // The chunk baseplug's VST2 wrapper saved, the bare model before slope, alignment and
+        // the mix stage existed
+        let instance = vst2::host::Instance::new(crate::VSTPluginMain);
+        assert!(instance.load(include_bytes!("../fixtures/vst2_chunk_v0.json")));
+        let saved = String::from_utf8(instance.save()).unwrap();
+        let model: OnePoleModel = state::from_json(&saved).unwrap();
+        assert_eq!(model.gain, -2.0);
+        assert_eq!(model.freq, 250.0);
+        assert_eq!(model.kind, 2.0);
+        assert_eq!(model.slope, 1.0);
+        assert_eq!(model.alignment, 1.0);
+        assert_eq!(model.mix, 1.0);
+        assert_eq!(model.trim, 1.0);
+    }
+
+    #[test]
+    fn test_vst3_export() {
+        vst3::host::validate::<OnePole>(crate::GetPluginFactory);
+    }
+}
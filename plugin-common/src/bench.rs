@@ -0,0 +1,130 @@
+// The plugins' criterion benches of their full process, and a check that a plugin keeps to a
+// share of real time. Both drive the plugin through the test Harness, like a host would.
+
+use std::time::Instant;
+
+use baseplug::Plugin;
+use criterion::{black_box, BenchmarkId, Criterion, Throughput};
+
+use crate::clap::ClapExport;
+use crate::harness::Harness;
+
+pub const SAMPLE_RATES: [f32; 3] = [44100.0, 48000.0, 96000.0];
+pub const BLOCK_SIZES: [usize; 3] = [64, 256, 1024];
+
+/// Uniform noise in -1..1, the same on every run
+pub fn noise(len: usize) -> Vec<f32> {
+    let mut seed = 1u32;
+    (0..len)
+        .map(|_| {
+            seed = seed.wrapping_mul(1664525).wrapping_add(1013904223);
+            (seed as f32 / u32::MAX as f32) * 2.0 - 1.0
+        })
+        .collect()
+}
+
+/// The default model with the named parameters set, in the units the host shows them in.
+/// Panics on a name the plugin doesn't have.
+pub fn model_with<P: ClapExport>(values: &[(&str, f64)]) -> P::Model {
+    let params = P::clap_params();
+    let mut model = P::Model::default();
+    for &(name, value) in values {
+        let param = params
+            .iter()
+            .find(|param| param.name == name)
+            .unwrap_or_else(|| panic!("no parameter named {}", name));
+        (param.set)(&mut model, value);
+    }
+    model
+}
+
+/// Runs the plugin's full process over a stereo block of noise, at each sample rate and block
+/// size
+pub fn bench_plugin<P: Plugin>(c: &mut Criterion, name: &str, model: impl Fn() -> P::Model) {
+    let mut group = c.benchmark_group(name);
+    for &sample_rate in SAMPLE_RATES.iter() {
+        for &nframes in BLOCK_SIZES.iter() {
+            let mut harness = Harness::<P>::new(&model, sample_rate, nframes);
+            let input = noise(nframes);
+
+            group.throughput(Throughput::Elements(nframes as u64));
+            group.bench_function(BenchmarkId::new(sample_rate.to_string(), nframes), |b| {
+                b.iter(|| {
+                    black_box(harness.process([&input, &input]));
+                })
+            });
+        }
+    }
+    group.finish();
+}
+
+/// Times 10 seconds of audio through the plugin's full process, block by block, against each
+/// block's real-time duration, i.e. nframes / sample_rate. Panics if on average a block took more
+/// than 1 / headroom of its duration, leaving room for headroom - 1 more instances like it, or if
+/// 1 block in 100 took longer than its duration, which would be a dropout. The shares are
+/// printed either way.
+/// Only optimized builds without rt-check's instrumentation are timed, e.g. under `cargo bench`.
+/// Other builds print that the check was skipped, so `cargo test --benches` still passes.
+pub fn assert_budget<P: Plugin>(
+    name: &str,
+    model: impl Fn() -> P::Model,
+    sample_rate: f32,
+    nframes: usize,
+    headroom: f64,
+) {
+    if cfg!(debug_assertions) || cfg!(feature = "rt-check") {
+        println!(
+            "{}: budget check skipped, it only runs in release builds without rt-check",
+            name
+        );
+        return;
+    }
+
+    let seconds = 10.0;
+    let blocks = (seconds * sample_rate as f64 / nframes as f64).ceil() as usize;
+    let block_duration = nframes as f64 / sample_rate as f64;
+    let mut harness = Harness::<P>::new(&model, sample_rate, nframes);
+    let input = noise(nframes);
+
+    // Warm up, so the first blocks' cache misses aren't counted
+    for _ in 0..blocks / 10 {
+        black_box(harness.process([&input, &input]));
+    }
+
+    // Shares of each block's duration, allocated up front
+    let mut shares = vec![0.0; blocks];
+    for share in shares.iter_mut() {
+        let start = Instant::now();
+        black_box(harness.process([&input, &input]));
+        *share = start.elapsed().as_secs_f64() / block_duration;
+    }
+    let mean = shares.iter().sum::<f64>() / blocks as f64;
+    shares.sort_by(|a, b| a.partial_cmp(b).unwrap());
+    let p99 = shares[(blocks * 99 / 100).min(blocks - 1)];
+    let budget = 1.0 / headroom;
+
+    println!(
+        "{}: {:.1}% of each block's duration on average, {:.1}% at the 99th percentile, at {}Hz \
+         with {} sample blocks (budget {:.1}%, {}x headroom)",
+        name,
+        mean * 100.0,
+        p99 * 100.0,
+        sample_rate,
+        nframes,
+        budget * 100.0,
+        headroom
+    );
+    assert!(
+        mean <= budget,
+        "{} took {:.1}% of each block's duration, over its {:.1}% budget",
+        name,
+        mean * 100.0,
+        budget * 100.0
+    );
+    assert!(
+        p99 <= 1.0,
+        "{} took {:.1}% of a block's duration at the 99th percentile, it would drop out",
+        name,
+        p99 * 100.0
+    );
+}
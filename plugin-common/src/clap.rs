@@ -0,0 +1,2308 @@
+// CLAP export for a baseplug Plugin, next to vst2_export!. The parameters come from the
+// plugin's model! declaration, through ClapExport::clap_params.
+
+use std::cell::UnsafeCell;
+use std::ffi::{c_char, c_void, CStr, CString};
+use std::marker::PhantomData;
+use std::path::Path;
+use std::ptr;
+use std::sync::atomic::{AtomicBool, AtomicPtr, AtomicU32, AtomicU64, Ordering};
+
+use baseplug::{AudioBus, AudioBusMut, Model, MusicalTime, Plugin, ProcessContext, SmoothModel};
+use clap_sys::audio_buffer::clap_audio_buffer;
+use clap_sys::entry::clap_plugin_entry;
+use clap_sys::events::{
+    clap_event_header, clap_event_midi, clap_event_param_value, clap_event_transport,
+    clap_input_events, clap_output_events, CLAP_CORE_EVENT_SPACE_ID, CLAP_EVENT_MIDI,
+    CLAP_EVENT_PARAM_VALUE, CLAP_TRANSPORT_HAS_BEATS_TIMELINE, CLAP_TRANSPORT_HAS_TEMPO,
+};
+use clap_sys::ext::audio_ports::{
+    clap_audio_port_info, clap_plugin_audio_ports, CLAP_AUDIO_PORT_IS_MAIN, CLAP_EXT_AUDIO_PORTS,
+    CLAP_PORT_STEREO,
+};
+use clap_sys::ext::latency::{clap_host_latency, clap_plugin_latency, CLAP_EXT_LATENCY};
+use clap_sys::ext::note_ports::{
+    clap_note_port_info, clap_plugin_note_ports, CLAP_EXT_NOTE_PORTS, CLAP_NOTE_DIALECT_MIDI,
+};
+use clap_sys::ext::params::{
+    clap_host_params, clap_param_info, clap_plugin_params, CLAP_EXT_PARAMS,
+    CLAP_PARAM_IS_AUTOMATABLE, CLAP_PARAM_IS_BYPASS, CLAP_PARAM_IS_STEPPED,
+    CLAP_PARAM_RESCAN_VALUES,
+};
+use clap_sys::ext::preset_load::{
+    clap_host_preset_load, clap_plugin_preset_load, CLAP_EXT_PRESET_LOAD,
+    CLAP_EXT_PRESET_LOAD_COMPAT,
+};
+use clap_sys::ext::state::{clap_plugin_state, CLAP_EXT_STATE};
+use clap_sys::factory::plugin_factory::{clap_plugin_factory, CLAP_PLUGIN_FACTORY_ID};
+use clap_sys::factory::preset_discovery::{
+    clap_preset_discovery_factory, clap_preset_discovery_filetype, clap_preset_discovery_indexer,
+    clap_preset_discovery_location, clap_preset_discovery_location_kind,
+    clap_preset_discovery_metadata_receiver, clap_preset_discovery_provider,
+    clap_preset_discovery_provider_descriptor, CLAP_PRESET_DISCOVERY_FACTORY_ID,
+    CLAP_PRESET_DISCOVERY_FACTORY_ID_COMPAT, CLAP_PRESET_DISCOVERY_IS_FACTORY_CONTENT,
+    CLAP_PRESET_DISCOVERY_IS_USER_CONTENT, CLAP_PRESET_DISCOVERY_LOCATION_FILE,
+    CLAP_PRESET_DISCOVERY_LOCATION_PLUGIN,
+};
+use clap_sys::fixedpoint::CLAP_BEATTIME_FACTOR;
+use clap_sys::host::clap_host;
+use clap_sys::id::clap_id;
+use clap_sys::plugin::{clap_plugin, clap_plugin_descriptor};
+use clap_sys::process::{clap_process, clap_process_status, CLAP_PROCESS_CONTINUE};
+use clap_sys::stream::{clap_istream, clap_ostream};
+use clap_sys::universal_plugin_id::clap_universal_plugin_id;
+use clap_sys::version::CLAP_VERSION;
+
+use crate::midi::{self, MidiMap, LEARN_PARAM_ID};
+use crate::presets::{self, FactoryPresets, PresetBank};
+use crate::state::{self, VersionedState};
+use crate::sync::Mutex;
+
+// baseplug's own wrapper never hands process more than this many frames at once
+pub(crate) const MAX_BLOCK: usize = 128;
+pub(crate) const CHANNELS: usize = 2;
+
+/// One parameter as CLAP hosts see it, in the units shown to the user (dB for gains the model
+/// holds as a coefficient)
+pub struct ClapParam<M> {
+    /// Hosts save automation against the id, never renumber or reuse one
+    pub id: clap_id,
+    pub name: &'static str,
+    pub unit: &'static str,
+    pub min: f64,
+    pub max: f64,
+    pub stepped: bool,
+    /// The power of the baseplug gradient, 1 for Linear. The VST2 export maps normalized
+    /// values through it like baseplug's VST2 wrapper did, so old VST2 automation still lands
+    /// on the same values.
+    pub exponent: f64,
+    pub get: fn(&M) -> f64,
+    pub set: fn(&mut M, f64),
+}
+
+/// What a plugin needs on top of Plugin to be exported with clap_export!
+pub trait ClapExport: Plugin {
+    /// Reverse domain name, stable across versions
+    const CLAP_ID: &'static str;
+    /// The plugin crate's version, env!("CARGO_PKG_VERSION") in the plugin
+    const VERSION: &'static str;
+    const CLAP_FEATURES: &'static [&'static CStr];
+    /// The plugin's soft bypass parameter, a 0 to 1 toggle. Hosts switch it instead of
+    /// bypassing the plugin themselves, so the plugin can fade to its dry signal (see mix.rs).
+    const BYPASS_PARAM: Option<clap_id> = None;
+
+    /// The model's parameters, clap_params() of a model declared with model!
+    fn clap_params() -> Vec<ClapParam<Self::Model>>;
+
+    /// Samples the output lags the input with model's settings, hosts delay everything else to
+    /// match. It's asked again whenever a parameter changes, so it has to be cheap, and process
+    /// has to delay its dry signal by as much (see latency.rs).
+    fn latency(_model: &Self::Model, _sample_rate: f32) -> u32 {
+        0
+    }
+}
+
+/// Exports plugin as a CLAP plugin from the crate's cdylib, e.g. clap_export!(DynSat);
+#[macro_export]
+macro_rules! clap_export {
+    ($plugin:ty) => {
+        #[no_mangle]
+        #[allow(non_upper_case_globals)]
+        pub static clap_entry: ::clap_sys::entry::clap_plugin_entry =
+            $crate::clap::entry::<$plugin>();
+    };
+}
+
+// Built by entry init, one plugin per library, with one provider for its presets
+struct Descriptor {
+    clap: clap_plugin_descriptor,
+    provider: clap_preset_discovery_provider_descriptor,
+    _strings: Vec<CString>,
+    _features: Vec<*const c_char>,
+}
+
+static DESCRIPTOR: AtomicPtr<Descriptor> = AtomicPtr::new(ptr::null_mut());
+// Hosts may init more than once, the descriptor lives until the last deinit
+static INIT_COUNT: Mutex<usize> = Mutex::new(0);
+
+fn c_string(s: &str) -> CString {
+    CString::new(s.replace('\0', "")).unwrap_or_default()
+}
+
+// Copies s into a fixed size C string, truncating it if needed
+pub(crate) fn write_c_str(s: &str, out: &mut [c_char]) {
+    let len = s.len().min(out.len() - 1);
+    for (o, b) in out.iter_mut().zip(&s.as_bytes()[..len]) {
+        *o = *b as c_char;
+    }
+    out[len] = 0;
+}
+
+pub const fn entry<P>() -> clap_plugin_entry
+where
+    P: ClapExport,
+    P::Model: Clone + VersionedState + FactoryPresets,
+{
+    clap_plugin_entry {
+        clap_version: CLAP_VERSION,
+        init: Some(entry_init::<P>),
+        deinit: Some(entry_deinit),
+        get_factory: Some(get_factory::<P>),
+    }
+}
+
+unsafe extern "C" fn entry_init<P: ClapExport>(_plugin_path: *const c_char) -> bool {
+    let mut count = INIT_COUNT.lock().unwrap_or_else(|e| e.into_inner());
+    *count += 1;
+    if *count > 1 {
+        return true;
+    }
+    let strings = vec![
+        c_string(P::CLAP_ID),
+        c_string(P::NAME),
+        c_string(P::VENDOR),
+        c_string(""),
+        c_string(P::VERSION),
+        c_string(P::PRODUCT),
+        c_string(&format!("{}.presets", P::CLAP_ID)),
+        c_string(&format!("{} presets", P::NAME)),
+    ];
+    let mut features: Vec<*const c_char> = P::CLAP_FEATURES.iter().map(|f| f.as_ptr()).collect();
+    features.push(ptr::null());
+    let descriptor = Box::new(Descriptor {
+        clap: clap_plugin_descriptor {
+            clap_version: CLAP_VERSION,
+            id: strings[0].as_ptr(),
+            name: strings[1].as_ptr(),
+            vendor: strings[2].as_ptr(),
+            url: strings[3].as_ptr(),
+            manual_url: strings[3].as_ptr(),
+            support_url: strings[3].as_ptr(),
+            version: strings[4].as_ptr(),
+            description: strings[5].as_ptr(),
+            features: features.as_ptr(),
+        },
+        provider: clap_preset_discovery_provider_descriptor {
+            clap_version: CLAP_VERSION,
+            id: strings[6].as_ptr(),
+            name: strings[7].as_ptr(),
+            vendor: strings[2].as_ptr(),
+        },
+        _strings: strings,
+        _features: features,
+    });
+    DESCRIPTOR.store(Box::into_raw(descriptor), Ordering::Release);
+    true
+}
+
+unsafe extern "C" fn entry_deinit() {
+    let mut count = INIT_COUNT.lock().unwrap_or_else(|e| e.into_inner());
+    if *count == 0 {
+        return;
+    }
+    *count -= 1;
+    if *count == 0 {
+        let descriptor = DESCRIPTOR.swap(ptr::null_mut(), Ordering::AcqRel);
+        if !descriptor.is_null() {
+            drop(Box::from_raw(descriptor));
+        }
+    }
+}
+
+fn descriptor() -> *const clap_plugin_descriptor {
+    let descriptor = DESCRIPTOR.load(Ordering::Acquire);
+    if descriptor.is_null() {
+        ptr::null()
+    } else {
+        unsafe { &(*descriptor).clap }
+    }
+}
+
+fn provider_descriptor() -> *const clap_preset_discovery_provider_descriptor {
+    let descriptor = DESCRIPTOR.load(Ordering::Acquire);
+    if descriptor.is_null() {
+        ptr::null()
+    } else {
+        unsafe { &(*descriptor).provider }
+    }
+}
+
+struct Factory<P>(PhantomData<P>);
+
+impl<P> Factory<P>
+where
+    P: ClapExport,
+    P::Model: Clone + VersionedState + FactoryPresets,
+{
+    const FACTORY: clap_plugin_factory = clap_plugin_factory {
+        get_plugin_count: Some(Self::count),
+        get_plugin_descriptor: Some(Self::get_descriptor),
+        create_plugin: Some(Self::create),
+    };
+
+    unsafe extern "C" fn count(_factory: *const clap_plugin_factory) -> u32 {
+        1
+    }
+
+    unsafe extern "C" fn get_descriptor(
+        _factory: *const clap_plugin_factory,
+        index: u32,
+    ) -> *const clap_plugin_descriptor {
+        if index == 0 {
+            descriptor()
+        } else {
+            ptr::null()
+        }
+    }
+
+    unsafe extern "C" fn create(
+        _factory: *const clap_plugin_factory,
+        host: *const clap_host,
+        plugin_id: *const c_char,
+    ) -> *const clap_plugin {
+        let descriptor = descriptor();
+        if descriptor.is_null()
+            || plugin_id.is_null()
+            || CStr::from_ptr(plugin_id) != CStr::from_ptr((*descriptor).id)
+        {
+            return ptr::null();
+        }
+        Instance::<P>::create(host, descriptor)
+    }
+}
+
+unsafe extern "C" fn get_factory<P>(factory_id: *const c_char) -> *const c_void
+where
+    P: ClapExport,
+    P::Model: Clone + VersionedState + FactoryPresets,
+{
+    if factory_id.is_null() {
+        return ptr::null();
+    }
+    let id = CStr::from_ptr(factory_id);
+    if id == CLAP_PLUGIN_FACTORY_ID {
+        &Factory::<P>::FACTORY as *const clap_plugin_factory as *const c_void
+    } else if id == CLAP_PRESET_DISCOVERY_FACTORY_ID
+        || id == CLAP_PRESET_DISCOVERY_FACTORY_ID_COMPAT
+    {
+        &PresetDiscovery::<P>::FACTORY as *const clap_preset_discovery_factory as *const c_void
+    } else {
+        ptr::null()
+    }
+}
+
+// Shows hosts the factory presets, which are loaded by their index, and the user presets,
+// which are files (see presets.rs). Both are loaded through the preset-load extension.
+struct PresetDiscovery<P>(PhantomData<P>);
+
+#[repr(C)]
+struct Provider {
+    clap: clap_preset_discovery_provider,
+    indexer: *const clap_preset_discovery_indexer,
+}
+
+impl<P> PresetDiscovery<P>
+where
+    P: ClapExport,
+    P::Model: Clone + VersionedState + FactoryPresets,
+{
+    const FACTORY: clap_preset_discovery_factory = clap_preset_discovery_factory {
+        count: Some(Self::count),
+        get_descriptor: Some(Self::get_descriptor),
+        create: Some(Self::create),
+    };
+
+    unsafe extern "C" fn count(_factory: *const clap_preset_discovery_factory) -> u32 {
+        1
+    }
+
+    unsafe extern "C" fn get_descriptor(
+        _factory: *const clap_preset_discovery_factory,
+        index: u32,
+    ) -> *const clap_preset_discovery_provider_descriptor {
+        if index == 0 {
+            provider_descriptor()
+        } else {
+            ptr::null()
+        }
+    }
+
+    unsafe extern "C" fn create(
+        _factory: *const clap_preset_discovery_factory,
+        indexer: *const clap_preset_discovery_indexer,
+        provider_id: *const c_char,
+    ) -> *const clap_preset_discovery_provider {
+        let descriptor = provider_descriptor();
+        if descriptor.is_null()
+            || provider_id.is_null()
+            || CStr::from_ptr(provider_id) != CStr::from_ptr((*descriptor).id)
+        {
+            return ptr::null();
+        }
+        let provider = Box::into_raw(Box::new(Provider {
+            clap: clap_preset_discovery_provider {
+                desc: descriptor,
+                provider_data: ptr::null_mut(),
+                init: Some(Self::init),
+                destroy: Some(Self::destroy),
+                get_metadata: Some(Self::get_metadata),
+                get_extension: Some(Self::get_extension),
+            },
+            indexer,
+        }));
+        (*provider).clap.provider_data = provider as *mut c_void;
+        &(*provider).clap
+    }
+
+    unsafe extern "C" fn init(provider: *const clap_preset_discovery_provider) -> bool {
+        let indexer = &*(*((*provider).provider_data as *const Provider)).indexer;
+        let (declare_filetype, declare_location) =
+            match (indexer.declare_filetype, indexer.declare_location) {
+                (Some(filetype), Some(location)) => (filetype, location),
+                _ => return false,
+            };
+        let name = c_string(&format!("{} preset", P::NAME));
+        let extension = c_string(presets::PRESET_EXTENSION);
+        let filetype = clap_preset_discovery_filetype {
+            name: name.as_ptr(),
+            description: ptr::null(),
+            file_extension: extension.as_ptr(),
+        };
+        declare_filetype(indexer, &filetype);
+        let factory = c_string("Factory");
+        let location = clap_preset_discovery_location {
+            flags: CLAP_PRESET_DISCOVERY_IS_FACTORY_CONTENT,
+            name: factory.as_ptr(),
+            kind: CLAP_PRESET_DISCOVERY_LOCATION_PLUGIN,
+            location: ptr::null(),
+        };
+        declare_location(indexer, &location);
+        if let Some(dir) = presets::user_preset_dir(P::NAME) {
+            let user = c_string("User");
+            let dir = c_string(&dir.to_string_lossy());
+            let location = clap_preset_discovery_location {
+                flags: CLAP_PRESET_DISCOVERY_IS_USER_CONTENT,
+                name: user.as_ptr(),
+                kind: CLAP_PRESET_DISCOVERY_LOCATION_FILE,
+                location: dir.as_ptr(),
+            };
+            declare_location(indexer, &location);
+        }
+        true
+    }
+
+    unsafe extern "C" fn destroy(provider: *const clap_preset_discovery_provider) {
+        drop(Box::from_raw((*provider).provider_data as *mut Provider));
+    }
+
+    // The factory presets for the plugin location, or the user preset in a file
+    unsafe extern "C" fn get_metadata(
+        _provider: *const clap_preset_discovery_provider,
+        location_kind: clap_preset_discovery_location_kind,
+        location: *const c_char,
+        receiver: *const clap_preset_discovery_metadata_receiver,
+    ) -> bool {
+        let receiver_ref = &*receiver;
+        let begin_preset = match receiver_ref.begin_preset {
+            Some(begin_preset) => begin_preset,
+            None => return false,
+        };
+        let abi = c_string("clap");
+        let id = c_string(P::CLAP_ID);
+        let plugin_id = clap_universal_plugin_id {
+            abi: abi.as_ptr(),
+            id: id.as_ptr(),
+        };
+        let describe = |name: &str, load_key: Option<&str>, flags: u32| {
+            let name = c_string(name);
+            let load_key = load_key.map(c_string);
+            let load_key = load_key.as_ref().map_or(ptr::null(), |key| key.as_ptr());
+            if !begin_preset(receiver, name.as_ptr(), load_key) {
+                return false;
+            }
+            if let Some(add_plugin_id) = receiver_ref.add_plugin_id {
+                add_plugin_id(receiver, &plugin_id);
+            }
+            if let Some(set_flags) = receiver_ref.set_flags {
+                set_flags(receiver, flags);
+            }
+            true
+        };
+        if location_kind == CLAP_PRESET_DISCOVERY_LOCATION_PLUGIN {
+            let bank = PresetBank::<P::Model>::new();
+            for index in 0..bank.factory_count() {
+                let name = bank.program_name(index).unwrap_or_default();
+                let key = index.to_string();
+                if !describe(name, Some(&key), CLAP_PRESET_DISCOVERY_IS_FACTORY_CONTENT) {
+                    break;
+                }
+            }
+            return true;
+        }
+        if location_kind != CLAP_PRESET_DISCOVERY_LOCATION_FILE || location.is_null() {
+            return false;
+        }
+        let path = CStr::from_ptr(location).to_string_lossy().into_owned();
+        match presets::read_preset::<P::Model>(Path::new(&path)) {
+            Ok(preset) => {
+                describe(&preset.name, None, CLAP_PRESET_DISCOVERY_IS_USER_CONTENT);
+                true
+            }
+            Err(error) => {
+                if let Some(on_error) = receiver_ref.on_error {
+                    let message = c_string(&error.to_string());
+                    on_error(
+                        receiver,
+                        error.raw_os_error().unwrap_or(0),
+                        message.as_ptr(),
+                    );
+                }
+                false
+            }
+        }
+    }
+
+    unsafe extern "C" fn get_extension(
+        _provider: *const clap_preset_discovery_provider,
+        _extension_id: *const c_char,
+    ) -> *const c_void {
+        ptr::null()
+    }
+}
+
+impl<M> ClapParam<M> {
+    /// value in range, and a whole number if the parameter is stepped
+    pub fn clamp(&self, value: f64) -> f64 {
+        let value = if value.is_nan() { self.min } else { value };
+        let value = value.max(self.min).min(self.max);
+        if self.stepped {
+            value.round()
+        } else {
+            value
+        }
+    }
+}
+
+// Everything the audio thread owns, made in activate and dropped in deactivate. The VST3
+// export runs plugins through this as well.
+pub(crate) struct Audio<P: Plugin> {
+    plugin: P,
+    pub model: P::Model,
+    pub smooth: <P::Model as Model<P>>::Smooth,
+    sample_rate: f32,
+    pub inputs: [Vec<f32>; CHANNELS],
+    scratch: [Vec<f32>; CHANNELS],
+}
+
+struct InputEvents(*const clap_input_events);
+
+impl InputEvents {
+    unsafe fn len(&self) -> u32 {
+        match self.0.as_ref() {
+            Some(&clap_input_events {
+                size: Some(size), ..
+            }) => size(self.0),
+            _ => 0,
+        }
+    }
+
+    unsafe fn get<'a>(&self, index: u32) -> Option<&'a clap_event_header> {
+        let get = (*self.0).get?;
+        get(self.0, index).as_ref()
+    }
+}
+
+struct Instance<P: Plugin> {
+    clap: clap_plugin,
+    host: *const clap_host,
+    params: Vec<ClapParam<P::Model>>,
+    // Current parameter values as f64 bits, shared by the main and audio threads
+    values: Vec<AtomicU64>,
+    // Set when the values were changed outside process, e.g. by loading state
+    values_changed: AtomicBool,
+    midi: MidiMap,
+    presets: PresetBank<P::Model>,
+    // The latency reported to the host, which only takes a new one on activate
+    latency: AtomicU32,
+    restart_requested: AtomicBool,
+    audio: UnsafeCell<Option<Audio<P>>>,
+}
+
+impl<P> Instance<P>
+where
+    P: ClapExport,
+    P::Model: Clone + VersionedState + FactoryPresets,
+{
+    const PARAMS: clap_plugin_params = clap_plugin_params {
+        count: Some(Self::params_count),
+        get_info: Some(Self::params_get_info),
+        get_value: Some(Self::params_get_value),
+        value_to_text: Some(Self::params_value_to_text),
+        text_to_value: Some(Self::params_text_to_value),
+        flush: Some(Self::params_flush),
+    };
+
+    const STATE: clap_plugin_state = clap_plugin_state {
+        save: Some(Self::state_save),
+        load: Some(Self::state_load),
+    };
+
+    const AUDIO_PORTS: clap_plugin_audio_ports = clap_plugin_audio_ports {
+        count: Some(Self::audio_ports_count),
+        get: Some(Self::audio_ports_get),
+    };
+
+    const NOTE_PORTS: clap_plugin_note_ports = clap_plugin_note_ports {
+        count: Some(Self::note_ports_count),
+        get: Some(Self::note_ports_get),
+    };
+
+    const LATENCY: clap_plugin_latency = clap_plugin_latency {
+        get: Some(Self::latency_get),
+    };
+
+    const PRESET_LOAD: clap_plugin_preset_load = clap_plugin_preset_load {
+        from_location: Some(Self::preset_load_from_location),
+    };
+
+    unsafe fn create(
+        host: *const clap_host,
+        descriptor: *const clap_plugin_descriptor,
+    ) -> *const clap_plugin {
+        let mut params = P::clap_params();
+        params.push(midi::learn_param(&params));
+        let model = P::Model::default();
+        let values = params
+            .iter()
+            .map(|param| AtomicU64::new((param.get)(&model).to_bits()))
+            .collect();
+        let instance = Box::new(Instance::<P> {
+            clap: clap_plugin {
+                desc: descriptor,
+                plugin_data: ptr::null_mut(),
+                init: Some(Self::init),
+                destroy: Some(Self::destroy),
+                activate: Some(Self::activate),
+                deactivate: Some(Self::deactivate),
+                start_processing: Some(Self::start_processing),
+                stop_processing: Some(Self::stop_processing),
+                reset: Some(Self::reset),
+                process: Some(Self::process),
+                get_extension: Some(Self::get_extension),
+                on_main_thread: Some(Self::on_main_thread),
+            },
+            host,
+            params,
+            values,
+            values_changed: AtomicBool::new(false),
+            midi: MidiMap::new(),
+            presets: PresetBank::new(),
+            latency: AtomicU32::new(0),
+            restart_requested: AtomicBool::new(false),
+            audio: UnsafeCell::new(None),
+        });
+        let instance = Box::into_raw(instance);
+        (*instance).clap.plugin_data = instance as *mut c_void;
+        &(*instance).clap
+    }
+
+    unsafe fn from_clap<'a>(plugin: *const clap_plugin) -> &'a Instance<P> {
+        &*((*plugin).plugin_data as *const Instance<P>)
+    }
+
+    // Only called from the audio thread, or the main thread while not active, as CLAP requires
+    #[allow(clippy::mut_from_ref)]
+    unsafe fn audio(&self) -> &mut Option<Audio<P>> {
+        &mut *self.audio.get()
+    }
+
+    fn value(&self, index: usize) -> f64 {
+        f64::from_bits(self.values[index].load(Ordering::Relaxed))
+    }
+
+    fn set_value(&self, index: usize, value: f64) -> f64 {
+        let value = self.params[index].clamp(value);
+        self.values[index].store(value.to_bits(), Ordering::Relaxed);
+        if self.params[index].id == LEARN_PARAM_ID {
+            self.midi.set_learning(&self.params, value);
+        }
+        value
+    }
+
+    fn index(&self, param_id: clap_id) -> Option<usize> {
+        self.params.iter().position(|param| param.id == param_id)
+    }
+
+    // The model with every parameter at its current value
+    fn model(&self) -> P::Model {
+        let mut model = P::Model::default();
+        for (index, param) in self.params.iter().enumerate() {
+            (param.set)(&mut model, self.value(index));
+        }
+        model
+    }
+
+    // Stores the value of a parameter change event, returning its index and new value
+    unsafe fn apply(&self, header: &clap_event_header) -> Option<(usize, f64)> {
+        if header.space_id != CLAP_CORE_EVENT_SPACE_ID || header.type_ != CLAP_EVENT_PARAM_VALUE {
+            return None;
+        }
+        let event = &*(header as *const clap_event_header as *const clap_event_param_value);
+        let index = self.index(event.param_id)?;
+        Some((index, self.set_value(index, event.value)))
+    }
+
+    // Stores the value a control change sets through its MIDI mapping, returning the
+    // parameter's index and new value. Values the plugin changes itself are sent to the host.
+    unsafe fn apply_midi(
+        &self,
+        header: &clap_event_header,
+        out: *const clap_output_events,
+    ) -> Option<(usize, f64)> {
+        if header.space_id != CLAP_CORE_EVENT_SPACE_ID || header.type_ != CLAP_EVENT_MIDI {
+            return None;
+        }
+        let event = &*(header as *const clap_event_header as *const clap_event_midi);
+        let change = self.midi.midi_input(event.data)?;
+        if change.learned {
+            if let Some(learn) = self.index(LEARN_PARAM_ID) {
+                self.set_value(learn, 0.0);
+                push_value(out, header.time, LEARN_PARAM_ID, 0.0);
+            }
+        }
+        let index = self.index(change.param)?;
+        let param = &self.params[index];
+        let value = self.set_value(
+            index,
+            param.min + (param.max - param.min) * change.normalized,
+        );
+        push_value(out, header.time, param.id, value);
+        Some((index, value))
+    }
+
+    unsafe extern "C" fn init(_plugin: *const clap_plugin) -> bool {
+        true
+    }
+
+    unsafe extern "C" fn destroy(plugin: *const clap_plugin) {
+        drop(Box::from_raw((*plugin).plugin_data as *mut Instance<P>));
+    }
+
+    unsafe extern "C" fn activate(
+        plugin: *const clap_plugin,
+        sample_rate: f64,
+        _min_frames_count: u32,
+        max_frames_count: u32,
+    ) -> bool {
+        let instance = Self::from_clap(plugin);
+        let model = instance.model();
+        let latency = P::latency(&model, sample_rate as f32);
+        *instance.audio() = Some(Audio::new(
+            sample_rate as f32,
+            model,
+            max_frames_count as usize,
+        ));
+        instance.values_changed.store(false, Ordering::Relaxed);
+        instance.restart_requested.store(false, Ordering::Relaxed);
+        if instance.latency.swap(latency, Ordering::Relaxed) != latency {
+            let host_latency = instance.host_extension::<clap_host_latency>(CLAP_EXT_LATENCY);
+            if let Some(changed) = host_latency.and_then(|latency| latency.changed) {
+                changed(instance.host);
+            }
+        }
+        true
+    }
+
+    unsafe extern "C" fn deactivate(plugin: *const clap_plugin) {
+        *Self::from_clap(plugin).audio() = None;
+    }
+
+    unsafe extern "C" fn start_processing(_plugin: *const clap_plugin) -> bool {
+        true
+    }
+
+    unsafe extern "C" fn stop_processing(_plugin: *const clap_plugin) {}
+
+    unsafe extern "C" fn reset(plugin: *const clap_plugin) {
+        if let Some(audio) = Self::from_clap(plugin).audio() {
+            audio.reset();
+        }
+    }
+
+    unsafe extern "C" fn process(
+        plugin: *const clap_plugin,
+        process: *const clap_process,
+    ) -> clap_process_status {
+        let instance = Self::from_clap(plugin);
+        let audio = match instance.audio() {
+            Some(audio) => audio,
+            None => return CLAP_PROCESS_CONTINUE,
+        };
+        let process = &*process;
+        let nframes = (process.frames_count as usize).min(audio.inputs[0].len());
+
+        let mut changed = instance.values_changed.swap(false, Ordering::Relaxed);
+        if changed {
+            for (index, param) in instance.params.iter().enumerate() {
+                (param.set)(&mut audio.model, instance.value(index));
+            }
+            audio.smooth.set(&audio.model);
+        }
+
+        let inputs = bus_channels(process.audio_inputs, process.audio_inputs_count);
+        audio.copy_inputs(inputs, nframes);
+        let outputs = bus_channels(process.audio_outputs, process.audio_outputs_count);
+        let musical_time = musical_time(process.transport);
+
+        // Parameter changes and MIDI control changes are applied at the frame they're
+        // timestamped with, the block is split at each one
+        let events = InputEvents(process.in_events);
+        let event_count = events.len();
+        let mut event = 0;
+        let mut start = 0;
+        loop {
+            while event < event_count {
+                let header = events.get(event);
+                if let Some(header) = header {
+                    if header.time as usize > start && start < nframes {
+                        break;
+                    }
+                    let applied = instance
+                        .apply(header)
+                        .or_else(|| instance.apply_midi(header, process.out_events));
+                    if let Some((index, value)) = applied {
+                        (instance.params[index].set)(&mut audio.model, value);
+                        audio.smooth.set(&audio.model);
+                        changed = true;
+                    }
+                }
+                event += 1;
+            }
+            if start >= nframes {
+                break;
+            }
+            let next_event = match events.get(event).filter(|_| event < event_count) {
+                Some(header) => (header.time as usize).min(nframes),
+                None => nframes,
+            };
+            let end = next_event.min(start + MAX_BLOCK);
+            audio.run(start, end, outputs, &musical_time);
+            start = end;
+        }
+
+        // The new latency is reported on the next activate, so the host is asked to restart
+        // the plugin once
+        if changed
+            && P::latency(&audio.model, audio.sample_rate)
+                != instance.latency.load(Ordering::Relaxed)
+            && !instance.restart_requested.swap(true, Ordering::Relaxed)
+        {
+            if let Some(request_restart) = instance.host.as_ref().and_then(|h| h.request_restart) {
+                request_restart(instance.host);
+            }
+        }
+        CLAP_PROCESS_CONTINUE
+    }
+
+    unsafe extern "C" fn get_extension(
+        _plugin: *const clap_plugin,
+        id: *const c_char,
+    ) -> *const c_void {
+        if id.is_null() {
+            return ptr::null();
+        }
+        let id = CStr::from_ptr(id);
+        if id == CLAP_EXT_PARAMS {
+            &Self::PARAMS as *const clap_plugin_params as *const c_void
+        } else if id == CLAP_EXT_STATE {
+            &Self::STATE as *const clap_plugin_state as *const c_void
+        } else if id == CLAP_EXT_AUDIO_PORTS {
+            &Self::AUDIO_PORTS as *const clap_plugin_audio_ports as *const c_void
+        } else if id == CLAP_EXT_NOTE_PORTS {
+            &Self::NOTE_PORTS as *const clap_plugin_note_ports as *const c_void
+        } else if id == CLAP_EXT_LATENCY {
+            &Self::LATENCY as *const clap_plugin_latency as *const c_void
+        } else if id == CLAP_EXT_PRESET_LOAD || id == CLAP_EXT_PRESET_LOAD_COMPAT {
+            &Self::PRESET_LOAD as *const clap_plugin_preset_load as *const c_void
+        } else {
+            ptr::null()
+        }
+    }
+
+    unsafe extern "C" fn on_main_thread(_plugin: *const clap_plugin) {}
+
+    unsafe extern "C" fn params_count(plugin: *const clap_plugin) -> u32 {
+        Self::from_clap(plugin).params.len() as u32
+    }
+
+    unsafe extern "C" fn params_get_info(
+        plugin: *const clap_plugin,
+        param_index: u32,
+        param_info: *mut clap_param_info,
+    ) -> bool {
+        let instance = Self::from_clap(plugin);
+        let param = match instance.params.get(param_index as usize) {
+            Some(param) => param,
+            None => return false,
+        };
+        let info = &mut *param_info;
+        info.id = param.id;
+        info.flags = if param.id == LEARN_PARAM_ID {
+            0
+        } else {
+            CLAP_PARAM_IS_AUTOMATABLE
+        };
+        if param.stepped {
+            info.flags |= CLAP_PARAM_IS_STEPPED;
+        }
+        if P::BYPASS_PARAM == Some(param.id) {
+            info.flags |= CLAP_PARAM_IS_BYPASS;
+        }
+        info.cookie = ptr::null_mut();
+        write_c_str(param.name, &mut info.name);
+        write_c_str("", &mut info.module);
+        info.min_value = param.min;
+        info.max_value = param.max;
+        info.default_value = (param.get)(&P::Model::default());
+        true
+    }
+
+    unsafe extern "C" fn params_get_value(
+        plugin: *const clap_plugin,
+        param_id: clap_id,
+        out_value: *mut f64,
+    ) -> bool {
+        let instance = Self::from_clap(plugin);
+        match instance.index(param_id) {
+            Some(index) => {
+                *out_value = instance.value(index);
+                true
+            }
+            None => false,
+        }
+    }
+
+    unsafe extern "C" fn params_value_to_text(
+        plugin: *const clap_plugin,
+        param_id: clap_id,
+        value: f64,
+        out_buffer: *mut c_char,
+        out_buffer_capacity: u32,
+    ) -> bool {
+        let instance = Self::from_clap(plugin);
+        let param = match instance.index(param_id) {
+            Some(index) => &instance.params[index],
+            None => return false,
+        };
+        if out_buffer.is_null() || out_buffer_capacity == 0 {
+            return false;
+        }
+        let text = if param.id == LEARN_PARAM_ID {
+            midi::format_learn(&instance.params, value)
+        } else {
+            format_value(param, value)
+        };
+        let out = std::slice::from_raw_parts_mut(out_buffer, out_buffer_capacity as usize);
+        write_c_str(&text, out);
+        true
+    }
+
+    unsafe extern "C" fn params_text_to_value(
+        plugin: *const clap_plugin,
+        param_id: clap_id,
+        param_value_text: *const c_char,
+        out_value: *mut f64,
+    ) -> bool {
+        let instance = Self::from_clap(plugin);
+        if instance.index(param_id).is_none() || param_value_text.is_null() {
+            return false;
+        }
+        let text = CStr::from_ptr(param_value_text).to_string_lossy();
+        let value = if param_id == LEARN_PARAM_ID {
+            midi::parse_learn(&instance.params, &text)
+        } else {
+            parse_value(&text)
+        };
+        match value {
+            Some(value) => {
+                *out_value = value;
+                true
+            }
+            None => false,
+        }
+    }
+
+    // Called when not processing, the audio thread picks the values up on its next process
+    unsafe extern "C" fn params_flush(
+        plugin: *const clap_plugin,
+        in_: *const clap_input_events,
+        _out: *const clap_output_events,
+    ) {
+        let instance = Self::from_clap(plugin);
+        let events = InputEvents(in_);
+        for i in 0..events.len() {
+            if let Some(header) = events.get(i) {
+                if instance.apply(header).is_some() {
+                    instance.values_changed.store(true, Ordering::Relaxed);
+                }
+            }
+        }
+    }
+
+    unsafe extern "C" fn state_save(
+        plugin: *const clap_plugin,
+        stream: *const clap_ostream,
+    ) -> bool {
+        let instance = Self::from_clap(plugin);
+        let json = match state::plugin_to_json(&instance.model(), &instance.midi) {
+            Ok(json) => json,
+            Err(_) => return false,
+        };
+        let write = match (*stream).write {
+            Some(write) => write,
+            None => return false,
+        };
+        let mut bytes = json.as_bytes();
+        while !bytes.is_empty() {
+            let written = write(stream, bytes.as_ptr() as *const c_void, bytes.len() as u64);
+            if written <= 0 {
+                return false;
+            }
+            bytes = &bytes[(written as usize).min(bytes.len())..];
+        }
+        true
+    }
+
+    unsafe extern "C" fn state_load(
+        plugin: *const clap_plugin,
+        stream: *const clap_istream,
+    ) -> bool {
+        let instance = Self::from_clap(plugin);
+        let read = match (*stream).read {
+            Some(read) => read,
+            None => return false,
+        };
+        let mut json = Vec::new();
+        let mut buffer = [0u8; 4096];
+        loop {
+            let count = read(
+                stream,
+                buffer.as_mut_ptr() as *mut c_void,
+                buffer.len() as u64,
+            );
+            if count < 0 {
+                return false;
+            }
+            if count == 0 {
+                break;
+            }
+            json.extend_from_slice(&buffer[..count as usize]);
+        }
+        let model: P::Model = match std::str::from_utf8(&json)
+            .ok()
+            .and_then(|json| state::plugin_from_json(json, &instance.midi).ok())
+        {
+            Some(model) => model,
+            None => return false,
+        };
+        instance.load_model(&model);
+        true
+    }
+
+    // Sets every parameter to model's value, the audio thread picks them up on its next
+    // process and the host is told to read them again
+    unsafe fn load_model(&self, model: &P::Model) {
+        for (index, param) in self.params.iter().enumerate() {
+            self.set_value(index, (param.get)(model));
+        }
+        self.values_changed.store(true, Ordering::Relaxed);
+        self.rescan_values();
+    }
+
+    // A factory preset by the index preset discovery gave as its load key, or a user preset
+    // file. MIDI mappings are kept, like they are when a host loads a preset's state.
+    unsafe extern "C" fn preset_load_from_location(
+        plugin: *const clap_plugin,
+        location_kind: clap_preset_discovery_location_kind,
+        location: *const c_char,
+        load_key: *const c_char,
+    ) -> bool {
+        let instance = Self::from_clap(plugin);
+        let model = if location_kind == CLAP_PRESET_DISCOVERY_LOCATION_PLUGIN {
+            let key = Some(load_key).filter(|key| !key.is_null());
+            let index = key.and_then(|key| CStr::from_ptr(key).to_str().ok()?.parse().ok());
+            match index.and_then(|index| instance.presets.get(index)) {
+                Some(preset) => Ok(preset.model.clone()),
+                None => Err((0, "no such factory preset".to_string())),
+            }
+        } else if location_kind == CLAP_PRESET_DISCOVERY_LOCATION_FILE && !location.is_null() {
+            let path = CStr::from_ptr(location).to_string_lossy().into_owned();
+            presets::read_preset::<P::Model>(Path::new(&path))
+                .map(|preset| preset.model)
+                .map_err(|error| (error.raw_os_error().unwrap_or(0), error.to_string()))
+        } else {
+            Err((0, "unknown preset location".to_string()))
+        };
+        let host_preset_load = instance
+            .host_extension::<clap_host_preset_load>(CLAP_EXT_PRESET_LOAD)
+            .or_else(|| instance.host_extension(CLAP_EXT_PRESET_LOAD_COMPAT));
+        match model {
+            Ok(model) => {
+                instance.load_model(&model);
+                if let Some(loaded) = host_preset_load.and_then(|host| host.loaded) {
+                    loaded(instance.host, location_kind, location, load_key);
+                }
+                true
+            }
+            Err((os_error, message)) => {
+                if let Some(on_error) = host_preset_load.and_then(|host| host.on_error) {
+                    let message = c_string(&message);
+                    on_error(
+                        instance.host,
+                        location_kind,
+                        location,
+                        load_key,
+                        os_error,
+                        message.as_ptr(),
+                    );
+                }
+                false
+            }
+        }
+    }
+
+    unsafe fn host_extension<T>(&self, id: &CStr) -> Option<&T> {
+        let get_extension = self.host.as_ref()?.get_extension?;
+        (get_extension(self.host, id.as_ptr()) as *const T).as_ref()
+    }
+
+    unsafe fn rescan_values(&self) {
+        let params = self.host_extension::<clap_host_params>(CLAP_EXT_PARAMS);
+        if let Some(rescan) = params.and_then(|params| params.rescan) {
+            rescan(self.host, CLAP_PARAM_RESCAN_VALUES);
+        }
+    }
+
+    unsafe extern "C" fn latency_get(plugin: *const clap_plugin) -> u32 {
+        Self::from_clap(plugin).latency.load(Ordering::Relaxed)
+    }
+
+    unsafe extern "C" fn audio_ports_count(_plugin: *const clap_plugin, _is_input: bool) -> u32 {
+        1
+    }
+
+    unsafe extern "C" fn audio_ports_get(
+        _plugin: *const clap_plugin,
+        index: u32,
+        is_input: bool,
+        info: *mut clap_audio_port_info,
+    ) -> bool {
+        if index != 0 {
+            return false;
+        }
+        let info = &mut *info;
+        info.id = 0;
+        write_c_str(if is_input { "Input" } else { "Output" }, &mut info.name);
+        info.flags = CLAP_AUDIO_PORT_IS_MAIN;
+        info.channel_count = CHANNELS as u32;
+        info.port_type = CLAP_PORT_STEREO.as_ptr();
+        info.in_place_pair = 0;
+        true
+    }
+
+    // A MIDI input for control changes, see midi.rs
+    unsafe extern "C" fn note_ports_count(_plugin: *const clap_plugin, is_input: bool) -> u32 {
+        if is_input {
+            1
+        } else {
+            0
+        }
+    }
+
+    unsafe extern "C" fn note_ports_get(
+        _plugin: *const clap_plugin,
+        index: u32,
+        is_input: bool,
+        info: *mut clap_note_port_info,
+    ) -> bool {
+        if index != 0 || !is_input {
+            return false;
+        }
+        let info = &mut *info;
+        info.id = 0;
+        info.supported_dialects = CLAP_NOTE_DIALECT_MIDI;
+        info.preferred_dialect = CLAP_NOTE_DIALECT_MIDI;
+        write_c_str("MIDI In", &mut info.name);
+        true
+    }
+}
+
+// Tells the host about a value the plugin changed itself
+unsafe fn push_value(out: *const clap_output_events, time: u32, id: clap_id, value: f64) {
+    let try_push = match out.as_ref().and_then(|out| out.try_push) {
+        Some(try_push) => try_push,
+        None => return,
+    };
+    let event = clap_event_param_value {
+        header: clap_event_header {
+            size: std::mem::size_of::<clap_event_param_value>() as u32,
+            time,
+            space_id: CLAP_CORE_EVENT_SPACE_ID,
+            type_: CLAP_EVENT_PARAM_VALUE,
+            flags: 0,
+        },
+        param_id: id,
+        cookie: ptr::null_mut(),
+        note_id: -1,
+        port_index: -1,
+        channel: -1,
+        key: -1,
+        value,
+    };
+    try_push(out, &event.header);
+}
+
+impl<P: Plugin> Audio<P>
+where
+    P::Model: Clone,
+{
+    pub fn new(sample_rate: f32, model: P::Model, max_frames: usize) -> Audio<P> {
+        let mut smooth = <P::Model as Model<P>>::Smooth::from_model(model.clone());
+        smooth.set_sample_rate(sample_rate);
+        let frames = max_frames.max(1);
+        Audio {
+            plugin: P::new(sample_rate, &model),
+            model,
+            smooth,
+            sample_rate,
+            inputs: [vec![0.0; frames], vec![0.0; frames]],
+            scratch: [vec![0.0; MAX_BLOCK], vec![0.0; MAX_BLOCK]],
+        }
+    }
+
+    // baseplug plugins have no way to clear their DSP state, so this only snaps the
+    // parameter smoothing to the current values
+    pub fn reset(&mut self) {
+        self.smooth = <P::Model as Model<P>>::Smooth::from_model(self.model.clone());
+        self.smooth.set_sample_rate(self.sample_rate);
+    }
+
+    /// Copies the host's input channels, hosts can process in place and baseplug takes the
+    /// input and output buffers at the same time. Missing channels are silence.
+    pub unsafe fn copy_inputs(&mut self, channels: Option<&[*mut f32]>, nframes: usize) {
+        for (channel, input) in self.inputs.iter_mut().enumerate() {
+            match channels.and_then(|bus| bus.get(channel)) {
+                Some(&data) if !data.is_null() => {
+                    input[..nframes].copy_from_slice(std::slice::from_raw_parts(data, nframes))
+                }
+                _ => input[..nframes].iter_mut().for_each(|x| *x = 0.0),
+            }
+        }
+    }
+
+    /// Runs the plugin over frames start..end of the host's buffers, at most MAX_BLOCK
+    pub unsafe fn run(
+        &mut self,
+        start: usize,
+        end: usize,
+        outputs: Option<&[*mut f32]>,
+        musical_time: &MusicalTime,
+    ) {
+        let nframes = end - start;
+        if nframes == 0 {
+            return;
+        }
+        let input_buffers = [&self.inputs[0][start..end], &self.inputs[1][start..end]];
+        // Channels the host didn't give us are written to scratch
+        let [scratch_l, scratch_r] = &mut self.scratch;
+        let mut output_buffers: [&mut [f32]; CHANNELS] = [
+            match outputs.and_then(|bus| bus.first()) {
+                Some(&data) if !data.is_null() => {
+                    std::slice::from_raw_parts_mut(data.add(start), nframes)
+                }
+                _ => &mut scratch_l[..nframes],
+            },
+            match outputs.and_then(|bus| bus.get(1)) {
+                Some(&data) if !data.is_null() => {
+                    std::slice::from_raw_parts_mut(data.add(start), nframes)
+                }
+                _ => &mut scratch_r[..nframes],
+            },
+        ];
+        let audio_inputs = [AudioBus {
+            connected_channels: 2,
+            buffers: &input_buffers,
+        }];
+        let mut audio_outputs = [AudioBusMut {
+            connected_channels: 2,
+            buffers: &mut output_buffers,
+        }];
+        let mut enqueue_event = |_| {};
+        let mut ctx = ProcessContext {
+            nframes,
+            sample_rate: self.sample_rate,
+            inputs: &audio_inputs,
+            outputs: &mut audio_outputs,
+            enqueue_event: &mut enqueue_event,
+            musical_time,
+        };
+        self.plugin.process(&self.smooth.process(nframes), &mut ctx);
+    }
+}
+
+// The channel pointers of the first bus, if there is one with 32 bit data
+unsafe fn bus_channels<'a>(buses: *const clap_audio_buffer, count: u32) -> Option<&'a [*mut f32]> {
+    if buses.is_null() || count == 0 {
+        return None;
+    }
+    let bus = &*buses;
+    if bus.data32.is_null() {
+        return None;
+    }
+    Some(std::slice::from_raw_parts(
+        bus.data32 as *const *mut f32,
+        bus.channel_count as usize,
+    ))
+}
+
+unsafe fn musical_time(transport: *const clap_event_transport) -> MusicalTime {
+    let mut time = MusicalTime {
+        bpm: 120.0,
+        beat: 0.0,
+    };
+    if let Some(transport) = transport.as_ref() {
+        if transport.flags & CLAP_TRANSPORT_HAS_TEMPO != 0 {
+            time.bpm = transport.tempo;
+        }
+        if transport.flags & CLAP_TRANSPORT_HAS_BEATS_TIMELINE != 0 {
+            time.beat = transport.song_pos_beats as f64 / CLAP_BEATTIME_FACTOR as f64;
+        }
+    }
+    time
+}
+
+pub(crate) fn format_value<M>(param: &ClapParam<M>, value: f64) -> String {
+    let text = format_number(param, value);
+    if param.unit.is_empty() {
+        text
+    } else {
+        format!("{} {}", text, param.unit)
+    }
+}
+
+// The value without its unit
+pub(crate) fn format_number<M>(param: &ClapParam<M>, value: f64) -> String {
+    if param.stepped {
+        format!("{}", value.round())
+    } else {
+        format!("{:.2}", value)
+    }
+}
+
+// The number at the start of text, any unit after it is ignored
+pub(crate) fn parse_value(text: &str) -> Option<f64> {
+    let text = text.trim();
+    let end = text
+        .find(|c: char| !(c.is_ascii_digit() || "+-.eE".contains(c)))
+        .unwrap_or(text.len());
+    text[..end].parse().ok()
+}
+
+// A minimal in-process host for the plugins' tests, it drives clap_entry the same way a host
+// drives the exported .clap
+#[cfg(feature = "testing")]
+pub mod host {
+    use super::*;
+    use clap_sys::plugin_features::CLAP_PLUGIN_FEATURE_AUDIO_EFFECT;
+    use std::sync::atomic::AtomicUsize;
+
+    use crate::presets::Preset;
+
+    pub const SAMPLE_RATE: f64 = 48000.0;
+    pub const MAX_FRAMES: u32 = 1024;
+
+    struct HostData {
+        rescans: AtomicUsize,
+        restarts: AtomicUsize,
+        latency_changes: AtomicUsize,
+        preset_loads: AtomicUsize,
+        preset_errors: AtomicUsize,
+    }
+
+    unsafe extern "C" fn host_get_extension(
+        _host: *const clap_host,
+        id: *const c_char,
+    ) -> *const c_void {
+        const PARAMS: clap_host_params = clap_host_params {
+            rescan: Some(host_rescan),
+            clear: None,
+            request_flush: None,
+        };
+        const LATENCY: clap_host_latency = clap_host_latency {
+            changed: Some(host_latency_changed),
+        };
+        const PRESET_LOAD: clap_host_preset_load = clap_host_preset_load {
+            on_error: Some(host_preset_error),
+            loaded: Some(host_preset_loaded),
+        };
+        if CStr::from_ptr(id) == CLAP_EXT_PARAMS {
+            &PARAMS as *const clap_host_params as *const c_void
+        } else if CStr::from_ptr(id) == CLAP_EXT_LATENCY {
+            &LATENCY as *const clap_host_latency as *const c_void
+        } else if CStr::from_ptr(id) == CLAP_EXT_PRESET_LOAD {
+            &PRESET_LOAD as *const clap_host_preset_load as *const c_void
+        } else {
+            ptr::null()
+        }
+    }
+
+    unsafe extern "C" fn host_rescan(host: *const clap_host, _flags: u32) {
+        let data = &*((*host).host_data as *const HostData);
+        data.rescans.fetch_add(1, Ordering::Relaxed);
+    }
+
+    unsafe extern "C" fn host_latency_changed(host: *const clap_host) {
+        let data = &*((*host).host_data as *const HostData);
+        data.latency_changes.fetch_add(1, Ordering::Relaxed);
+    }
+
+    unsafe extern "C" fn host_preset_error(
+        host: *const clap_host,
+        _location_kind: clap_preset_discovery_location_kind,
+        _location: *const c_char,
+        _load_key: *const c_char,
+        _os_error: i32,
+        _message: *const c_char,
+    ) {
+        let data = &*((*host).host_data as *const HostData);
+        data.preset_errors.fetch_add(1, Ordering::Relaxed);
+    }
+
+    unsafe extern "C" fn host_preset_loaded(
+        host: *const clap_host,
+        _location_kind: clap_preset_discovery_location_kind,
+        _location: *const c_char,
+        _load_key: *const c_char,
+    ) {
+        let data = &*((*host).host_data as *const HostData);
+        data.preset_loads.fetch_add(1, Ordering::Relaxed);
+    }
+
+    unsafe extern "C" fn host_restart(host: *const clap_host) {
+        let data = &*((*host).host_data as *const HostData);
+        data.restarts.fetch_add(1, Ordering::Relaxed);
+    }
+
+    unsafe extern "C" fn host_request(_host: *const clap_host) {}
+
+    pub struct Host {
+        clap: Box<clap_host>,
+        data: Box<HostData>,
+        entry: &'static clap_plugin_entry,
+    }
+
+    // A plugin instance and its extensions
+    pub struct Instance {
+        pub plugin: *const clap_plugin,
+        pub params: &'static clap_plugin_params,
+        pub state: &'static clap_plugin_state,
+        pub audio_ports: &'static clap_plugin_audio_ports,
+        pub note_ports: &'static clap_plugin_note_ports,
+        pub latency: &'static clap_plugin_latency,
+        pub preset_load: &'static clap_plugin_preset_load,
+    }
+
+    /// A preset as preset discovery describes it
+    #[derive(Clone, Debug, Default, PartialEq)]
+    pub struct DiscoveredPreset {
+        pub name: String,
+        pub load_key: Option<String>,
+        pub plugin_ids: Vec<String>,
+        pub flags: u32,
+    }
+
+    /// What the preset provider declares when it's initialized, and the presets it finds in
+    /// the plugin location and in a preset file
+    #[derive(Debug, Default)]
+    pub struct PresetIndex {
+        pub extensions: Vec<String>,
+        /// The kind and flags of each location
+        pub locations: Vec<(clap_preset_discovery_location_kind, u32)>,
+        pub presets: Vec<DiscoveredPreset>,
+    }
+
+    unsafe extern "C" fn indexer_declare_filetype(
+        indexer: *const clap_preset_discovery_indexer,
+        filetype: *const clap_preset_discovery_filetype,
+    ) -> bool {
+        let index = &mut *((*indexer).indexer_data as *mut PresetIndex);
+        let extension = CStr::from_ptr((*filetype).file_extension);
+        index
+            .extensions
+            .push(extension.to_string_lossy().into_owned());
+        true
+    }
+
+    unsafe extern "C" fn indexer_declare_location(
+        indexer: *const clap_preset_discovery_indexer,
+        location: *const clap_preset_discovery_location,
+    ) -> bool {
+        let index = &mut *((*indexer).indexer_data as *mut PresetIndex);
+        index.locations.push(((*location).kind, (*location).flags));
+        true
+    }
+
+    unsafe extern "C" fn receiver_begin_preset(
+        receiver: *const clap_preset_discovery_metadata_receiver,
+        name: *const c_char,
+        load_key: *const c_char,
+    ) -> bool {
+        let presets = &mut *((*receiver).receiver_data as *mut Vec<DiscoveredPreset>);
+        presets.push(DiscoveredPreset {
+            name: CStr::from_ptr(name).to_string_lossy().into_owned(),
+            load_key: Some(load_key)
+                .filter(|key| !key.is_null())
+                .map(|key| CStr::from_ptr(key).to_string_lossy().into_owned()),
+            ..DiscoveredPreset::default()
+        });
+        true
+    }
+
+    unsafe extern "C" fn receiver_add_plugin_id(
+        receiver: *const clap_preset_discovery_metadata_receiver,
+        plugin_id: *const clap_universal_plugin_id,
+    ) {
+        let presets = &mut *((*receiver).receiver_data as *mut Vec<DiscoveredPreset>);
+        if let Some(preset) = presets.last_mut() {
+            let id = CStr::from_ptr((*plugin_id).id)
+                .to_string_lossy()
+                .into_owned();
+            preset.plugin_ids.push(id);
+        }
+    }
+
+    unsafe extern "C" fn receiver_set_flags(
+        receiver: *const clap_preset_discovery_metadata_receiver,
+        flags: u32,
+    ) {
+        let presets = &mut *((*receiver).receiver_data as *mut Vec<DiscoveredPreset>);
+        if let Some(preset) = presets.last_mut() {
+            preset.flags = flags;
+        }
+    }
+
+    /// One parameter value change at a frame of the next block
+    #[derive(Clone, Copy, Debug, PartialEq)]
+    pub struct ParamEvent {
+        pub time: u32,
+        pub id: clap_id,
+        pub value: f64,
+    }
+
+    /// One MIDI message at a frame of the next block
+    pub struct MidiEvent {
+        pub time: u32,
+        pub data: [u8; 3],
+    }
+
+    // A block has either kind of event, so they're in time order
+    struct EventList {
+        params: Vec<clap_event_param_value>,
+        midi: Vec<clap_event_midi>,
+    }
+
+    unsafe extern "C" fn events_size(list: *const clap_input_events) -> u32 {
+        let list = &*((*list).ctx as *const EventList);
+        (list.params.len() + list.midi.len()) as u32
+    }
+
+    unsafe extern "C" fn events_get(
+        list: *const clap_input_events,
+        index: u32,
+    ) -> *const clap_event_header {
+        let list = &*((*list).ctx as *const EventList);
+        let index = index as usize;
+        match list.params.get(index) {
+            Some(event) => &event.header,
+            None => match list.midi.get(index - list.params.len()) {
+                Some(event) => &event.header,
+                None => ptr::null(),
+            },
+        }
+    }
+
+    // Collects the values the plugin reports
+    unsafe extern "C" fn events_try_push(
+        list: *const clap_output_events,
+        event: *const clap_event_header,
+    ) -> bool {
+        let reported = &mut *((*list).ctx as *mut Vec<ParamEvent>);
+        if (*event).type_ == CLAP_EVENT_PARAM_VALUE {
+            let event = &*(event as *const clap_event_param_value);
+            reported.push(ParamEvent {
+                time: event.header.time,
+                id: event.param_id,
+                value: event.value,
+            });
+        }
+        true
+    }
+
+    fn midi_events(events: &[MidiEvent]) -> Box<EventList> {
+        Box::new(EventList {
+            params: Vec::new(),
+            midi: events
+                .iter()
+                .map(|event| clap_event_midi {
+                    header: clap_event_header {
+                        size: std::mem::size_of::<clap_event_midi>() as u32,
+                        time: event.time,
+                        space_id: CLAP_CORE_EVENT_SPACE_ID,
+                        type_: CLAP_EVENT_MIDI,
+                        flags: 0,
+                    },
+                    port_index: 0,
+                    data: event.data,
+                })
+                .collect(),
+        })
+    }
+
+    fn input_events(events: &[ParamEvent]) -> Box<EventList> {
+        Box::new(EventList {
+            params: events
+                .iter()
+                .map(|event| clap_event_param_value {
+                    header: clap_event_header {
+                        size: std::mem::size_of::<clap_event_param_value>() as u32,
+                        time: event.time,
+                        space_id: CLAP_CORE_EVENT_SPACE_ID,
+                        type_: CLAP_EVENT_PARAM_VALUE,
+                        flags: 0,
+                    },
+                    param_id: event.id,
+                    cookie: ptr::null_mut(),
+                    note_id: -1,
+                    port_index: -1,
+                    channel: -1,
+                    key: -1,
+                    value: event.value,
+                })
+                .collect(),
+            midi: Vec::new(),
+        })
+    }
+
+    unsafe extern "C" fn stream_write(
+        stream: *const clap_ostream,
+        buffer: *const c_void,
+        size: u64,
+    ) -> i64 {
+        // Short writes, the plugin has to keep writing
+        let size = size.min(7) as usize;
+        let out = &mut *((*stream).ctx as *mut Vec<u8>);
+        out.extend_from_slice(std::slice::from_raw_parts(buffer as *const u8, size));
+        size as i64
+    }
+
+    unsafe extern "C" fn stream_read(
+        stream: *const clap_istream,
+        buffer: *mut c_void,
+        size: u64,
+    ) -> i64 {
+        let input = &mut *((*stream).ctx as *mut &[u8]);
+        let size = (size as usize).min(input.len()).min(5);
+        std::ptr::copy_nonoverlapping(input.as_ptr(), buffer as *mut u8, size);
+        *input = &input[size..];
+        size as i64
+    }
+
+    fn c_str_array(chars: &[c_char]) -> String {
+        let bytes: Vec<u8> = chars
+            .iter()
+            .take_while(|c| **c != 0)
+            .map(|c| *c as u8)
+            .collect();
+        String::from_utf8_lossy(&bytes).into_owned()
+    }
+
+    impl Host {
+        pub fn new(entry: &'static clap_plugin_entry) -> Host {
+            let data = Box::new(HostData {
+                rescans: AtomicUsize::new(0),
+                restarts: AtomicUsize::new(0),
+                latency_changes: AtomicUsize::new(0),
+                preset_loads: AtomicUsize::new(0),
+                preset_errors: AtomicUsize::new(0),
+            });
+            let clap = Box::new(clap_host {
+                clap_version: CLAP_VERSION,
+                host_data: &*data as *const HostData as *mut c_void,
+                name: b"test host\0".as_ptr() as *const c_char,
+                vendor: b"\0".as_ptr() as *const c_char,
+                url: b"\0".as_ptr() as *const c_char,
+                version: b"1\0".as_ptr() as *const c_char,
+                get_extension: Some(host_get_extension),
+                request_restart: Some(host_restart),
+                request_process: Some(host_request),
+                request_callback: Some(host_request),
+            });
+            unsafe {
+                assert!(entry.init.unwrap()(b"\0".as_ptr() as *const c_char));
+            }
+            Host { clap, data, entry }
+        }
+
+        pub fn rescans(&self) -> usize {
+            self.data.rescans.load(Ordering::Relaxed)
+        }
+
+        pub fn restarts(&self) -> usize {
+            self.data.restarts.load(Ordering::Relaxed)
+        }
+
+        pub fn latency_changes(&self) -> usize {
+            self.data.latency_changes.load(Ordering::Relaxed)
+        }
+
+        /// Presets the plugin loaded and failed to load, as it told the host
+        pub fn preset_loads(&self) -> (usize, usize) {
+            (
+                self.data.preset_loads.load(Ordering::Relaxed),
+                self.data.preset_errors.load(Ordering::Relaxed),
+            )
+        }
+
+        /// Indexes the plugin's presets the way a host's preset browser does, through the
+        /// preset discovery factory's only provider. The presets are those in the plugin
+        /// location, followed by the one in file if it's given.
+        pub fn index_presets(&self, file: Option<&Path>) -> PresetIndex {
+            let mut index = PresetIndex::default();
+            unsafe {
+                let factory =
+                    self.entry.get_factory.unwrap()(CLAP_PRESET_DISCOVERY_FACTORY_ID.as_ptr());
+                assert!(!factory.is_null());
+                let factory = &*(factory as *const clap_preset_discovery_factory);
+                assert_eq!(factory.count.unwrap()(factory), 1);
+                assert!(factory.get_descriptor.unwrap()(factory, 1).is_null());
+                let descriptor = &*factory.get_descriptor.unwrap()(factory, 0);
+                let indexer = clap_preset_discovery_indexer {
+                    clap_version: CLAP_VERSION,
+                    name: b"test host\0".as_ptr() as *const c_char,
+                    vendor: b"\0".as_ptr() as *const c_char,
+                    url: b"\0".as_ptr() as *const c_char,
+                    version: b"1\0".as_ptr() as *const c_char,
+                    indexer_data: &mut index as *mut PresetIndex as *mut c_void,
+                    declare_filetype: Some(indexer_declare_filetype),
+                    declare_location: Some(indexer_declare_location),
+                    declare_soundpack: None,
+                    get_extension: None,
+                };
+                let provider = factory.create.unwrap()(factory, &indexer, descriptor.id);
+                assert!(!provider.is_null());
+                assert!((*provider).init.unwrap()(provider));
+
+                let mut presets = Vec::new();
+                let receiver = clap_preset_discovery_metadata_receiver {
+                    receiver_data: &mut presets as *mut Vec<DiscoveredPreset> as *mut c_void,
+                    on_error: None,
+                    begin_preset: Some(receiver_begin_preset),
+                    add_plugin_id: Some(receiver_add_plugin_id),
+                    set_soundpack_id: None,
+                    set_flags: Some(receiver_set_flags),
+                    add_creator: None,
+                    set_description: None,
+                    set_timestamps: None,
+                    add_feature: None,
+                    add_extra_info: None,
+                };
+                let get_metadata = (*provider).get_metadata.unwrap();
+                assert!(get_metadata(
+                    provider,
+                    CLAP_PRESET_DISCOVERY_LOCATION_PLUGIN,
+                    ptr::null(),
+                    &receiver
+                ));
+                if let Some(file) = file {
+                    let file = c_string(&file.to_string_lossy());
+                    assert!(get_metadata(
+                        provider,
+                        CLAP_PRESET_DISCOVERY_LOCATION_FILE,
+                        file.as_ptr(),
+                        &receiver
+                    ));
+                }
+                (*provider).destroy.unwrap()(provider);
+                index.presets = presets;
+            }
+            index
+        }
+
+        fn factory(&self) -> &clap_plugin_factory {
+            unsafe {
+                let factory = self.entry.get_factory.unwrap()(CLAP_PLUGIN_FACTORY_ID.as_ptr());
+                assert!(!factory.is_null());
+                &*(factory as *const clap_plugin_factory)
+            }
+        }
+
+        pub fn descriptor(&self) -> &clap_plugin_descriptor {
+            let factory = self.factory();
+            unsafe {
+                assert_eq!(factory.get_plugin_count.unwrap()(factory), 1);
+                assert!(factory.get_plugin_descriptor.unwrap()(factory, 1).is_null());
+                &*factory.get_plugin_descriptor.unwrap()(factory, 0)
+            }
+        }
+
+        pub fn create(&self) -> Instance {
+            let factory = self.factory();
+            unsafe {
+                let plugin =
+                    factory.create_plugin.unwrap()(factory, &*self.clap, self.descriptor().id);
+                assert!(!plugin.is_null());
+                assert!((*plugin).init.unwrap()(plugin));
+                let extension = |id: &CStr| {
+                    let extension = (*plugin).get_extension.unwrap()(plugin, id.as_ptr());
+                    assert!(!extension.is_null(), "missing {:?}", id);
+                    extension
+                };
+                Instance {
+                    plugin,
+                    params: &*(extension(CLAP_EXT_PARAMS) as *const clap_plugin_params),
+                    state: &*(extension(CLAP_EXT_STATE) as *const clap_plugin_state),
+                    audio_ports: &*(extension(CLAP_EXT_AUDIO_PORTS)
+                        as *const clap_plugin_audio_ports),
+                    note_ports: &*(extension(CLAP_EXT_NOTE_PORTS) as *const clap_plugin_note_ports),
+                    latency: &*(extension(CLAP_EXT_LATENCY) as *const clap_plugin_latency),
+                    preset_load: &*(extension(CLAP_EXT_PRESET_LOAD)
+                        as *const clap_plugin_preset_load),
+                }
+            }
+        }
+    }
+
+    impl Drop for Host {
+        fn drop(&mut self) {
+            unsafe { self.entry.deinit.unwrap()() }
+        }
+    }
+
+    impl Instance {
+        pub fn param_infos(&self) -> Vec<clap_param_info> {
+            unsafe {
+                let count = self.params.count.unwrap()(self.plugin);
+                (0..count)
+                    .map(|i| {
+                        let mut info: clap_param_info = std::mem::zeroed();
+                        assert!(self.params.get_info.unwrap()(self.plugin, i, &mut info));
+                        info
+                    })
+                    .collect()
+            }
+        }
+
+        pub fn latency(&self) -> u32 {
+            unsafe { self.latency.get.unwrap()(self.plugin) }
+        }
+
+        pub fn value(&self, id: clap_id) -> f64 {
+            let mut value = 0.0;
+            unsafe {
+                assert!(self.params.get_value.unwrap()(self.plugin, id, &mut value));
+            }
+            value
+        }
+
+        pub fn activate(&self) {
+            unsafe {
+                assert!((*self.plugin).activate.unwrap()(
+                    self.plugin,
+                    SAMPLE_RATE,
+                    1,
+                    MAX_FRAMES
+                ));
+                assert!((*self.plugin).start_processing.unwrap()(self.plugin));
+            }
+        }
+
+        pub fn deactivate(&self) {
+            unsafe {
+                (*self.plugin).stop_processing.unwrap()(self.plugin);
+                (*self.plugin).deactivate.unwrap()(self.plugin);
+            }
+        }
+
+        /// Processes the buffers in place with events applied, returns the values the plugin
+        /// reported changing itself
+        pub fn process(
+            &self,
+            buffers: &mut [Vec<f32>; 2],
+            events: &[ParamEvent],
+        ) -> Vec<ParamEvent> {
+            self.run(buffers, &input_events(events))
+        }
+
+        pub fn process_midi(
+            &self,
+            buffers: &mut [Vec<f32>; 2],
+            events: &[MidiEvent],
+        ) -> Vec<ParamEvent> {
+            self.run(buffers, &midi_events(events))
+        }
+
+        fn run(&self, buffers: &mut [Vec<f32>; 2], events: &EventList) -> Vec<ParamEvent> {
+            let mut reported = Vec::new();
+            let in_events = clap_input_events {
+                ctx: events as *const EventList as *mut c_void,
+                size: Some(events_size),
+                get: Some(events_get),
+            };
+            let out_events = clap_output_events {
+                ctx: &mut reported as *mut Vec<ParamEvent> as *mut c_void,
+                try_push: Some(events_try_push),
+            };
+            let frames = buffers[0].len();
+            let mut channels = [buffers[0].as_mut_ptr(), buffers[1].as_mut_ptr()];
+            let audio_input = clap_audio_buffer {
+                data32: channels.as_mut_ptr(),
+                data64: ptr::null_mut(),
+                channel_count: 2,
+                latency: 0,
+                constant_mask: 0,
+            };
+            let mut audio_output = audio_input;
+            let process = clap_process {
+                steady_time: -1,
+                frames_count: frames as u32,
+                transport: ptr::null(),
+                audio_inputs: &audio_input,
+                audio_outputs: &mut audio_output,
+                audio_inputs_count: 1,
+                audio_outputs_count: 1,
+                in_events: &in_events,
+                out_events: &out_events,
+            };
+            unsafe {
+                assert_eq!(
+                    (*self.plugin).process.unwrap()(self.plugin, &process),
+                    CLAP_PROCESS_CONTINUE
+                );
+            }
+            reported
+        }
+
+        pub fn flush(&self, events: &[ParamEvent]) {
+            let events = input_events(events);
+            let in_events = clap_input_events {
+                ctx: &*events as *const EventList as *mut c_void,
+                size: Some(events_size),
+                get: Some(events_get),
+            };
+            unsafe { self.params.flush.unwrap()(self.plugin, &in_events, ptr::null()) }
+        }
+
+        pub fn save(&self) -> Vec<u8> {
+            let mut saved = Vec::new();
+            let stream = clap_ostream {
+                ctx: &mut saved as *mut Vec<u8> as *mut c_void,
+                write: Some(stream_write),
+            };
+            unsafe { assert!(self.state.save.unwrap()(self.plugin, &stream)) };
+            saved
+        }
+
+        /// Loads a factory preset by its load key, or the user preset in file
+        pub fn load_preset(&self, load_key: Option<&str>, file: Option<&Path>) -> bool {
+            let kind = if file.is_some() {
+                CLAP_PRESET_DISCOVERY_LOCATION_FILE
+            } else {
+                CLAP_PRESET_DISCOVERY_LOCATION_PLUGIN
+            };
+            let file = file.map(|file| c_string(&file.to_string_lossy()));
+            let load_key = load_key.map(c_string);
+            unsafe {
+                self.preset_load.from_location.unwrap()(
+                    self.plugin,
+                    kind,
+                    file.as_ref().map_or(ptr::null(), |file| file.as_ptr()),
+                    load_key.as_ref().map_or(ptr::null(), |key| key.as_ptr()),
+                )
+            }
+        }
+
+        pub fn load(&self, saved: &[u8]) -> bool {
+            let mut input = saved;
+            let stream = clap_istream {
+                ctx: &mut input as *mut &[u8] as *mut c_void,
+                read: Some(stream_read),
+            };
+            unsafe { self.state.load.unwrap()(self.plugin, &stream) }
+        }
+    }
+
+    impl Drop for Instance {
+        fn drop(&mut self) {
+            unsafe { (*self.plugin).destroy.unwrap()(self.plugin) }
+        }
+    }
+
+    fn noise(frames: usize, seed: &mut u32) -> Vec<f32> {
+        (0..frames)
+            .map(|_| {
+                *seed = seed.wrapping_mul(1664525).wrapping_add(1013904223);
+                (*seed as f32 / u32::MAX as f32) * 2.0 - 1.0
+            })
+            .collect()
+    }
+
+    /// Checks the exported plugin the way a CLAP validator would: descriptor, ports, parameter
+    /// info and text, automation inside and past the end of a block, state save and load, MIDI
+    /// learn, latency and presets. Returns the host for plugin specific checks.
+    pub fn validate<P>(entry: &'static clap_plugin_entry) -> Host
+    where
+        P: ClapExport,
+        P::Model: FactoryPresets + VersionedState + Clone,
+    {
+        let host = Host::new(entry);
+        let descriptor = host.descriptor();
+        unsafe {
+            assert_eq!(CStr::from_ptr(descriptor.name).to_str().unwrap(), P::NAME);
+            let mut features = Vec::new();
+            let mut feature = descriptor.features;
+            while !(*feature).is_null() {
+                features.push(CStr::from_ptr(*feature));
+                feature = feature.add(1);
+            }
+            assert!(features.contains(&CLAP_PLUGIN_FEATURE_AUDIO_EFFECT));
+        }
+
+        let instance = host.create();
+        for &is_input in [true, false].iter() {
+            unsafe {
+                assert_eq!(
+                    instance.audio_ports.count.unwrap()(instance.plugin, is_input),
+                    1
+                );
+                let mut info: clap_audio_port_info = std::mem::zeroed();
+                assert!(instance.audio_ports.get.unwrap()(
+                    instance.plugin,
+                    0,
+                    is_input,
+                    &mut info
+                ));
+                assert_eq!(info.channel_count, 2);
+                assert_eq!(CStr::from_ptr(info.port_type), CLAP_PORT_STEREO);
+            }
+        }
+
+        unsafe {
+            assert_eq!(instance.note_ports.count.unwrap()(instance.plugin, true), 1);
+            assert_eq!(
+                instance.note_ports.count.unwrap()(instance.plugin, false),
+                0
+            );
+            let mut info: clap_note_port_info = std::mem::zeroed();
+            assert!(instance.note_ports.get.unwrap()(
+                instance.plugin,
+                0,
+                true,
+                &mut info
+            ));
+            assert_ne!(info.supported_dialects & CLAP_NOTE_DIALECT_MIDI, 0);
+        }
+
+        let infos = instance.param_infos();
+        assert!(!infos.is_empty());
+        assert_eq!(infos.last().unwrap().id, LEARN_PARAM_ID);
+        let mut ids: Vec<clap_id> = infos.iter().map(|info| info.id).collect();
+        ids.sort_unstable();
+        ids.dedup();
+        assert_eq!(ids.len(), infos.len(), "parameter ids aren't unique");
+        for info in infos.iter() {
+            let name = c_str_array(&info.name);
+            assert!(!name.is_empty());
+            assert!(info.min_value < info.max_value, "{}", name);
+            assert!(
+                info.min_value <= info.default_value && info.default_value <= info.max_value,
+                "{} default {} is out of range",
+                name,
+                info.default_value
+            );
+            assert_eq!(instance.value(info.id), info.default_value, "{}", name);
+
+            let mut text = [0 as c_char; 64];
+            let mut parsed = 0.0;
+            unsafe {
+                assert!(instance.params.value_to_text.unwrap()(
+                    instance.plugin,
+                    info.id,
+                    info.max_value,
+                    text.as_mut_ptr(),
+                    text.len() as u32
+                ));
+                assert!(instance.params.text_to_value.unwrap()(
+                    instance.plugin,
+                    info.id,
+                    text.as_ptr(),
+                    &mut parsed
+                ));
+            }
+            assert!(
+                (parsed - info.max_value).abs() <= 0.005 * info.max_value.abs().max(1.0),
+                "{} text {:?} parsed as {}",
+                name,
+                c_str_array(&text),
+                parsed
+            );
+        }
+
+        // The host's bypass switch is the plugin's bypass parameter, if it has one
+        let bypass: Vec<&clap_param_info> = infos
+            .iter()
+            .filter(|info| info.flags & CLAP_PARAM_IS_BYPASS != 0)
+            .collect();
+        assert!(bypass.len() <= 1);
+        assert_eq!(bypass.first().map(|info| info.id), P::BYPASS_PARAM);
+        if let Some(info) = bypass.first() {
+            assert_ne!(info.flags & CLAP_PARAM_IS_STEPPED, 0);
+            assert_eq!((info.min_value, info.max_value), (0.0, 1.0));
+            assert_eq!(info.default_value, 0.0);
+        }
+
+        // MIDI Learn is the only parameter the host doesn't automate
+        let automatable: Vec<&clap_param_info> = infos
+            .iter()
+            .filter(|info| info.flags & CLAP_PARAM_IS_AUTOMATABLE != 0)
+            .collect();
+        assert_eq!(automatable.len(), infos.len() - 1);
+
+        // Every parameter automated to its max partway through a block, then back to its
+        // default at a time past the end of the next one
+        instance.activate();
+        let mut seed = 1;
+        for block in 0..8 {
+            let events: Vec<ParamEvent> = automatable
+                .iter()
+                .enumerate()
+                .map(|(i, info)| match block {
+                    2 => ParamEvent {
+                        time: 100 + i as u32 * 37,
+                        id: info.id,
+                        value: info.max_value,
+                    },
+                    3 => ParamEvent {
+                        time: MAX_FRAMES * 2,
+                        id: info.id,
+                        value: info.default_value,
+                    },
+                    _ => ParamEvent {
+                        time: 0,
+                        id: info.id,
+                        value: info.min_value + (info.max_value - info.min_value) * 0.25,
+                    },
+                })
+                .filter(|_| block == 2 || block == 3 || block == 6)
+                .collect();
+            let frames = if block == 5 { 7 } else { MAX_FRAMES as usize };
+            let mut buffers = [noise(frames, &mut seed), noise(frames, &mut seed)];
+            instance.process(&mut buffers, &events);
+            for x in buffers.iter().flatten() {
+                assert!(x.is_finite(), "block {} output {}", block, x);
+            }
+            for info in automatable.iter() {
+                let expected = match block {
+                    2 => info.max_value,
+                    3..=5 => info.default_value,
+                    _ => continue,
+                };
+                assert_eq!(instance.value(info.id), expected);
+            }
+        }
+
+        // State carries every parameter, the loaded values are on the next process
+        let events: Vec<ParamEvent> = automatable
+            .iter()
+            .map(|info| ParamEvent {
+                time: 0,
+                id: info.id,
+                value: info.min_value + (info.max_value - info.min_value) * 0.75,
+            })
+            .collect();
+        instance.flush(&events);
+        let saved = instance.save();
+        let loaded = host.create();
+        let rescans = host.rescans();
+        assert!(loaded.load(&saved));
+        assert_eq!(host.rescans(), rescans + 1);
+        for info in automatable.iter() {
+            let expected = instance.value(info.id);
+            let value = loaded.value(info.id);
+            assert!(
+                (value - expected).abs() <= 1e-4 * expected.abs().max(1.0),
+                "{} loaded as {}, saved {}",
+                c_str_array(&info.name),
+                value,
+                expected
+            );
+        }
+        assert!(!loaded.load(b"{ not state"));
+        instance.deactivate();
+        loaded.activate();
+        let mut buffers = [noise(256, &mut seed), noise(256, &mut seed)];
+        loaded.process(&mut buffers, &[]);
+        loaded.deactivate();
+        drop(loaded);
+
+        // MIDI Learn maps the next controller to the first parameter, which it then sets. The
+        // host is told about both changes and the mapping is saved with the state.
+        let first = automatable[0];
+        let learn = ParamEvent {
+            time: 0,
+            id: LEARN_PARAM_ID,
+            value: 1.0,
+        };
+        instance.flush(&[learn]);
+        instance.activate();
+        let cc = MidiEvent {
+            time: 10,
+            data: [0xb0, 74, 127],
+        };
+        let mut buffers = [noise(256, &mut seed), noise(256, &mut seed)];
+        let reported = instance.process_midi(&mut buffers, &[cc]);
+        assert_eq!(instance.value(first.id), first.max_value);
+        assert_eq!(instance.value(LEARN_PARAM_ID), 0.0);
+        let learned = ParamEvent {
+            time: 10,
+            id: LEARN_PARAM_ID,
+            value: 0.0,
+        };
+        let set = ParamEvent {
+            time: 10,
+            id: first.id,
+            value: first.max_value,
+        };
+        assert_eq!(reported, vec![learned, set]);
+        instance.deactivate();
+
+        let loaded = host.create();
+        assert!(loaded.load(&instance.save()));
+        loaded.activate();
+        let cc = MidiEvent {
+            time: 0,
+            data: [0xb0, 74, 0],
+        };
+        loaded.process_midi(&mut buffers, &[cc]);
+        assert_eq!(loaded.value(first.id), first.min_value);
+        loaded.deactivate();
+        drop(loaded);
+        drop(instance);
+
+        // Latency is reported on activate. If a parameter changes it, the host is asked to
+        // restart the plugin and told the new latency when it's activated again.
+        let latent = host.create();
+        latent.activate();
+        let latency = P::latency(&P::Model::default(), SAMPLE_RATE as f32);
+        assert_eq!(latent.latency(), latency);
+        let change = P::clap_params().into_iter().find_map(|param| {
+            let mut model = P::Model::default();
+            (param.set)(&mut model, param.max);
+            let changed = P::latency(&model, SAMPLE_RATE as f32);
+            Some((param, changed)).filter(|_| changed != latency)
+        });
+        if let Some((param, changed)) = change {
+            let restarts = host.restarts();
+            let event = ParamEvent {
+                time: 0,
+                id: param.id,
+                value: param.max,
+            };
+            latent.process(&mut buffers, &[event]);
+            latent.process(&mut buffers, &[]);
+            assert_eq!(host.restarts(), restarts + 1);
+            assert_eq!(latent.latency(), latency);
+            latent.deactivate();
+            let latency_changes = host.latency_changes();
+            latent.activate();
+            assert_eq!(latent.latency(), changed);
+            assert_eq!(host.latency_changes(), latency_changes + 1);
+        }
+        latent.deactivate();
+        drop(latent);
+
+        // Factory presets are found through preset discovery and loaded by their load key,
+        // user presets by their file. The host is told about each load and reads the values
+        // again.
+        let dir =
+            std::env::temp_dir().join(format!("clap-presets-{}-{}", P::NAME, std::process::id()));
+        let mut bank = PresetBank::<P::Model>::new();
+        let params = P::clap_params();
+        let mut user = P::Model::default();
+        for param in params.iter() {
+            (param.set)(
+                &mut user,
+                param.clamp(param.min + (param.max - param.min) * 0.75),
+            );
+        }
+        let path = bank.save_user_preset(&dir, "Test", &user).unwrap();
+        let index = host.index_presets(Some(&path));
+        assert_eq!(index.extensions, vec![presets::PRESET_EXTENSION]);
+        assert!(index.locations.contains(&(
+            CLAP_PRESET_DISCOVERY_LOCATION_PLUGIN,
+            CLAP_PRESET_DISCOVERY_IS_FACTORY_CONTENT
+        )));
+        let mut expected: Vec<(String, Option<String>, u32)> = (0..bank.factory_count())
+            .map(|i| {
+                let name = bank.program_name(i).unwrap().to_string();
+                (
+                    name,
+                    Some(i.to_string()),
+                    CLAP_PRESET_DISCOVERY_IS_FACTORY_CONTENT,
+                )
+            })
+            .collect();
+        expected.push((
+            "Test".to_string(),
+            None,
+            CLAP_PRESET_DISCOVERY_IS_USER_CONTENT,
+        ));
+        let found: Vec<(String, Option<String>, u32)> = index
+            .presets
+            .iter()
+            .map(|preset| (preset.name.clone(), preset.load_key.clone(), preset.flags))
+            .collect();
+        assert_eq!(found, expected);
+        assert!(index
+            .presets
+            .iter()
+            .all(|preset| preset.plugin_ids == [P::CLAP_ID]));
+
+        let instance = host.create();
+        for (i, preset) in index.presets.iter().enumerate() {
+            let rescans = host.rescans();
+            let file = Some(path.as_path()).filter(|_| preset.load_key.is_none());
+            assert!(instance.load_preset(preset.load_key.as_deref(), file));
+            assert_eq!(host.rescans(), rescans + 1);
+            let Preset { name, model } = bank.get(i).unwrap();
+            for param in params.iter() {
+                let expected = param.clamp((param.get)(model));
+                let value = instance.value(param.id);
+                assert!(
+                    (value - expected).abs() <= 1e-4 * expected.abs().max(1.0),
+                    "{} {} loaded as {}, expected {}",
+                    name,
+                    param.name,
+                    value,
+                    expected
+                );
+            }
+        }
+        assert_eq!(host.preset_loads(), (index.presets.len(), 0));
+        assert!(!instance.load_preset(Some("none"), None));
+        assert!(!instance.load_preset(None, Some(&dir.join("missing.json"))));
+        assert_eq!(host.preset_loads(), (index.presets.len(), 2));
+        drop(instance);
+        std::fs::remove_dir_all(&dir).unwrap();
+        host
+    }
+}
@@ -0,0 +1,512 @@
+// Level detectors for metering and dynamics: RMS over a window or a time constant, peak with
+// hold, true peak and BS.1770 loudness.
+
+use std::f64::consts::PI;
+
+use crate::ring::VariableRingBuffer;
+
+// Loudness windows from ITU-R BS.1770
+pub const MOMENTARY_MS: f64 = 400.0;
+pub const SHORT_TERM_MS: f64 = 3000.0;
+// Returned by Loudness when the window is silent, instead of -inf
+pub const LOUDNESS_FLOOR: f64 = -120.0;
+
+const TRUE_PEAK_OVERSAMPLING: usize = 4;
+const TRUE_PEAK_TAPS: usize = 16;
+
+/// Mean of the last size values.
+/// The running sum is added to and subtracted from every sample, which on its own drifts and
+/// can go negative over a long session. A second sum is started fresh every window, once it
+/// covers a whole window it replaces the running sum, so error never builds up past one window.
+struct WindowedMean {
+    buffer: VariableRingBuffer<f64>,
+    sum: f64,
+    fresh_sum: f64,
+    fresh_count: usize,
+}
+
+impl WindowedMean {
+    fn new(size: usize, max_size: usize) -> WindowedMean {
+        WindowedMean {
+            buffer: VariableRingBuffer::new(size, max_size),
+            sum: 0.0,
+            fresh_sum: 0.0,
+            fresh_count: 0,
+        }
+    }
+
+    fn size(&self) -> usize {
+        self.buffer.size()
+    }
+
+    fn resize(&mut self, size: usize) {
+        self.buffer.resize(size);
+        self.buffer.clear();
+        self.sum = 0.0;
+        self.fresh_sum = 0.0;
+        self.fresh_count = 0;
+    }
+
+    fn process(&mut self, value: f64) -> f64 {
+        //remove the oldest value, add new one
+        self.sum += value - self.buffer.oldest();
+        self.buffer.push(value);
+
+        self.fresh_sum += value;
+        self.fresh_count += 1;
+        if self.fresh_count >= self.buffer.size() {
+            self.sum = self.fresh_sum;
+            self.fresh_sum = 0.0;
+            self.fresh_count = 0;
+        }
+        self.sum.max(0.0) / self.buffer.size() as f64
+    }
+}
+
+/// RMS over a sliding window of rms_size_ms
+pub struct AccumulatingRMS {
+    mean: WindowedMean,
+}
+
+impl AccumulatingRMS {
+    pub fn new(sample_rate: usize, rms_size_ms: f32, rms_max_size_samp: usize) -> AccumulatingRMS {
+        AccumulatingRMS {
+            mean: WindowedMean::new(
+                ((sample_rate as f32) * (rms_size_ms / 1000.0)) as usize,
+                rms_max_size_samp,
+            ),
+        }
+    }
+    pub fn resize(&mut self, sample_rate: usize, rms_size_ms: f32) {
+        let new_size = (((sample_rate as f32) * (rms_size_ms / 1000.0)) as usize).max(1);
+        if new_size != self.mean.size() {
+            self.mean.resize(new_size);
+        }
+    }
+    pub fn process(&mut self, value: f32) -> f32 {
+        let value = value as f64;
+        self.mean.process(value * value).sqrt() as f32
+    }
+}
+
+/// RMS from a one pole average of the squared signal, time_ms is the time constant
+pub struct ExponentialRMS {
+    mean_square: f64,
+    coeff: f64,
+}
+
+impl ExponentialRMS {
+    pub fn new(sample_rate: f64, time_ms: f64) -> ExponentialRMS {
+        let mut rms = ExponentialRMS {
+            mean_square: 0.0,
+            coeff: 0.0,
+        };
+        rms.set_time(sample_rate, time_ms);
+        rms
+    }
+
+    pub fn set_time(&mut self, sample_rate: f64, time_ms: f64) {
+        self.coeff = (-1000.0 / (time_ms.max(0.001) * sample_rate)).exp();
+    }
+
+    pub fn process(&mut self, value: f64) -> f64 {
+        self.mean_square = value * value + self.coeff * (self.mean_square - value * value);
+        self.mean_square.max(0.0).sqrt()
+    }
+}
+
+/// Peak level that holds for hold_ms after each new peak, then falls with a release_ms time
+/// constant
+pub struct PeakHold {
+    peak: f64,
+    hold_remaining: usize,
+    hold_samples: usize,
+    release: f64,
+}
+
+impl PeakHold {
+    pub fn new(sample_rate: f64, hold_ms: f64, release_ms: f64) -> PeakHold {
+        let mut peak = PeakHold {
+            peak: 0.0,
+            hold_remaining: 0,
+            hold_samples: 0,
+            release: 0.0,
+        };
+        peak.set_times(sample_rate, hold_ms, release_ms);
+        peak
+    }
+
+    pub fn set_times(&mut self, sample_rate: f64, hold_ms: f64, release_ms: f64) {
+        self.hold_samples = (sample_rate * hold_ms.max(0.0) / 1000.0) as usize;
+        self.release = (-1000.0 / (release_ms.max(0.001) * sample_rate)).exp();
+    }
+
+    pub fn process(&mut self, value: f64) -> f64 {
+        let value = value.abs();
+        if value >= self.peak {
+            self.peak = value;
+            self.hold_remaining = self.hold_samples;
+        } else if self.hold_remaining > 0 {
+            self.hold_remaining -= 1;
+        } else {
+            self.peak = value + self.release * (self.peak - value);
+        }
+        self.peak
+    }
+}
+
+/// Inter-sample peak level, found by 4x oversampling with a windowed sinc interpolator.
+/// The result is delayed by half the interpolator length.
+pub struct TruePeak {
+    // Written twice so the last TRUE_PEAK_TAPS values are always one contiguous slice
+    history: [f64; TRUE_PEAK_TAPS * 2],
+    position: usize,
+    phases: [[f64; TRUE_PEAK_TAPS]; TRUE_PEAK_OVERSAMPLING],
+}
+
+impl Default for TruePeak {
+    fn default() -> Self {
+        TruePeak::new()
+    }
+}
+
+impl TruePeak {
+    pub fn new() -> TruePeak {
+        let mut phases = [[0.0; TRUE_PEAK_TAPS]; TRUE_PEAK_OVERSAMPLING];
+        let half = (TRUE_PEAK_TAPS / 2) as f64;
+        for (phase, taps) in phases.iter_mut().enumerate() {
+            // Interpolates between the two middle taps, phase 0 lands on the earlier one
+            let center = half - 1.0 + phase as f64 / TRUE_PEAK_OVERSAMPLING as f64;
+            for (k, tap) in taps.iter_mut().enumerate() {
+                let t = k as f64 - center;
+                let sinc = if t == 0.0 {
+                    1.0
+                } else {
+                    (PI * t).sin() / (PI * t)
+                };
+                // Blackman window, zero at +-half
+                let window =
+                    0.42 + 0.5 * (PI * t / half).cos() + 0.08 * (2.0 * PI * t / half).cos();
+                *tap = sinc * window;
+            }
+            let sum: f64 = taps.iter().sum();
+            for tap in taps.iter_mut() {
+                *tap /= sum;
+            }
+        }
+        TruePeak {
+            history: [0.0; TRUE_PEAK_TAPS * 2],
+            position: 0,
+            phases,
+        }
+    }
+
+    pub fn process(&mut self, value: f64) -> f64 {
+        self.history[self.position] = value;
+        self.history[self.position + TRUE_PEAK_TAPS] = value;
+        self.position = (self.position + 1) % TRUE_PEAK_TAPS;
+
+        // Oldest first
+        let history = &self.history[self.position..self.position + TRUE_PEAK_TAPS];
+        let mut peak = 0.0f64;
+        for taps in self.phases.iter() {
+            let y: f64 = taps.iter().zip(history).map(|(tap, x)| tap * x).sum();
+            peak = peak.max(y.abs());
+        }
+        peak
+    }
+}
+
+/// Transposed direct form II biquad, a0 normalized to 1
+#[derive(Clone, Copy, Debug)]
+pub struct Biquad {
+    b0: f64,
+    b1: f64,
+    b2: f64,
+    a1: f64,
+    a2: f64,
+    z1: f64,
+    z2: f64,
+}
+
+impl Biquad {
+    pub fn new(b0: f64, b1: f64, b2: f64, a1: f64, a2: f64) -> Biquad {
+        Biquad {
+            b0,
+            b1,
+            b2,
+            a1,
+            a2,
+            z1: 0.0,
+            z2: 0.0,
+        }
+    }
+
+    pub fn process(&mut self, x: f64) -> f64 {
+        let y = self.b0 * x + self.z1;
+        self.z1 = self.b1 * x - self.a1 * y + self.z2;
+        self.z2 = self.b2 * x - self.a2 * y;
+        y
+    }
+}
+
+/// BS.1770 K-weighting, a high shelf followed by a high pass.
+/// Calculated from the analog prototype so it works at any sample rate,
+/// at 48kHz it matches the coefficients given in the standard.
+#[derive(Clone, Copy, Debug)]
+pub struct KWeighting {
+    shelf: Biquad,
+    high_pass: Biquad,
+}
+
+impl KWeighting {
+    pub fn new(sample_rate: f64) -> KWeighting {
+        let f0 = 1681.974450955533;
+        let gain_db = 3.999843853973347;
+        let q = 0.7071752369554196;
+        let k = (PI * f0 / sample_rate).tan();
+        let vh = 10.0f64.powf(gain_db / 20.0);
+        let vb = vh.powf(0.4996667741545416);
+        let a0 = 1.0 + k / q + k * k;
+        let shelf = Biquad::new(
+            (vh + vb * k / q + k * k) / a0,
+            2.0 * (k * k - vh) / a0,
+            (vh - vb * k / q + k * k) / a0,
+            2.0 * (k * k - 1.0) / a0,
+            (1.0 - k / q + k * k) / a0,
+        );
+
+        let f0 = 38.13547087602444;
+        let q = 0.5003270373238773;
+        let k = (PI * f0 / sample_rate).tan();
+        let a0 = 1.0 + k / q + k * k;
+        let high_pass = Biquad::new(
+            1.0,
+            -2.0,
+            1.0,
+            2.0 * (k * k - 1.0) / a0,
+            (1.0 - k / q + k * k) / a0,
+        );
+
+        KWeighting { shelf, high_pass }
+    }
+
+    pub fn process(&mut self, x: f64) -> f64 {
+        self.high_pass.process(self.shelf.process(x))
+    }
+}
+
+/// Stereo K-weighted loudness in LUFS over a sliding window,
+/// use MOMENTARY_MS or SHORT_TERM_MS for the standard meters. There's no gating.
+pub struct Loudness {
+    filters: [KWeighting; 2],
+    windows: [WindowedMean; 2],
+}
+
+impl Loudness {
+    pub fn new(sample_rate: f64, window_ms: f64) -> Loudness {
+        let size = ((sample_rate * window_ms / 1000.0) as usize).max(1);
+        Loudness {
+            filters: [KWeighting::new(sample_rate); 2],
+            windows: [WindowedMean::new(size, size), WindowedMean::new(size, size)],
+        }
+    }
+
+    pub fn process(&mut self, l: f64, r: f64) -> f64 {
+        let mut sum = 0.0;
+        for (i, x) in [l, r].iter().enumerate() {
+            let y = self.filters[i].process(*x);
+            sum += self.windows[i].process(y * y);
+        }
+        if sum > 0.0 {
+            (-0.691 + 10.0 * sum.log10()).max(LOUDNESS_FLOOR)
+        } else {
+            LOUDNESS_FLOOR
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Long runs use a low sample rate so hours of signal stay quick to simulate
+    const LONG_RATE: usize = 500;
+    const HOURS: usize = 2;
+
+    struct Noise(u32);
+
+    impl Noise {
+        fn next(&mut self) -> f64 {
+            self.0 = self.0.wrapping_mul(1664525).wrapping_add(1013904223);
+            (self.0 as f64 / u32::MAX as f64) * 2.0 - 1.0
+        }
+    }
+
+    // Alternates a minute of loud noise with a minute of quiet noise
+    fn session_sample(noise: &mut Noise, n: usize, rate: usize) -> f64 {
+        let level = if (n / (rate * 60)) % 2 == 0 {
+            1.0
+        } else {
+            0.001
+        };
+        noise.next() * level
+    }
+
+    #[test]
+    fn test_accumulating_rms_over_hours() {
+        // 200ms window
+        let window = LONG_RATE / 5;
+        let mut rms = AccumulatingRMS::new(LONG_RATE, 200.0, window);
+        let mut history = VariableRingBuffer::<f64>::new(window, window);
+        let mut noise = Noise(1);
+        for n in 0..LONG_RATE * 3600 * HOURS {
+            let x = session_sample(&mut noise, n, LONG_RATE) as f32;
+            let value = rms.process(x);
+            history.push((x as f64) * (x as f64));
+            assert!(value.is_finite() && value >= 0.0);
+            if n % 100_003 == 0 {
+                let exact = ((0..window).map(|d| history.read(d)).sum::<f64>() / window as f64)
+                    .sqrt() as f32;
+                assert!((value - exact).abs() <= exact * 1e-5 + 1e-9);
+            }
+        }
+        // A full window of silence reads exactly zero
+        for _ in 0..window {
+            rms.process(0.0);
+        }
+        assert_eq!(rms.process(0.0), 0.0);
+    }
+
+    #[test]
+    fn test_accumulating_rms_resize() {
+        let mut rms = AccumulatingRMS::new(1000, 10.0, 100);
+        for _ in 0..10 {
+            rms.process(1.0);
+        }
+        assert!((rms.process(1.0) - 1.0).abs() < 1e-6);
+        rms.resize(1000, 20.0);
+        // Half of the new window has been filled
+        for _ in 0..9 {
+            rms.process(1.0);
+        }
+        assert!((rms.process(1.0) - 0.5f32.sqrt()).abs() < 1e-6);
+    }
+
+    #[test]
+    fn test_exponential_rms_over_hours() {
+        let mut rms = ExponentialRMS::new(LONG_RATE as f64, 300.0);
+        for n in 0..LONG_RATE * 3600 * HOURS {
+            // Sine at a quarter of the sample rate, 0.5 amplitude
+            let x = [0.0, 0.5, 0.0, -0.5][n % 4];
+            let value = rms.process(x);
+            assert!(value.is_finite() && value >= 0.0);
+        }
+        assert!((rms.process(0.0) - 0.5 / 2.0f64.sqrt()).abs() < 0.01);
+    }
+
+    #[test]
+    fn test_peak_hold() {
+        let mut peak = PeakHold::new(1000.0, 10.0, 100.0);
+        assert_eq!(peak.process(-0.5), 0.5);
+        for _ in 0..10 {
+            assert_eq!(peak.process(0.1), 0.5);
+        }
+        assert!(peak.process(0.1) < 0.5);
+        for _ in 0..2000 {
+            peak.process(0.1);
+        }
+        assert!((peak.process(0.1) - 0.1).abs() < 1e-6);
+        assert_eq!(peak.process(0.8), 0.8);
+    }
+
+    #[test]
+    fn test_peak_hold_over_hours() {
+        let mut peak = PeakHold::new(LONG_RATE as f64, 500.0, 1000.0);
+        let mut noise = Noise(2);
+        for n in 0..LONG_RATE * 3600 * HOURS {
+            let x = session_sample(&mut noise, n, LONG_RATE);
+            let value = peak.process(x);
+            assert!(value >= x.abs() && value <= 1.0);
+        }
+    }
+
+    #[test]
+    fn test_true_peak() {
+        let mut true_peak = TruePeak::new();
+        let mut sample_peak = 0.0f64;
+        let mut max = 0.0f64;
+        for n in 0..1000 {
+            // Quarter sample rate sine sampled 45 degrees off its peaks
+            let x = (PI * 0.5 * n as f64 + PI * 0.25).sin();
+            sample_peak = sample_peak.max(x.abs());
+            max = max.max(true_peak.process(x));
+        }
+        assert!((sample_peak - 0.5f64.sqrt()).abs() < 1e-9);
+        // Within 0.2dB, BS.1770 allows more for 4x oversampling
+        assert!((max - 1.0).abs() < 0.025);
+    }
+
+    #[test]
+    fn test_true_peak_over_hours() {
+        let mut true_peak = TruePeak::new();
+        let mut noise = Noise(3);
+        // Delayed by half the interpolator, and overshoots between samples by a bounded amount
+        let mut input = VariableRingBuffer::<f64>::new(TRUE_PEAK_TAPS, TRUE_PEAK_TAPS);
+        for n in 0..LONG_RATE * 3600 {
+            let x = session_sample(&mut noise, n, LONG_RATE);
+            input.push(x);
+            let value = true_peak.process(x);
+            assert!(value.is_finite());
+            assert!(value >= input.read(TRUE_PEAK_TAPS / 2).abs() - 1e-12);
+            assert!(value < 4.0);
+        }
+    }
+
+    #[test]
+    fn test_k_weighting_coefficients() {
+        // From ITU-R BS.1770-4 for 48kHz
+        let k = KWeighting::new(48000.0);
+        let close = |a: f64, b: f64| (a - b).abs() < 1e-9;
+        assert!(close(k.shelf.b0, 1.53512485958697));
+        assert!(close(k.shelf.b1, -2.69169618940638));
+        assert!(close(k.shelf.b2, 1.19839281085285));
+        assert!(close(k.shelf.a1, -1.69065929318241));
+        assert!(close(k.shelf.a2, 0.73248077421585));
+        assert!(close(k.high_pass.a1, -1.99004745483398));
+        assert!(close(k.high_pass.a2, 0.99007225036621));
+    }
+
+    #[test]
+    fn test_loudness() {
+        // A full scale 1kHz sine in one channel reads -3.01 LUFS
+        let fs = 48000.0;
+        let mut loudness = Loudness::new(fs, MOMENTARY_MS);
+        let mut value = 0.0;
+        for n in 0..48000 {
+            let x = (2.0 * PI * 1000.0 * n as f64 / fs).sin();
+            value = loudness.process(x, 0.0);
+        }
+        assert!((value + 3.01).abs() < 0.02);
+
+        let mut loudness = Loudness::new(fs, MOMENTARY_MS);
+        assert_eq!(loudness.process(0.0, 0.0), LOUDNESS_FLOOR);
+    }
+
+    #[test]
+    fn test_loudness_over_hours() {
+        // K-weighting needs the shelf below nyquist
+        let rate = 4000;
+        let mut loudness = Loudness::new(rate as f64, MOMENTARY_MS);
+        let mut noise = Noise(4);
+        for n in 0..rate * 3600 {
+            let x = session_sample(&mut noise, n, rate);
+            let value = loudness.process(x, -x);
+            assert!(value.is_finite() && (LOUDNESS_FLOOR..10.0).contains(&value));
+        }
+        for _ in 0..rate {
+            loudness.process(0.0, 0.0);
+        }
+        assert!(loudness.process(0.0, 0.0) < -100.0);
+    }
+}
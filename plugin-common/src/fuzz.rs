@@ -0,0 +1,185 @@
+// Test only property checks that drive a plugin's process with random parameter automation
+// and random input, including silence, DC, full scale noise and non-finite samples.
+
+use baseplug::Plugin;
+use proptest::prelude::*;
+use proptest::test_runner::TestCaseError;
+
+use crate::harness::Harness;
+
+// Blocks of noise at the probe model after the random blocks
+const PROBE_BLOCKS: usize = 8;
+// Time for the parameter smoothing to settle, within rounding, on a new model
+const SETTLE_SECONDS: f32 = 0.1;
+
+/// One block of input, the same on both channels
+#[derive(Clone, Debug)]
+pub enum Signal {
+    Silence,
+    Dc(f32),
+    Noise { amplitude: f32, seed: u32 },
+    Sine { hz: f32, amplitude: f32 },
+    // Full scale noise with value at position, or every sample when position is None
+    NonFinite { value: f32, position: Option<usize> },
+}
+
+impl Signal {
+    pub fn render(&self, sample_rate: f32, nframes: usize) -> Vec<f32> {
+        let mut seed = match *self {
+            Signal::Noise { seed, .. } => seed,
+            _ => 1,
+        };
+        (0..nframes)
+            .map(|i| {
+                seed = seed.wrapping_mul(1664525).wrapping_add(1013904223);
+                let noise = (seed as f32 / u32::MAX as f32) * 2.0 - 1.0;
+                match *self {
+                    Signal::Silence => 0.0,
+                    Signal::Dc(level) => level,
+                    Signal::Noise { amplitude, .. } => noise * amplitude,
+                    Signal::Sine { hz, amplitude } => {
+                        (2.0 * std::f32::consts::PI * hz * i as f32 / sample_rate).sin() * amplitude
+                    }
+                    Signal::NonFinite { value, position } => match position {
+                        Some(position) if position % nframes != i => noise,
+                        _ => value,
+                    },
+                }
+            })
+            .collect()
+    }
+}
+
+/// Any input a host could send, sample values are within full scale apart from non-finite ones
+pub fn signal() -> impl Strategy<Value = Signal> {
+    let non_finite = prop_oneof![Just(f32::NAN), Just(f32::INFINITY), Just(f32::NEG_INFINITY)];
+    prop_oneof![
+        Just(Signal::Silence),
+        (-1.0f32..=1.0).prop_map(Signal::Dc),
+        (0.0f32..=1.0, any::<u32>())
+            .prop_map(|(amplitude, seed)| Signal::Noise { amplitude, seed }),
+        Just(Signal::Noise {
+            amplitude: 1.0,
+            seed: 1
+        }),
+        (20.0f32..20000.0, 0.0f32..=1.0).prop_map(|(hz, amplitude)| Signal::Sine { hz, amplitude }),
+        (non_finite, proptest::option::of(any::<usize>()))
+            .prop_map(|(value, position)| Signal::NonFinite { value, position }),
+    ]
+}
+
+pub fn sample_rate() -> impl Strategy<Value = f32> {
+    prop_oneof![Just(44100.0f32), Just(48000.0), Just(96000.0)]
+}
+
+/// Runs blocks through a new plugin, each block's model is set on the smoothed model so
+/// parameters ramp between blocks like host automation. Fails if any output sample isn't
+/// finite or is louder than max_output of the models the block's parameters can still be
+/// ramping from, the block's own and those of the blocks before it. Afterwards plays noise at
+/// the probe model and fails if the plugin has gone silent, e.g. because non-finite input got
+/// stuck in its state.
+pub fn fuzz_process<P: Plugin>(
+    blocks: &[(P::Model, Signal)],
+    probe: P::Model,
+    sample_rate: f32,
+    nframes: usize,
+    max_output: impl Fn(&[P::Model]) -> f32,
+) -> Result<(), TestCaseError>
+where
+    P::Model: Clone,
+{
+    let probe_signal = Signal::Noise {
+        amplitude: 1.0,
+        seed: 7,
+    };
+    let probe_blocks = std::iter::repeat((probe, probe_signal)).take(PROBE_BLOCKS);
+    let blocks: Vec<(P::Model, Signal)> = blocks.iter().cloned().chain(probe_blocks).collect();
+    let models: Vec<P::Model> = blocks.iter().map(|(model, _)| model.clone()).collect();
+    let settle_blocks = (SETTLE_SECONDS * sample_rate / nframes as f32).ceil() as usize;
+
+    let mut harness = Harness::<P>::new(|| models[0].clone(), sample_rate, nframes);
+    let mut silent = true;
+    for (block, (model, signal)) in blocks.iter().enumerate() {
+        harness.set_model(model);
+        let input = signal.render(sample_rate, nframes);
+        let output = harness.process([&input, &input]);
+
+        let ceiling = max_output(&models[block.saturating_sub(settle_blocks)..=block]);
+        for (i, y) in output[0].iter().chain(output[1]).enumerate() {
+            prop_assert!(
+                y.is_finite() && y.abs() <= ceiling,
+                "{} block {} ({:?}) sample {} is {}, over {}",
+                P::NAME,
+                block,
+                signal,
+                i % nframes,
+                y,
+                ceiling
+            );
+        }
+        silent = output[0].iter().chain(output[1]).all(|y| *y == 0.0);
+    }
+    prop_assert!(!silent, "{} is silent after the fuzzed blocks", P::NAME);
+    Ok(())
+}
+
+/// The largest value of a parameter across models
+pub fn peak<M>(models: &[M], param: impl Fn(&M) -> f32) -> f32 {
+    models.iter().map(param).fold(f32::NEG_INFINITY, f32::max)
+}
+
+/// Ceiling for a plugin's output after the mix stage, from the peaks of its wet signal and of
+/// Trim. The dry input is within full scale. Any blend of the two is within their sum with the
+/// gains of either mix law, and while bypassed with tails the wet signal carries on over the
+/// dry input. A little headroom covers rounding.
+pub fn mixed_max_output(wet_max: f32, trim_max: f32) -> f32 {
+    let mixed = 2.0f32.sqrt() * wet_max.max(1.0) * trim_max;
+    let bypassed = 1.0 + wet_max * trim_max;
+    mixed.max(bypassed) * 1.001
+}
+
+pub fn blocks<M: std::fmt::Debug>(
+    model: impl Strategy<Value = M>,
+    count: std::ops::Range<usize>,
+) -> impl Strategy<Value = Vec<(M, Signal)>> {
+    proptest::collection::vec((model, signal()), count)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_signals() {
+        let nframes = 64;
+        assert!(Signal::Silence
+            .render(48000.0, nframes)
+            .iter()
+            .all(|x| *x == 0.0));
+        assert!(Signal::Dc(-0.5)
+            .render(48000.0, nframes)
+            .iter()
+            .all(|x| *x == -0.5));
+        let noise = Signal::Noise {
+            amplitude: 1.0,
+            seed: 3,
+        }
+        .render(48000.0, nframes);
+        assert!(noise.iter().all(|x| x.abs() <= 1.0));
+        assert!(noise.iter().any(|x| *x != noise[0]));
+
+        let spike = Signal::NonFinite {
+            value: f32::NAN,
+            position: Some(nframes + 5),
+        }
+        .render(48000.0, nframes);
+        assert_eq!(spike.iter().filter(|x| !x.is_finite()).count(), 1);
+        assert!(spike[5].is_nan());
+        let all = Signal::NonFinite {
+            value: f32::INFINITY,
+            position: None,
+        }
+        .render(48000.0, nframes);
+        assert!(all.iter().all(|x| *x == f32::INFINITY));
+    }
+}
@@ -0,0 +1,64 @@
+// Drives a plugin's process outside a host for tests and benchmarks, the way baseplug's
+// wrappers do: the model is smoothed and each block goes through a ProcessContext.
+
+use baseplug::{AudioBus, AudioBusMut, Model, MusicalTime, Plugin, ProcessContext, SmoothModel};
+
+const MUSICAL_TIME: MusicalTime = MusicalTime {
+    bpm: 120.0,
+    beat: 0.0,
+};
+
+/// A plugin with its smoothed model and output buffers. Everything is allocated up front, so
+/// process only does what the plugin's own process does.
+pub struct Harness<P: Plugin> {
+    pub plugin: P,
+    smooth: <P::Model as Model<P>>::Smooth,
+    sample_rate: f32,
+    outputs: [Vec<f32>; 2],
+}
+
+impl<P: Plugin> Harness<P> {
+    /// A new plugin at model, for blocks of at most max_frames
+    pub fn new(model: impl Fn() -> P::Model, sample_rate: f32, max_frames: usize) -> Harness<P> {
+        let plugin = P::new(sample_rate, &model());
+        let mut smooth = <P::Model as Model<P>>::Smooth::from_model(model());
+        smooth.set_sample_rate(sample_rate);
+        Harness {
+            plugin,
+            smooth,
+            sample_rate,
+            outputs: [vec![0.0; max_frames], vec![0.0; max_frames]],
+        }
+    }
+
+    /// The model the parameters smooth towards from the next block on, like host automation
+    pub fn set_model(&mut self, model: &P::Model) {
+        self.smooth.set(model);
+    }
+
+    /// Runs one block through the plugin's process and returns its output
+    pub fn process(&mut self, inputs: [&[f32]; 2]) -> [&[f32]; 2] {
+        let nframes = inputs[0].len();
+        let [output_l, output_r] = &mut self.outputs;
+        let mut output_buffers = [&mut output_l[..nframes], &mut output_r[..nframes]];
+        let audio_inputs = [AudioBus {
+            connected_channels: 2,
+            buffers: &inputs,
+        }];
+        let mut audio_outputs = [AudioBusMut {
+            connected_channels: 2,
+            buffers: &mut output_buffers,
+        }];
+        let mut enqueue_event = |_| {};
+        let mut ctx = ProcessContext {
+            nframes,
+            sample_rate: self.sample_rate,
+            inputs: &audio_inputs,
+            outputs: &mut audio_outputs,
+            enqueue_event: &mut enqueue_event,
+            musical_time: &MUSICAL_TIME,
+        };
+        self.plugin.process(&self.smooth.process(nframes), &mut ctx);
+        [&self.outputs[0][..nframes], &self.outputs[1][..nframes]]
+    }
+}
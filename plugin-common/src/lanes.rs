@@ -0,0 +1,151 @@
+// N f64 lanes with element-wise arithmetic, for running the same DSP on several channels or
+// bands at once. With the "simd" feature, on by default, they're std::simd vectors, so each
+// operation is explicit SIMD on targets that have it. Without it they're arrays looped over lane
+// by lane. Either way every lane gives the same result as the scalar f64 arithmetic would.
+
+use std::ops::{Add, Index, IndexMut, Mul, Sub};
+
+#[cfg(feature = "simd")]
+use std::simd::cmp::SimdPartialOrd;
+#[cfg(feature = "simd")]
+use std::simd::num::SimdFloat;
+#[cfg(feature = "simd")]
+use std::simd::{Select, Simd};
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Lanes<const N: usize>(
+    #[cfg(feature = "simd")] Simd<f64, N>,
+    #[cfg(not(feature = "simd"))] [f64; N],
+);
+
+#[cfg(feature = "simd")]
+impl<const N: usize> Lanes<N> {
+    pub fn splat(x: f64) -> Lanes<N> {
+        Lanes(Simd::splat(x))
+    }
+
+    pub fn from_array(lanes: [f64; N]) -> Lanes<N> {
+        Lanes(Simd::from_array(lanes))
+    }
+
+    pub fn to_array(self) -> [f64; N] {
+        self.0.to_array()
+    }
+
+    pub fn abs(self) -> Lanes<N> {
+        Lanes(self.0.abs())
+    }
+
+    /// if_ge's lanes where self is >= other, otherwise's elsewhere, including where either is NaN
+    pub fn select_ge(self, other: Lanes<N>, if_ge: Lanes<N>, otherwise: Lanes<N>) -> Lanes<N> {
+        Lanes(self.0.simd_ge(other.0).select(if_ge.0, otherwise.0))
+    }
+}
+
+#[cfg(not(feature = "simd"))]
+impl<const N: usize> Lanes<N> {
+    pub fn splat(x: f64) -> Lanes<N> {
+        Lanes([x; N])
+    }
+
+    pub fn from_array(lanes: [f64; N]) -> Lanes<N> {
+        Lanes(lanes)
+    }
+
+    pub fn to_array(self) -> [f64; N] {
+        self.0
+    }
+
+    pub fn abs(self) -> Lanes<N> {
+        let mut out = self.0;
+        for x in out.iter_mut() {
+            *x = x.abs();
+        }
+        Lanes(out)
+    }
+
+    /// if_ge's lanes where self is >= other, otherwise's elsewhere, including where either is NaN
+    pub fn select_ge(self, other: Lanes<N>, if_ge: Lanes<N>, otherwise: Lanes<N>) -> Lanes<N> {
+        let mut out = otherwise.0;
+        for i in 0..N {
+            if self.0[i] >= other.0[i] {
+                out[i] = if_ge.0[i];
+            }
+        }
+        Lanes(out)
+    }
+}
+
+macro_rules! lanes_op {
+    ($trait:ident, $fn:ident, $op:tt) => {
+        impl<const N: usize> $trait for Lanes<N> {
+            type Output = Lanes<N>;
+
+            #[inline]
+            #[cfg(feature = "simd")]
+            fn $fn(self, rhs: Lanes<N>) -> Lanes<N> {
+                Lanes(self.0 $op rhs.0)
+            }
+
+            #[inline]
+            #[cfg(not(feature = "simd"))]
+            fn $fn(self, rhs: Lanes<N>) -> Lanes<N> {
+                let mut out = self.0;
+                for (x, rhs) in out.iter_mut().zip(rhs.0.iter()) {
+                    *x = *x $op *rhs;
+                }
+                Lanes(out)
+            }
+        }
+    };
+}
+
+lanes_op!(Add, add, +);
+lanes_op!(Sub, sub, -);
+lanes_op!(Mul, mul, *);
+
+impl<const N: usize> Index<usize> for Lanes<N> {
+    type Output = f64;
+
+    fn index(&self, lane: usize) -> &f64 {
+        &self.0[lane]
+    }
+}
+
+impl<const N: usize> IndexMut<usize> for Lanes<N> {
+    fn index_mut(&mut self, lane: usize) -> &mut f64 {
+        &mut self.0[lane]
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_lanes_match_scalar() {
+        let a = [1.5, -2.0, 0.25, f64::NAN];
+        let b = [0.5, 3.0, 0.25, 1.0];
+        let (la, lb) = (Lanes::from_array(a), Lanes::from_array(b));
+        let sum = (la + lb).to_array();
+        let difference = (la - lb).to_array();
+        let product = (la * lb).to_array();
+        let abs = la.abs().to_array();
+        let selected = la
+            .select_ge(lb, Lanes::splat(1.0), Lanes::splat(0.0))
+            .to_array();
+        for i in 0..3 {
+            assert_eq!(sum[i], a[i] + b[i]);
+            assert_eq!(difference[i], a[i] - b[i]);
+            assert_eq!(product[i], a[i] * b[i]);
+            assert_eq!(abs[i], a[i].abs());
+        }
+        assert!(sum[3].is_nan());
+        assert_eq!(selected, [1.0, 0.0, 1.0, 0.0]);
+
+        let mut lanes = Lanes::<2>::splat(1.0);
+        lanes[1] = 2.0;
+        assert_eq!(lanes.to_array(), [1.0, 2.0]);
+        assert_eq!(lanes[0], 1.0);
+    }
+}
@@ -0,0 +1,354 @@
+// Latency: how many samples a plugin's output lags its input, e.g. for lookahead or
+// oversampling. Plugins report it through ClapExport::latency, which the CLAP and VST3 exports
+// pass on to the host, and delay their dry signal by the same amount with DryDelay so blending it
+// with the wet signal stays phase-aligned.
+
+/// The most latency a DryDelay made with it matches, 50 ms at 192 kHz
+pub const MAX_LATENCY: usize = 9600;
+
+/// Delays a stereo dry signal by the plugin's latency. The buffers are allocated once, in new, so
+/// the delay can change in process.
+pub struct DryDelay {
+    buffers: [Vec<f32>; 2],
+    write: usize,
+    delay: usize,
+}
+
+impl DryDelay {
+    pub fn new(max_delay: usize) -> DryDelay {
+        DryDelay {
+            buffers: [vec![0.0; max_delay + 1], vec![0.0; max_delay + 1]],
+            write: 0,
+            delay: 0,
+        }
+    }
+
+    pub fn delay(&self) -> usize {
+        self.delay
+    }
+
+    /// Delays by delay samples from now on, up to the max_delay it was made with. Hosts restart
+    /// a plugin whose latency changes, so the jump isn't smoothed.
+    pub fn set_delay(&mut self, delay: usize) {
+        self.delay = delay.min(self.buffers[0].len() - 1);
+    }
+
+    /// Takes one frame of dry signal, returns the one from delay frames ago
+    pub fn process(&mut self, frame: [f32; 2]) -> [f32; 2] {
+        let len = self.buffers[0].len();
+        let read = (self.write + len - self.delay) % len;
+        let mut delayed = [0.0; 2];
+        for ((buffer, x), out) in self.buffers.iter_mut().zip(frame).zip(delayed.iter_mut()) {
+            buffer[self.write] = x;
+            *out = buffer[read];
+        }
+        self.write = (self.write + 1) % len;
+        delayed
+    }
+
+    pub fn reset(&mut self) {
+        for buffer in self.buffers.iter_mut() {
+            buffer.iter_mut().for_each(|x| *x = 0.0);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_dry_delay() {
+        let mut delay = DryDelay::new(4);
+        delay.set_delay(3);
+        let out: Vec<[f32; 2]> = (0..6)
+            .map(|i| delay.process(if i == 0 { [0.5, 0.25] } else { [0.0; 2] }))
+            .collect();
+        assert_eq!(out[3], [0.5, 0.25]);
+        assert!(out
+            .iter()
+            .enumerate()
+            .all(|(i, x)| i == 3 || *x == [0.0; 2]));
+
+        delay.set_delay(0);
+        assert_eq!(delay.process([1.0, -1.0]), [1.0, -1.0]);
+        delay.set_delay(100);
+        assert_eq!(delay.delay(), 4);
+        delay.process([1.0; 2]);
+        delay.reset();
+        assert!((0..5).all(|_| delay.process([0.0; 2]) == [0.0; 2]));
+    }
+
+    // A plugin with latency, none of the workspace's have any yet. Its wet signal is the input
+    // delayed by the Lookahead parameter, which sets the latency, so a mix of the two is only a
+    // clean copy of the input if the dry signal lags by the same amount.
+    #[cfg(feature = "testing")]
+    mod latent {
+        use std::ffi::{c_void, CStr};
+
+        use baseplug::{Plugin, ProcessContext};
+        use clap_sys::entry::clap_plugin_entry;
+        use clap_sys::plugin_features::CLAP_PLUGIN_FEATURE_AUDIO_EFFECT;
+        use serde::{Deserialize, Serialize};
+        use serde_json::{Map, Value};
+
+        use crate::clap::{self, ClapExport, ClapParam};
+        use crate::latency::{DryDelay, MAX_LATENCY};
+        use crate::mix::{MixLaw, MixStage};
+        use crate::presets::{FactoryPresets, Preset};
+        use crate::state::VersionedState;
+        use crate::vst3::sys::{uid, Tuid};
+        use crate::vst3::{self, Vst3Export};
+
+        crate::model! {
+            #[derive(Debug, Clone, Serialize, Deserialize)]
+            pub struct LatentModel {
+                #[model(min = 0.0, max = 64.0)]
+                #[parameter(name = "Lookahead", unit = "Generic",
+                    gradient = "Linear")]
+                #[export(stepped, label = "samples")]
+                lookahead: f32,
+                #[model(min = 0.0, max = 1.0)]
+                #[parameter(name = "Mix", unit = "Generic",
+                    gradient = "Linear")]
+                mix: f32
+            }
+        }
+
+        impl Default for LatentModel {
+            fn default() -> Self {
+                Self {
+                    lookahead: 16.0,
+                    mix: 0.5,
+                }
+            }
+        }
+
+        impl FactoryPresets for LatentModel {
+            fn factory_presets() -> Vec<Preset<Self>> {
+                vec![
+                    Preset {
+                        name: "Short".to_string(),
+                        model: LatentModel::default(),
+                    },
+                    Preset {
+                        name: "Long".to_string(),
+                        model: LatentModel {
+                            lookahead: 64.0,
+                            ..LatentModel::default()
+                        },
+                    },
+                ]
+            }
+        }
+
+        impl VersionedState for LatentModel {
+            const STATE_VERSION: u32 = 1;
+
+            fn migrate(_from_version: u32, _model: &mut Map<String, Value>) {}
+        }
+
+        struct Latent {
+            wet: DryDelay,
+            mix: MixStage,
+        }
+
+        impl Plugin for Latent {
+            const NAME: &'static str = "latent test plug";
+            const PRODUCT: &'static str = "latent test plug";
+            const VENDOR: &'static str = "spicy plugins & co";
+
+            const INPUT_CHANNELS: usize = 2;
+            const OUTPUT_CHANNELS: usize = 2;
+
+            type Model = LatentModel;
+
+            fn new(sample_rate: f32, model: &LatentModel) -> Self {
+                // Hosts restart the plugin when the latency changes, so it's fixed until then
+                let latency = Self::latency(model, sample_rate);
+                let mut wet = DryDelay::new(MAX_LATENCY);
+                wet.set_delay(latency as usize);
+                let mut mix = MixStage::new(sample_rate);
+                mix.set_latency(latency);
+                Self { wet, mix }
+            }
+
+            fn process(&mut self, model: &LatentModelProcess, ctx: &mut ProcessContext<Self>) {
+                let input = &ctx.inputs[0].buffers;
+                let output = &mut ctx.outputs[0].buffers;
+                for i in 0..ctx.nframes {
+                    let [l, r] = self.wet.process([input[0][i], input[1][i]]);
+                    let dry = [input[0][i] as f64, input[1][i] as f64];
+                    let wet = [l as f64, r as f64];
+                    let mix = model.mix[i] as f64;
+                    let [l, r] = self.mix.process(dry, wet, mix, MixLaw::Linear, 1.0);
+                    output[0][i] = l as f32;
+                    output[1][i] = r as f32;
+                }
+            }
+        }
+
+        impl ClapExport for Latent {
+            const CLAP_ID: &'static str = "net.dgdigital.latent-test";
+            const VERSION: &'static str = env!("CARGO_PKG_VERSION");
+            const CLAP_FEATURES: &'static [&'static CStr] = &[CLAP_PLUGIN_FEATURE_AUDIO_EFFECT];
+
+            fn clap_params() -> Vec<ClapParam<LatentModel>> {
+                LatentModel::clap_params()
+            }
+
+            fn latency(model: &LatentModel, _sample_rate: f32) -> u32 {
+                model.lookahead.round() as u32
+            }
+        }
+
+        impl Vst3Export for Latent {
+            const VST3_CLASS_ID: Tuid = uid(0x5A7E0C41, 0x3B2D4F18, 0x9C6E1A27, 0xD4F08B63);
+            const VST3_CATEGORIES: &'static str = "Fx|Delay";
+        }
+
+        static CLAP_ENTRY: clap_plugin_entry = clap::entry::<Latent>();
+
+        extern "system" fn get_factory() -> *mut c_void {
+            vst3::factory::<Latent>()
+        }
+
+        // An impulse at the start of 256 frames, on both channels
+        fn impulse() -> [Vec<f32>; 2] {
+            let mut buffer = vec![0.0; 256];
+            buffer[0] = 1.0;
+            [buffer.clone(), buffer]
+        }
+
+        // With a half mix, aligned dry and wet signals add back up to the impulse, delayed by the
+        // latency. Out of step they'd be two half height impulses.
+        fn assert_aligned(buffers: &[Vec<f32>; 2], latency: usize) {
+            for buffer in buffers.iter() {
+                for (i, x) in buffer.iter().enumerate() {
+                    let expected = if i == latency { 1.0 } else { 0.0 };
+                    assert!((x - expected).abs() < 1e-6, "{} at {}", x, i);
+                }
+            }
+        }
+
+        #[test]
+        fn test_clap_latency() {
+            let host = clap::host::validate::<Latent>(&CLAP_ENTRY);
+
+            let instance = host.create();
+            instance.activate();
+            assert_eq!(instance.latency(), 16);
+            let mut buffers = impulse();
+            instance.process(&mut buffers, &[]);
+            assert_aligned(&buffers, 16);
+
+            // The change is reported on the next activate, after the host restarts the plugin
+            let restarts = host.restarts();
+            let event = clap::host::ParamEvent {
+                time: 0,
+                id: 0,
+                value: 40.0,
+            };
+            instance.process(&mut [vec![0.0; 256], vec![0.0; 256]], &[event]);
+            assert_eq!(host.restarts(), restarts + 1);
+            assert_eq!(instance.latency(), 16);
+            instance.deactivate();
+            let latency_changes = host.latency_changes();
+            instance.activate();
+            assert_eq!(instance.latency(), 40);
+            assert_eq!(host.latency_changes(), latency_changes + 1);
+            let mut buffers = impulse();
+            instance.process(&mut buffers, &[]);
+            assert_aligned(&buffers, 40);
+            instance.deactivate();
+        }
+
+        // An impulse every period frames, on both channels
+        fn impulse_train(period: usize) -> [Vec<f32>; 2] {
+            let buffer: Vec<f32> = (0..256)
+                .map(|i| if i % period == 0 { 1.0 } else { 0.0 })
+                .collect();
+            [buffer.clone(), buffer]
+        }
+
+        // The train delayed by latency, less than a period, at full height, so dry and wet are in
+        // step. The blocks are whole periods, so each one looks the same however long the train
+        // has been playing.
+        fn assert_train_aligned(buffers: &[Vec<f32>; 2], period: usize, latency: usize) {
+            for buffer in buffers.iter() {
+                for (i, x) in buffer.iter().enumerate() {
+                    let expected = if i % period == latency { 1.0 } else { 0.0 };
+                    assert!((x - expected).abs() < 1e-6, "{} at {}", x, i);
+                }
+            }
+        }
+
+        // Lookahead automated in the middle of a block while audio is playing: the plugin asks
+        // the host for a restart and keeps its latency until then, so dry and wet stay in step
+        // before, during and after the change.
+        #[test]
+        fn test_clap_latency_change_mid_stream() {
+            let host = clap::host::validate::<Latent>(&CLAP_ENTRY);
+
+            let instance = host.create();
+            instance.activate();
+            let mut buffers = impulse_train(64);
+            instance.process(&mut buffers, &[]);
+            assert_train_aligned(&buffers, 64, 16);
+
+            let restarts = host.restarts();
+            let event = clap::host::ParamEvent {
+                time: 100,
+                id: 0,
+                value: 40.0,
+            };
+            let mut buffers = impulse_train(64);
+            instance.process(&mut buffers, &[event]);
+            assert_eq!(host.restarts(), restarts + 1);
+            assert_train_aligned(&buffers, 64, 16);
+            // Still playing while the host gets round to the restart
+            let mut buffers = impulse_train(64);
+            instance.process(&mut buffers, &[]);
+            assert_eq!(host.restarts(), restarts + 1);
+            assert_train_aligned(&buffers, 64, 16);
+
+            instance.deactivate();
+            let latency_changes = host.latency_changes();
+            instance.activate();
+            assert_eq!(host.latency_changes(), latency_changes + 1);
+            assert_eq!(instance.latency(), 40);
+            let mut buffers = impulse_train(64);
+            instance.process(&mut buffers, &[]);
+            assert_train_aligned(&buffers, 64, 40);
+            let mut buffers = impulse_train(64);
+            instance.process(&mut buffers, &[]);
+            assert_train_aligned(&buffers, 64, 40);
+            instance.deactivate();
+        }
+
+        #[test]
+        fn test_vst3_latency() {
+            let host = vst3::host::validate::<Latent>(get_factory);
+
+            let instance = host.create();
+            instance.activate();
+            assert_eq!(instance.latency(), 16);
+            let mut buffers = impulse();
+            instance.process(&mut buffers, &[]);
+            assert_aligned(&buffers, 16);
+
+            // getLatencySamples has the new latency as soon as the host is asked to restart
+            let restarts = host.restarts();
+            instance.set_normalized(0, 40.0 / 64.0);
+            assert_eq!(host.restarts(), restarts + 1);
+            assert_eq!(instance.latency(), 40);
+            instance.deactivate();
+            instance.activate();
+            assert_eq!(instance.latency(), 40);
+            let mut buffers = impulse();
+            instance.process(&mut buffers, &[]);
+            assert_aligned(&buffers, 40);
+            instance.deactivate();
+        }
+    }
+}
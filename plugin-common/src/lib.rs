@@ -0,0 +1,40 @@
+#![allow(incomplete_features)]
+#![feature(generic_associated_types)]
+#![cfg_attr(feature = "simd", feature(portable_simd))]
+
+//! What every plugin in the workspace shares on top of baseplug: the CLAP, VST2 and VST3 exports,
+//! the model declaration they take their parameters from, saved state and presets, MIDI learn,
+//! latency, logging, the dry/wet mix stage and output protection.
+
+#[cfg(feature = "bench")]
+pub mod bench;
+pub mod clap;
+pub mod detectors;
+#[cfg(feature = "testing")]
+pub mod fuzz;
+#[cfg(any(feature = "testing", feature = "bench"))]
+pub mod harness;
+pub mod lanes;
+pub mod latency;
+pub mod logging;
+mod midi;
+pub mod mix;
+mod model;
+pub mod presets;
+pub mod protect;
+pub mod ramp;
+pub mod ring;
+#[cfg(feature = "rt-check")]
+pub mod rt_check;
+pub mod rtlog;
+pub mod smooth;
+pub mod state;
+pub mod svf;
+pub mod sync;
+pub mod units;
+pub mod vst2;
+pub mod vst3;
+
+// model! expands to baseplug::model! through this
+#[doc(hidden)]
+pub use baseplug;
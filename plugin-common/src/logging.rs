@@ -0,0 +1,280 @@
+use std::env;
+use std::fs::{self, File, OpenOptions};
+use std::io::{self, Write};
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Once;
+
+use log::LevelFilter;
+
+// The log file is rotated once it grows past this, keeping LOG_ROTATIONS old files
+const MAX_LOG_BYTES: u64 = 1024 * 1024;
+const LOG_ROTATIONS: usize = 3;
+const DEFAULT_LEVEL: LevelFilter = LevelFilter::Info;
+
+static INIT: Once = Once::new();
+static INSTANCES: AtomicUsize = AtomicUsize::new(0);
+
+/// Where and how much to log, from <PLUGIN>_LOG (level: off, error, warn, info, debug, trace)
+/// and <PLUGIN>_LOG_PATH, e.g. DYNSAT_LOG=debug, or given to init_with
+#[derive(Clone, Debug, PartialEq)]
+pub struct LogConfig {
+    pub level: LevelFilter,
+    pub path: PathBuf,
+}
+
+impl LogConfig {
+    pub fn from_env(plugin: &str) -> LogConfig {
+        let prefix = plugin.to_uppercase();
+        LogConfig::from_vars(
+            plugin,
+            env::var(format!("{}_LOG", prefix)).ok(),
+            env::var_os(format!("{}_LOG_PATH", prefix)).map(PathBuf::from),
+        )
+    }
+
+    fn from_vars(plugin: &str, level: Option<String>, path: Option<PathBuf>) -> LogConfig {
+        LogConfig {
+            level: level
+                .and_then(|level| LevelFilter::from_str(level.trim()).ok())
+                .unwrap_or(DEFAULT_LEVEL),
+            path: path.unwrap_or_else(|| default_log_dir(plugin).join(format!("{}.log", plugin))),
+        }
+    }
+}
+
+/// $XDG_STATE_HOME/<plugin> or ~/.local/state/<plugin> on Linux, the local data directory on
+/// other platforms, and the temp directory when there's no home directory
+pub fn default_log_dir(plugin: &str) -> PathBuf {
+    let state_dir = if cfg!(target_os = "linux") {
+        env::var_os("XDG_STATE_HOME")
+            .map(PathBuf::from)
+            .filter(|dir| dir.is_absolute())
+            .or_else(|| dirs::home_dir().map(|home| home.join(".local").join("state")))
+    } else {
+        dirs::data_local_dir()
+    };
+    state_dir.unwrap_or_else(env::temp_dir).join(plugin)
+}
+
+/// Appends to a log file, moving it to .1 (and .1 to .2 and so on) once it's too big.
+/// Records are written in pieces, so it only rotates at the start of a line.
+pub struct RotatingFile {
+    path: PathBuf,
+    file: Option<File>,
+    size: u64,
+    max_bytes: u64,
+    line_start: bool,
+}
+
+impl RotatingFile {
+    pub fn open(path: &Path, max_bytes: u64) -> io::Result<RotatingFile> {
+        if let Some(dir) = path.parent() {
+            fs::create_dir_all(dir)?;
+        }
+        let file = OpenOptions::new().create(true).append(true).open(path)?;
+        let size = file.metadata()?.len();
+        Ok(RotatingFile {
+            path: path.to_path_buf(),
+            file: Some(file),
+            size,
+            max_bytes,
+            line_start: true,
+        })
+    }
+
+    fn rotated_path(&self, n: usize) -> PathBuf {
+        let mut path = self.path.clone().into_os_string();
+        path.push(format!(".{}", n));
+        PathBuf::from(path)
+    }
+
+    fn rotate(&mut self) -> io::Result<()> {
+        self.file = None;
+        for n in (1..LOG_ROTATIONS).rev() {
+            let from = self.rotated_path(n);
+            if from.exists() {
+                fs::rename(&from, self.rotated_path(n + 1))?;
+            }
+        }
+        fs::rename(&self.path, self.rotated_path(1))?;
+        self.file = Some(File::create(&self.path)?);
+        self.size = 0;
+        Ok(())
+    }
+}
+
+impl Write for RotatingFile {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        if self.line_start && self.size > 0 && self.size + buf.len() as u64 > self.max_bytes {
+            // If rotating fails keep going with whichever file can still be opened
+            if self.rotate().is_err() && self.file.is_none() {
+                self.file = OpenOptions::new().append(true).open(&self.path).ok();
+            }
+        }
+        match self.file.as_mut() {
+            Some(file) => {
+                let written = file.write(buf)?;
+                self.size += written as u64;
+                if written > 0 {
+                    self.line_start = buf[written - 1] == b'\n';
+                }
+                Ok(written)
+            }
+            None => Err(io::Error::new(io::ErrorKind::NotFound, "log file not open")),
+        }
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        match self.file.as_mut() {
+            Some(file) => file.flush(),
+            None => Ok(()),
+        }
+    }
+}
+
+// The file logger for config, None if logging is off or the file can't be opened
+fn file_logger(config: &LogConfig) -> Option<Box<simplelog::WriteLogger<RotatingFile>>> {
+    if config.level == LevelFilter::Off {
+        return None;
+    }
+    let file = RotatingFile::open(&config.path, MAX_LOG_BYTES).ok()?;
+    let log_config = simplelog::ConfigBuilder::new()
+        .set_time_to_local(true)
+        // Every record gets its target, the instance tag
+        .set_target_level(LevelFilter::Error)
+        .build();
+    Some(simplelog::WriteLogger::new(config.level, log_config, file))
+}
+
+fn instance_tag(plugin: &str) -> String {
+    let instance = INSTANCES.fetch_add(1, Ordering::Relaxed) + 1;
+    format!("{}#{}", plugin, instance)
+}
+
+/// Sets up logging from <PLUGIN>_LOG and <PLUGIN>_LOG_PATH the first time it's called in a
+/// process and returns a tag for this instance, e.g. "DynSat#2", to log with as the target.
+/// Never panics, if the log file can't be opened logging is just off. With the "testing"
+/// feature the environment isn't read and nothing is logged to a file. With "rt-check" as well
+/// rt_check's logger is installed, so it sees any log call from process.
+pub fn init(plugin: &str) -> String {
+    #[cfg(not(feature = "testing"))]
+    INIT.call_once(|| install(LogConfig::from_env(plugin)));
+    #[cfg(feature = "rt-check")]
+    crate::rt_check::install_logger();
+    instance_tag(plugin)
+}
+
+/// init with the config given rather than read from the environment
+pub fn init_with(plugin: &str, config: LogConfig) -> String {
+    INIT.call_once(|| install(config));
+    instance_tag(plugin)
+}
+
+fn install(config: LogConfig) {
+    if let Some(logger) = file_logger(&config) {
+        if log::set_boxed_logger(logger).is_ok() {
+            log::set_max_level(config.level);
+            log_panics::init();
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use log::{Level, Log, Record};
+
+    fn temp_dir(name: &str) -> PathBuf {
+        let dir = env::temp_dir().join(format!("logging-{}-{}", name, std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        dir
+    }
+
+    #[test]
+    fn test_config() {
+        let config = LogConfig::from_vars("Test", Some("debug".to_string()), None);
+        assert_eq!(config.level, LevelFilter::Debug);
+        assert_eq!(config.path, default_log_dir("Test").join("Test.log"));
+
+        let path = PathBuf::from("/var/log/test.log");
+        let config = LogConfig::from_vars("Test", Some("loud".to_string()), Some(path.clone()));
+        assert_eq!(config.level, DEFAULT_LEVEL);
+        assert_eq!(config.path, path);
+    }
+
+    #[test]
+    fn test_rotation() {
+        let dir = temp_dir("rotation");
+        let path = dir.join("test.log");
+        let mut file = RotatingFile::open(&path, 10).unwrap();
+        for i in 0..6 {
+            // Written in three pieces, lines stay whole
+            writeln!(file, "line {}...", i).unwrap();
+        }
+        file.flush().unwrap();
+        assert_eq!(fs::read_to_string(&path).unwrap(), "line 5...\n");
+        assert_eq!(
+            fs::read_to_string(dir.join("test.log.1")).unwrap(),
+            "line 4...\n"
+        );
+        assert_eq!(
+            fs::read_to_string(dir.join("test.log.3")).unwrap(),
+            "line 2...\n"
+        );
+        assert!(!dir.join("test.log.4").exists());
+
+        // Reopening appends
+        let mut file = RotatingFile::open(&path, 100).unwrap();
+        writeln!(file, "line 6").unwrap();
+        assert_eq!(fs::read_to_string(&path).unwrap(), "line 5...\nline 6\n");
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_file_logger() {
+        let dir = temp_dir("file-logger");
+        let path = dir.join("test.log");
+        let config = LogConfig {
+            level: LevelFilter::Warn,
+            path: path.clone(),
+        };
+        let logger = file_logger(&config).unwrap();
+        for (level, message) in [(Level::Warn, "kept"), (Level::Info, "filtered")] {
+            logger.log(
+                &Record::builder()
+                    .level(level)
+                    .target("Test#1")
+                    .args(format_args!("{}", message))
+                    .build(),
+            );
+        }
+        logger.flush();
+        let written = fs::read_to_string(&path).unwrap();
+        assert!(written.contains("Test#1"));
+        assert!(written.contains("kept"));
+        assert!(!written.contains("filtered"));
+        fs::remove_dir_all(&dir).unwrap();
+
+        let off = LogConfig {
+            level: LevelFilter::Off,
+            ..config
+        };
+        assert!(file_logger(&off).is_none());
+        assert!(!path.exists());
+    }
+
+    #[test]
+    fn test_init_tags_instances() {
+        // Off, so nothing is installed
+        let config = LogConfig {
+            level: LevelFilter::Off,
+            path: temp_dir("init").join("test.log"),
+        };
+        let first = init_with("Test", config);
+        let second = init("Test");
+        assert!(first.starts_with("Test#"));
+        assert_ne!(first, second);
+    }
+}
@@ -0,0 +1,264 @@
+// MIDI learn: control changes from the host's MIDI input set parameters, each controller across
+// its parameter's whole range. The CLAP and VST3 exports add a "MIDI Learn" parameter that arms
+// learning for one parameter, the next controller that moves is mapped to it. The mappings are
+// saved with the plugin's state.
+
+use std::sync::atomic::{AtomicU32, Ordering};
+
+use clap_sys::id::{clap_id, CLAP_INVALID_ID};
+use serde_json::{Map, Value};
+
+use crate::clap::{parse_value, ClapParam};
+
+/// Id of the MIDI Learn parameter, clear of the plugins' own ids
+pub const LEARN_PARAM_ID: clap_id = 1000;
+pub const CONTROLLERS: usize = 128;
+
+const LEARN_OFF: &str = "Off";
+
+/// A control change the host sent, after any learning it caused
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct CcChange {
+    pub param: clap_id,
+    /// Where in the parameter's range, 0 to 1
+    pub normalized: f64,
+    /// The controller was just mapped to the parameter
+    pub learned: bool,
+}
+
+/// The parameter each controller is mapped to, a parameter has one controller at most.
+/// Everything is atomic, so the audio thread learns and applies mappings while the main thread
+/// saves and loads them.
+pub struct MidiMap {
+    params: [AtomicU32; CONTROLLERS],
+    learning: AtomicU32,
+}
+
+impl MidiMap {
+    pub fn new() -> MidiMap {
+        MidiMap {
+            params: std::array::from_fn(|_| AtomicU32::new(CLAP_INVALID_ID)),
+            learning: AtomicU32::new(CLAP_INVALID_ID),
+        }
+    }
+
+    /// The next controller that moves is mapped to param
+    pub fn learn(&self, param: clap_id) {
+        self.learning.store(param, Ordering::Relaxed);
+    }
+
+    pub fn stop_learning(&self) {
+        self.learning.store(CLAP_INVALID_ID, Ordering::Relaxed);
+    }
+
+    pub fn learning(&self) -> Option<clap_id> {
+        Some(self.learning.load(Ordering::Relaxed)).filter(|&id| id != CLAP_INVALID_ID)
+    }
+
+    pub fn param(&self, cc: u8) -> Option<clap_id> {
+        let param = self.params.get(cc as usize)?.load(Ordering::Relaxed);
+        Some(param).filter(|&id| id != CLAP_INVALID_ID)
+    }
+
+    /// Maps cc to param, replacing the controller's and the parameter's mappings
+    pub fn map(&self, cc: u8, param: clap_id) {
+        if cc as usize >= CONTROLLERS {
+            return;
+        }
+        for mapped in self.params.iter() {
+            let _ = mapped.compare_exchange(
+                param,
+                CLAP_INVALID_ID,
+                Ordering::Relaxed,
+                Ordering::Relaxed,
+            );
+        }
+        self.params[cc as usize].store(param, Ordering::Relaxed);
+    }
+
+    pub fn clear(&self) {
+        for mapped in self.params.iter() {
+            mapped.store(CLAP_INVALID_ID, Ordering::Relaxed);
+        }
+    }
+
+    /// A MIDI message from the host, on any channel. Returns the parameter a control change
+    /// sets, mapping the controller first if a parameter is learning. Never allocates.
+    pub fn midi_input(&self, data: [u8; 3]) -> Option<CcChange> {
+        let [status, cc, value] = data;
+        if status & 0xf0 != 0xb0 || cc as usize >= CONTROLLERS {
+            return None;
+        }
+        let learning = self.learning.swap(CLAP_INVALID_ID, Ordering::Relaxed);
+        if learning != CLAP_INVALID_ID {
+            self.map(cc, learning);
+        }
+        Some(CcChange {
+            param: self.param(cc)?,
+            normalized: f64::from(value.min(127)) / 127.0,
+            learned: learning != CLAP_INVALID_ID,
+        })
+    }
+
+    /// {"74": 3, ..}, controller to parameter id
+    pub fn to_value(&self) -> Value {
+        let mappings: Map<String, Value> = (0..CONTROLLERS as u8)
+            .filter_map(|cc| Some((cc.to_string(), Value::from(self.param(cc)?))))
+            .collect();
+        Value::Object(mappings)
+    }
+
+    /// Replaces the mappings with saved ones, entries that aren't a controller and a parameter
+    /// id are skipped. Mappings to parameters the plugin doesn't have are never applied.
+    pub fn load_value(&self, value: Option<&Value>) {
+        self.clear();
+        let mappings = match value {
+            Some(Value::Object(mappings)) => mappings,
+            _ => return,
+        };
+        for (cc, param) in mappings {
+            let param = param.as_u64().filter(|&id| id < CLAP_INVALID_ID as u64);
+            if let (Ok(cc), Some(param)) = (cc.parse::<u8>(), param) {
+                self.map(cc, param as clap_id);
+            }
+        }
+    }
+
+    /// Applies a value of the MIDI Learn parameter, params being the plugin's
+    pub fn set_learning<M>(&self, params: &[ClapParam<M>], value: f64) {
+        let param = (value.round() as usize)
+            .checked_sub(1)
+            .and_then(|index| params.get(index))
+            .filter(|param| param.id != LEARN_PARAM_ID);
+        match param {
+            Some(param) => self.learn(param.id),
+            None => self.stop_learning(),
+        }
+    }
+}
+
+/// The MIDI Learn parameter for a plugin's params: 0 is off, n learns the nth of them. It
+/// isn't automatable and isn't part of the model.
+pub fn learn_param<M>(params: &[ClapParam<M>]) -> ClapParam<M> {
+    ClapParam {
+        id: LEARN_PARAM_ID,
+        name: "MIDI Learn",
+        unit: "",
+        min: 0.0,
+        max: params.len() as f64,
+        stepped: true,
+        exponent: 1.0,
+        get: |_| 0.0,
+        set: |_, _| {},
+    }
+}
+
+/// "Off" or the name of the parameter learning
+pub fn format_learn<M>(params: &[ClapParam<M>], value: f64) -> String {
+    (value.round() as usize)
+        .checked_sub(1)
+        .and_then(|index| params.get(index))
+        .map_or(LEARN_OFF, |param| param.name)
+        .to_string()
+}
+
+/// A MIDI Learn value from "Off", a parameter name or a number
+pub fn parse_learn<M>(params: &[ClapParam<M>], text: &str) -> Option<f64> {
+    let text = text.trim();
+    if text.eq_ignore_ascii_case(LEARN_OFF) {
+        return Some(0.0);
+    }
+    match params
+        .iter()
+        .position(|param| param.name.eq_ignore_ascii_case(text))
+    {
+        Some(index) => Some(index as f64 + 1.0),
+        None => parse_value(text),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn params() -> Vec<ClapParam<f64>> {
+        let param = |id, name| ClapParam {
+            id,
+            name,
+            unit: "",
+            min: 0.0,
+            max: 1.0,
+            stepped: false,
+            exponent: 1.0,
+            get: |model: &f64| *model,
+            set: |model: &mut f64, value| *model = value,
+        };
+        vec![param(0, "Drive"), param(4, "Out Gain")]
+    }
+
+    #[test]
+    fn test_learn() {
+        let midi = MidiMap::new();
+        assert_eq!(midi.midi_input([0xb0, 74, 127]), None);
+
+        midi.set_learning(&params(), 2.0);
+        assert_eq!(midi.learning(), Some(4));
+        // Notes don't learn
+        assert_eq!(midi.midi_input([0x90, 60, 100]), None);
+        let change = CcChange {
+            param: 4,
+            normalized: 1.0,
+            learned: true,
+        };
+        assert_eq!(midi.midi_input([0xb3, 74, 127]), Some(change));
+        assert_eq!(midi.learning(), None);
+        let change = CcChange {
+            param: 4,
+            normalized: 0.0,
+            learned: false,
+        };
+        assert_eq!(midi.midi_input([0xb0, 74, 0]), Some(change));
+
+        // Relearning moves the parameter to the new controller
+        midi.learn(4);
+        midi.midi_input([0xb0, 1, 64]);
+        assert_eq!(midi.param(1), Some(4));
+        assert_eq!(midi.param(74), None);
+
+        midi.set_learning(&params(), 1.0);
+        assert_eq!(midi.learning(), Some(0));
+        midi.set_learning(&params(), 0.0);
+        assert_eq!(midi.learning(), None);
+    }
+
+    #[test]
+    fn test_save_and_load() {
+        let midi = MidiMap::new();
+        midi.map(74, 4);
+        midi.map(1, 0);
+        let saved = midi.to_value();
+        assert_eq!(saved.to_string(), r#"{"1":0,"74":4}"#);
+
+        let loaded = MidiMap::new();
+        loaded.map(2, 7);
+        loaded.load_value(Some(&saved));
+        assert_eq!(loaded.to_value(), saved);
+
+        let invalid = serde_json::json!({"128": 1, "x": 2, "3": "Drive", "5": 9});
+        loaded.load_value(Some(&invalid));
+        assert_eq!(loaded.to_value().to_string(), r#"{"5":9}"#);
+        loaded.load_value(None);
+        assert_eq!(loaded.to_value().to_string(), "{}");
+    }
+
+    #[test]
+    fn test_learn_text() {
+        let params = params();
+        assert_eq!(format_learn(&params, 0.0), "Off");
+        assert_eq!(format_learn(&params, 2.0), "Out Gain");
+        assert_eq!(parse_learn(&params, "off"), Some(0.0));
+        assert_eq!(parse_learn(&params, "out gain"), Some(2.0));
+        assert_eq!(parse_learn(&params, "1"), Some(1.0));
+        assert_eq!(parse_learn(&params, "Tone"), None);
+    }
+}
@@ -0,0 +1,216 @@
+// Dry/wet mix, output trim and soft bypass, the last stage of every plugin. Blending the dry
+// input back in gives parallel processing inside the plugin, without routing a send in the host.
+// Bypass fades to the dry input instead of switching, so it doesn't click. The dry signal goes
+// through a latency::DryDelay first, so it stays aligned with wet signals that lag.
+
+use std::f64::consts::FRAC_PI_2;
+
+use crate::latency::{DryDelay, MAX_LATENCY};
+
+// Time for bypass to fade between the plugin's output and its dry input
+const BYPASS_TIME_MS: f64 = 20.0;
+
+/// How the dry and wet gains follow the mix amount
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum MixLaw {
+    /// Gains sum to 1, right for signals that are correlated like a filter's or a saturator's,
+    /// which would otherwise bump up in level halfway
+    Linear,
+    /// Powers sum to 1, right for uncorrelated signals like a reverb's
+    EqualPower,
+}
+
+impl MixLaw {
+    /// From the stepped "Mix Law" parameter, 0 is linear and 1 equal power
+    pub fn from_param(value: f32) -> MixLaw {
+        if value >= 0.5 {
+            MixLaw::EqualPower
+        } else {
+            MixLaw::Linear
+        }
+    }
+
+    /// Dry and wet gains for mix, 0 is all dry and 1 all wet. Both ends are exact, so a mix of
+    /// 1 passes the wet signal unchanged with either law.
+    pub fn gains(self, mix: f64) -> (f64, f64) {
+        if mix <= 0.0 {
+            return (1.0, 0.0);
+        }
+        if mix >= 1.0 {
+            return (0.0, 1.0);
+        }
+        match self {
+            MixLaw::Linear => (1.0 - mix, mix),
+            MixLaw::EqualPower => ((mix * FRAC_PI_2).cos(), (mix * FRAC_PI_2).sin()),
+        }
+    }
+}
+
+fn ramp(x: f64, target: f64, step: f64) -> f64 {
+    if x < target {
+        (x + step).min(target)
+    } else {
+        (x - step).max(target)
+    }
+}
+
+pub struct MixStage {
+    dry: DryDelay,
+    // 0 is processing and 1 bypassed, both ramp linearly to their targets
+    bypass: f64,
+    bypass_target: f64,
+    // How much of the plugin's output carries on over the dry input while it's bypassed
+    tails: f64,
+    tails_target: f64,
+    step: f64,
+}
+
+impl MixStage {
+    pub fn new(sample_rate: f32) -> MixStage {
+        MixStage {
+            dry: DryDelay::new(MAX_LATENCY),
+            bypass: 0.0,
+            bypass_target: 0.0,
+            tails: 0.0,
+            tails_target: 0.0,
+            step: 1000.0 / (BYPASS_TIME_MS * sample_rate as f64),
+        }
+    }
+
+    /// Delays the dry signal by the plugin's latency, from ClapExport::latency
+    pub fn set_latency(&mut self, latency: u32) {
+        self.dry.set_delay(latency as usize);
+    }
+
+    /// Bypassed, the output fades to the dry input, without trim. With tails the plugin keeps
+    /// running on input that fades to silence, and what it still puts out, e.g. a reverb's tail,
+    /// carries on over the dry input. Without them it stops processing once the fade is done and
+    /// picks up from the same state when it's switched back on. Set before each frame.
+    pub fn set_bypass(&mut self, bypass: bool, tails: bool) {
+        self.bypass_target = if bypass { 1.0 } else { 0.0 };
+        self.tails_target = if tails { 1.0 } else { 0.0 };
+    }
+
+    /// Whether the plugin has to process this frame, when it doesn't its wet output is ignored
+    pub fn processing(&self) -> bool {
+        self.bypass < 1.0 || self.tails > 0.0
+    }
+
+    /// Gain for the plugin's input, it fades to silence when bypassed with tails
+    pub fn input_gain(&self) -> f64 {
+        1.0 - self.bypass * self.tails
+    }
+
+    /// Blends one frame of dry input with the plugin's wet output and applies trim, a
+    /// coefficient, to the result, then fades to the bypassed output
+    pub fn process(
+        &mut self,
+        dry: [f64; 2],
+        wet: [f64; 2],
+        mix: f64,
+        law: MixLaw,
+        trim: f64,
+    ) -> [f64; 2] {
+        let dry = self.dry.process([dry[0] as f32, dry[1] as f32]);
+        let (dry_gain, wet_gain) = law.gains(mix);
+        let mut out = [0.0; 2];
+        for ((out, dry), wet) in out.iter_mut().zip(dry).zip(wet) {
+            let dry = dry as f64;
+            let mixed = (dry * dry_gain + wet * wet_gain) * trim;
+            let bypassed = dry + wet * wet_gain * trim * self.tails;
+            *out = mixed * (1.0 - self.bypass) + bypassed * self.bypass;
+        }
+        self.bypass = ramp(self.bypass, self.bypass_target, self.step);
+        self.tails = ramp(self.tails, self.tails_target, self.step);
+        out
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_mix_laws() {
+        for &law in [MixLaw::Linear, MixLaw::EqualPower].iter() {
+            assert_eq!(law.gains(0.0), (1.0, 0.0));
+            assert_eq!(law.gains(1.0), (0.0, 1.0));
+            assert_eq!(law.gains(1.5), (0.0, 1.0));
+        }
+        assert_eq!(MixLaw::Linear.gains(0.25), (0.75, 0.25));
+        let (dry, wet) = MixLaw::EqualPower.gains(0.5);
+        assert!((dry - 0.5f64.sqrt()).abs() < 1e-12);
+        assert!((wet - 0.5f64.sqrt()).abs() < 1e-12);
+        for i in 1..10 {
+            let (dry, wet) = MixLaw::EqualPower.gains(i as f64 / 10.0);
+            assert!((dry * dry + wet * wet - 1.0).abs() < 1e-12);
+        }
+        assert_eq!(MixLaw::from_param(0.0), MixLaw::Linear);
+        assert_eq!(MixLaw::from_param(1.0), MixLaw::EqualPower);
+    }
+
+    #[test]
+    fn test_mix_stage() {
+        let mut stage = MixStage::new(48000.0);
+        let wet = [0.3, -0.6];
+        assert_eq!(
+            stage.process([1.0, 1.0], wet, 1.0, MixLaw::EqualPower, 1.0),
+            wet
+        );
+        assert_eq!(
+            stage.process([0.5, -0.5], wet, 0.0, MixLaw::Linear, 2.0),
+            [1.0, -1.0]
+        );
+        assert_eq!(
+            stage.process([0.5, 0.5], [0.0; 2], 0.5, MixLaw::Linear, 0.5),
+            [0.125, 0.125]
+        );
+
+        // The dry signal lags by the latency, in step with the wet signal
+        let mut stage = MixStage::new(48000.0);
+        stage.set_latency(2);
+        let out: Vec<[f64; 2]> = (0..4)
+            .map(|i| {
+                let dry = if i == 0 { [0.5, 0.25] } else { [0.0; 2] };
+                stage.process(dry, [0.0; 2], 0.0, MixLaw::Linear, 1.0)
+            })
+            .collect();
+        assert_eq!(out, [[0.0; 2], [0.0; 2], [0.5, 0.25], [0.0; 2]]);
+    }
+
+    #[test]
+    fn test_bypass() {
+        // 20 ms at 48 kHz
+        let fade = 960;
+        let mut stage = MixStage::new(48000.0);
+        let run = |stage: &mut MixStage, bypass, tails, frames| {
+            let mut out = [0.0; 2];
+            for _ in 0..frames {
+                stage.set_bypass(bypass, tails);
+                out = stage.process([0.5; 2], [0.25; 2], 1.0, MixLaw::Linear, 4.0);
+            }
+            out
+        };
+        assert_eq!(run(&mut stage, false, false, 1), [1.0; 2]);
+
+        // Halfway through the fade the output is halfway to the dry input, at the end it's
+        // exactly the dry input and processing stops
+        let out = run(&mut stage, true, false, fade / 2);
+        assert!((out[0] - 0.75).abs() < 1e-3);
+        run(&mut stage, true, false, fade / 2);
+        assert!(!stage.processing());
+        assert_eq!(run(&mut stage, true, false, 1), [0.5; 2]);
+        assert_eq!(stage.input_gain(), 1.0);
+        assert_eq!(run(&mut stage, false, false, fade + 1), [1.0; 2]);
+        assert!(stage.processing());
+
+        // With tails the input fades out and the output carries on over the dry input
+        let mut stage = MixStage::new(48000.0);
+        run(&mut stage, false, true, 1);
+        assert_eq!(run(&mut stage, true, true, fade + 1), [1.5; 2]);
+        assert!(stage.processing());
+        assert_eq!(stage.input_gain(), 0.0);
+        run(&mut stage, false, true, fade / 4);
+        assert!((stage.input_gain() - 0.25).abs() < 1e-9);
+    }
+}
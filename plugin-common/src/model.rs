@@ -0,0 +1,136 @@
+// A plugin's model is declared once, with model!, and the CLAP, VST2 and VST3 exports take
+// their parameters from the same declaration as baseplug does. baseplug::model! keeps its
+// parameter table to itself, so model! passes the struct on to it and also reads the #[model]
+// and #[parameter] attributes into the ClapParam list.
+
+/// Declares a plugin's model like baseplug::model!, with every field an f32 parameter, and adds
+/// `clap_params()` listing them for ClapExport::clap_params. Ids follow the order of the fields,
+/// hosts save automation against them, so new parameters only ever go at the end.
+///
+/// Values are shown in dB for "Decibels" parameters, which the model holds as a coefficient,
+/// and as the model holds them otherwise. An optional `#[export(..)]` after `#[parameter(..)]`
+/// adds what baseplug has no place for: `stepped` for whole numbers only, `label = ".."` for
+/// the unit shown after the value.
+///
+/// ```ignore
+/// plugin_common::model! {
+///     #[derive(Debug, Clone, Serialize, Deserialize)]
+///     pub struct DynSatModel {
+///         #[model(min = 1.0, max = 10.0)]
+///         #[parameter(name = "Mode", unit = "Generic",
+///             gradient = "Linear")]
+///         #[export(stepped)]
+///         mode: f32,
+///     }
+/// }
+/// ```
+#[macro_export]
+macro_rules! model {
+    (
+        $(#[$($attr:tt)*])*
+        pub struct $model:ident {
+            $(
+                #[model($($range:tt)*)]
+                #[parameter($($parameter:tt)*)]
+                $(#[export($($export:tt)*)])?
+                $field:ident: f32
+            ),* $(,)?
+        }
+    ) => {
+        $crate::baseplug::model! {
+            $(#[$($attr)*])*
+            pub struct $model {
+                $(
+                    #[model($($range)*)]
+                    #[parameter($($parameter)*)]
+                    $field: f32
+                ),*
+            }
+        }
+
+        impl $model {
+            /// The parameters as the CLAP, VST2 and VST3 exports show them
+            // Each id is the count pushed before it, so the list can't be a vec![]
+            #[allow(clippy::vec_init_then_push)]
+            pub fn clap_params() -> Vec<$crate::clap::ClapParam<$model>> {
+                let mut params = Vec::new();
+                $(
+                    params.push($crate::__model_export!(
+                        $crate::__model_param!(
+                            $model,
+                            $field,
+                            params.len() as u32;
+                            $($range)*;
+                            $($parameter)*
+                        );
+                        $($($export)*)?
+                    ));
+                )*
+                params
+            }
+        }
+    };
+}
+
+// One field's ClapParam, from its #[model] and #[parameter] attributes
+#[doc(hidden)]
+#[macro_export]
+macro_rules! __model_param {
+    (
+        $model:ident, $field:ident, $id:expr;
+        min = $min:literal, max = $max:literal;
+        name = $name:literal, unit = "Decibels", gradient = $gradient:literal
+    ) => {
+        $crate::clap::ClapParam {
+            id: $id,
+            name: $name,
+            unit: "dB",
+            min: $min,
+            max: $max,
+            stepped: false,
+            exponent: $crate::vst2::gradient_exponent($gradient),
+            get: |model: &$model| $crate::units::Units::lin_to_db(model.$field as f64),
+            set: |model: &mut $model, value| {
+                model.$field = $crate::units::Units::db_to_lin(value) as f32
+            },
+        }
+    };
+    (
+        $model:ident, $field:ident, $id:expr;
+        min = $min:literal, max = $max:literal;
+        name = $name:literal, unit = $unit:literal, gradient = $gradient:literal
+    ) => {
+        $crate::clap::ClapParam {
+            id: $id,
+            name: $name,
+            unit: "",
+            min: $min,
+            max: $max,
+            stepped: false,
+            exponent: $crate::vst2::gradient_exponent($gradient),
+            get: |model: &$model| model.$field as f64,
+            set: |model: &mut $model, value| model.$field = value as f32,
+        }
+    };
+}
+
+// Applies the options of an #[export(..)] attribute to a ClapParam
+#[doc(hidden)]
+#[macro_export]
+macro_rules! __model_export {
+    ($param:expr;) => {
+        $param
+    };
+    ($param:expr; stepped $(, $($rest:tt)*)?) => {
+        $crate::__model_export!(
+            $crate::clap::ClapParam { stepped: true, ..$param };
+            $($($rest)*)?
+        )
+    };
+    ($param:expr; label = $label:literal $(, $($rest:tt)*)?) => {
+        $crate::__model_export!(
+            $crate::clap::ClapParam { unit: $label, ..$param };
+            $($($rest)*)?
+        )
+    };
+}
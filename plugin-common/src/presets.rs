@@ -0,0 +1,275 @@
+use std::ffi::OsStr;
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+
+use serde_json::Value;
+
+use crate::state::{self, VersionedState};
+
+pub const PRESET_EXTENSION: &str = "json";
+const NAME_KEY: &str = "name";
+
+/// A named model, user presets are saved as a versioned state with a name
+#[derive(Clone, Debug)]
+pub struct Preset<M> {
+    pub name: String,
+    pub model: M,
+}
+
+/// Presets shipped with a plugin, the first one should match the model's default
+pub trait FactoryPresets: Sized {
+    fn factory_presets() -> Vec<Preset<Self>>;
+}
+
+/// Default directory for a plugin's user presets, e.g. ~/.config/Varb/presets on Linux
+pub fn user_preset_dir(plugin_name: &str) -> Option<PathBuf> {
+    Some(dirs::config_dir()?.join(plugin_name).join("presets"))
+}
+
+/// Factory presets followed by any user presets, indexed like VST programs.
+/// The CLAP export lists them through preset discovery, the VST2 export as its programs and the
+/// VST3 export as a program list.
+pub struct PresetBank<M> {
+    presets: Vec<Preset<M>>,
+    factory_count: usize,
+    current: usize,
+}
+
+impl<M: FactoryPresets + VersionedState + Clone> Default for PresetBank<M> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<M: FactoryPresets + VersionedState + Clone> PresetBank<M> {
+    pub fn new() -> PresetBank<M> {
+        let presets = M::factory_presets();
+        PresetBank {
+            factory_count: presets.len(),
+            presets,
+            current: 0,
+        }
+    }
+
+    pub fn count(&self) -> usize {
+        self.presets.len()
+    }
+
+    pub fn factory_count(&self) -> usize {
+        self.factory_count
+    }
+
+    pub fn get(&self, index: usize) -> Option<&Preset<M>> {
+        self.presets.get(index)
+    }
+
+    pub fn find(&self, name: &str) -> Option<usize> {
+        self.presets.iter().position(|preset| preset.name == name)
+    }
+
+    pub fn program(&self) -> usize {
+        self.current
+    }
+
+    pub fn program_name(&self, index: usize) -> Option<&str> {
+        self.get(index).map(|preset| preset.name.as_str())
+    }
+
+    /// Makes index the current program and returns its model
+    pub fn set_program(&mut self, index: usize) -> Option<M> {
+        let model = self.get(index)?.model.clone();
+        self.current = index;
+        Some(model)
+    }
+
+    /// Replaces the user presets with the .json files in dir, sorted by name.
+    /// Files that can't be parsed are skipped and returned.
+    pub fn load_user_presets(&mut self, dir: &Path) -> io::Result<Vec<PathBuf>> {
+        let mut paths = Vec::new();
+        for entry in fs::read_dir(dir)? {
+            let path = entry?.path();
+            if path.extension() == Some(OsStr::new(PRESET_EXTENSION)) {
+                paths.push(path);
+            }
+        }
+        paths.sort();
+
+        self.presets.truncate(self.factory_count);
+        if self.current >= self.factory_count {
+            self.current = 0;
+        }
+        let mut skipped = Vec::new();
+        for path in paths {
+            match read_preset(&path) {
+                Ok(preset) => self.presets.push(preset),
+                Err(_) => skipped.push(path),
+            }
+        }
+        Ok(skipped)
+    }
+
+    /// Writes a user preset to dir and adds it to the bank, replacing a user preset with the
+    /// same name. Returns the path written.
+    pub fn save_user_preset(&mut self, dir: &Path, name: &str, model: &M) -> io::Result<PathBuf> {
+        let mut value = state::to_value(model)?;
+        if let Value::Object(envelope) = &mut value {
+            envelope.insert(NAME_KEY.to_string(), Value::from(name));
+        }
+        let path = dir.join(preset_file_name(name));
+        fs::create_dir_all(dir)?;
+        let json = serde_json::to_string_pretty(&value)
+            .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))?;
+        fs::write(&path, json)?;
+
+        let preset = Preset {
+            name: name.to_string(),
+            model: model.clone(),
+        };
+
+        match self.presets[self.factory_count..]
+            .iter()
+            .position(|p| p.name == preset.name)
+        {
+            Some(i) => self.presets[self.factory_count + i] = preset,
+            None => self.presets.push(preset),
+        }
+        Ok(path)
+    }
+}
+
+/// Reads a user preset, migrating it if it was saved by an older version
+pub fn read_preset<M: VersionedState>(path: &Path) -> io::Result<Preset<M>> {
+    let json = fs::read_to_string(path)?;
+    let value: Value =
+        serde_json::from_str(&json).map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))?;
+    let name = value
+        .get(NAME_KEY)
+        .and_then(|name| name.as_str())
+        .ok_or_else(|| io::Error::new(io::ErrorKind::InvalidData, "preset has no name"))?
+        .to_string();
+    Ok(Preset {
+        name,
+        model: state::from_value(value)?,
+    })
+}
+
+// Keeps names usable as file names on every platform
+fn preset_file_name(name: &str) -> String {
+    let stem: String = name
+        .chars()
+        .map(|c| {
+            if c.is_alphanumeric() || c == ' ' || c == '-' || c == '_' {
+                c
+            } else {
+                '_'
+            }
+        })
+        .collect();
+    format!("{}.{}", stem.trim(), PRESET_EXTENSION)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde::{Deserialize, Serialize};
+    use serde_json::Map;
+
+    #[derive(Clone, Debug, Default, PartialEq, Serialize, Deserialize)]
+    struct TestModel {
+        gain: f32,
+        mode: f32,
+    }
+
+    impl VersionedState for TestModel {
+        const STATE_VERSION: u32 = 1;
+
+        fn migrate(_from_version: u32, _model: &mut Map<String, Value>) {}
+    }
+
+    impl FactoryPresets for TestModel {
+        fn factory_presets() -> Vec<Preset<Self>> {
+            vec![
+                Preset {
+                    name: "Init".to_string(),
+                    model: TestModel {
+                        gain: 1.0,
+                        mode: 1.0,
+                    },
+                },
+                Preset {
+                    name: "Loud".to_string(),
+                    model: TestModel {
+                        gain: 4.0,
+                        mode: 2.0,
+                    },
+                },
+            ]
+        }
+    }
+
+    fn temp_dir(name: &str) -> PathBuf {
+        let dir = std::env::temp_dir().join(format!("presets-{}-{}", name, std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        dir
+    }
+
+    #[test]
+    fn test_factory_programs() {
+        let mut bank = PresetBank::<TestModel>::new();
+        assert_eq!(bank.count(), 2);
+        assert_eq!(bank.program_name(1), Some("Loud"));
+        assert_eq!(bank.find("Loud"), Some(1));
+        assert_eq!(bank.set_program(1).unwrap().gain, 4.0);
+        assert_eq!(bank.program(), 1);
+        assert!(bank.set_program(2).is_none());
+        assert_eq!(bank.program(), 1);
+    }
+
+    #[test]
+    fn test_user_presets_round_trip() {
+        let dir = temp_dir("round-trip");
+        let mut bank = PresetBank::<TestModel>::new();
+        let model = TestModel {
+            gain: 0.25,
+            mode: 3.0,
+        };
+        let path = bank.save_user_preset(&dir, "My/Preset", &model).unwrap();
+        assert_eq!(path.file_name().unwrap(), "My_Preset.json");
+        assert_eq!(bank.count(), 3);
+
+        // Saving again under the same name replaces it
+        bank.save_user_preset(&dir, "My/Preset", &model).unwrap();
+        assert_eq!(bank.count(), 3);
+
+        fs::write(dir.join("broken.json"), "{ not json").unwrap();
+        fs::write(dir.join("notes.txt"), "ignored").unwrap();
+        // Saved before presets had a version
+        fs::write(
+            dir.join("old.json"),
+            r#"{"name":"Old","model":{"gain":0.5}}"#,
+        )
+        .unwrap();
+
+        let mut loaded = PresetBank::<TestModel>::new();
+        let skipped = loaded.load_user_presets(&dir).unwrap();
+        assert_eq!(skipped, vec![dir.join("broken.json")]);
+        assert_eq!(loaded.count(), 4);
+        let index = loaded.find("My/Preset").unwrap();
+        assert_eq!(loaded.set_program(index), Some(model));
+        let index = loaded.find("Old").unwrap();
+        assert_eq!(
+            loaded.set_program(index),
+            Some(TestModel {
+                gain: 0.5,
+                mode: 0.0,
+            })
+        );
+
+        // Loading again doesn't duplicate anything
+        loaded.load_user_presets(&dir).unwrap();
+        assert_eq!(loaded.count(), 4);
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
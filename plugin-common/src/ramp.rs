@@ -0,0 +1,126 @@
+// Coefficient ramping for filters whose parameters are automated. Coefficients are only
+// recalculated when the parameters change, at most once per update segment, and are
+// interpolated across the segment so changes don't step.
+
+/// Coefficients that can be linearly ramped from one calculated set to another
+pub trait Interpolate: Copy {
+    /// Per sample step that takes self to target in n samples
+    fn step_to(&self, target: &Self, n: usize) -> Self;
+    /// Adds a step made by step_to
+    fn add_step(&mut self, step: &Self);
+}
+
+/// How often coefficients are recalculated while their parameters are changing
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum UpdateRate {
+    Sample,
+    Samples(usize),
+    Block,
+}
+
+/// Recalculates coefficients at the start of each update segment, only if the parameters have
+/// changed since the last calculation, then ramps to them across the segment.
+/// The coefficients reached at the end of a segment are the ones for the parameters at that sample.
+pub struct CoeffRamp<P, C> {
+    pub rate: UpdateRate,
+    params: P,
+    current: C,
+    target: C,
+    step: C,
+    ramping: bool,
+    remaining: usize,
+}
+
+impl<P: Copy + PartialEq, C: Interpolate> CoeffRamp<P, C> {
+    pub fn new(rate: UpdateRate, params: P, coeffs: C) -> CoeffRamp<P, C> {
+        CoeffRamp {
+            rate,
+            params,
+            current: coeffs,
+            target: coeffs,
+            step: coeffs,
+            ramping: false,
+            remaining: 0,
+        }
+    }
+
+    /// Coefficients for sample i of a block of nframes. params gives the parameters at a sample
+    /// in the block, calc turns parameters into coefficients and is only called when they change.
+    pub fn next<F, G>(&mut self, i: usize, nframes: usize, params: F, calc: G) -> C
+    where
+        F: Fn(usize) -> P,
+        G: FnOnce(P) -> C,
+    {
+        if self.remaining == 0 {
+            let len = match self.rate {
+                UpdateRate::Sample => 1,
+                UpdateRate::Samples(n) => n,
+                UpdateRate::Block => nframes,
+            }
+            .min(nframes - i)
+            .max(1);
+            let end_params = params(i + len - 1);
+            self.ramping = end_params != self.params;
+            if self.ramping {
+                self.params = end_params;
+                self.target = calc(end_params);
+                self.step = self.current.step_to(&self.target, len);
+            }
+            self.remaining = len;
+        }
+        self.remaining -= 1;
+        if self.remaining == 0 {
+            self.current = self.target;
+        } else if self.ramping {
+            self.current.add_step(&self.step);
+        }
+        self.current
+    }
+}
+
+impl Interpolate for f64 {
+    fn step_to(&self, target: &f64, n: usize) -> f64 {
+        (target - self) / n as f64
+    }
+    fn add_step(&mut self, step: &f64) {
+        *self += step;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_coeff_ramp() {
+        let params = [1.0, 1.0, 1.0, 1.0, 5.0, 5.0, 5.0, 5.0, 9.0, 9.0];
+        let mut calcs = 0;
+        let mut ramp = CoeffRamp::new(UpdateRate::Samples(4), 1.0, 1.0);
+        let mut out = Vec::new();
+        for i in 0..params.len() {
+            out.push(ramp.next(
+                i,
+                params.len(),
+                |j| params[j],
+                |p| {
+                    calcs += 1;
+                    p
+                },
+            ));
+        }
+        // Static for the first segment, then ramps to reach each segment's last value
+        assert_eq!(out, vec![1.0, 1.0, 1.0, 1.0, 2.0, 3.0, 4.0, 5.0, 7.0, 9.0]);
+        assert_eq!(calcs, 2);
+
+        let mut ramp = CoeffRamp::new(UpdateRate::Sample, 1.0, 1.0);
+        for (i, p) in params.iter().enumerate() {
+            assert_eq!(ramp.next(i, params.len(), |j| params[j], |p| p), *p);
+        }
+
+        let mut ramp = CoeffRamp::new(UpdateRate::Block, 0.0, 0.0);
+        let last = (0..params.len())
+            .map(|i| ramp.next(i, params.len(), |j| params[j], |p| p))
+            .last();
+        assert_eq!(last, Some(9.0));
+    }
+}
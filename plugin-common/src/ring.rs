@@ -0,0 +1,227 @@
+// Delay lines and sample history that can be resized on the audio thread without allocating.
+
+use std::ops::{Add, Mul, Sub};
+
+/// Sample types a VariableRingBuffer can hold
+pub trait Sample:
+    Copy + Default + Add<Output = Self> + Sub<Output = Self> + Mul<Output = Self>
+{
+    fn from_f64(x: f64) -> Self;
+}
+
+impl Sample for f32 {
+    fn from_f64(x: f64) -> f32 {
+        x as f32
+    }
+}
+
+impl Sample for f64 {
+    fn from_f64(x: f64) -> f64 {
+        x
+    }
+}
+
+/// Ring buffer with a size that can change at run time, up to the max_size given to new().
+/// All memory is allocated in new(), nothing after that allocates so it's safe to use on the
+/// audio thread, e.g. as a delay line.
+/// Delays count back from the most recently pushed value, which is at delay 0.
+/// Indices passed to get() count forward from the oldest value. Both wrap or clamp rather
+/// than panicking when out of range.
+pub struct VariableRingBuffer<T = f32> {
+    buffer: Vec<T>,
+    position: usize,
+    size: usize,
+}
+
+impl<T: Sample> VariableRingBuffer<T> {
+    pub fn new(init_size: usize, max_size: usize) -> VariableRingBuffer<T> {
+        let max_size = max_size.max(1);
+        VariableRingBuffer {
+            buffer: vec![T::default(); max_size],
+            position: 0,
+            size: init_size.max(1).min(max_size),
+        }
+    }
+
+    pub fn push(&mut self, value: T) {
+        self.buffer[self.position] = value;
+        self.position = (self.position + 1) % self.size;
+    }
+
+    /// The value that will be overwritten by the next push
+    pub fn oldest(&self) -> T {
+        self.buffer[self.position]
+    }
+
+    /// Value at index counting forward from the oldest, wraps at size
+    pub fn get(&self, index: usize) -> T {
+        self.buffer[(self.position + index % self.size) % self.size]
+    }
+
+    /// Value pushed delay samples ago, 0 being the most recent. Clamped to size - 1.
+    pub fn read(&self, delay: usize) -> T {
+        let delay = delay.min(self.size - 1);
+        self.buffer[(self.position + self.size - 1 - delay) % self.size]
+    }
+
+    /// Linearly interpolated read between whole sample delays. Clamped to 0..size - 1.
+    pub fn read_fractional(&self, delay: f64) -> T {
+        let delay = delay.max(0.0).min((self.size - 1) as f64);
+        let whole = delay.floor();
+        let a = self.read(whole as usize);
+        let b = self.read(whole as usize + 1);
+        a + (b - a) * T::from_f64(delay - whole)
+    }
+
+    /// Reads a tap for each delay into out, stopping at the shorter of the two
+    pub fn read_taps(&self, delays: &[usize], out: &mut [T]) {
+        for (out, delay) in out.iter_mut().zip(delays.iter()) {
+            *out = self.read(*delay);
+        }
+    }
+
+    pub fn size(&self) -> usize {
+        self.size
+    }
+
+    pub fn capacity(&self) -> usize {
+        self.buffer.len()
+    }
+
+    /// Changes the size in place without clearing. The most recent values are kept at the same
+    /// delays, when growing the newly added older values are zero.
+    /// Returns the new size, which is clamped to 1..capacity.
+    pub fn resize(&mut self, new_size: usize) -> usize {
+        let new_size = new_size.max(1).min(self.buffer.len());
+        let size = self.size;
+        // Put the contents in order, oldest first
+        self.buffer[..size].rotate_left(self.position);
+        if new_size > size {
+            for x in self.buffer[size..new_size].iter_mut() {
+                *x = T::default();
+            }
+            self.buffer[..new_size].rotate_right(new_size - size);
+        } else {
+            self.buffer[..size].rotate_left(size - new_size);
+        }
+        self.position = 0;
+        self.size = new_size;
+        new_size
+    }
+
+    pub fn clear(&mut self) {
+        for x in self.buffer.iter_mut() {
+            *x = T::default();
+        }
+        self.position = 0;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn filled(size: usize, max_size: usize, count: usize) -> VariableRingBuffer {
+        let mut buffer = VariableRingBuffer::new(size, max_size);
+        for i in 0..count {
+            buffer.push(i as f32);
+        }
+        buffer
+    }
+
+    #[test]
+    fn test_ring_buffer_get() {
+        let buffer = filled(4, 8, 10);
+        // Holds 6, 7, 8, 9, oldest first
+        assert_eq!(buffer.oldest(), 6.0);
+        assert_eq!(buffer.get(0), 6.0);
+        assert_eq!(buffer.get(3), 9.0);
+        assert_eq!(buffer.get(4), 6.0);
+        assert_eq!(buffer.get(9), 7.0);
+        assert_eq!(buffer.get(usize::MAX), buffer.get(usize::MAX % 4));
+    }
+
+    #[test]
+    fn test_ring_buffer_read() {
+        let buffer = filled(4, 8, 10);
+        assert_eq!(buffer.read(0), 9.0);
+        assert_eq!(buffer.read(3), 6.0);
+        // Clamped to the oldest
+        assert_eq!(buffer.read(4), 6.0);
+        assert_eq!(buffer.read(usize::MAX), 6.0);
+
+        assert_eq!(buffer.read_fractional(0.0), 9.0);
+        assert_eq!(buffer.read_fractional(0.5), 8.5);
+        assert_eq!(buffer.read_fractional(2.25), 6.75);
+        assert_eq!(buffer.read_fractional(-1.0), 9.0);
+        assert_eq!(buffer.read_fractional(100.0), 6.0);
+
+        let mut taps = [0.0; 3];
+        buffer.read_taps(&[0, 2, 3], &mut taps);
+        assert_eq!(taps, [9.0, 7.0, 6.0]);
+    }
+
+    #[test]
+    fn test_ring_buffer_f64() {
+        let mut buffer = VariableRingBuffer::<f64>::new(3, 3);
+        buffer.push(1.0);
+        buffer.push(2.0);
+        assert_eq!(buffer.read_fractional(0.5), 1.5);
+    }
+
+    #[test]
+    fn test_ring_buffer_resize() {
+        let mut buffer = filled(4, 8, 10);
+        assert_eq!(buffer.resize(6), 6);
+        // Grown without losing anything, the new older values are zero
+        assert_eq!(
+            (0..6).map(|d| buffer.read(d)).collect::<Vec<f32>>(),
+            vec![9.0, 8.0, 7.0, 6.0, 0.0, 0.0]
+        );
+        buffer.push(10.0);
+        assert_eq!(buffer.read(0), 10.0);
+        assert_eq!(buffer.read(5), 0.0);
+
+        // Shrinking keeps the most recent
+        assert_eq!(buffer.resize(2), 2);
+        assert_eq!(buffer.read(0), 10.0);
+        assert_eq!(buffer.read(1), 9.0);
+        buffer.push(11.0);
+        assert_eq!(buffer.oldest(), 10.0);
+
+        assert_eq!(buffer.resize(100), 8);
+        assert_eq!(buffer.resize(0), 1);
+        assert_eq!(buffer.read(0), 11.0);
+
+        buffer.clear();
+        assert_eq!(buffer.read(0), 0.0);
+    }
+
+    #[test]
+    fn test_ring_buffer_does_not_allocate() {
+        let mut buffer = filled(16, 64, 0);
+        let ptr = buffer.buffer.as_ptr();
+        let capacity = buffer.buffer.capacity();
+        let mut taps = [0.0; 4];
+        for i in 0..10000 {
+            buffer.push(i as f32);
+            if i % 100 == 0 {
+                buffer.resize(1 + i % 64);
+            }
+            buffer.read_taps(&[0, 1, 5, 100], &mut taps);
+            buffer.read_fractional(i as f64 * 0.1);
+        }
+        assert_eq!(buffer.buffer.as_ptr(), ptr);
+        assert_eq!(buffer.buffer.capacity(), capacity);
+    }
+
+    #[test]
+    fn test_ring_buffer_zero_size() {
+        let mut buffer = VariableRingBuffer::<f32>::new(0, 0);
+        buffer.push(1.0);
+        buffer.push(2.0);
+        assert_eq!(buffer.size(), 1);
+        assert_eq!(buffer.read(0), 2.0);
+        assert_eq!(buffer.get(5), 2.0);
+    }
+}
@@ -0,0 +1,203 @@
+// Test only checks that a plugin's process doesn't allocate, lock or log, with the "rt-check"
+// feature. A global allocator counts heap calls made by the checked thread, the plugins' locks
+// go through sync::Mutex, which counts them here, and a test logger counts log calls from it,
+// since any real logger writes under a lock. Locks taken through std directly can't be seen,
+// so take them through sync::Mutex.
+
+use std::alloc::{GlobalAlloc, Layout, System};
+use std::cell::Cell;
+use std::sync::Once;
+
+use baseplug::Plugin;
+use log::{LevelFilter, Metadata, Record};
+
+use crate::harness::Harness;
+
+struct CheckingAllocator;
+
+thread_local! {
+    static ARMED: Cell<bool> = const { Cell::new(false) };
+    static ALLOCATIONS: Cell<usize> = const { Cell::new(0) };
+    static DEALLOCATIONS: Cell<usize> = const { Cell::new(0) };
+    static LOG_CALLS: Cell<usize> = const { Cell::new(0) };
+    static LOCKS: Cell<usize> = const { Cell::new(0) };
+}
+
+fn count(counter: &'static std::thread::LocalKey<Cell<usize>>) {
+    // try_with, the allocator is still called while thread locals are being torn down
+    let _ = counter.try_with(|count| count.set(count.get() + 1));
+}
+
+unsafe impl GlobalAlloc for CheckingAllocator {
+    unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+        count(&ALLOCATIONS);
+        System.alloc(layout)
+    }
+
+    unsafe fn alloc_zeroed(&self, layout: Layout) -> *mut u8 {
+        count(&ALLOCATIONS);
+        System.alloc_zeroed(layout)
+    }
+
+    unsafe fn realloc(&self, ptr: *mut u8, layout: Layout, new_size: usize) -> *mut u8 {
+        count(&ALLOCATIONS);
+        System.realloc(ptr, layout, new_size)
+    }
+
+    unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+        count(&DEALLOCATIONS);
+        System.dealloc(ptr, layout)
+    }
+}
+
+#[global_allocator]
+static ALLOCATOR: CheckingAllocator = CheckingAllocator;
+
+struct CheckingLogger;
+
+impl log::Log for CheckingLogger {
+    fn enabled(&self, _metadata: &Metadata) -> bool {
+        true
+    }
+
+    fn log(&self, _record: &Record) {
+        if ARMED.with(|armed| armed.get()) {
+            count(&LOG_CALLS);
+        }
+    }
+
+    fn flush(&self) {}
+}
+
+static LOGGER: CheckingLogger = CheckingLogger;
+static INIT_LOGGER: Once = Once::new();
+
+/// Installs the logger that counts log calls. Panics if another logger got there first, log
+/// calls would go to it and never be counted.
+pub fn install_logger() {
+    INIT_LOGGER.call_once(|| {
+        log::set_logger(&LOGGER).expect("a logger was installed before rt_check's");
+        log::set_max_level(LevelFilter::Trace);
+    });
+}
+
+/// Counts a lock taken by this thread, sync::Mutex calls it
+pub(crate) fn count_lock() {
+    count(&LOCKS);
+}
+
+/// What happened on the checked thread while f ran
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct Violations {
+    pub allocations: usize,
+    pub deallocations: usize,
+    pub log_calls: usize,
+    pub locks: usize,
+}
+
+/// Allocations and reallocations made by this thread so far
+pub fn allocations() -> usize {
+    ALLOCATIONS.with(|count| count.get())
+}
+
+pub fn deallocations() -> usize {
+    DEALLOCATIONS.with(|count| count.get())
+}
+
+/// Runs f on this thread and returns anything it did that isn't real-time safe
+pub fn check<F: FnOnce()>(f: F) -> Violations {
+    install_logger();
+    let log_calls = LOG_CALLS.with(|count| count.get());
+    let locks = LOCKS.with(|count| count.get());
+    let before = (allocations(), deallocations());
+    ARMED.with(|armed| armed.set(true));
+    f();
+    ARMED.with(|armed| armed.set(false));
+    Violations {
+        allocations: allocations() - before.0,
+        deallocations: deallocations() - before.1,
+        log_calls: LOG_CALLS.with(|count| count.get()) - log_calls,
+        locks: LOCKS.with(|count| count.get()) - locks,
+    }
+}
+
+// Noise with a few blocks of silence, non-finite and over range samples mixed in, so process
+// goes through its clipping, sanitizing and logging paths
+fn test_signal(block: usize, nframes: usize, seed: &mut u32) -> Vec<f32> {
+    (0..nframes)
+        .map(|i| {
+            *seed = seed.wrapping_mul(1664525).wrapping_add(1013904223);
+            let noise = (*seed as f32 / u32::MAX as f32) * 2.0 - 1.0;
+            match (block % 8, i) {
+                (1, _) => 0.0,
+                (3, 0) => f32::NAN,
+                (5, 7) => f32::INFINITY,
+                (6, _) => noise * 100.0,
+                _ => noise,
+            }
+        })
+        .collect()
+}
+
+/// Runs the plugin's full process for blocks blocks of nframes at sample_rate and panics if
+/// it allocates, frees, locks or logs. The plugin and buffers are made outside the check.
+pub fn check_process<P: Plugin>(
+    model: impl Fn() -> P::Model,
+    sample_rate: f32,
+    nframes: usize,
+    blocks: usize,
+) {
+    let mut harness = Harness::<P>::new(model, sample_rate, nframes);
+    let mut seed = 1u32;
+    let inputs: Vec<Vec<f32>> = (0..blocks)
+        .map(|block| test_signal(block, nframes, &mut seed))
+        .collect();
+
+    for (block, input) in inputs.iter().enumerate() {
+        let violations = check(|| {
+            harness.process([input, input]);
+        });
+        assert_eq!(
+            violations,
+            Violations::default(),
+            "{} process block {} isn't real-time safe",
+            P::NAME,
+            block
+        );
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::sync::Mutex;
+
+    #[test]
+    fn test_check_catches_allocations_locks_and_logging() {
+        let mutex = Mutex::new(0);
+        let violations = check(|| {
+            let v = std::hint::black_box(vec![1.0f32; 16]);
+            assert_eq!(v.len(), 16);
+            *mutex.lock().unwrap() += 1;
+            log::warn!("from the audio thread");
+        });
+        assert_eq!(
+            violations,
+            Violations {
+                allocations: 1,
+                deallocations: 1,
+                log_calls: 1,
+                locks: 1,
+            }
+        );
+        assert_eq!(check(|| {}), Violations::default());
+    }
+
+    #[test]
+    fn test_plugin_logging_is_counted() {
+        // What a plugin's new does, it mustn't leave a logger installed that check can't see
+        let tag = crate::logging::init("Test");
+        let violations = check(|| log::info!(target: &tag, "from process"));
+        assert_eq!(violations.log_calls, 1);
+    }
+}
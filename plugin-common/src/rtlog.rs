@@ -0,0 +1,528 @@
+// Logging from process. The audio thread pushes records into a lock-free queue per instance
+// and one background thread per process checks every instance's queue. It hands the queues with
+// records in them to writer threads, which run the sinks. There's one writer unless a sink
+// blocks: a queue is only with one writer at a time, so a slow sink holds up its own records and
+// the drain thread starts another writer for everyone else's. The threads start with the first
+// RtLog and stop once the last one is dropped, so nothing of a plugin's is left running when a
+// host unloads it.
+
+use std::cell::UnsafeCell;
+use std::sync::atomic::{AtomicBool, AtomicIsize, AtomicUsize, Ordering};
+use std::sync::mpsc::{self, Receiver, Sender};
+use std::sync::{Arc, MutexGuard};
+use std::thread::{self, JoinHandle};
+use std::time::Duration;
+
+use log::Level;
+
+use crate::sync::Mutex;
+
+const QUEUE_SIZE: usize = 256;
+const DRAIN_INTERVAL: Duration = Duration::from_millis(50);
+
+/// A log message from the audio thread, the message is static so nothing needs formatting or
+/// allocating until the background thread writes it
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct RtRecord {
+    pub level: Level,
+    pub message: &'static str,
+    pub value: f64,
+}
+
+// Single producer single consumer ring, head and tail only ever count up
+struct Queue {
+    records: Box<[UnsafeCell<RtRecord>]>,
+    head: AtomicUsize,
+    tail: AtomicUsize,
+    dropped: AtomicUsize,
+}
+
+// The producer only writes slots the consumer has released and the consumer only reads slots
+// the producer has published, RtLog::push taking &mut self keeps it to one producer
+unsafe impl Sync for Queue {}
+
+impl Queue {
+    fn new(size: usize) -> Queue {
+        let empty = RtRecord {
+            level: Level::Info,
+            message: "",
+            value: 0.0,
+        };
+        Queue {
+            records: (0..size).map(|_| UnsafeCell::new(empty)).collect(),
+            head: AtomicUsize::new(0),
+            tail: AtomicUsize::new(0),
+            dropped: AtomicUsize::new(0),
+        }
+    }
+
+    fn push(&self, record: RtRecord) -> bool {
+        let tail = self.tail.load(Ordering::Relaxed);
+        let head = self.head.load(Ordering::Acquire);
+        if tail.wrapping_sub(head) >= self.records.len() {
+            self.dropped.fetch_add(1, Ordering::Relaxed);
+            return false;
+        }
+        unsafe {
+            *self.records[tail % self.records.len()].get() = record;
+        }
+        self.tail.store(tail.wrapping_add(1), Ordering::Release);
+        true
+    }
+
+    fn pop(&self) -> Option<RtRecord> {
+        let head = self.head.load(Ordering::Relaxed);
+        let tail = self.tail.load(Ordering::Acquire);
+        if head == tail {
+            return None;
+        }
+        let record = unsafe { *self.records[head % self.records.len()].get() };
+        self.head.store(head.wrapping_add(1), Ordering::Release);
+        Some(record)
+    }
+
+    fn is_empty(&self) -> bool {
+        self.head.load(Ordering::Relaxed) == self.tail.load(Ordering::Acquire)
+    }
+}
+
+type Sink = Box<dyn FnMut(Option<&RtRecord>, usize) + Send>;
+
+// A queue with the sink its records go to
+struct Source {
+    queue: Queue,
+    sink: Mutex<Sink>,
+    // Set while it's with a writer, so it's only ever with one
+    writing: AtomicBool,
+}
+
+impl Source {
+    fn pending(&self) -> bool {
+        !self.queue.is_empty() || self.queue.dropped.load(Ordering::Relaxed) > 0
+    }
+
+    fn drain(&self) {
+        let mut sink = match self.sink.lock() {
+            Ok(sink) => sink,
+            Err(poisoned) => poisoned.into_inner(),
+        };
+        let dropped = self.queue.dropped.swap(0, Ordering::Relaxed);
+        if dropped > 0 {
+            sink(None, dropped);
+        }
+        while let Some(record) = self.queue.pop() {
+            sink(Some(&record), 0);
+        }
+    }
+}
+
+// The threads the sinks run on. Queues wait for a free writer, and if they're still waiting a
+// round later the writers are stuck in slow sinks, so another one is started.
+struct Writers {
+    jobs: Option<Sender<Arc<Source>>>,
+    receiver: Arc<Mutex<Receiver<Arc<Source>>>>,
+    // Writers waiting for a queue, less the queues sent that none of them has taken yet
+    free: Arc<AtomicIsize>,
+    stalled: bool,
+    threads: Vec<JoinHandle<()>>,
+}
+
+impl Writers {
+    fn new() -> Writers {
+        let (jobs, receiver) = mpsc::channel();
+        let mut writers = Writers {
+            jobs: Some(jobs),
+            receiver: Arc::new(Mutex::new(receiver)),
+            free: Arc::new(AtomicIsize::new(0)),
+            stalled: false,
+            threads: Vec::new(),
+        };
+        writers.spawn();
+        writers
+    }
+
+    fn spawn(&mut self) {
+        let receiver = self.receiver.clone();
+        let free = self.free.clone();
+        let writer = thread::Builder::new()
+            .name("rtlog writer".to_string())
+            .spawn(move || loop {
+                free.fetch_add(1, Ordering::AcqRel);
+                let job = match receiver.lock() {
+                    Ok(receiver) => receiver.recv(),
+                    Err(poisoned) => poisoned.into_inner().recv(),
+                };
+                // Ends once the drain thread has stopped
+                let source: Arc<Source> = match job {
+                    Ok(source) => source,
+                    Err(_) => break,
+                };
+                source.drain();
+                source.writing.store(false, Ordering::Release);
+            });
+        // If it can't be started the queue waits for the writers there are
+        if let Ok(writer) = writer {
+            self.threads.push(writer);
+        }
+    }
+
+    fn send(&mut self, source: Arc<Source>) {
+        self.free.fetch_sub(1, Ordering::AcqRel);
+        if let Some(jobs) = &self.jobs {
+            let _ = jobs.send(source);
+        }
+    }
+
+    // Called once a round, starts a writer when queues have waited since the last round
+    fn check_stalled(&mut self) {
+        let stalled = self.free.load(Ordering::Acquire) < 0;
+        if stalled && self.stalled {
+            self.spawn();
+        }
+        self.stalled = stalled;
+    }
+}
+
+impl Drop for Writers {
+    // Joins every writer, one that's in a sink returns once the sink does
+    fn drop(&mut self) {
+        self.jobs = None;
+        for writer in self.threads.drain(..) {
+            let _ = writer.join();
+        }
+    }
+}
+
+struct Drain {
+    sources: Vec<Arc<Source>>,
+    worker: Option<(JoinHandle<()>, Arc<AtomicBool>)>,
+}
+
+static DRAIN: Mutex<Drain> = Mutex::new(Drain {
+    sources: Vec::new(),
+    worker: None,
+});
+
+fn lock_drain() -> MutexGuard<'static, Drain> {
+    match DRAIN.lock() {
+        Ok(drain) => drain,
+        Err(poisoned) => poisoned.into_inner(),
+    }
+}
+
+fn spawn_worker() -> Option<(JoinHandle<()>, Arc<AtomicBool>)> {
+    let stop = Arc::new(AtomicBool::new(false));
+    let worker_stop = stop.clone();
+    thread::Builder::new()
+        .name("rtlog".to_string())
+        .spawn(move || {
+            let mut writers = Writers::new();
+            while !worker_stop.load(Ordering::Acquire) {
+                // Sinks run on the writers, outside the lock, so a slow one doesn't hold up new
+                // instances or the other queues
+                let sources = lock_drain().sources.clone();
+                for source in &sources {
+                    if source.pending() && !source.writing.swap(true, Ordering::AcqRel) {
+                        writers.send(source.clone());
+                    }
+                }
+                writers.check_stalled();
+                thread::park_timeout(DRAIN_INTERVAL);
+            }
+        })
+        .ok()
+        .map(|worker| (worker, stop))
+}
+
+/// Logging that's safe to call from process, push never locks, allocates or waits.
+/// The shared background threads write the records out, if the queue is full records are
+/// dropped and counted instead.
+pub struct RtLog {
+    source: Arc<Source>,
+}
+
+impl RtLog {
+    /// Writes records to log with the instance tag as the target
+    pub fn new(tag: String) -> RtLog {
+        RtLog::with_sink(move |record, dropped| {
+            if dropped > 0 {
+                log::warn!(target: &tag, "dropped {} log records", dropped);
+            }
+            if let Some(record) = record {
+                log::log!(target: &tag, record.level, "{}: {}", record.message, record.value);
+            }
+        })
+    }
+
+    /// Hands each record to sink on a writer thread, along with the number of records dropped
+    /// since the last call. A sink that blocks holds up this log's records, and its drop, but
+    /// not other logs'.
+    pub fn with_sink<F>(sink: F) -> RtLog
+    where
+        F: FnMut(Option<&RtRecord>, usize) + Send + 'static,
+    {
+        let source = Arc::new(Source {
+            queue: Queue::new(QUEUE_SIZE),
+            sink: Mutex::new(Box::new(sink)),
+            writing: AtomicBool::new(false),
+        });
+        let mut drain = lock_drain();
+        drain.sources.push(source.clone());
+        // If the thread can't be started records fill the queue and are dropped, until the
+        // log is dropped and writes them out
+        if drain.worker.is_none() {
+            drain.worker = spawn_worker();
+        }
+        RtLog { source }
+    }
+
+    /// Queues a record, returns false if the queue was full and it was dropped
+    pub fn push(&mut self, level: Level, message: &'static str, value: f64) -> bool {
+        self.source.queue.push(RtRecord {
+            level,
+            message,
+            value,
+        })
+    }
+}
+
+impl Drop for RtLog {
+    // Writes out what's left in the queue, and stops the threads after the last log
+    fn drop(&mut self) {
+        let worker = {
+            let mut drain = lock_drain();
+            drain
+                .sources
+                .retain(|source| !Arc::ptr_eq(source, &self.source));
+            if drain.sources.is_empty() {
+                drain.worker.take()
+            } else {
+                None
+            }
+        };
+        if let Some((worker, stop)) = worker {
+            stop.store(true, Ordering::Release);
+            worker.thread().unpark();
+            let _ = worker.join();
+        }
+        self.source.drain();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::mpsc;
+
+    #[test]
+    fn test_queue_order_and_overflow() {
+        let queue = Queue::new(4);
+        for i in 0..6 {
+            let pushed = queue.push(RtRecord {
+                level: Level::Warn,
+                message: "value",
+                value: i as f64,
+            });
+            assert_eq!(pushed, i < 4);
+        }
+        assert_eq!(queue.dropped.load(Ordering::Relaxed), 2);
+        for i in 0..4 {
+            assert_eq!(queue.pop().unwrap().value, i as f64);
+        }
+        assert!(queue.pop().is_none());
+        // Wraps around the ring
+        for i in 0..10 {
+            assert!(queue.push(RtRecord {
+                level: Level::Info,
+                message: "again",
+                value: i as f64,
+            }));
+            assert_eq!(queue.pop().unwrap().value, i as f64);
+        }
+    }
+
+    #[test]
+    fn test_records_reach_sink() {
+        let (sender, receiver) = mpsc::channel();
+        let mut log = RtLog::with_sink(move |record, dropped| {
+            let _ = sender.send((record.copied(), dropped));
+        });
+        assert!(log.push(Level::Warn, "clipping", 1.5));
+        let (record, _) = receiver.recv_timeout(Duration::from_secs(5)).unwrap();
+        assert_eq!(
+            record,
+            Some(RtRecord {
+                level: Level::Warn,
+                message: "clipping",
+                value: 1.5,
+            })
+        );
+    }
+
+    fn source<F>(sink: F) -> Arc<Source>
+    where
+        F: FnMut(Option<&RtRecord>, usize) + Send + 'static,
+    {
+        Arc::new(Source {
+            queue: Queue::new(4),
+            sink: Mutex::new(Box::new(sink)),
+            writing: AtomicBool::new(true),
+        })
+    }
+
+    #[test]
+    fn test_queues_share_one_writer() {
+        let (sender, receiver) = mpsc::channel();
+        let mut writers = Writers::new();
+        for i in 0..8 {
+            let sender = sender.clone();
+            let source = source(move |record, _| {
+                let _ = sender.send((record.copied(), thread::current().id()));
+            });
+            assert!(source.queue.push(RtRecord {
+                level: Level::Info,
+                message: "value",
+                value: i as f64,
+            }));
+            writers.send(source);
+        }
+        let threads: Vec<thread::ThreadId> = (0..8)
+            .map(|_| receiver.recv_timeout(Duration::from_secs(5)).unwrap().1)
+            .collect();
+        assert!(threads.iter().all(|thread| *thread == threads[0]));
+        assert_ne!(threads[0], thread::current().id());
+        assert_eq!(writers.threads.len(), 1);
+    }
+
+    #[test]
+    fn test_stuck_writer_gets_a_replacement() {
+        let mut writers = Writers::new();
+        let (entered, wait_entered) = mpsc::channel();
+        let (release, wait) = mpsc::channel::<()>();
+        let slow = source(move |_, _| {
+            let _ = entered.send(());
+            let _ = wait.recv();
+        });
+        slow.queue.dropped.store(1, Ordering::Relaxed);
+        writers.send(slow.clone());
+        wait_entered.recv_timeout(Duration::from_secs(5)).unwrap();
+
+        // The only writer is in the slow sink, so the next queue waits for a round, then gets
+        // a new writer
+        let (sender, receiver) = mpsc::channel();
+        let fast = source(move |record, _| {
+            let _ = sender.send(record.copied());
+        });
+        assert!(fast.queue.push(RtRecord {
+            level: Level::Warn,
+            message: "value",
+            value: 1.0,
+        }));
+        writers.send(fast.clone());
+        writers.check_stalled();
+        assert_eq!(writers.threads.len(), 1);
+        writers.check_stalled();
+        assert_eq!(writers.threads.len(), 2);
+        let record = receiver.recv_timeout(Duration::from_secs(5)).unwrap();
+        assert_eq!(record.unwrap().value, 1.0);
+        assert!(slow.writing.load(Ordering::Acquire));
+
+        drop(release);
+        drop(writers);
+        assert!(!slow.writing.load(Ordering::Acquire));
+        assert!(!fast.writing.load(Ordering::Acquire));
+    }
+
+    // The log's sink is stuck on its first record until release is dropped, so the queue fills
+    fn blocked_log() -> (RtLog, Sender<()>, Receiver<(bool, usize)>) {
+        let (entered, wait_entered) = mpsc::channel();
+        let (release, wait) = mpsc::channel::<()>();
+        let (sender, receiver) = mpsc::channel();
+        let mut log = RtLog::with_sink(move |record, dropped| {
+            let _ = entered.send(());
+            let _ = wait.recv();
+            let _ = sender.send((record.is_some(), dropped));
+        });
+        assert!(log.push(Level::Info, "first", 0.0));
+        wait_entered.recv_timeout(Duration::from_secs(5)).unwrap();
+        (log, release, receiver)
+    }
+
+    #[test]
+    fn test_full_queue_drops_and_counts() {
+        let (mut log, release, receiver) = blocked_log();
+        // The sink is stuck, so pushes only return because they never wait for it
+        for i in 0..QUEUE_SIZE {
+            assert!(log.push(Level::Info, "value", i as f64));
+        }
+        for i in 0..10 {
+            assert!(!log.push(Level::Info, "value", i as f64));
+            assert_eq!(log.source.queue.dropped.load(Ordering::Relaxed), i + 1);
+        }
+
+        drop(release);
+        drop(log);
+        let written: Vec<(bool, usize)> = receiver.iter().collect();
+        let records = written.iter().filter(|(record, _)| *record).count();
+        let dropped: usize = written.iter().map(|(_, dropped)| dropped).sum();
+        assert_eq!(records, QUEUE_SIZE + 1);
+        assert_eq!(dropped, 10);
+    }
+
+    #[test]
+    fn test_slow_sink_does_not_starve_others() {
+        let (_blocked, release, _) = blocked_log();
+        let (sender, receiver) = mpsc::channel();
+        let mut log = RtLog::with_sink(move |record, _| {
+            let _ = sender.send(record.copied());
+        });
+        for i in 0..3 {
+            assert!(log.push(Level::Info, "value", i as f64));
+            let record = receiver.recv_timeout(Duration::from_secs(5)).unwrap();
+            assert_eq!(record.unwrap().value, i as f64);
+        }
+        drop(release);
+    }
+
+    #[test]
+    fn test_drop_writes_out_the_queue() {
+        let (sender, receiver) = mpsc::channel();
+        let mut log = RtLog::with_sink(move |record, _| {
+            let _ = sender.send(record.copied());
+        });
+        for i in 0..8 {
+            assert!(log.push(Level::Info, "value", i as f64));
+        }
+        drop(log);
+        // The sink goes with the log, so this ends
+        let written: Vec<Option<RtRecord>> = receiver.iter().collect();
+        assert_eq!(written.len(), 8);
+    }
+
+    #[cfg(feature = "rt-check")]
+    #[test]
+    fn test_push_never_allocates() {
+        use crate::rt_check;
+
+        let (mut log, release, receiver) = blocked_log();
+        let mut pushed = 0;
+        let violations = rt_check::check(|| {
+            for i in 0..QUEUE_SIZE * 4 {
+                if log.push(Level::Info, "value", i as f64) {
+                    pushed += 1;
+                }
+            }
+        });
+        assert_eq!(violations, rt_check::Violations::default());
+        assert_eq!(pushed, QUEUE_SIZE);
+
+        // Everything pushed is written and everything else is counted as dropped
+        drop(release);
+        drop(log);
+        let written: Vec<(bool, usize)> = receiver.iter().collect();
+        let records = written.iter().filter(|(record, _)| *record).count();
+        let dropped: usize = written.iter().map(|(_, dropped)| dropped).sum();
+        assert_eq!(records, pushed + 1);
+        assert_eq!(dropped, QUEUE_SIZE * 3);
+    }
+}
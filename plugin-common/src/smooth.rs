@@ -0,0 +1,216 @@
+// Per sample parameter smoothing inside a plugin, for values the model's own smoothing
+// doesn't cover or that need a particular ramp shape.
+
+// Exponential smoothing snaps to the target once it's closer than this
+const SETTLE_THRESHOLD: f64 = 1e-6;
+// Multiplicative ramps start from or end at this instead of zero, -100dB
+const MIN_GAIN: f64 = 1e-5;
+
+/// How a Smooth moves towards its target
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Curve {
+    /// One pole, covers 63% of the distance to the target in time_ms
+    Exponential { time_ms: f64 },
+    /// Straight line to the target over a fixed number of samples
+    Linear { samples: usize },
+    /// Constant ratio per sample over a fixed number of samples, a straight line in dB.
+    /// For gains, values at or below zero ramp from or to -100dB.
+    Multiplicative { samples: usize },
+}
+
+/// Smooths a parameter towards a target, call next once per sample
+pub struct Smooth {
+    curve: Curve,
+    value: f64,
+    target: f64,
+    // Exponential coefficient, or the per sample linear step or ratio
+    step: f64,
+    remaining: usize,
+}
+
+impl Smooth {
+    pub fn new(value: f64, curve: Curve, sample_rate: f64) -> Smooth {
+        let mut smooth = Smooth {
+            curve,
+            value,
+            target: value,
+            step: 0.0,
+            remaining: 0,
+        };
+        smooth.set_sample_rate(sample_rate);
+        smooth
+    }
+
+    /// Only exponential smoothing depends on the sample rate, ramps are given in samples
+    pub fn set_sample_rate(&mut self, sample_rate: f64) {
+        if let Curve::Exponential { time_ms } = self.curve {
+            self.step = (-1000.0 / (time_ms.max(0.001) * sample_rate)).exp();
+        }
+    }
+
+    /// Starts moving towards target, a ramp restarts only if the target has changed
+    pub fn set_target(&mut self, target: f64) {
+        if target == self.target {
+            return;
+        }
+        self.target = target;
+        match self.curve {
+            Curve::Exponential { .. } => {}
+            Curve::Linear { samples: 0 } | Curve::Multiplicative { samples: 0 } => {
+                self.value = target;
+                self.remaining = 0;
+            }
+            Curve::Linear { samples } => {
+                self.remaining = samples;
+                self.step = (target - self.value) / samples as f64;
+            }
+            Curve::Multiplicative { samples } => {
+                self.remaining = samples;
+                self.value = self.value.max(MIN_GAIN);
+                self.step = (target.max(MIN_GAIN) / self.value).powf(1.0 / samples as f64);
+            }
+        }
+    }
+
+    /// Jumps straight to value
+    pub fn reset(&mut self, value: f64) {
+        self.value = value;
+        self.target = value;
+        self.remaining = 0;
+    }
+
+    /// Advances one sample and returns the new value
+    #[allow(clippy::should_implement_trait)]
+    pub fn next(&mut self) -> f64 {
+        match self.curve {
+            Curve::Exponential { .. } => {
+                if self.value != self.target {
+                    self.value = self.target + self.step * (self.value - self.target);
+                    if (self.target - self.value).abs() < SETTLE_THRESHOLD {
+                        self.value = self.target;
+                    }
+                }
+            }
+            Curve::Linear { .. } => {
+                if self.remaining > 0 {
+                    self.remaining -= 1;
+                    self.value = if self.remaining == 0 {
+                        self.target
+                    } else {
+                        self.value + self.step
+                    };
+                }
+            }
+            Curve::Multiplicative { .. } => {
+                if self.remaining > 0 {
+                    self.remaining -= 1;
+                    self.value = if self.remaining == 0 {
+                        self.target
+                    } else {
+                        self.value * self.step
+                    };
+                }
+            }
+        }
+        self.value
+    }
+
+    pub fn value(&self) -> f64 {
+        self.value
+    }
+
+    pub fn target(&self) -> f64 {
+        self.target
+    }
+
+    /// True once the value has reached the target, callers can skip per sample work
+    pub fn is_settled(&self) -> bool {
+        self.value == self.target
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_exponential() {
+        for &sample_rate in [8000.0, 44100.0, 192000.0].iter() {
+            let mut smooth = Smooth::new(0.0, Curve::Exponential { time_ms: 10.0 }, sample_rate);
+            smooth.set_target(1.0);
+            let n = (sample_rate * 0.01) as usize;
+            for _ in 0..n {
+                smooth.next();
+            }
+            // One time constant is the same fraction of the way at any sample rate
+            assert!((smooth.value() - (1.0 - (-1.0f64).exp())).abs() < 1e-3);
+            assert!(!smooth.is_settled());
+            for _ in 0..n * 20 {
+                smooth.next();
+            }
+            assert!(smooth.is_settled());
+            assert_eq!(smooth.value(), 1.0);
+        }
+    }
+
+    #[test]
+    fn test_exponential_short_time_is_stable() {
+        // The old linear factor overshot once the time was shorter than a sample
+        let mut smooth = Smooth::new(0.0, Curve::Exponential { time_ms: 0.001 }, 8000.0);
+        smooth.set_target(1.0);
+        let mut last = 0.0;
+        for _ in 0..10 {
+            let x = smooth.next();
+            assert!(x >= last && x <= 1.0);
+            last = x;
+        }
+        assert!(smooth.is_settled());
+    }
+
+    #[test]
+    fn test_linear() {
+        let mut smooth = Smooth::new(1.0, Curve::Linear { samples: 4 }, 48000.0);
+        assert!(smooth.is_settled());
+        smooth.set_target(3.0);
+        assert_eq!(smooth.next(), 1.5);
+        assert_eq!(smooth.next(), 2.0);
+        assert_eq!(smooth.next(), 2.5);
+        assert!(!smooth.is_settled());
+        assert_eq!(smooth.next(), 3.0);
+        assert!(smooth.is_settled());
+        assert_eq!(smooth.next(), 3.0);
+
+        // Setting the same target again doesn't restart anything
+        smooth.set_target(3.0);
+        assert!(smooth.is_settled());
+
+        let mut smooth = Smooth::new(1.0, Curve::Linear { samples: 0 }, 48000.0);
+        smooth.set_target(2.0);
+        assert!(smooth.is_settled());
+        assert_eq!(smooth.next(), 2.0);
+    }
+
+    #[test]
+    fn test_multiplicative() {
+        let mut smooth = Smooth::new(1.0, Curve::Multiplicative { samples: 4 }, 48000.0);
+        smooth.set_target(0.01);
+        // -10dB per sample
+        let step = 10.0f64.powf(-10.0 / 20.0);
+        let mut expected = 1.0;
+        for _ in 0..3 {
+            expected *= step;
+            assert!((smooth.next() - expected).abs() < 1e-12);
+        }
+        assert_eq!(smooth.next(), 0.01);
+        assert!(smooth.is_settled());
+
+        // Down to silence and back
+        smooth.set_target(0.0);
+        for _ in 0..4 {
+            assert!(smooth.next() >= 0.0);
+        }
+        assert_eq!(smooth.value(), 0.0);
+        smooth.set_target(1.0);
+        assert!((smooth.next() - MIN_GAIN.powf(0.75)).abs() < 1e-12);
+    }
+}
@@ -0,0 +1,236 @@
+use std::io;
+
+use serde::de::DeserializeOwned;
+use serde::Serialize;
+use serde_json::{Map, Value};
+
+use crate::midi::MidiMap;
+
+const VERSION_KEY: &str = "version";
+const MODEL_KEY: &str = "model";
+const MIDI_KEY: &str = "midi";
+
+/// A model that's saved with a schema version.
+/// Adding a parameter doesn't need a new version, parameters missing from old state take
+/// their default. Bump the version when a parameter is renamed, rescaled or changes meaning,
+/// and handle the old version in migrate.
+pub trait VersionedState: Default + Serialize + DeserializeOwned {
+    const STATE_VERSION: u32;
+
+    /// Upgrades model fields saved at from_version to from_version + 1.
+    /// Version 0 is state saved before there was a version.
+    fn migrate(from_version: u32, model: &mut Map<String, Value>);
+}
+
+fn invalid_data<E: Into<Box<dyn std::error::Error + Send + Sync>>>(error: E) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, error)
+}
+
+/// The model in a {"version": .., "model": {..}} envelope
+pub fn to_value<M: VersionedState>(model: &M) -> io::Result<Value> {
+    let mut envelope = Map::new();
+    envelope.insert(VERSION_KEY.to_string(), Value::from(M::STATE_VERSION));
+    envelope.insert(
+        MODEL_KEY.to_string(),
+        serde_json::to_value(model).map_err(invalid_data)?,
+    );
+    Ok(Value::Object(envelope))
+}
+
+pub fn to_json<M: VersionedState>(model: &M) -> io::Result<String> {
+    serde_json::to_string(&to_value(model)?).map_err(invalid_data)
+}
+
+/// Loads an envelope of any version, or a bare model saved before versioning.
+/// State from a newer version loads as far as this version understands it, parameters it
+/// doesn't know are dropped.
+pub fn from_value<M: VersionedState>(value: Value) -> io::Result<M> {
+    let (version, model) = match value {
+        Value::Object(mut envelope) if envelope.contains_key(MODEL_KEY) => {
+            let version = match envelope.get(VERSION_KEY) {
+                None => 0,
+                Some(version) => version
+                    .as_u64()
+                    .ok_or_else(|| invalid_data("state version is not a number"))?
+                    as u32,
+            };
+            (version, envelope.remove(MODEL_KEY).unwrap())
+        }
+        model => (0, model),
+    };
+    let mut model = match model {
+        Value::Object(model) => model,
+        _ => return Err(invalid_data("state model is not an object")),
+    };
+
+    for from_version in version..M::STATE_VERSION {
+        M::migrate(from_version, &mut model);
+    }
+
+    let mut state = match serde_json::to_value(M::default()).map_err(invalid_data)? {
+        Value::Object(state) => state,
+        _ => return Err(invalid_data("model doesn't serialize to an object")),
+    };
+    for (key, value) in model {
+        if let Some(field) = state.get_mut(&key) {
+            *field = value;
+        }
+    }
+    serde_json::from_value(Value::Object(state)).map_err(invalid_data)
+}
+
+pub fn from_json<M: VersionedState>(json: &str) -> io::Result<M> {
+    from_value(serde_json::from_str(json).map_err(invalid_data)?)
+}
+
+/// State for hosts to save, the envelope with the MIDI learn mappings next to the model
+pub fn plugin_to_json<M: VersionedState>(model: &M, midi: &MidiMap) -> io::Result<String> {
+    let mut state = to_value(model)?;
+    if let Value::Object(envelope) = &mut state {
+        envelope.insert(MIDI_KEY.to_string(), midi.to_value());
+    }
+    serde_json::to_string(&state).map_err(invalid_data)
+}
+
+/// Loads state saved by plugin_to_json or any from_json loads. The MIDI mappings are replaced
+/// by the saved ones, state without any clears them. Nothing changes if the model doesn't load.
+pub fn plugin_from_json<M: VersionedState>(json: &str, midi: &MidiMap) -> io::Result<M> {
+    let mut state: Value = serde_json::from_str(json).map_err(invalid_data)?;
+    let mappings = match &mut state {
+        Value::Object(envelope) if envelope.contains_key(MODEL_KEY) => envelope.remove(MIDI_KEY),
+        _ => None,
+    };
+    let model = from_value(state)?;
+    midi.load_value(mappings.as_ref());
+    Ok(model)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde::Deserialize;
+
+    // v1 added the envelope, v2 renamed level to gain and changed it from a percentage to a
+    // coefficient, drive was added without a version bump
+    #[derive(Debug, PartialEq, Serialize, Deserialize)]
+    struct TestModel {
+        gain: f32,
+        mode: f32,
+        drive: f32,
+    }
+
+    impl Default for TestModel {
+        fn default() -> Self {
+            TestModel {
+                gain: 1.0,
+                mode: 1.0,
+                drive: 0.5,
+            }
+        }
+    }
+
+    impl VersionedState for TestModel {
+        const STATE_VERSION: u32 = 2;
+
+        fn migrate(from_version: u32, model: &mut Map<String, Value>) {
+            if from_version == 1 {
+                if let Some(level) = model.remove("level").and_then(|v| v.as_f64()) {
+                    model.insert("gain".to_string(), Value::from(level / 100.0));
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn test_round_trip() {
+        let model = TestModel {
+            gain: 0.25,
+            mode: 3.0,
+            drive: 0.75,
+        };
+        let json = to_json(&model).unwrap();
+        assert_eq!(
+            json,
+            r#"{"model":{"drive":0.75,"gain":0.25,"mode":3.0},"version":2}"#
+        );
+        assert_eq!(from_json::<TestModel>(&json).unwrap(), model);
+    }
+
+    #[test]
+    fn test_migrates_old_state() {
+        let expected = TestModel {
+            gain: 0.5,
+            mode: 2.0,
+            drive: 0.5,
+        };
+        // Unversioned
+        assert_eq!(
+            from_json::<TestModel>(r#"{"level":50.0,"mode":2.0}"#).unwrap(),
+            expected
+        );
+        // Envelope without a version
+        assert_eq!(
+            from_json::<TestModel>(r#"{"name":"Old","model":{"level":50.0,"mode":2.0}}"#).unwrap(),
+            expected
+        );
+        assert_eq!(
+            from_json::<TestModel>(r#"{"version":1,"model":{"level":50.0,"mode":2.0}}"#).unwrap(),
+            expected
+        );
+        // Version 2 state isn't migrated again
+        assert_eq!(
+            from_json::<TestModel>(r#"{"version":2,"model":{"gain":0.5,"mode":2.0}}"#).unwrap(),
+            expected
+        );
+    }
+
+    #[test]
+    fn test_newer_state() {
+        let model =
+            from_json::<TestModel>(r#"{"version":7,"model":{"gain":0.5,"mode":2.0,"tone":1.0}}"#)
+                .unwrap();
+        assert_eq!(
+            model,
+            TestModel {
+                gain: 0.5,
+                mode: 2.0,
+                drive: 0.5,
+            }
+        );
+    }
+
+    #[test]
+    fn test_plugin_state() {
+        let model = TestModel {
+            gain: 0.25,
+            mode: 3.0,
+            drive: 0.75,
+        };
+        let midi = MidiMap::new();
+        midi.map(74, 2);
+        let json = plugin_to_json(&model, &midi).unwrap();
+        assert_eq!(
+            json,
+            r#"{"midi":{"74":2},"model":{"drive":0.75,"gain":0.25,"mode":3.0},"version":2}"#
+        );
+
+        let loaded = MidiMap::new();
+        assert_eq!(
+            plugin_from_json::<TestModel>(&json, &loaded).unwrap(),
+            model
+        );
+        assert_eq!(loaded.param(74), Some(2));
+        assert!(plugin_from_json::<TestModel>(r#"{"gain":"loud"}"#, &loaded).is_err());
+        assert_eq!(loaded.param(74), Some(2));
+        // Presets and older state have no mappings
+        plugin_from_json::<TestModel>(&to_json(&model).unwrap(), &loaded).unwrap();
+        assert_eq!(loaded.param(74), None);
+    }
+
+    #[test]
+    fn test_invalid_state() {
+        assert!(from_json::<TestModel>("[1.0]").is_err());
+        assert!(from_json::<TestModel>(r#"{"version":"two","model":{}}"#).is_err());
+        assert!(from_json::<TestModel>(r#"{"gain":"loud"}"#).is_err());
+    }
+}
@@ -0,0 +1,19 @@
+// Locks the plugins take go through here, so the real-time check can see them. With the
+// "rt-check" feature every lock is counted for rt_check, otherwise this is std's Mutex.
+
+use std::sync::{LockResult, MutexGuard};
+
+/// std::sync::Mutex, with locks counted by rt_check in the real-time safety tests
+pub struct Mutex<T>(std::sync::Mutex<T>);
+
+impl<T> Mutex<T> {
+    pub const fn new(value: T) -> Mutex<T> {
+        Mutex(std::sync::Mutex::new(value))
+    }
+
+    pub fn lock(&self) -> LockResult<MutexGuard<'_, T>> {
+        #[cfg(feature = "rt-check")]
+        crate::rt_check::count_lock();
+        self.0.lock()
+    }
+}
@@ -0,0 +1,142 @@
+use std::f32::consts::LN_2 as LN_2_F32;
+use std::f64::consts::LN_2 as LN_2_F64;
+use std::f64::consts::PI;
+
+/// Used to implement conversions to the Hertz struct
+#[allow(clippy::wrong_self_convention)]
+pub trait Units<T> {
+    /// From hertz
+    fn to_range(self, bottom: T, top: T) -> T;
+    fn from_range(self, bottom: T, top: T) -> T;
+    fn db_to_lin(self) -> T;
+    fn lin_to_db(self) -> T;
+    fn sign(self, b: T) -> T;
+    fn bw_to_q(self, f0: T, fs: T) -> T;
+}
+
+impl Units<f64> for f64 {
+    fn to_range(self, bottom: f64, top: f64) -> f64 {
+        self * (top - bottom) + bottom
+    }
+    fn from_range(self, bottom: f64, top: f64) -> f64 {
+        (self - bottom) / (top - bottom)
+    }
+    fn db_to_lin(self) -> f64 {
+        (10.0f64).powf(self * 0.05)
+    }
+    fn lin_to_db(self) -> f64 {
+        self.max(0.0).log(10.0) * 20.0
+    }
+    fn sign(self, b: f64) -> f64 {
+        if b < 0.0 {
+            -self
+        } else {
+            self
+        }
+    }
+    fn bw_to_q(self, _f0: f64, _fs: f64) -> f64 {
+        // Tried to compensate for q squashing at high frequencies
+        // but seems too extreme at the very top
+        //let w0 = 2.0 * PI * f0 / fs;
+        //1.0 / (2.0 * (LN_2_F64 / 2.0 * self * w0 / (w0).sin()).sinh())
+
+        1.0 / (2.0 * (LN_2_F64 / 2.0 * self).sinh())
+    }
+}
+
+impl Units<f32> for f32 {
+    //Just a copy of the f64 version with units swapped
+    fn to_range(self, bottom: f32, top: f32) -> f32 {
+        self * (top - bottom) + bottom
+    }
+    fn from_range(self, bottom: f32, top: f32) -> f32 {
+        (self - bottom) / (top - bottom)
+    }
+    fn db_to_lin(self) -> f32 {
+        (10.0f32).powf(self * 0.05)
+    }
+    fn lin_to_db(self) -> f32 {
+        self.max(0.0).log(10.0) * 20.0
+    }
+    fn sign(self, b: f32) -> f32 {
+        if b < 0.0 {
+            -self
+        } else {
+            self
+        }
+    }
+    fn bw_to_q(self, _f0: f32, _fs: f32) -> f32 {
+        // Tried to compensate for q squashing at high frequencies
+        // but seems too extreme at the very top
+        //let w0 = 2.0 * std::f32::consts::PI * f0 / fs;
+        //1.0 / (2.0 * (LN_2_F32 / 2.0 * self * w0 / (w0).sin()).sinh())
+
+        1.0 / (2.0 * (LN_2_F32 / 2.0 * self).sinh())
+    }
+}
+
+pub fn map_to_freq(n: f32) -> f32 {
+    //0-1 to freq
+    let n = ((1000.0f32).powf(n) - 1.0) / (1000.0f32 - 1.0);
+    n.to_range(20.0, 20000.0)
+}
+
+pub fn reverse_map_to_freq(n: f32) -> f32 {
+    let n = n.from_range(20.0, 20000.0);
+    ((1000.0f32 - 1.0) * n + 1.0).ln() / 1000.0f32.ln()
+}
+
+pub fn butterworth_cascade_q(filter_order: u32, pole: u32) -> f64 {
+    //let pairs = filter_order >> 1;
+    let mut pole = pole;
+    let pole_inc = PI / (filter_order as f64);
+    let even_order = filter_order % 2 == 0;
+
+    let first_angle = if even_order {
+        pole_inc * 0.5
+    } else {
+        if pole == 0 {
+            return 0.5; //Also needs to be 1 pole (not biquad)
+        }
+        pole -= 1;
+        pole_inc
+    };
+
+    1.0 / (2.0 * (first_angle + pole as f64 * pole_inc).cos())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // The function works in f64, these are to f32 precision
+    fn assert_q(expected: f64, filter_order: u32, pole: u32) {
+        let q = butterworth_cascade_q(filter_order, pole);
+        assert!(
+            (q - expected).abs() < 1e-6,
+            "order {} pole {}: {}",
+            filter_order,
+            pole,
+            q
+        );
+    }
+
+    #[test]
+    fn test_butterworth_cascade_q() {
+        assert_q(0.70710677, 2, 0);
+
+        assert_q(0.5, 3, 0);
+        assert_q(1.0000001, 3, 1);
+
+        assert_q(0.5411961, 4, 0);
+        assert_q(1.306563, 4, 1);
+
+        assert_q(0.5, 5, 0);
+        assert_q(0.618034, 5, 1);
+        assert_q(1.6180341, 5, 2);
+
+        assert_q(0.5176381, 6, 0);
+        assert_q(0.70710677, 6, 1);
+        assert_q(1.9318514, 6, 2);
+    }
+}
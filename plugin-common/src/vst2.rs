@@ -0,0 +1,859 @@
+// VST2 export for a baseplug Plugin, in place of baseplug::vst2!, whose chunk is the bare model
+// with no version to migrate from. Parameters are the CLAP ones in the same order, which is the
+// order baseplug's wrapper had them in, and processing goes through the CLAP export's Audio. The
+// chunk is the versioned state the CLAP and VST3 exports save, and a chunk baseplug's wrapper
+// saved loads as version 0. Presets are the plugin's programs.
+
+use std::cell::{Cell, UnsafeCell};
+use std::convert::TryFrom;
+use std::ffi::{c_char, c_void, CStr};
+use std::ptr;
+use std::sync::atomic::{AtomicBool, AtomicU64, AtomicUsize, Ordering};
+
+use baseplug::{MusicalTime, Plugin, SmoothModel};
+
+use crate::clap::{format_number, parse_value, write_c_str, Audio, ClapExport, ClapParam};
+use crate::clap::{CHANNELS, MAX_BLOCK};
+use crate::midi::MidiMap;
+use crate::presets::{self, FactoryPresets, PresetBank};
+use crate::state::{self, VersionedState};
+
+pub use vst2_sys as sys;
+
+use self::sys::*;
+
+const VST_VERSION: isize = 2400;
+// The SDK limits parameter strings to 8 bytes, hosts all give more than that
+const MAX_PARAM_STR_LEN: usize = 32;
+const MAX_PROGRAM_NAME_LEN: usize = 24;
+
+// Opcodes vst2-sys leaves out
+const SET_PROGRAM_NAME: i32 = 4;
+const GET_PROGRAM_NAME_INDEXED: i32 = 29;
+
+/// Exports plugin as a VST2 plugin from the crate's cdylib, with the unique id hosts save
+/// projects against, e.g. plugin_common::vst2_export!(DynSat, b"tAnE");
+#[macro_export]
+macro_rules! vst2_export {
+    ($plugin:ty, $unique_id:expr) => {
+        #[no_mangle]
+        #[allow(non_snake_case)]
+        pub extern "C" fn VSTPluginMain(
+            host: $crate::vst2::sys::HostCallbackProc,
+        ) -> *mut $crate::vst2::sys::AEffect {
+            $crate::vst2::main::<$plugin>(host, *$unique_id)
+        }
+
+        #[cfg(target_os = "macos")]
+        #[no_mangle]
+        pub extern "C" fn main_macho(
+            host: $crate::vst2::sys::HostCallbackProc,
+        ) -> *mut $crate::vst2::sys::AEffect {
+            VSTPluginMain(host)
+        }
+    };
+}
+
+/// The power of a baseplug gradient, "Linear" or "Power(..)", for ClapParam::exponent
+pub fn gradient_exponent(gradient: &str) -> f64 {
+    if gradient == "Linear" {
+        return 1.0;
+    }
+    gradient
+        .strip_prefix("Power(")
+        .and_then(|power| power.strip_suffix(')'))
+        .and_then(|power| power.trim().parse().ok())
+        .unwrap_or_else(|| panic!("unsupported gradient {}", gradient))
+}
+
+fn to_normalized<M>(param: &ClapParam<M>, value: f64) -> f64 {
+    ((value - param.min) / (param.max - param.min))
+        .clamp(0.0, 1.0)
+        .powf(1.0 / param.exponent)
+}
+
+fn from_normalized<M>(param: &ClapParam<M>, normalized: f64) -> f64 {
+    param.clamp(
+        param.min + normalized.clamp(0.0, 1.0).powf(param.exponent) * (param.max - param.min),
+    )
+}
+
+/// A new instance of the plugin for VSTPluginMain, hosts free it with effClose
+pub fn main<P>(host: HostCallbackProc, unique_id: [u8; 4]) -> *mut AEffect
+where
+    P: ClapExport,
+    P::Model: Clone + VersionedState + FactoryPresets,
+{
+    let params = P::clap_params();
+    let model = P::Model::default();
+    let values = params
+        .iter()
+        .map(|param| AtomicU64::new((param.get)(&model).to_bits()))
+        .collect();
+    // Presets that can't be read are left out, there's nowhere to report them
+    let mut presets = PresetBank::new();
+    if let Some(dir) = presets::user_preset_dir(P::NAME) {
+        let _ = presets.load_user_presets(&dir);
+    }
+    let instance = Box::new(Instance::<P> {
+        effect: AEffect {
+            magic: MAGIC,
+            dispatcher: Instance::<P>::dispatcher,
+            process: Instance::<P>::process_replacing,
+            set_parameter: Instance::<P>::set_parameter,
+            get_parameter: Instance::<P>::get_parameter,
+            num_programs: presets.count() as i32,
+            num_params: params.len() as i32,
+            num_inputs: CHANNELS as i32,
+            num_outputs: CHANNELS as i32,
+            flags: effect_flags::CAN_REPLACING | effect_flags::PROGRAM_CHUNKS,
+            ptr_1: ptr::null_mut(),
+            ptr_2: ptr::null_mut(),
+            initial_delay: 0,
+            empty_2: [0; 8],
+            unknown_float: 0.0,
+            object: ptr::null_mut(),
+            user: ptr::null_mut(),
+            unique_id: i32::from_be_bytes(unique_id),
+            version: 1,
+            process_replacing: Instance::<P>::process_replacing,
+            process_double_replacing: Instance::<P>::process_double_replacing,
+        },
+        host,
+        params,
+        values,
+        values_changed: AtomicBool::new(false),
+        midi: MidiMap::new(),
+        presets,
+        program: AtomicUsize::new(0),
+        sample_rate: Cell::new(44100.0),
+        block_size: Cell::new(MAX_BLOCK),
+        audio: UnsafeCell::new(None),
+        chunk: UnsafeCell::new(Vec::new()),
+    });
+    Box::into_raw(instance) as *mut AEffect
+}
+
+// The AEffect comes first, so the pointer hosts hold is the instance's
+#[repr(C)]
+struct Instance<P: Plugin> {
+    effect: AEffect,
+    host: HostCallbackProc,
+    params: Vec<ClapParam<P::Model>>,
+    // Current parameter values as f64 bits, in the parameter's units like the CLAP export
+    values: Vec<AtomicU64>,
+    // Set when the values were changed outside process, by the host or by loading a chunk
+    values_changed: AtomicBool,
+    // VST2 has no MIDI learn, this is only here so the chunk is the other exports' state
+    midi: MidiMap,
+    // Factory presets followed by the user's, and the index of the last one loaded
+    presets: PresetBank<P::Model>,
+    program: AtomicUsize,
+    sample_rate: Cell<f32>,
+    block_size: Cell<usize>,
+    // Made on resume and dropped on suspend, only touched by process in between
+    audio: UnsafeCell<Option<Audio<P>>>,
+    // The last chunk handed to the host, which reads it after effGetChunk returns
+    chunk: UnsafeCell<Vec<u8>>,
+}
+
+impl<P> Instance<P>
+where
+    P: ClapExport,
+    P::Model: Clone + VersionedState + FactoryPresets,
+{
+    unsafe fn from_effect<'a>(effect: *mut AEffect) -> &'a Self {
+        &*(effect as *const Self)
+    }
+
+    // Only called from the audio thread, or the main thread while suspended
+    #[allow(clippy::mut_from_ref)]
+    unsafe fn audio(&self) -> &mut Option<Audio<P>> {
+        &mut *self.audio.get()
+    }
+
+    // The parameter at a host's index, if there is one
+    fn param_index(&self, index: i32) -> Option<usize> {
+        usize::try_from(index)
+            .ok()
+            .filter(|&index| index < self.params.len())
+    }
+
+    fn value(&self, index: usize) -> f64 {
+        f64::from_bits(self.values[index].load(Ordering::Relaxed))
+    }
+
+    fn set_value(&self, index: usize, value: f64) {
+        let value = self.params[index].clamp(value);
+        self.values[index].store(value.to_bits(), Ordering::Relaxed);
+        self.values_changed.store(true, Ordering::Relaxed);
+    }
+
+    // The model with every parameter at its current value
+    fn model(&self) -> P::Model {
+        let mut model = P::Model::default();
+        for (index, param) in self.params.iter().enumerate() {
+            (param.set)(&mut model, self.value(index));
+        }
+        model
+    }
+
+    // Sets every parameter to the preset's value, returning false if there's no such program
+    fn load_program(&self, index: usize) -> bool {
+        let preset = match self.presets.get(index) {
+            Some(preset) => preset,
+            None => return false,
+        };
+        for (index, param) in self.params.iter().enumerate() {
+            self.set_value(index, (param.get)(&preset.model));
+        }
+        self.program.store(index, Ordering::Relaxed);
+        true
+    }
+
+    unsafe fn musical_time(&self) -> MusicalTime {
+        let mut time = MusicalTime {
+            bpm: 120.0,
+            beat: 0.0,
+        };
+        let flags = time_info_flags::TEMPO_VALID | time_info_flags::PPQ_POS_VALID;
+        let effect = &self.effect as *const AEffect as *mut AEffect;
+        let info = (self.host)(
+            effect,
+            host_opcodes::GET_TIME,
+            0,
+            flags as isize,
+            ptr::null_mut(),
+            0.0,
+        ) as *const TimeInfo;
+        if let Some(info) = info.as_ref() {
+            if info.flags & time_info_flags::TEMPO_VALID != 0 {
+                time.bpm = info.tempo;
+            }
+            if info.flags & time_info_flags::PPQ_POS_VALID != 0 {
+                time.beat = info.ppq_pos;
+            }
+        }
+        time
+    }
+
+    unsafe fn get_chunk(&self, data: *mut *mut c_void) -> isize {
+        if data.is_null() {
+            return 0;
+        }
+        let chunk = &mut *self.chunk.get();
+        *chunk = match state::plugin_to_json(&self.model(), &self.midi) {
+            Ok(json) => json.into_bytes(),
+            Err(_) => return 0,
+        };
+        *data = chunk.as_mut_ptr() as *mut c_void;
+        chunk.len() as isize
+    }
+
+    // Loads a chunk get_chunk saved, or the bare model baseplug's VST2 wrapper saved, which has
+    // no version and is migrated from version 0. Nothing changes if it doesn't load.
+    unsafe fn set_chunk(&self, data: *const c_void, len: isize) -> isize {
+        if data.is_null() || len <= 0 {
+            return 0;
+        }
+        let chunk = std::slice::from_raw_parts(data as *const u8, len as usize);
+        let model: P::Model = match std::str::from_utf8(chunk)
+            .ok()
+            .and_then(|json| state::plugin_from_json(json, &self.midi).ok())
+        {
+            Some(model) => model,
+            None => return 0,
+        };
+        for (index, param) in self.params.iter().enumerate() {
+            self.set_value(index, (param.get)(&model));
+        }
+        1
+    }
+
+    extern "C" fn dispatcher(
+        effect: *mut AEffect,
+        opcode: i32,
+        index: i32,
+        value: isize,
+        ptr: *mut c_void,
+        opt: f32,
+    ) -> isize {
+        unsafe {
+            if opcode == effect_opcodes::CLOSE {
+                drop(Box::from_raw(effect as *mut Self));
+                return 1;
+            }
+            let instance = Self::from_effect(effect);
+            let param = instance.param_index(index);
+            match (opcode, param) {
+                (effect_opcodes::GET_PARAM_NAME, Some(param)) => {
+                    write_str(instance.params[param].name, ptr, MAX_PARAM_STR_LEN)
+                }
+                (effect_opcodes::GET_PARAM_LABEL, Some(param)) => {
+                    write_str(instance.params[param].unit, ptr, MAX_PARAM_STR_LEN)
+                }
+                (effect_opcodes::GET_PARAM_DISPLAY, Some(param)) => {
+                    let text = format_number(&instance.params[param], instance.value(param));
+                    write_str(&text, ptr, MAX_PARAM_STR_LEN)
+                }
+                (effect_opcodes::CAN_BE_AUTOMATED, Some(_)) => 1,
+                // A null string asks whether the plugin parses text at all
+                (effect_opcodes::STRING_TO_PARAMETER, Some(param)) => {
+                    if ptr.is_null() {
+                        return 1;
+                    }
+                    let text = CStr::from_ptr(ptr as *const c_char).to_string_lossy();
+                    match parse_value(&text) {
+                        Some(value) => {
+                            instance.set_value(param, value);
+                            1
+                        }
+                        None => 0,
+                    }
+                }
+                (effect_opcodes::SET_PROGRAM, _) => {
+                    instance.load_program(value.max(0) as usize);
+                    0
+                }
+                (effect_opcodes::GET_PROGRAM, _) => {
+                    instance.program.load(Ordering::Relaxed) as isize
+                }
+                (effect_opcodes::GET_PROGRAM_NAME, _) => {
+                    let program = instance.program.load(Ordering::Relaxed);
+                    let name = instance.presets.program_name(program).unwrap_or("");
+                    write_str(name, ptr, MAX_PROGRAM_NAME_LEN)
+                }
+                (GET_PROGRAM_NAME_INDEXED, _) => {
+                    match usize::try_from(index)
+                        .ok()
+                        .and_then(|index| instance.presets.program_name(index))
+                    {
+                        Some(name) => write_str(name, ptr, MAX_PROGRAM_NAME_LEN),
+                        None => 0,
+                    }
+                }
+                // Presets are renamed by saving them again under the new name
+                (SET_PROGRAM_NAME, _) => 0,
+                (effect_opcodes::SET_SAMPLE_RATE, _) => {
+                    instance.sample_rate.set(opt);
+                    0
+                }
+                (effect_opcodes::SET_BLOCK_SIZE, _) => {
+                    instance.block_size.set(value.max(1) as usize);
+                    0
+                }
+                (effect_opcodes::MAINS_CHANGED, _) => {
+                    *instance.audio() = if value != 0 {
+                        let audio = Audio::new(
+                            instance.sample_rate.get(),
+                            instance.model(),
+                            instance.block_size.get(),
+                        );
+                        instance.values_changed.store(false, Ordering::Relaxed);
+                        Some(audio)
+                    } else {
+                        None
+                    };
+                    0
+                }
+                (effect_opcodes::GET_CHUNK, _) => instance.get_chunk(ptr as *mut *mut c_void),
+                (effect_opcodes::SET_CHUNK, _) => instance.set_chunk(ptr, value),
+                (effect_opcodes::GET_PLUG_CATEGORY, _) => plug_category::EFFECT as isize,
+                (effect_opcodes::GET_EFFECT_NAME, _) => {
+                    write_str(P::NAME, ptr, string_constants::MAX_NAME_LEN)
+                }
+                (effect_opcodes::GET_VENDOR_STRING, _) => {
+                    write_str(P::VENDOR, ptr, string_constants::MAX_NAME_LEN)
+                }
+                (effect_opcodes::GET_PRODUCT_STRING, _) => {
+                    write_str(P::PRODUCT, ptr, string_constants::MAX_NAME_LEN)
+                }
+                (effect_opcodes::GET_VST_VERSION, _) => VST_VERSION,
+                _ => 0,
+            }
+        }
+    }
+
+    extern "C" fn set_parameter(effect: *mut AEffect, index: i32, normalized: f32) {
+        let instance = unsafe { Self::from_effect(effect) };
+        if let Some(index) = instance.param_index(index) {
+            let value = from_normalized(&instance.params[index], normalized as f64);
+            instance.set_value(index, value);
+        }
+    }
+
+    extern "C" fn get_parameter(effect: *mut AEffect, index: i32) -> f32 {
+        let instance = unsafe { Self::from_effect(effect) };
+        match instance.param_index(index) {
+            Some(index) => to_normalized(&instance.params[index], instance.value(index)) as f32,
+            None => 0.0,
+        }
+    }
+
+    // Also the accumulating process, which VST 2.4 hosts never call
+    extern "C" fn process_replacing(
+        effect: *mut AEffect,
+        inputs: *const *const f32,
+        outputs: *mut *mut f32,
+        nframes: i32,
+    ) {
+        unsafe {
+            let instance = Self::from_effect(effect);
+            let nframes = nframes.max(0) as usize;
+            let audio = match instance.audio() {
+                Some(audio) => audio,
+                None => {
+                    silence(outputs, nframes);
+                    return;
+                }
+            };
+
+            // Parameters are set between blocks, so changes apply from the start of the next one
+            if instance.values_changed.swap(false, Ordering::Relaxed) {
+                for (index, param) in instance.params.iter().enumerate() {
+                    (param.set)(&mut audio.model, instance.value(index));
+                }
+                audio.smooth.set(&audio.model);
+            }
+            let musical_time = instance.musical_time();
+
+            // Hosts can send more frames than the block size they set, those are run through
+            // the input buffers a block size at a time
+            let mut done = 0;
+            while done < nframes {
+                let len = (nframes - done).min(audio.inputs[0].len());
+                let input_channels = channels(inputs as *const *mut f32, done);
+                let output_channels = channels(outputs, done);
+                audio.copy_inputs(Some(&input_channels), len);
+                let mut start = 0;
+                while start < len {
+                    let end = (start + MAX_BLOCK).min(len);
+                    audio.run(start, end, Some(&output_channels), &musical_time);
+                    start = end;
+                }
+                done += len;
+            }
+        }
+    }
+
+    // Never called, hosts only use it with the double precision flag set
+    extern "C" fn process_double_replacing(
+        _effect: *mut AEffect,
+        _inputs: *const *const f64,
+        outputs: *mut *mut f64,
+        nframes: i32,
+    ) {
+        unsafe {
+            if outputs.is_null() {
+                return;
+            }
+            for channel in 0..CHANNELS {
+                let data = *outputs.add(channel);
+                if !data.is_null() {
+                    std::slice::from_raw_parts_mut(data, nframes.max(0) as usize).fill(0.0);
+                }
+            }
+        }
+    }
+}
+
+// Copies s into a host buffer of len bytes, truncating it if needed
+unsafe fn write_str(s: &str, ptr: *mut c_void, len: usize) -> isize {
+    if ptr.is_null() {
+        return 0;
+    }
+    write_c_str(s, std::slice::from_raw_parts_mut(ptr as *mut c_char, len));
+    1
+}
+
+// The host's channel pointers, offset frames in. Missing channels are null.
+unsafe fn channels(buffers: *const *mut f32, offset: usize) -> [*mut f32; CHANNELS] {
+    let mut channels = [ptr::null_mut(); CHANNELS];
+    if !buffers.is_null() {
+        for (channel, data) in channels.iter_mut().enumerate() {
+            let buffer = *buffers.add(channel);
+            if !buffer.is_null() {
+                *data = buffer.add(offset);
+            }
+        }
+    }
+    channels
+}
+
+unsafe fn silence(outputs: *mut *mut f32, nframes: usize) {
+    for data in channels(outputs, 0).iter() {
+        if !data.is_null() {
+            std::slice::from_raw_parts_mut(*data, nframes).fill(0.0);
+        }
+    }
+}
+
+// A minimal in-process host for the plugins' tests, it drives VSTPluginMain the same way a host
+// drives the exported library
+#[cfg(feature = "testing")]
+pub mod host {
+    use std::ffi::CString;
+
+    use super::*;
+
+    pub const SAMPLE_RATE: f32 = 48000.0;
+    pub const BLOCK_SIZE: usize = 512;
+
+    // Plugins only ask the host for the time, which this host doesn't have
+    extern "C" fn callback(
+        _effect: *mut AEffect,
+        opcode: i32,
+        _index: i32,
+        _value: isize,
+        _ptr: *mut c_void,
+        _opt: f32,
+    ) -> isize {
+        match opcode {
+            host_opcodes::VERSION => VST_VERSION,
+            _ => 0,
+        }
+    }
+
+    /// An opened plugin, at SAMPLE_RATE and BLOCK_SIZE. It's closed when dropped.
+    pub struct Instance {
+        effect: *mut AEffect,
+    }
+
+    impl Instance {
+        pub fn new(main: extern "C" fn(HostCallbackProc) -> *mut AEffect) -> Instance {
+            let instance = Instance {
+                effect: main(callback),
+            };
+            assert!(!instance.effect.is_null());
+            assert_eq!(instance.effect().magic, MAGIC);
+            instance.dispatch(effect_opcodes::OPEN, 0, 0, ptr::null_mut(), 0.0);
+            instance.dispatch(
+                effect_opcodes::SET_SAMPLE_RATE,
+                0,
+                0,
+                ptr::null_mut(),
+                SAMPLE_RATE,
+            );
+            let block_size = BLOCK_SIZE as isize;
+            instance.dispatch(
+                effect_opcodes::SET_BLOCK_SIZE,
+                0,
+                block_size,
+                ptr::null_mut(),
+                0.0,
+            );
+            instance
+        }
+
+        pub fn effect(&self) -> &AEffect {
+            unsafe { &*self.effect }
+        }
+
+        pub fn dispatch(
+            &self,
+            opcode: i32,
+            index: i32,
+            value: isize,
+            ptr: *mut c_void,
+            opt: f32,
+        ) -> isize {
+            (self.effect().dispatcher)(self.effect, opcode, index, value, ptr, opt)
+        }
+
+        // The string the plugin writes for opcode into a buffer of len bytes
+        fn string(&self, opcode: i32, index: i32, len: usize) -> String {
+            let mut text = vec![0 as c_char; len];
+            self.dispatch(opcode, index, 0, text.as_mut_ptr() as *mut c_void, 0.0);
+            unsafe { CStr::from_ptr(text.as_ptr()) }
+                .to_string_lossy()
+                .into_owned()
+        }
+
+        pub fn param_name(&self, index: i32) -> String {
+            self.string(effect_opcodes::GET_PARAM_NAME, index, MAX_PARAM_STR_LEN)
+        }
+
+        pub fn param_display(&self, index: i32) -> String {
+            self.string(effect_opcodes::GET_PARAM_DISPLAY, index, MAX_PARAM_STR_LEN)
+        }
+
+        /// Sets a parameter from text the way a host's text entry does, false if it wasn't
+        /// taken
+        pub fn set_param_text(&self, index: i32, text: &str) -> bool {
+            let text = CString::new(text).unwrap();
+            let ptr = text.as_ptr() as *mut c_void;
+            self.dispatch(effect_opcodes::STRING_TO_PARAMETER, index, 0, ptr, 0.0) == 1
+        }
+
+        pub fn parameter(&self, index: i32) -> f32 {
+            (self.effect().get_parameter)(self.effect, index)
+        }
+
+        pub fn set_parameter(&self, index: i32, normalized: f32) {
+            (self.effect().set_parameter)(self.effect, index, normalized)
+        }
+
+        pub fn program_names(&self) -> Vec<String> {
+            (0..self.effect().num_programs)
+                .map(|index| self.string(GET_PROGRAM_NAME_INDEXED, index, MAX_PROGRAM_NAME_LEN))
+                .collect()
+        }
+
+        pub fn program(&self) -> isize {
+            self.dispatch(effect_opcodes::GET_PROGRAM, 0, 0, ptr::null_mut(), 0.0)
+        }
+
+        pub fn program_name(&self) -> String {
+            self.string(effect_opcodes::GET_PROGRAM_NAME, 0, MAX_PROGRAM_NAME_LEN)
+        }
+
+        /// Selects a program the way hosts do, between effBeginSetProgram and effEndSetProgram
+        pub fn set_program(&self, index: usize) {
+            self.dispatch(
+                effect_opcodes::BEGIN_SET_PROGRAM,
+                0,
+                0,
+                ptr::null_mut(),
+                0.0,
+            );
+            let index = index as isize;
+            self.dispatch(effect_opcodes::SET_PROGRAM, 0, index, ptr::null_mut(), 0.0);
+            self.dispatch(effect_opcodes::END_SET_PROGRAM, 0, 0, ptr::null_mut(), 0.0);
+        }
+
+        pub fn resume(&self) {
+            self.dispatch(effect_opcodes::MAINS_CHANGED, 0, 1, ptr::null_mut(), 0.0);
+        }
+
+        pub fn suspend(&self) {
+            self.dispatch(effect_opcodes::MAINS_CHANGED, 0, 0, ptr::null_mut(), 0.0);
+        }
+
+        /// Processes buffers in place, as hosts often do
+        pub fn process(&self, buffers: &mut [Vec<f32>; CHANNELS]) {
+            let nframes = buffers[0].len();
+            let mut channels = [buffers[0].as_mut_ptr(), buffers[1].as_mut_ptr()];
+            let inputs = channels.as_ptr() as *const *const f32;
+            let outputs = channels.as_mut_ptr();
+            (self.effect().process_replacing)(self.effect, inputs, outputs, nframes as i32);
+        }
+
+        pub fn save(&self) -> Vec<u8> {
+            let mut data: *mut c_void = ptr::null_mut();
+            let ptr = &mut data as *mut *mut c_void as *mut c_void;
+            let len = self.dispatch(effect_opcodes::GET_CHUNK, 0, 0, ptr, 0.0);
+            assert!(len > 0 && !data.is_null());
+            unsafe { std::slice::from_raw_parts(data as *const u8, len as usize) }.to_vec()
+        }
+
+        pub fn load(&self, chunk: &[u8]) -> bool {
+            let ptr = chunk.as_ptr() as *mut c_void;
+            self.dispatch(effect_opcodes::SET_CHUNK, 0, chunk.len() as isize, ptr, 0.0) == 1
+        }
+    }
+
+    impl Drop for Instance {
+        fn drop(&mut self) {
+            self.suspend();
+            self.dispatch(effect_opcodes::CLOSE, 0, 0, ptr::null_mut(), 0.0);
+        }
+    }
+
+    fn noise(frames: usize, seed: &mut u32) -> Vec<f32> {
+        (0..frames)
+            .map(|_| {
+                *seed = seed.wrapping_mul(1664525).wrapping_add(1013904223);
+                (*seed as f32 / u32::MAX as f32) * 2.0 - 1.0
+            })
+            .collect()
+    }
+
+    /// Checks the export the way a host would use it: the parameters, processing, a chunk round
+    /// trip and the programs
+    pub fn validate<P>(main: extern "C" fn(HostCallbackProc) -> *mut AEffect)
+    where
+        P: ClapExport,
+        P::Model: Clone + VersionedState + FactoryPresets,
+    {
+        let instance = Instance::new(main);
+        let effect = instance.effect();
+        let params = P::clap_params();
+        assert_eq!(effect.num_params as usize, params.len());
+        assert_eq!((effect.num_inputs, effect.num_outputs), (2, 2));
+        let flags = effect_flags::CAN_REPLACING | effect_flags::PROGRAM_CHUNKS;
+        assert_eq!(effect.flags & flags, flags);
+        let name_len = string_constants::MAX_NAME_LEN;
+        assert_eq!(
+            instance.string(effect_opcodes::GET_EFFECT_NAME, 0, name_len),
+            P::NAME
+        );
+        assert_eq!(
+            instance.dispatch(
+                effect_opcodes::GET_PLUG_CATEGORY,
+                0,
+                0,
+                ptr::null_mut(),
+                0.0
+            ),
+            plug_category::EFFECT as isize
+        );
+
+        // The CLAP parameters by index, at the default model's values. The text shown for a
+        // value parses back to it.
+        let model = P::Model::default();
+        for (index, param) in params.iter().enumerate() {
+            let index = index as i32;
+            assert_eq!(instance.param_name(index), param.name);
+            let automate = effect_opcodes::CAN_BE_AUTOMATED;
+            assert_eq!(
+                instance.dispatch(automate, index, 0, ptr::null_mut(), 0.0),
+                1
+            );
+            let default = to_normalized(param, (param.get)(&model)) as f32;
+            assert!(
+                (instance.parameter(index) - default).abs() <= 1e-6,
+                "{} is {}, not its default {}",
+                param.name,
+                instance.parameter(index),
+                default
+            );
+            instance.set_parameter(index, 1.0);
+            let text = instance.param_display(index);
+            instance.set_parameter(index, 0.0);
+            assert!(instance.set_param_text(index, &text));
+            assert!(
+                (instance.parameter(index) - 1.0).abs() <= 0.005,
+                "{} text {:?} parsed as {}",
+                param.name,
+                text,
+                instance.parameter(index)
+            );
+            instance.set_parameter(index, default);
+        }
+
+        // Silence before resume, then blocks of the block size, shorter and longer ones, with
+        // the parameters automated between them
+        let mut seed = 1;
+        let mut buffers = [noise(64, &mut seed), noise(64, &mut seed)];
+        instance.process(&mut buffers);
+        assert!(buffers.iter().flatten().all(|x| *x == 0.0));
+        instance.resume();
+        for (block, &frames) in [BLOCK_SIZE, 7, 0, BLOCK_SIZE * 3, BLOCK_SIZE]
+            .iter()
+            .enumerate()
+        {
+            if block == 3 {
+                for index in 0..params.len() {
+                    instance.set_parameter(index as i32, 0.25);
+                }
+            }
+            let mut buffers = [noise(frames, &mut seed), noise(frames, &mut seed)];
+            instance.process(&mut buffers);
+            for x in buffers.iter().flatten() {
+                assert!(x.is_finite(), "block {} output {}", block, x);
+            }
+        }
+        instance.suspend();
+
+        // The chunk is the versioned state the other exports save, with every parameter
+        for index in 0..params.len() {
+            instance.set_parameter(index as i32, 0.75);
+        }
+        let saved = instance.save();
+        let envelope: serde_json::Value = serde_json::from_slice(&saved).unwrap();
+        assert_eq!(envelope["version"], P::Model::STATE_VERSION);
+        let loaded = Instance::new(main);
+        assert!(loaded.load(&saved));
+        assert!(!loaded.load(b"{ not state"));
+        for (index, param) in params.iter().enumerate() {
+            let index = index as i32;
+            let expected = instance.parameter(index);
+            let value = loaded.parameter(index);
+            assert!(
+                (value - expected).abs() <= 1e-4,
+                "{} loaded as {}, saved {}",
+                param.name,
+                value,
+                expected
+            );
+        }
+        loaded.resume();
+        let mut buffers = [noise(256, &mut seed), noise(256, &mut seed)];
+        loaded.process(&mut buffers);
+        assert!(buffers.iter().flatten().all(|x| x.is_finite()));
+        drop(loaded);
+
+        // The factory presets come first in the program list. Selecting one sets every
+        // parameter to its value, a program past the end changes nothing.
+        let programs = Instance::new(main);
+        let names = programs.program_names();
+        let bank = PresetBank::<P::Model>::new();
+        assert!(names.len() >= bank.count());
+        for index in (0..bank.count()).rev() {
+            let preset = bank.get(index).unwrap();
+            let name: String = preset.name.chars().take(MAX_PROGRAM_NAME_LEN - 1).collect();
+            assert_eq!(names[index], name);
+            programs.set_program(index);
+            assert_eq!(programs.program(), index as isize);
+            assert_eq!(programs.program_name(), name);
+            for (param_index, param) in params.iter().enumerate() {
+                let expected = to_normalized(param, (param.get)(&preset.model)) as f32;
+                let value = programs.parameter(param_index as i32);
+                assert!(
+                    (value - expected).abs() <= 1e-4,
+                    "{} {} is {}, not {}",
+                    preset.name,
+                    param.name,
+                    value,
+                    expected
+                );
+            }
+        }
+        programs.set_program(names.len());
+        assert_eq!(programs.program(), 0);
+        programs.resume();
+        let mut buffers = [noise(256, &mut seed), noise(256, &mut seed)];
+        programs.process(&mut buffers);
+        assert!(buffers.iter().flatten().all(|x| x.is_finite()));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn param(min: f64, max: f64, gradient: &str) -> ClapParam<f64> {
+        ClapParam {
+            id: 0,
+            name: "Gain",
+            unit: "dB",
+            min,
+            max,
+            stepped: false,
+            exponent: gradient_exponent(gradient),
+            get: |model: &f64| *model,
+            set: |model: &mut f64, value| *model = value,
+        }
+    }
+
+    #[test]
+    fn test_gradients() {
+        assert_eq!(gradient_exponent("Linear"), 1.0);
+        assert_eq!(gradient_exponent("Power(0.15)"), 0.15);
+        assert_eq!(gradient_exponent("Power(10.0)"), 10.0);
+
+        let linear = param(-12.0, 12.0, "Linear");
+        assert_eq!(to_normalized(&linear, 0.0), 0.5);
+        assert_eq!(from_normalized(&linear, 0.25), -6.0);
+
+        // Gain's fader puts -6 dB about half way, like baseplug's VST2 wrapper did
+        let fader = param(-90.0, 3.0, "Power(0.15)");
+        assert!((to_normalized(&fader, -6.0) - 0.507).abs() < 0.001);
+        for &value in [-90.0, -48.0, -6.0, 0.0, 3.0].iter() {
+            let normalized = to_normalized(&fader, value);
+            assert!((from_normalized(&fader, normalized) - value).abs() < 1e-9);
+        }
+        assert_eq!(from_normalized(&fader, 2.0), 3.0);
+        assert_eq!(from_normalized(&fader, f64::NAN), -90.0);
+    }
+}
@@ -0,0 +1,2724 @@
+// VST3 export for a baseplug Plugin, next to vst2_export! and clap_export!. There's no VST3
+// binding crate to depend on, so the few interfaces a single component effect needs are
+// declared in sys from the SDK headers. Parameters are the CLAP ones, with the same ids, and
+// processing goes through the CLAP export's Audio. VST3 has no MIDI control change events, hosts
+// send them as changes of hidden parameters IMidiMapping assigns to each controller. Presets are
+// a program list of the root unit, selected with a program change parameter.
+
+use std::cell::UnsafeCell;
+use std::ffi::{c_char, c_void};
+use std::marker::PhantomData;
+use std::ptr;
+use std::sync::atomic::{AtomicBool, AtomicPtr, AtomicU32, AtomicU64, AtomicUsize, Ordering};
+
+use baseplug::{MusicalTime, Plugin, SmoothModel};
+
+use crate::clap::{format_value, parse_value, write_c_str, Audio, ClapExport, ClapParam};
+use crate::clap::{CHANNELS, MAX_BLOCK};
+use crate::midi::{self, MidiMap, CONTROLLERS, LEARN_PARAM_ID};
+use crate::presets::{self, FactoryPresets, PresetBank};
+use crate::state::{self, VersionedState};
+
+use self::sys::*;
+
+/// The SDK's interfaces, structs and constants, named after the SDK's apart from case
+#[allow(dead_code)]
+pub mod sys {
+    use std::ffi::{c_char, c_void};
+
+    pub type Tuid = [u8; 16];
+    pub type TResult = i32;
+    pub type String128 = [u16; 128];
+
+    /// A 16 byte id from the four numbers of the SDK's INLINE_UID
+    pub const fn uid(l1: u32, l2: u32, l3: u32, l4: u32) -> Tuid {
+        let [a, b, c, d] = l1.to_be_bytes();
+        let [e, f, g, h] = l2.to_be_bytes();
+        let [i, j, k, l] = l3.to_be_bytes();
+        let [m, n, o, p] = l4.to_be_bytes();
+        if cfg!(windows) {
+            // COM's GUID layout, the first three fields are little endian
+            [d, c, b, a, f, e, h, g, i, j, k, l, m, n, o, p]
+        } else {
+            [a, b, c, d, e, f, g, h, i, j, k, l, m, n, o, p]
+        }
+    }
+
+    pub const FUNKNOWN_IID: Tuid = uid(0x00000000, 0x00000000, 0xC0000000, 0x00000046);
+    pub const IPLUGIN_BASE_IID: Tuid = uid(0x22888DDB, 0x156E45AE, 0x8358B348, 0x08190625);
+    pub const IPLUGIN_FACTORY_IID: Tuid = uid(0x7A4D811C, 0x52114A1F, 0xAED9D2EE, 0x0B43BF9F);
+    pub const IPLUGIN_FACTORY2_IID: Tuid = uid(0x0007B650, 0xF24B4C0B, 0xA464EDB9, 0xF00B2ABB);
+    pub const ICOMPONENT_IID: Tuid = uid(0xE831FF31, 0xF2D54301, 0x928EBBEE, 0x25697802);
+    pub const IAUDIO_PROCESSOR_IID: Tuid = uid(0x42043F99, 0xB7DA453C, 0xA569E79D, 0x9AAEC33D);
+    pub const IEDIT_CONTROLLER_IID: Tuid = uid(0xDCD7BBE3, 0x7742448D, 0xA874AACC, 0x979C759E);
+    pub const IMIDI_MAPPING_IID: Tuid = uid(0xDF0FF9F7, 0x49B74669, 0xB63AB732, 0x7ADBF5E5);
+    pub const IUNIT_INFO_IID: Tuid = uid(0x3D4BD6B5, 0x913A4FD2, 0xA886E768, 0xA5EB92C1);
+
+    #[cfg(windows)]
+    mod results {
+        pub const RESULT_OK: i32 = 0;
+        pub const RESULT_FALSE: i32 = 1;
+        pub const NO_INTERFACE: i32 = 0x8000_4002u32 as i32;
+        pub const INVALID_ARGUMENT: i32 = 0x8007_0057u32 as i32;
+        pub const NOT_IMPLEMENTED: i32 = 0x8000_4001u32 as i32;
+        pub const NOT_INITIALIZED: i32 = 0x8000_FFFFu32 as i32;
+    }
+
+    #[cfg(not(windows))]
+    mod results {
+        pub const RESULT_OK: i32 = 0;
+        pub const RESULT_FALSE: i32 = 1;
+        pub const NO_INTERFACE: i32 = -1;
+        pub const INVALID_ARGUMENT: i32 = 2;
+        pub const NOT_IMPLEMENTED: i32 = 3;
+        pub const NOT_INITIALIZED: i32 = 5;
+    }
+
+    pub use self::results::*;
+
+    pub const FACTORY_UNICODE: i32 = 1 << 4;
+    pub const MANY_INSTANCES: i32 = 0x7FFF_FFFF;
+    pub const AUDIO_EFFECT_CLASS: &str = "Audio Module Class";
+    pub const SDK_VERSION: &str = "VST 3.7.7";
+
+    pub const MEDIA_AUDIO: i32 = 0;
+    pub const MEDIA_EVENT: i32 = 1;
+    pub const BUS_INPUT: i32 = 0;
+    pub const BUS_OUTPUT: i32 = 1;
+    pub const BUS_MAIN: i32 = 0;
+    pub const BUS_DEFAULT_ACTIVE: u32 = 1;
+    pub const SPEAKER_STEREO: u64 = 0x3;
+    pub const SAMPLE_32: i32 = 0;
+    pub const SAMPLE_64: i32 = 1;
+    pub const INFINITE_TAIL: u32 = u32::MAX;
+    pub const ROOT_UNIT: i32 = 0;
+    pub const NO_PARENT_UNIT: i32 = -1;
+    pub const NO_PROGRAM_LIST: i32 = -1;
+    pub const PARAM_CAN_AUTOMATE: i32 = 1;
+    pub const PARAM_IS_LIST: i32 = 1 << 3;
+    pub const PARAM_IS_HIDDEN: i32 = 1 << 4;
+    pub const PARAM_IS_PROGRAM_CHANGE: i32 = 1 << 15;
+    pub const PARAM_IS_BYPASS: i32 = 1 << 16;
+    pub const RESTART_PARAM_VALUES_CHANGED: i32 = 1 << 2;
+    pub const RESTART_LATENCY_CHANGED: i32 = 1 << 3;
+    pub const CONTEXT_PROJECT_TIME_MUSIC_VALID: u32 = 1 << 9;
+    pub const CONTEXT_TEMPO_VALID: u32 = 1 << 10;
+
+    #[repr(C)]
+    pub struct PFactoryInfo {
+        pub vendor: [c_char; 64],
+        pub url: [c_char; 256],
+        pub email: [c_char; 128],
+        pub flags: i32,
+    }
+
+    #[repr(C)]
+    pub struct PClassInfo {
+        pub cid: Tuid,
+        pub cardinality: i32,
+        pub category: [c_char; 32],
+        pub name: [c_char; 64],
+    }
+
+    #[repr(C)]
+    pub struct PClassInfo2 {
+        pub cid: Tuid,
+        pub cardinality: i32,
+        pub category: [c_char; 32],
+        pub name: [c_char; 64],
+        pub class_flags: u32,
+        pub sub_categories: [c_char; 128],
+        pub vendor: [c_char; 64],
+        pub version: [c_char; 64],
+        pub sdk_version: [c_char; 64],
+    }
+
+    #[repr(C)]
+    pub struct BusInfo {
+        pub media_type: i32,
+        pub direction: i32,
+        pub channel_count: i32,
+        pub name: String128,
+        pub bus_type: i32,
+        pub flags: u32,
+    }
+
+    #[repr(C)]
+    pub struct RoutingInfo {
+        pub media_type: i32,
+        pub bus_index: i32,
+        pub channel: i32,
+    }
+
+    #[repr(C)]
+    #[derive(Clone, Copy)]
+    pub struct ProcessSetup {
+        pub process_mode: i32,
+        pub symbolic_sample_size: i32,
+        pub max_samples_per_block: i32,
+        pub sample_rate: f64,
+    }
+
+    #[repr(C)]
+    pub struct AudioBusBuffers {
+        pub num_channels: i32,
+        pub silence_flags: u64,
+        // A union with the 64 bit channel buffers, only 32 bit processing is supported
+        pub channel_buffers_32: *mut *mut f32,
+    }
+
+    #[repr(C)]
+    pub struct ProcessContext {
+        pub state: u32,
+        pub sample_rate: f64,
+        pub project_time_samples: i64,
+        pub system_time: i64,
+        pub continous_time_samples: i64,
+        pub project_time_music: f64,
+        pub bar_position_music: f64,
+        pub cycle_start_music: f64,
+        pub cycle_end_music: f64,
+        pub tempo: f64,
+        pub time_sig_numerator: i32,
+        pub time_sig_denominator: i32,
+        pub chord_key_note: u8,
+        pub chord_root_note: u8,
+        pub chord_mask: i16,
+        pub smpte_offset_subframes: i32,
+        pub frames_per_second: u32,
+        pub frame_rate_flags: u32,
+        pub samples_to_next_clock: i32,
+    }
+
+    #[repr(C)]
+    pub struct ProcessData {
+        pub process_mode: i32,
+        pub symbolic_sample_size: i32,
+        pub num_samples: i32,
+        pub num_inputs: i32,
+        pub num_outputs: i32,
+        pub inputs: *mut AudioBusBuffers,
+        pub outputs: *mut AudioBusBuffers,
+        pub input_parameter_changes: *mut c_void,
+        pub output_parameter_changes: *mut c_void,
+        pub input_events: *mut c_void,
+        pub output_events: *mut c_void,
+        pub process_context: *mut ProcessContext,
+    }
+
+    #[repr(C)]
+    pub struct ParameterInfo {
+        pub id: u32,
+        pub title: String128,
+        pub short_title: String128,
+        pub units: String128,
+        pub step_count: i32,
+        pub default_normalized_value: f64,
+        pub unit_id: i32,
+        pub flags: i32,
+    }
+
+    #[repr(C)]
+    pub struct UnitInfo {
+        pub id: i32,
+        pub parent_unit_id: i32,
+        pub name: String128,
+        pub program_list_id: i32,
+    }
+
+    #[repr(C)]
+    pub struct ProgramListInfo {
+        pub id: i32,
+        pub name: String128,
+        pub program_count: i32,
+    }
+
+    #[repr(C)]
+    pub struct FUnknownVtbl {
+        pub query_interface:
+            unsafe extern "system" fn(*mut c_void, *const Tuid, *mut *mut c_void) -> TResult,
+        pub add_ref: unsafe extern "system" fn(*mut c_void) -> u32,
+        pub release: unsafe extern "system" fn(*mut c_void) -> u32,
+    }
+
+    #[repr(C)]
+    pub struct IPluginFactory2Vtbl {
+        pub unknown: FUnknownVtbl,
+        pub get_factory_info: unsafe extern "system" fn(*mut c_void, *mut PFactoryInfo) -> TResult,
+        pub count_classes: unsafe extern "system" fn(*mut c_void) -> i32,
+        pub get_class_info: unsafe extern "system" fn(*mut c_void, i32, *mut PClassInfo) -> TResult,
+        pub create_instance: unsafe extern "system" fn(
+            *mut c_void,
+            *const c_char,
+            *const c_char,
+            *mut *mut c_void,
+        ) -> TResult,
+        pub get_class_info2:
+            unsafe extern "system" fn(*mut c_void, i32, *mut PClassInfo2) -> TResult,
+    }
+
+    #[repr(C)]
+    pub struct IPluginBaseVtbl {
+        pub unknown: FUnknownVtbl,
+        pub initialize: unsafe extern "system" fn(*mut c_void, *mut c_void) -> TResult,
+        pub terminate: unsafe extern "system" fn(*mut c_void) -> TResult,
+    }
+
+    #[repr(C)]
+    pub struct IComponentVtbl {
+        pub base: IPluginBaseVtbl,
+        pub get_controller_class_id: unsafe extern "system" fn(*mut c_void, *mut Tuid) -> TResult,
+        pub set_io_mode: unsafe extern "system" fn(*mut c_void, i32) -> TResult,
+        pub get_bus_count: unsafe extern "system" fn(*mut c_void, i32, i32) -> i32,
+        pub get_bus_info:
+            unsafe extern "system" fn(*mut c_void, i32, i32, i32, *mut BusInfo) -> TResult,
+        pub get_routing_info:
+            unsafe extern "system" fn(*mut c_void, *mut RoutingInfo, *mut RoutingInfo) -> TResult,
+        pub activate_bus: unsafe extern "system" fn(*mut c_void, i32, i32, i32, u8) -> TResult,
+        pub set_active: unsafe extern "system" fn(*mut c_void, u8) -> TResult,
+        pub set_state: unsafe extern "system" fn(*mut c_void, *mut c_void) -> TResult,
+        pub get_state: unsafe extern "system" fn(*mut c_void, *mut c_void) -> TResult,
+    }
+
+    #[repr(C)]
+    pub struct IAudioProcessorVtbl {
+        pub unknown: FUnknownVtbl,
+        pub set_bus_arrangements:
+            unsafe extern "system" fn(*mut c_void, *mut u64, i32, *mut u64, i32) -> TResult,
+        pub get_bus_arrangement:
+            unsafe extern "system" fn(*mut c_void, i32, i32, *mut u64) -> TResult,
+        pub can_process_sample_size: unsafe extern "system" fn(*mut c_void, i32) -> TResult,
+        pub get_latency_samples: unsafe extern "system" fn(*mut c_void) -> u32,
+        pub setup_processing: unsafe extern "system" fn(*mut c_void, *mut ProcessSetup) -> TResult,
+        pub set_processing: unsafe extern "system" fn(*mut c_void, u8) -> TResult,
+        pub process: unsafe extern "system" fn(*mut c_void, *mut ProcessData) -> TResult,
+        pub get_tail_samples: unsafe extern "system" fn(*mut c_void) -> u32,
+    }
+
+    #[repr(C)]
+    pub struct IEditControllerVtbl {
+        pub base: IPluginBaseVtbl,
+        pub set_component_state: unsafe extern "system" fn(*mut c_void, *mut c_void) -> TResult,
+        pub set_state: unsafe extern "system" fn(*mut c_void, *mut c_void) -> TResult,
+        pub get_state: unsafe extern "system" fn(*mut c_void, *mut c_void) -> TResult,
+        pub get_parameter_count: unsafe extern "system" fn(*mut c_void) -> i32,
+        pub get_parameter_info:
+            unsafe extern "system" fn(*mut c_void, i32, *mut ParameterInfo) -> TResult,
+        pub get_param_string_by_value:
+            unsafe extern "system" fn(*mut c_void, u32, f64, *mut u16) -> TResult,
+        pub get_param_value_by_string:
+            unsafe extern "system" fn(*mut c_void, u32, *const u16, *mut f64) -> TResult,
+        pub normalized_param_to_plain: unsafe extern "system" fn(*mut c_void, u32, f64) -> f64,
+        pub plain_param_to_normalized: unsafe extern "system" fn(*mut c_void, u32, f64) -> f64,
+        pub get_param_normalized: unsafe extern "system" fn(*mut c_void, u32) -> f64,
+        pub set_param_normalized: unsafe extern "system" fn(*mut c_void, u32, f64) -> TResult,
+        pub set_component_handler: unsafe extern "system" fn(*mut c_void, *mut c_void) -> TResult,
+        pub create_view: unsafe extern "system" fn(*mut c_void, *const c_char) -> *mut c_void,
+    }
+
+    #[repr(C)]
+    pub struct IMidiMappingVtbl {
+        pub unknown: FUnknownVtbl,
+        pub get_midi_controller_assignment:
+            unsafe extern "system" fn(*mut c_void, i32, i16, i16, *mut u32) -> TResult,
+    }
+
+    #[repr(C)]
+    pub struct IUnitInfoVtbl {
+        pub unknown: FUnknownVtbl,
+        pub get_unit_count: unsafe extern "system" fn(*mut c_void) -> i32,
+        pub get_unit_info: unsafe extern "system" fn(*mut c_void, i32, *mut UnitInfo) -> TResult,
+        pub get_program_list_count: unsafe extern "system" fn(*mut c_void) -> i32,
+        pub get_program_list_info:
+            unsafe extern "system" fn(*mut c_void, i32, *mut ProgramListInfo) -> TResult,
+        pub get_program_name: unsafe extern "system" fn(*mut c_void, i32, i32, *mut u16) -> TResult,
+        pub get_program_info:
+            unsafe extern "system" fn(*mut c_void, i32, i32, *const c_char, *mut u16) -> TResult,
+        pub has_program_pitch_names: unsafe extern "system" fn(*mut c_void, i32, i32) -> TResult,
+        pub get_program_pitch_name:
+            unsafe extern "system" fn(*mut c_void, i32, i32, i16, *mut u16) -> TResult,
+        pub get_selected_unit: unsafe extern "system" fn(*mut c_void) -> i32,
+        pub select_unit: unsafe extern "system" fn(*mut c_void, i32) -> TResult,
+        pub get_unit_by_bus:
+            unsafe extern "system" fn(*mut c_void, i32, i32, i32, i32, *mut i32) -> TResult,
+        pub set_unit_program_data:
+            unsafe extern "system" fn(*mut c_void, i32, i32, *mut c_void) -> TResult,
+    }
+
+    #[repr(C)]
+    pub struct IComponentHandlerVtbl {
+        pub unknown: FUnknownVtbl,
+        pub begin_edit: unsafe extern "system" fn(*mut c_void, u32) -> TResult,
+        pub perform_edit: unsafe extern "system" fn(*mut c_void, u32, f64) -> TResult,
+        pub end_edit: unsafe extern "system" fn(*mut c_void, u32) -> TResult,
+        pub restart_component: unsafe extern "system" fn(*mut c_void, i32) -> TResult,
+    }
+
+    #[repr(C)]
+    pub struct IBStreamVtbl {
+        pub unknown: FUnknownVtbl,
+        pub read: unsafe extern "system" fn(*mut c_void, *mut c_void, i32, *mut i32) -> TResult,
+        pub write: unsafe extern "system" fn(*mut c_void, *mut c_void, i32, *mut i32) -> TResult,
+        pub seek: unsafe extern "system" fn(*mut c_void, i64, i32, *mut i64) -> TResult,
+        pub tell: unsafe extern "system" fn(*mut c_void, *mut i64) -> TResult,
+    }
+
+    #[repr(C)]
+    pub struct IParameterChangesVtbl {
+        pub unknown: FUnknownVtbl,
+        pub get_parameter_count: unsafe extern "system" fn(*mut c_void) -> i32,
+        pub get_parameter_data: unsafe extern "system" fn(*mut c_void, i32) -> *mut c_void,
+        pub add_parameter_data:
+            unsafe extern "system" fn(*mut c_void, *const u32, *mut i32) -> *mut c_void,
+    }
+
+    #[repr(C)]
+    pub struct IParamValueQueueVtbl {
+        pub unknown: FUnknownVtbl,
+        pub get_parameter_id: unsafe extern "system" fn(*mut c_void) -> u32,
+        pub get_point_count: unsafe extern "system" fn(*mut c_void) -> i32,
+        pub get_point: unsafe extern "system" fn(*mut c_void, i32, *mut i32, *mut f64) -> TResult,
+        pub add_point: unsafe extern "system" fn(*mut c_void, i32, f64, *mut i32) -> TResult,
+    }
+
+    /// The vtable of a COM object, whose first field points to it
+    ///
+    /// # Safety
+    /// object has to be a live object implementing V
+    pub unsafe fn vtbl<'a, V>(object: *mut c_void) -> &'a V {
+        &**(object as *const *const V)
+    }
+}
+
+/// What a plugin needs on top of ClapExport to be exported with vst3_export!
+pub trait Vst3Export: ClapExport {
+    /// Hosts save projects against the class id, never change it
+    const VST3_CLASS_ID: Tuid;
+    /// Sub categories separated by |, e.g. "Fx|Dynamics"
+    const VST3_CATEGORIES: &'static str;
+}
+
+/// Exports plugin as a VST3 plugin from the crate's cdylib, e.g.
+/// plugin_common::vst3_export!(DynSat);
+#[macro_export]
+macro_rules! vst3_export {
+    ($plugin:ty) => {
+        #[no_mangle]
+        #[allow(non_snake_case)]
+        pub extern "system" fn GetPluginFactory() -> *mut ::std::ffi::c_void {
+            $crate::vst3::factory::<$plugin>()
+        }
+
+        // Entry and exit points hosts call around loading the module, there's nothing to set
+        // up or tear down
+        #[cfg(target_os = "linux")]
+        #[no_mangle]
+        #[allow(non_snake_case)]
+        pub extern "C" fn ModuleEntry(_library: *mut ::std::ffi::c_void) -> bool {
+            true
+        }
+
+        #[cfg(target_os = "linux")]
+        #[no_mangle]
+        #[allow(non_snake_case)]
+        pub extern "C" fn ModuleExit() -> bool {
+            true
+        }
+
+        #[cfg(target_os = "macos")]
+        #[no_mangle]
+        pub extern "C" fn bundleEntry(_bundle: *mut ::std::ffi::c_void) -> bool {
+            true
+        }
+
+        #[cfg(target_os = "macos")]
+        #[no_mangle]
+        pub extern "C" fn bundleExit() -> bool {
+            true
+        }
+
+        #[cfg(windows)]
+        #[no_mangle]
+        #[allow(non_snake_case)]
+        pub extern "system" fn InitDll() -> bool {
+            true
+        }
+
+        #[cfg(windows)]
+        #[no_mangle]
+        #[allow(non_snake_case)]
+        pub extern "system" fn ExitDll() -> bool {
+            true
+        }
+    };
+}
+
+// Copies s into a fixed size UTF-16 string, truncating it if needed
+fn write_str16(s: &str, out: &mut [u16]) {
+    let max = out.len() - 1;
+    let mut len = 0;
+    for (o, c) in out[..max].iter_mut().zip(s.encode_utf16()) {
+        *o = c;
+        len += 1;
+    }
+    out[len] = 0;
+}
+
+unsafe fn read_str16(s: *const u16) -> String {
+    let mut len = 0;
+    while *s.add(len) != 0 {
+        len += 1;
+    }
+    String::from_utf16_lossy(std::slice::from_raw_parts(s, len))
+}
+
+fn to_normalized<M>(param: &ClapParam<M>, value: f64) -> f64 {
+    ((value - param.min) / (param.max - param.min)).clamp(0.0, 1.0)
+}
+
+fn from_normalized<M>(param: &ClapParam<M>, normalized: f64) -> f64 {
+    param.clamp(param.min + normalized * (param.max - param.min))
+}
+
+/// A new reference to the plugin's factory, for GetPluginFactory
+pub fn factory<P>() -> *mut c_void
+where
+    P: Vst3Export,
+    P::Model: Clone + VersionedState + FactoryPresets,
+{
+    let factory = Box::new(Factory::<P> {
+        vtbl: &Factory::<P>::VTBL,
+        refs: AtomicU32::new(1),
+        _plugin: PhantomData,
+    });
+    Box::into_raw(factory) as *mut c_void
+}
+
+#[repr(C)]
+struct Factory<P> {
+    vtbl: *const IPluginFactory2Vtbl,
+    refs: AtomicU32,
+    _plugin: PhantomData<P>,
+}
+
+impl<P> Factory<P>
+where
+    P: Vst3Export,
+    P::Model: Clone + VersionedState + FactoryPresets,
+{
+    const VTBL: IPluginFactory2Vtbl = IPluginFactory2Vtbl {
+        unknown: FUnknownVtbl {
+            query_interface: Self::query_interface,
+            add_ref: Self::add_ref,
+            release: Self::release,
+        },
+        get_factory_info: Self::get_factory_info,
+        count_classes: Self::count_classes,
+        get_class_info: Self::get_class_info,
+        create_instance: Self::create_instance,
+        get_class_info2: Self::get_class_info2,
+    };
+
+    unsafe extern "system" fn query_interface(
+        this: *mut c_void,
+        iid: *const Tuid,
+        obj: *mut *mut c_void,
+    ) -> TResult {
+        let iid = &*iid;
+        if *iid == FUNKNOWN_IID || *iid == IPLUGIN_FACTORY_IID || *iid == IPLUGIN_FACTORY2_IID {
+            Self::add_ref(this);
+            *obj = this;
+            RESULT_OK
+        } else {
+            *obj = ptr::null_mut();
+            NO_INTERFACE
+        }
+    }
+
+    unsafe extern "system" fn add_ref(this: *mut c_void) -> u32 {
+        (*(this as *const Self))
+            .refs
+            .fetch_add(1, Ordering::Relaxed)
+            + 1
+    }
+
+    unsafe extern "system" fn release(this: *mut c_void) -> u32 {
+        let refs = (*(this as *const Self)).refs.fetch_sub(1, Ordering::AcqRel) - 1;
+        if refs == 0 {
+            drop(Box::from_raw(this as *mut Self));
+        }
+        refs
+    }
+
+    unsafe extern "system" fn get_factory_info(
+        _this: *mut c_void,
+        info: *mut PFactoryInfo,
+    ) -> TResult {
+        let info = &mut *info;
+        write_c_str(P::VENDOR, &mut info.vendor);
+        write_c_str("", &mut info.url);
+        write_c_str("", &mut info.email);
+        info.flags = FACTORY_UNICODE;
+        RESULT_OK
+    }
+
+    unsafe extern "system" fn count_classes(_this: *mut c_void) -> i32 {
+        1
+    }
+
+    unsafe extern "system" fn get_class_info(
+        _this: *mut c_void,
+        index: i32,
+        info: *mut PClassInfo,
+    ) -> TResult {
+        if index != 0 {
+            return INVALID_ARGUMENT;
+        }
+        let info = &mut *info;
+        info.cid = P::VST3_CLASS_ID;
+        info.cardinality = MANY_INSTANCES;
+        write_c_str(AUDIO_EFFECT_CLASS, &mut info.category);
+        write_c_str(P::NAME, &mut info.name);
+        RESULT_OK
+    }
+
+    unsafe extern "system" fn get_class_info2(
+        _this: *mut c_void,
+        index: i32,
+        info: *mut PClassInfo2,
+    ) -> TResult {
+        if index != 0 {
+            return INVALID_ARGUMENT;
+        }
+        let info = &mut *info;
+        info.cid = P::VST3_CLASS_ID;
+        info.cardinality = MANY_INSTANCES;
+        write_c_str(AUDIO_EFFECT_CLASS, &mut info.category);
+        write_c_str(P::NAME, &mut info.name);
+        info.class_flags = 0;
+        write_c_str(P::VST3_CATEGORIES, &mut info.sub_categories);
+        write_c_str(P::VENDOR, &mut info.vendor);
+        write_c_str(P::VERSION, &mut info.version);
+        write_c_str(SDK_VERSION, &mut info.sdk_version);
+        RESULT_OK
+    }
+
+    unsafe extern "system" fn create_instance(
+        _this: *mut c_void,
+        cid: *const c_char,
+        iid: *const c_char,
+        obj: *mut *mut c_void,
+    ) -> TResult {
+        *obj = ptr::null_mut();
+        if cid.is_null() || *(cid as *const Tuid) != P::VST3_CLASS_ID {
+            return NO_INTERFACE;
+        }
+        let instance = Instance::<P>::create();
+        let result = (*instance).query_interface(iid as *const Tuid, obj);
+        Instance::<P>::release::<{ COMPONENT }>(instance as *mut c_void);
+        result
+    }
+}
+
+// The interfaces an Instance implements, in the order of their vtable pointers
+const COMPONENT: usize = 0;
+const PROCESSOR: usize = 1;
+const CONTROLLER: usize = 2;
+const MIDI_MAPPING: usize = 3;
+const UNIT_INFO: usize = 4;
+
+/// Id of the hidden parameter controller 0 is assigned to, the others follow it
+pub const MIDI_CC_PARAM_ID: u32 = 2000;
+
+/// Id of the program change parameter, which selects a preset of the program list
+pub const PROGRAM_PARAM_ID: u32 = 3000;
+
+/// Id of the root unit's program list, the plugin's presets
+pub const PROGRAM_LIST_ID: i32 = 0;
+
+// The controller a hidden MIDI CC parameter is for
+fn midi_cc(id: u32) -> Option<u8> {
+    id.checked_sub(MIDI_CC_PARAM_ID)
+        .filter(|&cc| (cc as usize) < CONTROLLERS)
+        .map(|cc| cc as u8)
+}
+
+// Everything the audio thread owns, made in setActive
+struct Active<P: Plugin> {
+    audio: Audio<P>,
+    // The next point of each parameter change queue, by queue index
+    points: Vec<i32>,
+}
+
+// A single component effect: the component, its audio processor and its edit controller are
+// one object, with a vtable pointer for each
+#[repr(C)]
+struct Instance<P: Plugin> {
+    component: *const IComponentVtbl,
+    processor: *const IAudioProcessorVtbl,
+    controller: *const IEditControllerVtbl,
+    midi_mapping: *const IMidiMappingVtbl,
+    unit_info: *const IUnitInfoVtbl,
+    refs: AtomicU32,
+    params: Vec<ClapParam<P::Model>>,
+    // Current parameter values as f64 bits, in the parameter's units like the CLAP export
+    values: Vec<AtomicU64>,
+    // Set when the values were changed outside process, e.g. by loading state
+    values_changed: AtomicBool,
+    midi: MidiMap,
+    // Factory presets followed by the user's, and the index of the last one loaded
+    presets: PresetBank<P::Model>,
+    program: AtomicUsize,
+    // The latency reported to the host
+    latency: AtomicU32,
+    handler: AtomicPtr<c_void>,
+    setup: UnsafeCell<ProcessSetup>,
+    active: UnsafeCell<Option<Active<P>>>,
+}
+
+impl<P> Instance<P>
+where
+    P: Vst3Export,
+    P::Model: Clone + VersionedState + FactoryPresets,
+{
+    const COMPONENT_VTBL: IComponentVtbl = IComponentVtbl {
+        base: IPluginBaseVtbl {
+            unknown: Self::unknown::<{ COMPONENT }>(),
+            initialize: Self::initialize,
+            terminate: Self::terminate,
+        },
+        get_controller_class_id: Self::get_controller_class_id,
+        set_io_mode: Self::set_io_mode,
+        get_bus_count: Self::get_bus_count,
+        get_bus_info: Self::get_bus_info,
+        get_routing_info: Self::get_routing_info,
+        activate_bus: Self::activate_bus,
+        set_active: Self::set_active,
+        set_state: Self::set_state,
+        get_state: Self::get_state,
+    };
+
+    const PROCESSOR_VTBL: IAudioProcessorVtbl = IAudioProcessorVtbl {
+        unknown: Self::unknown::<{ PROCESSOR }>(),
+        set_bus_arrangements: Self::set_bus_arrangements,
+        get_bus_arrangement: Self::get_bus_arrangement,
+        can_process_sample_size: Self::can_process_sample_size,
+        get_latency_samples: Self::get_latency_samples,
+        setup_processing: Self::setup_processing,
+        set_processing: Self::set_processing,
+        process: Self::process,
+        get_tail_samples: Self::get_tail_samples,
+    };
+
+    const CONTROLLER_VTBL: IEditControllerVtbl = IEditControllerVtbl {
+        base: IPluginBaseVtbl {
+            unknown: Self::unknown::<{ CONTROLLER }>(),
+            initialize: Self::initialize,
+            terminate: Self::terminate,
+        },
+        set_component_state: Self::set_component_state,
+        set_state: Self::controller_set_state,
+        get_state: Self::controller_get_state,
+        get_parameter_count: Self::get_parameter_count,
+        get_parameter_info: Self::get_parameter_info,
+        get_param_string_by_value: Self::get_param_string_by_value,
+        get_param_value_by_string: Self::get_param_value_by_string,
+        normalized_param_to_plain: Self::normalized_param_to_plain,
+        plain_param_to_normalized: Self::plain_param_to_normalized,
+        get_param_normalized: Self::get_param_normalized,
+        set_param_normalized: Self::set_param_normalized,
+        set_component_handler: Self::set_component_handler,
+        create_view: Self::create_view,
+    };
+
+    const MIDI_MAPPING_VTBL: IMidiMappingVtbl = IMidiMappingVtbl {
+        unknown: Self::unknown::<{ MIDI_MAPPING }>(),
+        get_midi_controller_assignment: Self::get_midi_controller_assignment,
+    };
+
+    const UNIT_INFO_VTBL: IUnitInfoVtbl = IUnitInfoVtbl {
+        unknown: Self::unknown::<{ UNIT_INFO }>(),
+        get_unit_count: Self::get_unit_count,
+        get_unit_info: Self::get_unit_info,
+        get_program_list_count: Self::get_program_list_count,
+        get_program_list_info: Self::get_program_list_info,
+        get_program_name: Self::get_program_name,
+        get_program_info: Self::get_program_info,
+        has_program_pitch_names: Self::has_program_pitch_names,
+        get_program_pitch_name: Self::get_program_pitch_name,
+        get_selected_unit: Self::get_selected_unit,
+        select_unit: Self::select_unit,
+        get_unit_by_bus: Self::get_unit_by_bus,
+        set_unit_program_data: Self::set_unit_program_data,
+    };
+
+    fn create() -> *mut Self {
+        let mut params = P::clap_params();
+        params.push(midi::learn_param(&params));
+        let model = P::Model::default();
+        let values = params
+            .iter()
+            .map(|param| AtomicU64::new((param.get)(&model).to_bits()))
+            .collect();
+        // Presets that can't be read are left out, there's nowhere to report them
+        let mut presets = PresetBank::new();
+        if let Some(dir) = presets::user_preset_dir(P::NAME) {
+            let _ = presets.load_user_presets(&dir);
+        }
+        Box::into_raw(Box::new(Instance::<P> {
+            component: &Self::COMPONENT_VTBL,
+            processor: &Self::PROCESSOR_VTBL,
+            controller: &Self::CONTROLLER_VTBL,
+            midi_mapping: &Self::MIDI_MAPPING_VTBL,
+            unit_info: &Self::UNIT_INFO_VTBL,
+            refs: AtomicU32::new(1),
+            params,
+            values,
+            values_changed: AtomicBool::new(false),
+            midi: MidiMap::new(),
+            presets,
+            program: AtomicUsize::new(0),
+            latency: AtomicU32::new(0),
+            handler: AtomicPtr::new(ptr::null_mut()),
+            setup: UnsafeCell::new(ProcessSetup {
+                process_mode: 0,
+                symbolic_sample_size: SAMPLE_32,
+                max_samples_per_block: MAX_BLOCK as i32,
+                sample_rate: 44100.0,
+            }),
+            active: UnsafeCell::new(None),
+        }))
+    }
+
+    // this is the address of the vtable pointer of one of the interfaces
+    unsafe fn from_interface<'a, const INTERFACE: usize>(this: *mut c_void) -> &'a Self {
+        &*((this as *const *const c_void).sub(INTERFACE) as *const Self)
+    }
+
+    // Only called from the audio thread, or the main thread while not active
+    #[allow(clippy::mut_from_ref)]
+    unsafe fn active(&self) -> &mut Option<Active<P>> {
+        &mut *self.active.get()
+    }
+
+    const fn unknown<const INTERFACE: usize>() -> FUnknownVtbl {
+        FUnknownVtbl {
+            query_interface: Self::interface_query_interface::<INTERFACE>,
+            add_ref: Self::add_ref::<INTERFACE>,
+            release: Self::release::<INTERFACE>,
+        }
+    }
+
+    unsafe fn query_interface(&self, iid: *const Tuid, obj: *mut *mut c_void) -> TResult {
+        let iid = &*iid;
+        let interface =
+            if *iid == FUNKNOWN_IID || *iid == IPLUGIN_BASE_IID || *iid == ICOMPONENT_IID {
+                &self.component as *const _ as *mut c_void
+            } else if *iid == IAUDIO_PROCESSOR_IID {
+                &self.processor as *const _ as *mut c_void
+            } else if *iid == IEDIT_CONTROLLER_IID {
+                &self.controller as *const _ as *mut c_void
+            } else if *iid == IMIDI_MAPPING_IID {
+                &self.midi_mapping as *const _ as *mut c_void
+            } else if *iid == IUNIT_INFO_IID {
+                &self.unit_info as *const _ as *mut c_void
+            } else {
+                *obj = ptr::null_mut();
+                return NO_INTERFACE;
+            };
+        self.refs.fetch_add(1, Ordering::Relaxed);
+        *obj = interface;
+        RESULT_OK
+    }
+
+    unsafe extern "system" fn interface_query_interface<const INTERFACE: usize>(
+        this: *mut c_void,
+        iid: *const Tuid,
+        obj: *mut *mut c_void,
+    ) -> TResult {
+        Self::from_interface::<INTERFACE>(this).query_interface(iid, obj)
+    }
+
+    unsafe extern "system" fn add_ref<const INTERFACE: usize>(this: *mut c_void) -> u32 {
+        let instance = Self::from_interface::<INTERFACE>(this);
+        instance.refs.fetch_add(1, Ordering::Relaxed) + 1
+    }
+
+    unsafe extern "system" fn release<const INTERFACE: usize>(this: *mut c_void) -> u32 {
+        let instance = Self::from_interface::<INTERFACE>(this);
+        let refs = instance.refs.fetch_sub(1, Ordering::AcqRel) - 1;
+        if refs == 0 {
+            drop(Box::from_raw(instance as *const Self as *mut Self));
+        }
+        refs
+    }
+
+    fn value(&self, index: usize) -> f64 {
+        f64::from_bits(self.values[index].load(Ordering::Relaxed))
+    }
+
+    fn set_value(&self, index: usize, value: f64) -> f64 {
+        let value = self.params[index].clamp(value);
+        self.values[index].store(value.to_bits(), Ordering::Relaxed);
+        if self.params[index].id == LEARN_PARAM_ID {
+            self.midi.set_learning(&self.params, value);
+        }
+        value
+    }
+
+    fn index(&self, id: u32) -> Option<usize> {
+        self.params.iter().position(|param| param.id == id)
+    }
+
+    // Stores the value of a queue point, returning the index and new value of the parameter it
+    // set. A MIDI CC parameter's point sets the parameter its controller is mapped to, values
+    // the plugin changes itself are sent to the host.
+    unsafe fn apply_point(
+        &self,
+        id: u32,
+        offset: i32,
+        normalized: f64,
+        output: &ParameterChanges,
+    ) -> Option<(usize, f64)> {
+        let cc = match midi_cc(id) {
+            Some(cc) => cc,
+            None => {
+                let index = self.index(id)?;
+                let value = from_normalized(&self.params[index], normalized);
+                return Some((index, self.set_value(index, value)));
+            }
+        };
+        let data = [0xb0, cc, (normalized.clamp(0.0, 1.0) * 127.0).round() as u8];
+        let change = self.midi.midi_input(data)?;
+        if change.learned {
+            if let Some(learn) = self.index(LEARN_PARAM_ID) {
+                self.set_value(learn, 0.0);
+                output.add_point(LEARN_PARAM_ID, offset, 0.0);
+            }
+        }
+        let index = self.index(change.param)?;
+        let param = &self.params[index];
+        let value = self.set_value(
+            index,
+            param.min + (param.max - param.min) * change.normalized,
+        );
+        output.add_point(param.id, offset, to_normalized(param, value));
+        Some((index, value))
+    }
+
+    // The model with every parameter at its current value
+    fn model(&self) -> P::Model {
+        let mut model = P::Model::default();
+        for (index, param) in self.params.iter().enumerate() {
+            (param.set)(&mut model, self.value(index));
+        }
+        model
+    }
+
+    // The program a normalized value of the program change parameter selects
+    fn program_index(&self, normalized: f64) -> usize {
+        let last = self.presets.count().saturating_sub(1);
+        (normalized.clamp(0.0, 1.0) * last as f64).round() as usize
+    }
+
+    fn program_normalized(&self, index: usize) -> f64 {
+        let last = self.presets.count().saturating_sub(1);
+        if last == 0 {
+            0.0
+        } else {
+            (index.min(last) as f64 / last as f64).clamp(0.0, 1.0)
+        }
+    }
+
+    // Sets every parameter to the preset's value, returning false if there's no such program.
+    // MIDI mappings are kept, like they are when a host loads a preset's state.
+    fn load_program(&self, index: usize) -> bool {
+        let preset = match self.presets.get(index) {
+            Some(preset) => preset,
+            None => return false,
+        };
+        for (index, param) in self.params.iter().enumerate() {
+            self.set_value(index, (param.get)(&preset.model));
+        }
+        self.program.store(index, Ordering::Relaxed);
+        true
+    }
+
+    // Updates the reported latency for the current values, returning the restart flag to tell
+    // the host if it changed. Hosts only take restarts from the main thread, so a change from
+    // automation in process is reported on the next setActive or main thread change.
+    unsafe fn update_latency(&self) -> i32 {
+        let sample_rate = (*self.setup.get()).sample_rate as f32;
+        let latency = P::latency(&self.model(), sample_rate);
+        if self.latency.swap(latency, Ordering::Relaxed) != latency {
+            RESTART_LATENCY_CHANGED
+        } else {
+            0
+        }
+    }
+
+    unsafe fn restart(&self, flags: i32) {
+        let handler = self.handler.load(Ordering::Acquire);
+        if !handler.is_null() {
+            (vtbl::<IComponentHandlerVtbl>(handler).restart_component)(handler, flags);
+        }
+    }
+
+    unsafe extern "system" fn initialize(_this: *mut c_void, _context: *mut c_void) -> TResult {
+        RESULT_OK
+    }
+
+    unsafe extern "system" fn terminate(_this: *mut c_void) -> TResult {
+        RESULT_OK
+    }
+
+    // The controller is part of the component, hosts query it from the component instead
+    unsafe extern "system" fn get_controller_class_id(
+        _this: *mut c_void,
+        _class_id: *mut Tuid,
+    ) -> TResult {
+        NOT_IMPLEMENTED
+    }
+
+    unsafe extern "system" fn set_io_mode(_this: *mut c_void, _mode: i32) -> TResult {
+        RESULT_OK
+    }
+
+    // A stereo bus each way, and a MIDI input for control changes
+    unsafe extern "system" fn get_bus_count(_this: *mut c_void, media: i32, dir: i32) -> i32 {
+        if media == MEDIA_AUDIO || (media == MEDIA_EVENT && dir == BUS_INPUT) {
+            1
+        } else {
+            0
+        }
+    }
+
+    unsafe extern "system" fn get_bus_info(
+        this: *mut c_void,
+        media: i32,
+        dir: i32,
+        index: i32,
+        info: *mut BusInfo,
+    ) -> TResult {
+        if index < 0 || Self::get_bus_count(this, media, dir) <= index {
+            return INVALID_ARGUMENT;
+        }
+        let info = &mut *info;
+        info.media_type = media;
+        info.direction = dir;
+        let name = if media == MEDIA_EVENT {
+            info.channel_count = 16;
+            "MIDI In"
+        } else {
+            info.channel_count = CHANNELS as i32;
+            if dir == BUS_INPUT {
+                "Input"
+            } else {
+                "Output"
+            }
+        };
+        write_str16(name, &mut info.name);
+        info.bus_type = BUS_MAIN;
+        info.flags = BUS_DEFAULT_ACTIVE;
+        RESULT_OK
+    }
+
+    unsafe extern "system" fn get_routing_info(
+        _this: *mut c_void,
+        _in_info: *mut RoutingInfo,
+        _out_info: *mut RoutingInfo,
+    ) -> TResult {
+        NOT_IMPLEMENTED
+    }
+
+    unsafe extern "system" fn activate_bus(
+        this: *mut c_void,
+        media: i32,
+        dir: i32,
+        index: i32,
+        _state: u8,
+    ) -> TResult {
+        if 0 <= index && index < Self::get_bus_count(this, media, dir) {
+            RESULT_OK
+        } else {
+            INVALID_ARGUMENT
+        }
+    }
+
+    unsafe extern "system" fn set_active(this: *mut c_void, state: u8) -> TResult {
+        let instance = Self::from_interface::<{ COMPONENT }>(this);
+        *instance.active() = if state != 0 {
+            instance.update_latency();
+            let setup = *instance.setup.get();
+            let audio = Audio::new(
+                setup.sample_rate as f32,
+                instance.model(),
+                setup.max_samples_per_block.max(0) as usize,
+            );
+            instance.values_changed.store(false, Ordering::Relaxed);
+            Some(Active {
+                audio,
+                points: vec![0; instance.params.len() + CONTROLLERS + 1],
+            })
+        } else {
+            None
+        };
+        RESULT_OK
+    }
+
+    unsafe extern "system" fn set_state(this: *mut c_void, stream: *mut c_void) -> TResult {
+        let instance = Self::from_interface::<{ COMPONENT }>(this);
+        let model: P::Model = match read_stream(stream)
+            .as_ref()
+            .and_then(|json| std::str::from_utf8(json).ok())
+            .and_then(|json| state::plugin_from_json(json, &instance.midi).ok())
+        {
+            Some(model) => model,
+            None => return RESULT_FALSE,
+        };
+        for (index, param) in instance.params.iter().enumerate() {
+            instance.set_value(index, (param.get)(&model));
+        }
+        instance.values_changed.store(true, Ordering::Relaxed);
+        instance.restart(RESTART_PARAM_VALUES_CHANGED | instance.update_latency());
+        RESULT_OK
+    }
+
+    unsafe extern "system" fn get_state(this: *mut c_void, stream: *mut c_void) -> TResult {
+        let instance = Self::from_interface::<{ COMPONENT }>(this);
+        match state::plugin_to_json(&instance.model(), &instance.midi) {
+            Ok(json) if write_stream(stream, json.as_bytes()) => RESULT_OK,
+            _ => RESULT_FALSE,
+        }
+    }
+
+    unsafe extern "system" fn set_bus_arrangements(
+        _this: *mut c_void,
+        inputs: *mut u64,
+        num_ins: i32,
+        outputs: *mut u64,
+        num_outs: i32,
+    ) -> TResult {
+        if num_ins == 1 && num_outs == 1 && *inputs == SPEAKER_STEREO && *outputs == SPEAKER_STEREO
+        {
+            RESULT_OK
+        } else {
+            RESULT_FALSE
+        }
+    }
+
+    unsafe extern "system" fn get_bus_arrangement(
+        _this: *mut c_void,
+        _dir: i32,
+        index: i32,
+        arrangement: *mut u64,
+    ) -> TResult {
+        if index != 0 {
+            return INVALID_ARGUMENT;
+        }
+        *arrangement = SPEAKER_STEREO;
+        RESULT_OK
+    }
+
+    unsafe extern "system" fn can_process_sample_size(_this: *mut c_void, size: i32) -> TResult {
+        if size == SAMPLE_32 {
+            RESULT_OK
+        } else {
+            RESULT_FALSE
+        }
+    }
+
+    unsafe extern "system" fn get_latency_samples(this: *mut c_void) -> u32 {
+        let instance = Self::from_interface::<{ PROCESSOR }>(this);
+        instance.latency.load(Ordering::Relaxed)
+    }
+
+    unsafe extern "system" fn setup_processing(
+        this: *mut c_void,
+        setup: *mut ProcessSetup,
+    ) -> TResult {
+        let instance = Self::from_interface::<{ PROCESSOR }>(this);
+        if (*setup).symbolic_sample_size != SAMPLE_32 {
+            return RESULT_FALSE;
+        }
+        *instance.setup.get() = *setup;
+        RESULT_OK
+    }
+
+    unsafe extern "system" fn set_processing(_this: *mut c_void, _state: u8) -> TResult {
+        RESULT_OK
+    }
+
+    unsafe extern "system" fn process(this: *mut c_void, data: *mut ProcessData) -> TResult {
+        let instance = Self::from_interface::<{ PROCESSOR }>(this);
+        let data = &*data;
+        let active = match instance.active() {
+            Some(active) => active,
+            None => return NOT_INITIALIZED,
+        };
+        if data.symbolic_sample_size != SAMPLE_32 {
+            return INVALID_ARGUMENT;
+        }
+        let audio = &mut active.audio;
+        let nframes = (data.num_samples.max(0) as usize).min(audio.inputs[0].len());
+
+        if instance.values_changed.swap(false, Ordering::Relaxed) {
+            for (index, param) in instance.params.iter().enumerate() {
+                (param.set)(&mut audio.model, instance.value(index));
+            }
+            audio.smooth.set(&audio.model);
+        }
+
+        audio.copy_inputs(bus_channels(data.inputs, data.num_inputs), nframes);
+        let outputs = bus_channels(data.outputs, data.num_outputs);
+        if let Some(bus) = data.outputs.as_mut().filter(|_| data.num_outputs > 0) {
+            bus.silence_flags = 0;
+        }
+        let musical_time = musical_time(data.process_context);
+
+        // Each queue's points are applied at their sample offset, the block is split at each
+        // one. Points past the end of the block are applied after it.
+        let changes = ParameterChanges(data.input_parameter_changes);
+        let output = ParameterChanges(data.output_parameter_changes);
+        let queue_count = (changes.len().max(0) as usize).min(active.points.len());
+        active.points[..queue_count]
+            .iter_mut()
+            .for_each(|point| *point = 0);
+        let mut start = 0;
+        loop {
+            let mut next = nframes;
+            for (queue_index, point) in active.points[..queue_count].iter_mut().enumerate() {
+                let queue = match changes.get(queue_index as i32) {
+                    Some(queue) => queue,
+                    None => continue,
+                };
+                let id = queue.id();
+                if instance.index(id).is_none() && midi_cc(id).is_none() && id != PROGRAM_PARAM_ID {
+                    continue;
+                }
+                let count = queue.len();
+                while *point < count {
+                    let (offset, normalized) = match queue.point(*point) {
+                        Some(point) => point,
+                        None => {
+                            *point = count;
+                            break;
+                        }
+                    };
+                    let offset = (offset.max(0) as usize).min(nframes);
+                    if offset > start {
+                        next = next.min(offset);
+                        break;
+                    }
+                    if id == PROGRAM_PARAM_ID {
+                        // Every parameter changes, the host is sent their new values
+                        if instance.load_program(instance.program_index(normalized)) {
+                            for (index, param) in instance.params.iter().enumerate() {
+                                let value = instance.value(index);
+                                (param.set)(&mut audio.model, value);
+                                output.add_point(
+                                    param.id,
+                                    offset as i32,
+                                    to_normalized(param, value),
+                                );
+                            }
+                            audio.smooth.set(&audio.model);
+                        }
+                    } else if let Some((index, value)) =
+                        instance.apply_point(id, offset as i32, normalized, &output)
+                    {
+                        (instance.params[index].set)(&mut audio.model, value);
+                        audio.smooth.set(&audio.model);
+                    }
+                    *point += 1;
+                }
+            }
+            if start >= nframes {
+                break;
+            }
+            let end = next.min(start + MAX_BLOCK);
+            audio.run(start, end, outputs, &musical_time);
+            start = end;
+        }
+        RESULT_OK
+    }
+
+    // baseplug plugins don't report their tail, so hosts are asked to keep processing
+    unsafe extern "system" fn get_tail_samples(_this: *mut c_void) -> u32 {
+        INFINITE_TAIL
+    }
+
+    // The component's state is already loaded, it's the same object
+    unsafe extern "system" fn set_component_state(
+        _this: *mut c_void,
+        _stream: *mut c_void,
+    ) -> TResult {
+        RESULT_OK
+    }
+
+    // The controller has no state of its own
+    unsafe extern "system" fn controller_set_state(
+        _this: *mut c_void,
+        _stream: *mut c_void,
+    ) -> TResult {
+        RESULT_OK
+    }
+
+    unsafe extern "system" fn controller_get_state(
+        _this: *mut c_void,
+        _stream: *mut c_void,
+    ) -> TResult {
+        RESULT_OK
+    }
+
+    // The MIDI CC parameters come after the plugin's, then the program change parameter
+    unsafe extern "system" fn get_parameter_count(this: *mut c_void) -> i32 {
+        (Self::from_interface::<{ CONTROLLER }>(this).params.len() + CONTROLLERS + 1) as i32
+    }
+
+    unsafe extern "system" fn get_parameter_info(
+        this: *mut c_void,
+        param_index: i32,
+        info: *mut ParameterInfo,
+    ) -> TResult {
+        let instance = Self::from_interface::<{ CONTROLLER }>(this);
+        let cc_index = (param_index.max(0) as usize).checked_sub(instance.params.len());
+        let param = match instance.params.get(param_index.max(0) as usize) {
+            Some(param) if param_index >= 0 => param,
+            _ => match cc_index.filter(|&cc| cc < CONTROLLERS) {
+                Some(cc) => {
+                    let info = &mut *info;
+                    info.id = MIDI_CC_PARAM_ID + cc as u32;
+                    write_str16(&format!("MIDI CC {}", cc), &mut info.title);
+                    write_str16(&format!("CC {}", cc), &mut info.short_title);
+                    write_str16("", &mut info.units);
+                    info.step_count = 127;
+                    info.default_normalized_value = 0.0;
+                    info.unit_id = ROOT_UNIT;
+                    info.flags = PARAM_IS_HIDDEN;
+                    return RESULT_OK;
+                }
+                None if cc_index == Some(CONTROLLERS) => {
+                    let info = &mut *info;
+                    info.id = PROGRAM_PARAM_ID;
+                    write_str16("Program", &mut info.title);
+                    write_str16("Program", &mut info.short_title);
+                    write_str16("", &mut info.units);
+                    info.step_count = instance.presets.count().saturating_sub(1) as i32;
+                    info.default_normalized_value = 0.0;
+                    info.unit_id = ROOT_UNIT;
+                    info.flags = PARAM_IS_PROGRAM_CHANGE | PARAM_IS_LIST;
+                    return RESULT_OK;
+                }
+                None => return INVALID_ARGUMENT,
+            },
+        };
+        let info = &mut *info;
+        info.id = param.id;
+        write_str16(param.name, &mut info.title);
+        write_str16(param.name, &mut info.short_title);
+        write_str16(param.unit, &mut info.units);
+        info.step_count = if param.stepped {
+            (param.max - param.min) as i32
+        } else {
+            0
+        };
+        info.default_normalized_value = to_normalized(param, (param.get)(&P::Model::default()));
+        info.unit_id = ROOT_UNIT;
+        info.flags = if param.id == LEARN_PARAM_ID {
+            0
+        } else {
+            PARAM_CAN_AUTOMATE
+        };
+        if P::BYPASS_PARAM == Some(param.id) {
+            info.flags |= PARAM_IS_BYPASS;
+        }
+        RESULT_OK
+    }
+
+    unsafe extern "system" fn get_param_string_by_value(
+        this: *mut c_void,
+        id: u32,
+        normalized: f64,
+        string: *mut u16,
+    ) -> TResult {
+        let instance = Self::from_interface::<{ CONTROLLER }>(this);
+        let string = std::slice::from_raw_parts_mut(string, 128);
+        if id == PROGRAM_PARAM_ID {
+            let index = instance.program_index(normalized);
+            write_str16(
+                instance.presets.program_name(index).unwrap_or_default(),
+                string,
+            );
+            return RESULT_OK;
+        }
+        let param = match instance.index(id) {
+            Some(index) => &instance.params[index],
+            None => return INVALID_ARGUMENT,
+        };
+        let value = from_normalized(param, normalized);
+        let text = if id == LEARN_PARAM_ID {
+            midi::format_learn(&instance.params, value)
+        } else {
+            format_value(param, value)
+        };
+        write_str16(&text, string);
+        RESULT_OK
+    }
+
+    unsafe extern "system" fn get_param_value_by_string(
+        this: *mut c_void,
+        id: u32,
+        string: *const u16,
+        normalized: *mut f64,
+    ) -> TResult {
+        let instance = Self::from_interface::<{ CONTROLLER }>(this);
+        if string.is_null() {
+            return INVALID_ARGUMENT;
+        }
+        let text = read_str16(string);
+        if id == PROGRAM_PARAM_ID {
+            return match instance.presets.find(&text) {
+                Some(index) => {
+                    *normalized = instance.program_normalized(index);
+                    RESULT_OK
+                }
+                None => RESULT_FALSE,
+            };
+        }
+        let param = match instance.index(id) {
+            Some(index) => &instance.params[index],
+            None => return INVALID_ARGUMENT,
+        };
+        let value = if id == LEARN_PARAM_ID {
+            midi::parse_learn(&instance.params, &text)
+        } else {
+            parse_value(&text)
+        };
+        match value {
+            Some(value) => {
+                *normalized = to_normalized(param, value);
+                RESULT_OK
+            }
+            None => RESULT_FALSE,
+        }
+    }
+
+    unsafe extern "system" fn normalized_param_to_plain(
+        this: *mut c_void,
+        id: u32,
+        normalized: f64,
+    ) -> f64 {
+        let instance = Self::from_interface::<{ CONTROLLER }>(this);
+        match instance.index(id) {
+            Some(index) => from_normalized(&instance.params[index], normalized),
+            None if id == PROGRAM_PARAM_ID => instance.program_index(normalized) as f64,
+            None => normalized,
+        }
+    }
+
+    unsafe extern "system" fn plain_param_to_normalized(
+        this: *mut c_void,
+        id: u32,
+        plain: f64,
+    ) -> f64 {
+        let instance = Self::from_interface::<{ CONTROLLER }>(this);
+        match instance.index(id) {
+            Some(index) => to_normalized(&instance.params[index], plain),
+            None if id == PROGRAM_PARAM_ID => instance.program_normalized(plain.round() as usize),
+            None => plain,
+        }
+    }
+
+    unsafe extern "system" fn get_param_normalized(this: *mut c_void, id: u32) -> f64 {
+        let instance = Self::from_interface::<{ CONTROLLER }>(this);
+        match instance.index(id) {
+            Some(index) => to_normalized(&instance.params[index], instance.value(index)),
+            None if id == PROGRAM_PARAM_ID => {
+                instance.program_normalized(instance.program.load(Ordering::Relaxed))
+            }
+            None => 0.0,
+        }
+    }
+
+    // Called by hosts when not processing, the audio thread picks the value up on its next
+    // process
+    unsafe extern "system" fn set_param_normalized(
+        this: *mut c_void,
+        id: u32,
+        normalized: f64,
+    ) -> TResult {
+        let instance = Self::from_interface::<{ CONTROLLER }>(this);
+        match instance.index(id) {
+            Some(index) => {
+                instance.set_value(index, from_normalized(&instance.params[index], normalized));
+                instance.values_changed.store(true, Ordering::Relaxed);
+                let latency_changed = instance.update_latency();
+                if latency_changed != 0 {
+                    instance.restart(latency_changed);
+                }
+                RESULT_OK
+            }
+            None if id == PROGRAM_PARAM_ID => {
+                if !instance.load_program(instance.program_index(normalized)) {
+                    return RESULT_FALSE;
+                }
+                instance.values_changed.store(true, Ordering::Relaxed);
+                instance.restart(RESTART_PARAM_VALUES_CHANGED | instance.update_latency());
+                RESULT_OK
+            }
+            // Hosts may keep the MIDI CC parameters in sync, they have no value of their own
+            None if midi_cc(id).is_some() => RESULT_OK,
+            None => INVALID_ARGUMENT,
+        }
+    }
+
+    unsafe extern "system" fn set_component_handler(
+        this: *mut c_void,
+        handler: *mut c_void,
+    ) -> TResult {
+        let instance = Self::from_interface::<{ CONTROLLER }>(this);
+        if !handler.is_null() {
+            (vtbl::<FUnknownVtbl>(handler).add_ref)(handler);
+        }
+        let old = instance.handler.swap(handler, Ordering::AcqRel);
+        if !old.is_null() {
+            (vtbl::<FUnknownVtbl>(old).release)(old);
+        }
+        RESULT_OK
+    }
+
+    unsafe extern "system" fn create_view(_this: *mut c_void, _name: *const c_char) -> *mut c_void {
+        ptr::null_mut()
+    }
+
+    // Every controller on the MIDI input, on any channel, has its own hidden parameter
+    unsafe extern "system" fn get_midi_controller_assignment(
+        _this: *mut c_void,
+        bus_index: i32,
+        _channel: i16,
+        cc: i16,
+        id: *mut u32,
+    ) -> TResult {
+        if bus_index != 0 || !(0..CONTROLLERS as i16).contains(&cc) {
+            return RESULT_FALSE;
+        }
+        *id = MIDI_CC_PARAM_ID + cc as u32;
+        RESULT_OK
+    }
+
+    // A single root unit, with the presets as its program list
+    unsafe extern "system" fn get_unit_count(_this: *mut c_void) -> i32 {
+        1
+    }
+
+    unsafe extern "system" fn get_unit_info(
+        _this: *mut c_void,
+        unit_index: i32,
+        info: *mut UnitInfo,
+    ) -> TResult {
+        if unit_index != 0 {
+            return INVALID_ARGUMENT;
+        }
+        let info = &mut *info;
+        info.id = ROOT_UNIT;
+        info.parent_unit_id = NO_PARENT_UNIT;
+        write_str16("Root", &mut info.name);
+        info.program_list_id = PROGRAM_LIST_ID;
+        RESULT_OK
+    }
+
+    unsafe extern "system" fn get_program_list_count(_this: *mut c_void) -> i32 {
+        1
+    }
+
+    unsafe extern "system" fn get_program_list_info(
+        this: *mut c_void,
+        list_index: i32,
+        info: *mut ProgramListInfo,
+    ) -> TResult {
+        let instance = Self::from_interface::<{ UNIT_INFO }>(this);
+        if list_index != 0 {
+            return INVALID_ARGUMENT;
+        }
+        let info = &mut *info;
+        info.id = PROGRAM_LIST_ID;
+        write_str16("Presets", &mut info.name);
+        info.program_count = instance.presets.count() as i32;
+        RESULT_OK
+    }
+
+    unsafe extern "system" fn get_program_name(
+        this: *mut c_void,
+        list_id: i32,
+        program_index: i32,
+        name: *mut u16,
+    ) -> TResult {
+        let instance = Self::from_interface::<{ UNIT_INFO }>(this);
+        let program_name = Some(program_index)
+            .filter(|&index| list_id == PROGRAM_LIST_ID && index >= 0)
+            .and_then(|index| instance.presets.program_name(index as usize));
+        match program_name {
+            Some(program_name) => {
+                write_str16(program_name, std::slice::from_raw_parts_mut(name, 128));
+                RESULT_OK
+            }
+            None => INVALID_ARGUMENT,
+        }
+    }
+
+    // Presets have no attributes besides their name
+    unsafe extern "system" fn get_program_info(
+        _this: *mut c_void,
+        _list_id: i32,
+        _program_index: i32,
+        _attribute_id: *const c_char,
+        _value: *mut u16,
+    ) -> TResult {
+        RESULT_FALSE
+    }
+
+    unsafe extern "system" fn has_program_pitch_names(
+        _this: *mut c_void,
+        _list_id: i32,
+        _program_index: i32,
+    ) -> TResult {
+        RESULT_FALSE
+    }
+
+    unsafe extern "system" fn get_program_pitch_name(
+        _this: *mut c_void,
+        _list_id: i32,
+        _program_index: i32,
+        _pitch: i16,
+        _name: *mut u16,
+    ) -> TResult {
+        RESULT_FALSE
+    }
+
+    unsafe extern "system" fn get_selected_unit(_this: *mut c_void) -> i32 {
+        ROOT_UNIT
+    }
+
+    unsafe extern "system" fn select_unit(_this: *mut c_void, unit_id: i32) -> TResult {
+        if unit_id == ROOT_UNIT {
+            RESULT_OK
+        } else {
+            RESULT_FALSE
+        }
+    }
+
+    unsafe extern "system" fn get_unit_by_bus(
+        _this: *mut c_void,
+        _media: i32,
+        _dir: i32,
+        _bus_index: i32,
+        _channel: i32,
+        unit_id: *mut i32,
+    ) -> TResult {
+        *unit_id = ROOT_UNIT;
+        RESULT_OK
+    }
+
+    // Programs are selected with the program change parameter, hosts don't send their data
+    unsafe extern "system" fn set_unit_program_data(
+        _this: *mut c_void,
+        _list_or_unit_id: i32,
+        _program_index: i32,
+        _data: *mut c_void,
+    ) -> TResult {
+        NOT_IMPLEMENTED
+    }
+}
+
+impl<P: Plugin> Drop for Instance<P> {
+    fn drop(&mut self) {
+        let handler = *self.handler.get_mut();
+        if !handler.is_null() {
+            unsafe { (vtbl::<FUnknownVtbl>(handler).release)(handler) };
+        }
+    }
+}
+
+struct ParameterChanges(*mut c_void);
+
+impl ParameterChanges {
+    unsafe fn len(&self) -> i32 {
+        if self.0.is_null() {
+            return 0;
+        }
+        (vtbl::<IParameterChangesVtbl>(self.0).get_parameter_count)(self.0)
+    }
+
+    unsafe fn get(&self, index: i32) -> Option<ParamValueQueue> {
+        let queue = (vtbl::<IParameterChangesVtbl>(self.0).get_parameter_data)(self.0, index);
+        if queue.is_null() {
+            None
+        } else {
+            Some(ParamValueQueue(queue))
+        }
+    }
+
+    // Adds a point to a parameter's queue, if there are output changes
+    unsafe fn add_point(&self, id: u32, offset: i32, normalized: f64) {
+        if self.0.is_null() {
+            return;
+        }
+        let mut index = 0;
+        let queue =
+            (vtbl::<IParameterChangesVtbl>(self.0).add_parameter_data)(self.0, &id, &mut index);
+        if !queue.is_null() {
+            (vtbl::<IParamValueQueueVtbl>(queue).add_point)(queue, offset, normalized, &mut index);
+        }
+    }
+}
+
+struct ParamValueQueue(*mut c_void);
+
+impl ParamValueQueue {
+    unsafe fn id(&self) -> u32 {
+        (vtbl::<IParamValueQueueVtbl>(self.0).get_parameter_id)(self.0)
+    }
+
+    unsafe fn len(&self) -> i32 {
+        (vtbl::<IParamValueQueueVtbl>(self.0).get_point_count)(self.0)
+    }
+
+    // The sample offset and normalized value of a point
+    unsafe fn point(&self, index: i32) -> Option<(i32, f64)> {
+        let mut offset = 0;
+        let mut value = 0.0;
+        let result = (vtbl::<IParamValueQueueVtbl>(self.0).get_point)(
+            self.0,
+            index,
+            &mut offset,
+            &mut value,
+        );
+        if result == RESULT_OK {
+            Some((offset, value))
+        } else {
+            None
+        }
+    }
+}
+
+// The channel pointers of the first bus, if there is one
+unsafe fn bus_channels<'a>(buses: *mut AudioBusBuffers, count: i32) -> Option<&'a [*mut f32]> {
+    if buses.is_null() || count <= 0 {
+        return None;
+    }
+    let bus = &*buses;
+    if bus.channel_buffers_32.is_null() {
+        return None;
+    }
+    Some(std::slice::from_raw_parts(
+        bus.channel_buffers_32,
+        bus.num_channels.max(0) as usize,
+    ))
+}
+
+unsafe fn musical_time(context: *const ProcessContext) -> MusicalTime {
+    let mut time = MusicalTime {
+        bpm: 120.0,
+        beat: 0.0,
+    };
+    if let Some(context) = context.as_ref() {
+        if context.state & CONTEXT_TEMPO_VALID != 0 {
+            time.bpm = context.tempo;
+        }
+        if context.state & CONTEXT_PROJECT_TIME_MUSIC_VALID != 0 {
+            time.beat = context.project_time_music;
+        }
+    }
+    time
+}
+
+unsafe fn read_stream(stream: *mut c_void) -> Option<Vec<u8>> {
+    if stream.is_null() {
+        return None;
+    }
+    let read = vtbl::<IBStreamVtbl>(stream).read;
+    let mut bytes = Vec::new();
+    let mut buffer = [0u8; 4096];
+    loop {
+        let mut count = 0;
+        let result = read(
+            stream,
+            buffer.as_mut_ptr() as *mut c_void,
+            buffer.len() as i32,
+            &mut count,
+        );
+        if result != RESULT_OK {
+            return None;
+        }
+        if count <= 0 {
+            return Some(bytes);
+        }
+        bytes.extend_from_slice(&buffer[..(count as usize).min(buffer.len())]);
+    }
+}
+
+unsafe fn write_stream(stream: *mut c_void, mut bytes: &[u8]) -> bool {
+    if stream.is_null() {
+        return false;
+    }
+    let write = vtbl::<IBStreamVtbl>(stream).write;
+    while !bytes.is_empty() {
+        let mut written = 0;
+        let result = write(
+            stream,
+            bytes.as_ptr() as *mut c_void,
+            bytes.len() as i32,
+            &mut written,
+        );
+        if result != RESULT_OK || written <= 0 {
+            return false;
+        }
+        bytes = &bytes[(written as usize).min(bytes.len())..];
+    }
+    true
+}
+
+// A minimal in-process host for the plugins' tests, it drives GetPluginFactory the same way a
+// host drives the exported .vst3
+#[cfg(feature = "testing")]
+pub mod host {
+    use super::*;
+
+    pub const SAMPLE_RATE: f64 = 48000.0;
+    pub const MAX_FRAMES: i32 = 1024;
+
+    unsafe extern "system" fn no_interface(
+        _this: *mut c_void,
+        _iid: *const Tuid,
+        obj: *mut *mut c_void,
+    ) -> TResult {
+        *obj = ptr::null_mut();
+        NO_INTERFACE
+    }
+
+    // Host objects live on the test's stack or in a Box it owns
+    unsafe extern "system" fn unowned(_this: *mut c_void) -> u32 {
+        1
+    }
+
+    const UNKNOWN: FUnknownVtbl = FUnknownVtbl {
+        query_interface: no_interface,
+        add_ref: unowned,
+        release: unowned,
+    };
+
+    #[repr(C)]
+    struct Handler {
+        vtbl: *const IComponentHandlerVtbl,
+        restarts: AtomicUsize,
+    }
+
+    unsafe extern "system" fn handler_edit(_this: *mut c_void, _id: u32) -> TResult {
+        RESULT_OK
+    }
+
+    unsafe extern "system" fn handler_perform_edit(
+        _this: *mut c_void,
+        _id: u32,
+        _value: f64,
+    ) -> TResult {
+        RESULT_OK
+    }
+
+    unsafe extern "system" fn handler_restart(this: *mut c_void, _flags: i32) -> TResult {
+        (*(this as *const Handler))
+            .restarts
+            .fetch_add(1, Ordering::Relaxed);
+        RESULT_OK
+    }
+
+    const HANDLER: IComponentHandlerVtbl = IComponentHandlerVtbl {
+        unknown: UNKNOWN,
+        begin_edit: handler_edit,
+        perform_edit: handler_perform_edit,
+        end_edit: handler_edit,
+        restart_component: handler_restart,
+    };
+
+    // An IBStream over memory that reads and writes a few bytes at a time, the plugin has to
+    // keep going
+    #[repr(C)]
+    struct Stream {
+        vtbl: *const IBStreamVtbl,
+        bytes: Vec<u8>,
+        position: usize,
+    }
+
+    unsafe extern "system" fn stream_read(
+        this: *mut c_void,
+        buffer: *mut c_void,
+        size: i32,
+        read: *mut i32,
+    ) -> TResult {
+        let stream = &mut *(this as *mut Stream);
+        let size = (size.max(0) as usize)
+            .min(stream.bytes.len() - stream.position)
+            .min(5);
+        let input = &stream.bytes[stream.position..stream.position + size];
+        std::ptr::copy_nonoverlapping(input.as_ptr(), buffer as *mut u8, size);
+        stream.position += size;
+        if !read.is_null() {
+            *read = size as i32;
+        }
+        RESULT_OK
+    }
+
+    unsafe extern "system" fn stream_write(
+        this: *mut c_void,
+        buffer: *mut c_void,
+        size: i32,
+        written: *mut i32,
+    ) -> TResult {
+        let stream = &mut *(this as *mut Stream);
+        let size = (size.max(0) as usize).min(7);
+        let output = std::slice::from_raw_parts(buffer as *const u8, size);
+        stream.bytes.extend_from_slice(output);
+        if !written.is_null() {
+            *written = size as i32;
+        }
+        RESULT_OK
+    }
+
+    unsafe extern "system" fn stream_seek(
+        _this: *mut c_void,
+        _pos: i64,
+        _mode: i32,
+        _result: *mut i64,
+    ) -> TResult {
+        NOT_IMPLEMENTED
+    }
+
+    unsafe extern "system" fn stream_tell(this: *mut c_void, pos: *mut i64) -> TResult {
+        *pos = (*(this as *const Stream)).position as i64;
+        RESULT_OK
+    }
+
+    const STREAM: IBStreamVtbl = IBStreamVtbl {
+        unknown: UNKNOWN,
+        read: stream_read,
+        write: stream_write,
+        seek: stream_seek,
+        tell: stream_tell,
+    };
+
+    fn stream(bytes: &[u8]) -> Stream {
+        Stream {
+            vtbl: &STREAM,
+            bytes: bytes.to_vec(),
+            position: 0,
+        }
+    }
+
+    /// One parameter value change at a sample offset of the next block
+    #[derive(Clone, Copy, Debug, PartialEq)]
+    pub struct ParamEvent {
+        pub offset: i32,
+        pub id: u32,
+        pub normalized: f64,
+    }
+
+    #[repr(C)]
+    struct Queue {
+        vtbl: *const IParamValueQueueVtbl,
+        id: u32,
+        points: Vec<(i32, f64)>,
+    }
+
+    unsafe extern "system" fn queue_id(this: *mut c_void) -> u32 {
+        (*(this as *const Queue)).id
+    }
+
+    unsafe extern "system" fn queue_len(this: *mut c_void) -> i32 {
+        (*(this as *const Queue)).points.len() as i32
+    }
+
+    unsafe extern "system" fn queue_point(
+        this: *mut c_void,
+        index: i32,
+        offset: *mut i32,
+        value: *mut f64,
+    ) -> TResult {
+        let queue = &*(this as *const Queue);
+        match queue.points.get(index as usize) {
+            Some(&point) => {
+                *offset = point.0;
+                *value = point.1;
+                RESULT_OK
+            }
+            None => INVALID_ARGUMENT,
+        }
+    }
+
+    unsafe extern "system" fn queue_add_point(
+        this: *mut c_void,
+        offset: i32,
+        value: f64,
+        index: *mut i32,
+    ) -> TResult {
+        let queue = &mut *(this as *mut Queue);
+        queue.points.push((offset, value));
+        *index = queue.points.len() as i32 - 1;
+        RESULT_OK
+    }
+
+    const QUEUE: IParamValueQueueVtbl = IParamValueQueueVtbl {
+        unknown: UNKNOWN,
+        get_parameter_id: queue_id,
+        get_point_count: queue_len,
+        get_point: queue_point,
+        add_point: queue_add_point,
+    };
+
+    #[repr(C)]
+    struct Changes {
+        vtbl: *const IParameterChangesVtbl,
+        queues: Vec<Queue>,
+    }
+
+    unsafe extern "system" fn changes_len(this: *mut c_void) -> i32 {
+        (*(this as *const Changes)).queues.len() as i32
+    }
+
+    unsafe extern "system" fn changes_get(this: *mut c_void, index: i32) -> *mut c_void {
+        let changes = &mut *(this as *mut Changes);
+        match changes.queues.get_mut(index as usize) {
+            Some(queue) => queue as *mut Queue as *mut c_void,
+            None => ptr::null_mut(),
+        }
+    }
+
+    unsafe extern "system" fn changes_add(
+        this: *mut c_void,
+        id: *const u32,
+        index: *mut i32,
+    ) -> *mut c_void {
+        let changes = &mut *(this as *mut Changes);
+        let position = match changes.queues.iter().position(|queue| queue.id == *id) {
+            Some(position) => position,
+            None => {
+                changes.queues.push(Queue {
+                    vtbl: &QUEUE,
+                    id: *id,
+                    points: Vec::new(),
+                });
+                changes.queues.len() - 1
+            }
+        };
+        *index = position as i32;
+        &mut changes.queues[position] as *mut Queue as *mut c_void
+    }
+
+    const CHANGES: IParameterChangesVtbl = IParameterChangesVtbl {
+        unknown: UNKNOWN,
+        get_parameter_count: changes_len,
+        get_parameter_data: changes_get,
+        add_parameter_data: changes_add,
+    };
+
+    // A queue per parameter, with its points in order like hosts send them
+    fn changes(events: &[ParamEvent]) -> Changes {
+        let mut queues: Vec<Queue> = Vec::new();
+        for event in events {
+            let point = (event.offset, event.normalized);
+            match queues.iter_mut().find(|queue| queue.id == event.id) {
+                Some(queue) => queue.points.push(point),
+                None => queues.push(Queue {
+                    vtbl: &QUEUE,
+                    id: event.id,
+                    points: vec![point],
+                }),
+            }
+        }
+        Changes {
+            vtbl: &CHANGES,
+            queues,
+        }
+    }
+
+    fn c_str_array(chars: &[c_char]) -> String {
+        let bytes: Vec<u8> = chars
+            .iter()
+            .take_while(|c| **c != 0)
+            .map(|c| *c as u8)
+            .collect();
+        String::from_utf8_lossy(&bytes).into_owned()
+    }
+
+    fn str16_array(chars: &[u16]) -> String {
+        unsafe { read_str16(chars.as_ptr()) }
+    }
+
+    pub struct Host {
+        factory: *mut c_void,
+        handler: Box<Handler>,
+    }
+
+    // A plugin instance's interfaces, each holding a reference
+    pub struct Instance {
+        pub component: *mut c_void,
+        pub processor: *mut c_void,
+        pub controller: *mut c_void,
+        pub midi_mapping: *mut c_void,
+        pub unit_info: *mut c_void,
+    }
+
+    impl Host {
+        pub fn new(get_factory: extern "system" fn() -> *mut c_void) -> Host {
+            let factory = get_factory();
+            assert!(!factory.is_null());
+            Host {
+                factory,
+                handler: Box::new(Handler {
+                    vtbl: &HANDLER,
+                    restarts: AtomicUsize::new(0),
+                }),
+            }
+        }
+
+        fn factory(&self) -> &IPluginFactory2Vtbl {
+            unsafe { vtbl(self.factory) }
+        }
+
+        pub fn restarts(&self) -> usize {
+            self.handler.restarts.load(Ordering::Relaxed)
+        }
+
+        pub fn class_info(&self) -> PClassInfo2 {
+            let factory = self.factory();
+            unsafe {
+                assert_eq!((factory.count_classes)(self.factory), 1);
+                let mut info: PClassInfo2 = std::mem::zeroed();
+                assert_eq!(
+                    (factory.get_class_info2)(self.factory, 1, &mut info),
+                    INVALID_ARGUMENT
+                );
+                assert_eq!(
+                    (factory.get_class_info2)(self.factory, 0, &mut info),
+                    RESULT_OK
+                );
+                info
+            }
+        }
+
+        pub fn create(&self) -> Instance {
+            let factory = self.factory();
+            let cid = self.class_info().cid;
+            unsafe {
+                let mut component = ptr::null_mut();
+                assert_eq!(
+                    (factory.create_instance)(
+                        self.factory,
+                        cid.as_ptr() as *const c_char,
+                        ICOMPONENT_IID.as_ptr() as *const c_char,
+                        &mut component
+                    ),
+                    RESULT_OK
+                );
+                let query = |iid: &Tuid| {
+                    let mut interface = ptr::null_mut();
+                    let unknown = vtbl::<FUnknownVtbl>(component);
+                    assert_eq!(
+                        (unknown.query_interface)(component, iid, &mut interface),
+                        RESULT_OK
+                    );
+                    interface
+                };
+                let instance = Instance {
+                    component,
+                    processor: query(&IAUDIO_PROCESSOR_IID),
+                    controller: query(&IEDIT_CONTROLLER_IID),
+                    midi_mapping: query(&IMIDI_MAPPING_IID),
+                    unit_info: query(&IUNIT_INFO_IID),
+                };
+                let base = &instance.component().base;
+                assert_eq!((base.initialize)(component, ptr::null_mut()), RESULT_OK);
+                let handler = &*self.handler as *const Handler as *mut c_void;
+                assert_eq!(
+                    (instance.controller().set_component_handler)(instance.controller, handler),
+                    RESULT_OK
+                );
+                instance
+            }
+        }
+    }
+
+    impl Drop for Host {
+        fn drop(&mut self) {
+            unsafe { (self.factory().unknown.release)(self.factory) };
+        }
+    }
+
+    impl Instance {
+        pub fn component(&self) -> &IComponentVtbl {
+            unsafe { vtbl(self.component) }
+        }
+
+        pub fn processor(&self) -> &IAudioProcessorVtbl {
+            unsafe { vtbl(self.processor) }
+        }
+
+        pub fn controller(&self) -> &IEditControllerVtbl {
+            unsafe { vtbl(self.controller) }
+        }
+
+        pub fn midi_mapping(&self) -> &IMidiMappingVtbl {
+            unsafe { vtbl(self.midi_mapping) }
+        }
+
+        pub fn unit_info(&self) -> &IUnitInfoVtbl {
+            unsafe { vtbl(self.unit_info) }
+        }
+
+        /// The names of the root unit's programs
+        pub fn program_names(&self) -> Vec<String> {
+            unsafe {
+                let units = self.unit_info();
+                assert_eq!((units.get_unit_count)(self.unit_info), 1);
+                let mut unit: UnitInfo = std::mem::zeroed();
+                assert_eq!(
+                    (units.get_unit_info)(self.unit_info, 0, &mut unit),
+                    RESULT_OK
+                );
+                assert_eq!(unit.id, ROOT_UNIT);
+                assert_eq!(unit.parent_unit_id, NO_PARENT_UNIT);
+                assert_ne!(unit.program_list_id, NO_PROGRAM_LIST);
+                assert_eq!((units.get_program_list_count)(self.unit_info), 1);
+                let mut list: ProgramListInfo = std::mem::zeroed();
+                assert_eq!(
+                    (units.get_program_list_info)(self.unit_info, 0, &mut list),
+                    RESULT_OK
+                );
+                assert_eq!(list.id, unit.program_list_id);
+                let names = (0..list.program_count)
+                    .map(|index| {
+                        let mut name = [0u16; 128];
+                        let result = (units.get_program_name)(
+                            self.unit_info,
+                            list.id,
+                            index,
+                            name.as_mut_ptr(),
+                        );
+                        assert_eq!(result, RESULT_OK);
+                        str16_array(&name)
+                    })
+                    .collect();
+                let mut name = [0u16; 128];
+                let result = (units.get_program_name)(
+                    self.unit_info,
+                    list.id,
+                    list.program_count,
+                    name.as_mut_ptr(),
+                );
+                assert_eq!(result, INVALID_ARGUMENT);
+                names
+            }
+        }
+
+        pub fn param_infos(&self) -> Vec<ParameterInfo> {
+            unsafe {
+                let count = (self.controller().get_parameter_count)(self.controller);
+                (0..count)
+                    .map(|i| {
+                        let mut info: ParameterInfo = std::mem::zeroed();
+                        let result =
+                            (self.controller().get_parameter_info)(self.controller, i, &mut info);
+                        assert_eq!(result, RESULT_OK);
+                        info
+                    })
+                    .collect()
+            }
+        }
+
+        pub fn normalized(&self, id: u32) -> f64 {
+            unsafe { (self.controller().get_param_normalized)(self.controller, id) }
+        }
+
+        pub fn latency(&self) -> u32 {
+            unsafe { (self.processor().get_latency_samples)(self.processor) }
+        }
+
+        pub fn set_normalized(&self, id: u32, normalized: f64) {
+            unsafe {
+                let result =
+                    (self.controller().set_param_normalized)(self.controller, id, normalized);
+                assert_eq!(result, RESULT_OK);
+            }
+        }
+
+        pub fn activate(&self) {
+            let mut setup = ProcessSetup {
+                process_mode: 0,
+                symbolic_sample_size: SAMPLE_32,
+                max_samples_per_block: MAX_FRAMES,
+                sample_rate: SAMPLE_RATE,
+            };
+            unsafe {
+                let processor = self.processor();
+                assert_eq!(
+                    (processor.setup_processing)(self.processor, &mut setup),
+                    RESULT_OK
+                );
+                assert_eq!((self.component().set_active)(self.component, 1), RESULT_OK);
+                assert_eq!((processor.set_processing)(self.processor, 1), RESULT_OK);
+            }
+        }
+
+        pub fn deactivate(&self) {
+            unsafe {
+                (self.processor().set_processing)(self.processor, 0);
+                assert_eq!((self.component().set_active)(self.component, 0), RESULT_OK);
+            }
+        }
+
+        /// Processes the buffers in place with events applied, empty buffers make a parameter
+        /// flush. Returns the values the plugin reported changing itself.
+        pub fn process(
+            &self,
+            buffers: &mut [Vec<f32>; 2],
+            events: &[ParamEvent],
+        ) -> Vec<ParamEvent> {
+            let mut output_changes = changes(&[]);
+            let mut changes = changes(events);
+            let frames = buffers[0].len();
+            let mut channels = [buffers[0].as_mut_ptr(), buffers[1].as_mut_ptr()];
+            let mut input = AudioBusBuffers {
+                num_channels: 2,
+                silence_flags: 0,
+                channel_buffers_32: channels.as_mut_ptr(),
+            };
+            let mut output = AudioBusBuffers {
+                num_channels: 2,
+                silence_flags: 0,
+                channel_buffers_32: channels.as_mut_ptr(),
+            };
+            let buses = if frames == 0 { 0 } else { 1 };
+            let mut data = ProcessData {
+                process_mode: 0,
+                symbolic_sample_size: SAMPLE_32,
+                num_samples: frames as i32,
+                num_inputs: buses,
+                num_outputs: buses,
+                inputs: &mut input,
+                outputs: &mut output,
+                input_parameter_changes: &mut changes as *mut Changes as *mut c_void,
+                output_parameter_changes: &mut output_changes as *mut Changes as *mut c_void,
+                input_events: ptr::null_mut(),
+                output_events: ptr::null_mut(),
+                process_context: ptr::null_mut(),
+            };
+            unsafe {
+                assert_eq!(
+                    (self.processor().process)(self.processor, &mut data),
+                    RESULT_OK
+                );
+            }
+            output_changes
+                .queues
+                .iter()
+                .flat_map(|queue| {
+                    queue
+                        .points
+                        .iter()
+                        .map(move |&(offset, normalized)| ParamEvent {
+                            offset,
+                            id: queue.id,
+                            normalized,
+                        })
+                })
+                .collect()
+        }
+
+        pub fn save(&self) -> Vec<u8> {
+            let mut saved = stream(&[]);
+            unsafe {
+                let stream = &mut saved as *mut Stream as *mut c_void;
+                assert_eq!(
+                    (self.component().get_state)(self.component, stream),
+                    RESULT_OK
+                );
+            }
+            saved.bytes
+        }
+
+        pub fn load(&self, saved: &[u8]) -> bool {
+            let mut saved = stream(saved);
+            unsafe {
+                let stream = &mut saved as *mut Stream as *mut c_void;
+                let result = (self.component().set_state)(self.component, stream);
+                saved.position = 0;
+                (self.controller().set_component_state)(self.controller, stream);
+                result == RESULT_OK
+            }
+        }
+    }
+
+    impl Drop for Instance {
+        fn drop(&mut self) {
+            unsafe {
+                (self.component().base.terminate)(self.component);
+                (self.processor().unknown.release)(self.processor);
+                (self.controller().base.unknown.release)(self.controller);
+                (self.midi_mapping().unknown.release)(self.midi_mapping);
+                (self.unit_info().unknown.release)(self.unit_info);
+                (self.component().base.unknown.release)(self.component);
+            }
+        }
+    }
+
+    fn noise(frames: usize, seed: &mut u32) -> Vec<f32> {
+        (0..frames)
+            .map(|_| {
+                *seed = seed.wrapping_mul(1664525).wrapping_add(1013904223);
+                (*seed as f32 / u32::MAX as f32) * 2.0 - 1.0
+            })
+            .collect()
+    }
+
+    /// Checks the exported plugin the way the SDK's validator would: class info, buses,
+    /// parameter ids, info and text, automation inside and past the end of a block, state save
+    /// and load, MIDI learn, latency and the program list. Returns the host for plugin specific
+    /// checks.
+    pub fn validate<P>(get_factory: extern "system" fn() -> *mut c_void) -> Host
+    where
+        P: Vst3Export,
+        P::Model: FactoryPresets + VersionedState + Clone,
+    {
+        let host = Host::new(get_factory);
+        let info = host.class_info();
+        assert_eq!(info.cid, P::VST3_CLASS_ID);
+        assert_eq!(c_str_array(&info.name), P::NAME);
+        assert_eq!(c_str_array(&info.category), AUDIO_EFFECT_CLASS);
+        assert!(c_str_array(&info.sub_categories).starts_with("Fx"));
+        unsafe {
+            let mut component = ptr::null_mut();
+            let other = uid(1, 2, 3, 4);
+            let result = (host.factory().create_instance)(
+                host.factory,
+                other.as_ptr() as *const c_char,
+                ICOMPONENT_IID.as_ptr() as *const c_char,
+                &mut component,
+            );
+            assert_eq!(result, NO_INTERFACE);
+            assert!(component.is_null());
+        }
+
+        let instance = host.create();
+        unsafe {
+            let component = instance.component();
+            for &dir in [BUS_INPUT, BUS_OUTPUT].iter() {
+                assert_eq!(
+                    (component.get_bus_count)(instance.component, MEDIA_AUDIO, dir),
+                    1
+                );
+                assert_eq!(
+                    (component.get_bus_count)(instance.component, MEDIA_EVENT, dir),
+                    if dir == BUS_INPUT { 1 } else { 0 }
+                );
+                let mut info: BusInfo = std::mem::zeroed();
+                let result =
+                    (component.get_bus_info)(instance.component, MEDIA_AUDIO, dir, 0, &mut info);
+                assert_eq!(result, RESULT_OK);
+                assert_eq!(info.channel_count, 2);
+                assert_eq!(info.bus_type, BUS_MAIN);
+            }
+            let processor = instance.processor();
+            let mut stereo = [SPEAKER_STEREO, SPEAKER_STEREO];
+            let [input, output] = &mut stereo;
+            let result = (processor.set_bus_arrangements)(instance.processor, input, 1, output, 1);
+            assert_eq!(result, RESULT_OK);
+            let mut mono = [1u64, 1];
+            let [input, output] = &mut mono;
+            let result = (processor.set_bus_arrangements)(instance.processor, input, 1, output, 1);
+            assert_eq!(result, RESULT_FALSE);
+            let can_process = processor.can_process_sample_size;
+            assert_eq!(can_process(instance.processor, SAMPLE_32), RESULT_OK);
+            assert_eq!(can_process(instance.processor, SAMPLE_64), RESULT_FALSE);
+        }
+
+        // The same ids as CLAP, so both formats save automation the same way, then MIDI Learn,
+        // the MIDI CC parameters and the program change
+        let infos = instance.param_infos();
+        let mut clap_ids: Vec<u32> = P::clap_params().iter().map(|param| param.id).collect();
+        clap_ids.push(LEARN_PARAM_ID);
+        clap_ids.extend((0..CONTROLLERS as u32).map(|cc| MIDI_CC_PARAM_ID + cc));
+        clap_ids.push(PROGRAM_PARAM_ID);
+        let ids: Vec<u32> = infos.iter().map(|info| info.id).collect();
+        assert_eq!(ids, clap_ids);
+        let mut unique = ids.clone();
+        unique.sort_unstable();
+        unique.dedup();
+        assert_eq!(unique.len(), ids.len(), "parameter ids aren't unique");
+        for info in infos.iter() {
+            let name = str16_array(&info.title);
+            assert!(!name.is_empty());
+            let default = info.default_normalized_value;
+            assert!(
+                (0.0..=1.0).contains(&default),
+                "{} default {}",
+                name,
+                default
+            );
+            assert_eq!(instance.normalized(info.id), default, "{}", name);
+            if info.flags & PARAM_IS_HIDDEN != 0 {
+                continue;
+            }
+
+            let mut text = [0u16; 128];
+            let mut parsed = 0.0;
+            unsafe {
+                let controller = instance.controller();
+                let result = (controller.get_param_string_by_value)(
+                    instance.controller,
+                    info.id,
+                    1.0,
+                    text.as_mut_ptr(),
+                );
+                assert_eq!(result, RESULT_OK);
+                let result = (controller.get_param_value_by_string)(
+                    instance.controller,
+                    info.id,
+                    text.as_ptr(),
+                    &mut parsed,
+                );
+                assert_eq!(result, RESULT_OK);
+            }
+            assert!(
+                (parsed - 1.0).abs() <= 0.005,
+                "{} text {:?} parsed as {}",
+                name,
+                str16_array(&text),
+                parsed
+            );
+        }
+
+        let automatable: Vec<&ParameterInfo> = infos
+            .iter()
+            .filter(|info| info.flags & PARAM_CAN_AUTOMATE != 0)
+            .collect();
+        assert_eq!(automatable.len(), P::clap_params().len());
+
+        // The host's bypass switch is the plugin's bypass parameter, if it has one
+        let bypass: Vec<&ParameterInfo> = infos
+            .iter()
+            .filter(|info| info.flags & PARAM_IS_BYPASS != 0)
+            .collect();
+        assert!(bypass.len() <= 1);
+        assert_eq!(bypass.first().map(|info| info.id), P::BYPASS_PARAM);
+        if let Some(info) = bypass.first() {
+            assert_eq!(info.step_count, 1);
+            assert_eq!(info.default_normalized_value, 0.0);
+        }
+
+        // Every parameter automated to its max partway through a block, then back to its
+        // default at an offset past the end of the next one
+        instance.activate();
+        let mut seed = 1;
+        for block in 0..8 {
+            let events: Vec<ParamEvent> = automatable
+                .iter()
+                .enumerate()
+                .map(|(i, info)| match block {
+                    2 => ParamEvent {
+                        offset: 100 + i as i32 * 37,
+                        id: info.id,
+                        normalized: 1.0,
+                    },
+                    3 => ParamEvent {
+                        offset: MAX_FRAMES * 2,
+                        id: info.id,
+                        normalized: info.default_normalized_value,
+                    },
+                    _ => ParamEvent {
+                        offset: 0,
+                        id: info.id,
+                        normalized: 0.25,
+                    },
+                })
+                .filter(|_| block == 2 || block == 3 || block == 6)
+                .collect();
+            let frames = match block {
+                4 => 0,
+                5 => 7,
+                _ => MAX_FRAMES as usize,
+            };
+            let mut buffers = [noise(frames, &mut seed), noise(frames, &mut seed)];
+            instance.process(&mut buffers, &events);
+            for x in buffers.iter().flatten() {
+                assert!(x.is_finite(), "block {} output {}", block, x);
+            }
+            for info in automatable.iter() {
+                let expected = match block {
+                    2 => 1.0,
+                    3..=5 => info.default_normalized_value,
+                    _ => continue,
+                };
+                assert!((instance.normalized(info.id) - expected).abs() < 1e-9);
+            }
+        }
+
+        // State carries every parameter, the loaded values are on the next process
+        for info in automatable.iter() {
+            instance.set_normalized(info.id, 0.75);
+        }
+        let saved = instance.save();
+        let loaded = host.create();
+        let restarts = host.restarts();
+        assert!(loaded.load(&saved));
+        assert_eq!(host.restarts(), restarts + 1);
+        for info in automatable.iter() {
+            let expected = instance.normalized(info.id);
+            let value = loaded.normalized(info.id);
+            assert!(
+                (value - expected).abs() <= 1e-4,
+                "{} loaded as {}, saved {}",
+                str16_array(&info.title),
+                value,
+                expected
+            );
+        }
+        assert!(!loaded.load(b"{ not state"));
+        instance.deactivate();
+        loaded.activate();
+        let mut buffers = [noise(256, &mut seed), noise(256, &mut seed)];
+        loaded.process(&mut buffers, &[]);
+        loaded.deactivate();
+        drop(loaded);
+
+        // MIDI Learn maps the next controller to the first parameter, which it then sets
+        // through the controller's hidden parameter. The host is told about both changes and the
+        // mapping is saved with the state.
+        let first = automatable[0];
+        let mut cc_param = 0;
+        unsafe {
+            let assignment = instance.midi_mapping().get_midi_controller_assignment;
+            assert_eq!(
+                assignment(instance.midi_mapping, 0, 3, 74, &mut cc_param),
+                RESULT_OK
+            );
+            let mut other = 0;
+            assert_eq!(
+                assignment(instance.midi_mapping, 1, 0, 74, &mut other),
+                RESULT_FALSE
+            );
+        }
+        let learn_steps = infos[automatable.len()].step_count as f64;
+        instance.set_normalized(LEARN_PARAM_ID, 1.0 / learn_steps);
+        instance.activate();
+        let cc = ParamEvent {
+            offset: 10,
+            id: cc_param,
+            normalized: 1.0,
+        };
+        let mut buffers = [noise(256, &mut seed), noise(256, &mut seed)];
+        let reported = instance.process(&mut buffers, &[cc]);
+        assert_eq!(instance.normalized(first.id), 1.0);
+        assert_eq!(instance.normalized(LEARN_PARAM_ID), 0.0);
+        let learned = ParamEvent {
+            offset: 10,
+            id: LEARN_PARAM_ID,
+            normalized: 0.0,
+        };
+        let set = ParamEvent {
+            offset: 10,
+            id: first.id,
+            normalized: 1.0,
+        };
+        assert_eq!(reported, vec![learned, set]);
+        instance.deactivate();
+
+        let loaded = host.create();
+        assert!(loaded.load(&instance.save()));
+        loaded.activate();
+        let cc = ParamEvent {
+            offset: 0,
+            id: cc_param,
+            normalized: 0.0,
+        };
+        loaded.process(&mut buffers, &[cc]);
+        assert_eq!(loaded.normalized(first.id), 0.0);
+        loaded.deactivate();
+        drop(loaded);
+        drop(instance);
+
+        // Latency is read after setActive, and again when a parameter that changes it has the
+        // plugin restart with the latency flag
+        let latent = host.create();
+        latent.activate();
+        let latency = P::latency(&P::Model::default(), SAMPLE_RATE as f32);
+        assert_eq!(latent.latency(), latency);
+        let change = P::clap_params().into_iter().find_map(|param| {
+            let mut model = P::Model::default();
+            (param.set)(&mut model, param.max);
+            let changed = P::latency(&model, SAMPLE_RATE as f32);
+            Some((param, changed)).filter(|_| changed != latency)
+        });
+        if let Some((param, changed)) = change {
+            let restarts = host.restarts();
+            latent.set_normalized(param.id, 1.0);
+            assert_eq!(host.restarts(), restarts + 1);
+            assert_eq!(latent.latency(), changed);
+        }
+        latent.deactivate();
+        drop(latent);
+
+        // The factory presets come first in the program list. Selecting one sets every
+        // parameter to its value, from the controller or as a program change in process.
+        let programs = host.create();
+        let names = programs.program_names();
+        let bank = PresetBank::<P::Model>::new();
+        assert!(names.len() >= bank.count());
+        let program = infos.last().unwrap();
+        assert_eq!(program.id, PROGRAM_PARAM_ID);
+        assert_eq!(program.flags, PARAM_IS_PROGRAM_CHANGE | PARAM_IS_LIST);
+        assert_eq!(program.step_count as usize, names.len() - 1);
+        let params = P::clap_params();
+        let assert_preset = |index: usize| {
+            let preset = bank.get(index).unwrap();
+            assert_eq!(names[index], preset.name);
+            for param in params.iter() {
+                let expected = to_normalized(param, (param.get)(&preset.model));
+                let value = programs.normalized(param.id);
+                assert!(
+                    (value - expected).abs() <= 1e-4,
+                    "{} {} is {}, not {}",
+                    preset.name,
+                    param.name,
+                    value,
+                    expected
+                );
+            }
+        };
+        let last = (names.len() - 1).max(1) as f64;
+        for index in (0..bank.count()).rev() {
+            let restarts = host.restarts();
+            programs.set_normalized(PROGRAM_PARAM_ID, index as f64 / last);
+            assert_eq!(host.restarts(), restarts + 1);
+            assert_eq!(programs.normalized(PROGRAM_PARAM_ID), index as f64 / last);
+            assert_preset(index);
+        }
+        programs.activate();
+        let index = bank.count() - 1;
+        let change = ParamEvent {
+            offset: 20,
+            id: PROGRAM_PARAM_ID,
+            normalized: index as f64 / last,
+        };
+        let mut buffers = [noise(256, &mut seed), noise(256, &mut seed)];
+        let reported = programs.process(&mut buffers, &[change]);
+        assert_preset(index);
+        for param in params.iter() {
+            assert!(reported
+                .iter()
+                .any(|event| event.id == param.id && event.offset == 20));
+        }
+        programs.deactivate();
+        drop(programs);
+        host
+    }
+}
@@ -0,0 +1,21 @@
+// DynSat on its own as a JACK client, the same as baseplug-tests live --plugin dynsat
+
+use clap::Parser;
+
+use baseplug_tests::live;
+
+/// Runs DynSat live as a JACK client, e.g. dynsat-live --connect. Parameters are set by MIDI
+/// CC, or typed in as NAME=VALUE.
+#[derive(Parser)]
+#[clap(name = "dynsat-live", version)]
+struct Args {
+    #[clap(flatten)]
+    options: live::Options,
+}
+
+fn main() {
+    if let Err(e) = live::run("dynsat", Args::parse().options) {
+        eprintln!("error: {}", e);
+        std::process::exit(1);
+    }
+}
@@ -0,0 +1,21 @@
+// Gain on its own as a JACK client, the same as baseplug-tests live --plugin gain
+
+use clap::Parser;
+
+use baseplug_tests::live;
+
+/// Runs Gain live as a JACK client, e.g. gain-live --connect. Parameters are set by MIDI
+/// CC, or typed in as NAME=VALUE.
+#[derive(Parser)]
+#[clap(name = "gain-live", version)]
+struct Args {
+    #[clap(flatten)]
+    options: live::Options,
+}
+
+fn main() {
+    if let Err(e) = live::run("gain", Args::parse().options) {
+        eprintln!("error: {}", e);
+        std::process::exit(1);
+    }
+}
@@ -0,0 +1,21 @@
+// OnePole on its own as a JACK client, the same as baseplug-tests live --plugin onepole
+
+use clap::Parser;
+
+use baseplug_tests::live;
+
+/// Runs OnePole live as a JACK client, e.g. onepole-live --connect. Parameters are set by MIDI
+/// CC, or typed in as NAME=VALUE.
+#[derive(Parser)]
+#[clap(name = "onepole-live", version)]
+struct Args {
+    #[clap(flatten)]
+    options: live::Options,
+}
+
+fn main() {
+    if let Err(e) = live::run("onepole", Args::parse().options) {
+        eprintln!("error: {}", e);
+        std::process::exit(1);
+    }
+}
@@ -0,0 +1,21 @@
+// Varb on its own as a JACK client, the same as baseplug-tests live --plugin varb
+
+use clap::Parser;
+
+use baseplug_tests::live;
+
+/// Runs Varb live as a JACK client, e.g. varb-live --connect. Parameters are set by MIDI
+/// CC, or typed in as NAME=VALUE.
+#[derive(Parser)]
+#[clap(name = "varb-live", version)]
+struct Args {
+    #[clap(flatten)]
+    options: live::Options,
+}
+
+fn main() {
+    if let Err(e) = live::run("varb", Args::parse().options) {
+        eprintln!("error: {}", e);
+        std::process::exit(1);
+    }
+}
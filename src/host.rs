@@ -0,0 +1,631 @@
+// A minimal CLAP host for running the plugins outside a DAW. The plugins are loaded from their
+// built libraries through clap_entry rather than linked in, every plugin library exports the
+// same entry points so they can't be linked into one binary.
+
+use std::ffi::{c_char, c_void, CStr, CString};
+use std::io;
+use std::marker::PhantomData;
+use std::path::{Path, PathBuf};
+use std::ptr;
+
+use clap_sys::audio_buffer::clap_audio_buffer;
+use clap_sys::entry::clap_plugin_entry;
+use clap_sys::events::{
+    clap_event_header, clap_event_param_value, clap_input_events, clap_output_events,
+    CLAP_CORE_EVENT_SPACE_ID, CLAP_EVENT_PARAM_VALUE,
+};
+use clap_sys::ext::latency::{clap_plugin_latency, CLAP_EXT_LATENCY};
+use clap_sys::ext::params::{
+    clap_param_info, clap_plugin_params, CLAP_EXT_PARAMS, CLAP_PARAM_IS_AUTOMATABLE,
+};
+use clap_sys::ext::state::{clap_plugin_state, CLAP_EXT_STATE};
+use clap_sys::factory::plugin_factory::{clap_plugin_factory, CLAP_PLUGIN_FACTORY_ID};
+use clap_sys::host::clap_host;
+use clap_sys::id::clap_id;
+use clap_sys::plugin::clap_plugin;
+use clap_sys::process::{clap_process, CLAP_PROCESS_ERROR};
+use clap_sys::stream::clap_istream;
+use clap_sys::version::CLAP_VERSION;
+
+/// Parameter changes per process call that fit without allocating
+const EVENT_CAPACITY: usize = 1024;
+
+fn error<E: Into<Box<dyn std::error::Error + Send + Sync>>>(error: E) -> io::Error {
+    io::Error::other(error)
+}
+
+/// A plugin library loaded through its clap_entry, initialized until dropped
+pub struct Library {
+    entry: *const clap_plugin_entry,
+    // Dropped after deinit
+    _library: libloading::Library,
+}
+
+impl Library {
+    /// Where a workspace plugin is built, next to this executable, e.g. libdynsat.so
+    pub fn built_plugin(name: &str) -> io::Result<PathBuf> {
+        let exe = std::env::current_exe()?;
+        let dir = exe.parent().unwrap_or_else(|| Path::new("."));
+        let file = format!(
+            "{}{}{}",
+            std::env::consts::DLL_PREFIX,
+            name.to_lowercase(),
+            std::env::consts::DLL_SUFFIX
+        );
+        Ok(dir.join(file))
+    }
+
+    /// A workspace plugin built next to this executable by name, e.g. varb, or any CLAP plugin
+    /// library by its path
+    pub fn find(plugin: &str) -> io::Result<Library> {
+        let path = Path::new(plugin);
+        if path.components().count() > 1 || path.is_file() {
+            Library::open(path)
+        } else {
+            Library::open(&Library::built_plugin(plugin)?)
+        }
+    }
+
+    pub fn open(path: &Path) -> io::Result<Library> {
+        unsafe {
+            let library = libloading::Library::new(path)
+                .map_err(|e| error(format!("can't load {}: {}", path.display(), e)))?;
+            let entry = *library
+                .get::<*const clap_plugin_entry>(b"clap_entry\0")
+                .map_err(|_| error(format!("{} isn't a CLAP plugin", path.display())))?;
+            let path = CString::new(path.to_string_lossy().as_bytes()).map_err(error)?;
+            let init = (*entry)
+                .init
+                .ok_or_else(|| error("clap_entry has no init"))?;
+            if !init(path.as_ptr()) {
+                return Err(error("plugin library failed to initialize"));
+            }
+            Ok(Library {
+                entry,
+                _library: library,
+            })
+        }
+    }
+
+    /// Creates the library's first plugin
+    pub fn create(&self) -> io::Result<Instance<'_>> {
+        unsafe {
+            let get_factory = (*self.entry)
+                .get_factory
+                .ok_or_else(|| error("no factory"))?;
+            let factory =
+                get_factory(CLAP_PLUGIN_FACTORY_ID.as_ptr()) as *const clap_plugin_factory;
+            let factory = factory.as_ref().ok_or_else(|| error("no plugin factory"))?;
+            let descriptor = match (factory.get_plugin_descriptor, factory.get_plugin_count) {
+                (Some(get_descriptor), Some(count)) if count(factory) > 0 => {
+                    get_descriptor(factory, 0)
+                }
+                _ => ptr::null(),
+            };
+            let descriptor = descriptor
+                .as_ref()
+                .ok_or_else(|| error("library has no plugins"))?;
+            let host = Box::new(clap_host {
+                clap_version: CLAP_VERSION,
+                host_data: ptr::null_mut(),
+                name: b"baseplug-tests\0".as_ptr() as *const c_char,
+                vendor: b"\0".as_ptr() as *const c_char,
+                url: b"\0".as_ptr() as *const c_char,
+                version: concat!(env!("CARGO_PKG_VERSION"), "\0").as_ptr() as *const c_char,
+                get_extension: Some(host_get_extension),
+                request_restart: Some(host_request),
+                request_process: Some(host_request),
+                request_callback: Some(host_request),
+            });
+            let create = factory
+                .create_plugin
+                .ok_or_else(|| error("no create_plugin"))?;
+            let plugin = create(factory, &*host, descriptor.id);
+            if plugin.is_null() {
+                return Err(error("plugin couldn't be created"));
+            }
+            let mut instance = Instance {
+                plugin,
+                params: None,
+                state: None,
+                latency: None,
+                active: false,
+                events: Vec::with_capacity(EVENT_CAPACITY),
+                _host: host,
+                _library: PhantomData,
+            };
+            match (*plugin).init {
+                Some(init) if init(plugin) => {}
+                _ => return Err(error("plugin failed to initialize")),
+            }
+            instance.params =
+                (instance.extension(CLAP_EXT_PARAMS) as *const clap_plugin_params).as_ref();
+            instance.state =
+                (instance.extension(CLAP_EXT_STATE) as *const clap_plugin_state).as_ref();
+            instance.latency =
+                (instance.extension(CLAP_EXT_LATENCY) as *const clap_plugin_latency).as_ref();
+            Ok(instance)
+        }
+    }
+}
+
+impl Drop for Library {
+    fn drop(&mut self) {
+        unsafe {
+            if let Some(deinit) = (*self.entry).deinit {
+                deinit();
+            }
+        }
+    }
+}
+
+unsafe extern "C" fn host_get_extension(
+    _host: *const clap_host,
+    _id: *const c_char,
+) -> *const c_void {
+    ptr::null()
+}
+
+unsafe extern "C" fn host_request(_host: *const clap_host) {}
+
+#[derive(Clone, Debug)]
+pub struct ParamInfo {
+    pub id: clap_id,
+    pub name: String,
+    pub min: f64,
+    pub max: f64,
+    pub default: f64,
+}
+
+/// One parameter value change at a frame of the next process call
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct ParamValue {
+    pub time: u32,
+    pub id: clap_id,
+    pub value: f64,
+}
+
+/// A plugin instance, destroyed when dropped
+pub struct Instance<'a> {
+    plugin: *const clap_plugin,
+    params: Option<&'a clap_plugin_params>,
+    state: Option<&'a clap_plugin_state>,
+    latency: Option<&'a clap_plugin_latency>,
+    active: bool,
+    events: Vec<clap_event_param_value>,
+    _host: Box<clap_host>,
+    _library: PhantomData<&'a Library>,
+}
+
+// CLAP plugins only need their calls to come from one thread at a time, the live host activates
+// on the main thread, moves the instance to the audio thread and back
+unsafe impl<'a> Send for Instance<'a> {}
+
+impl<'a> Instance<'a> {
+    unsafe fn extension(&self, id: &CStr) -> *const c_void {
+        match (*self.plugin).get_extension {
+            Some(get_extension) => get_extension(self.plugin, id.as_ptr()),
+            None => ptr::null(),
+        }
+    }
+
+    /// The parameters a host automates, which leaves out e.g. the plugins' MIDI Learn
+    pub fn params(&self) -> Vec<ParamInfo> {
+        let params = match self.params {
+            Some(params) => params,
+            None => return Vec::new(),
+        };
+        let (count, get_info) = match (params.count, params.get_info) {
+            (Some(count), Some(get_info)) => (count, get_info),
+            _ => return Vec::new(),
+        };
+        unsafe {
+            (0..count(self.plugin))
+                .filter_map(|index| {
+                    let mut info: clap_param_info = std::mem::zeroed();
+                    if !get_info(self.plugin, index, &mut info)
+                        || info.flags & CLAP_PARAM_IS_AUTOMATABLE == 0
+                    {
+                        return None;
+                    }
+                    let name = CStr::from_ptr(info.name.as_ptr());
+                    Some(ParamInfo {
+                        id: info.id,
+                        name: name.to_string_lossy().into_owned(),
+                        min: info.min_value,
+                        max: info.max_value,
+                        default: info.default_value,
+                    })
+                })
+                .collect()
+        }
+    }
+
+    /// Loads a preset or saved state, call before activate
+    pub fn load_state(&mut self, state: &[u8]) -> io::Result<()> {
+        let load = self
+            .state
+            .and_then(|state| state.load)
+            .ok_or_else(|| error("plugin has no state to load"))?;
+        let mut input = state;
+        let stream = clap_istream {
+            ctx: &mut input as *mut &[u8] as *mut c_void,
+            read: Some(stream_read),
+        };
+        if unsafe { load(self.plugin, &stream) } {
+            Ok(())
+        } else {
+            Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                "plugin rejected the state",
+            ))
+        }
+    }
+
+    pub fn activate(&mut self, sample_rate: f64, max_frames: u32) -> io::Result<()> {
+        unsafe {
+            let plugin = &*self.plugin;
+            let activate = plugin.activate.ok_or_else(|| error("no activate"))?;
+            if !activate(self.plugin, sample_rate, 1, max_frames) {
+                return Err(error("plugin failed to activate"));
+            }
+            self.active = true;
+            match plugin.start_processing {
+                Some(start_processing) if !start_processing(self.plugin) => {
+                    Err(error("plugin failed to start processing"))
+                }
+                _ => Ok(()),
+            }
+        }
+    }
+
+    /// Samples the output lags the input, plugins only report it once activated
+    pub fn latency(&self) -> u32 {
+        match self.latency.and_then(|latency| latency.get) {
+            Some(get) => unsafe { get(self.plugin) },
+            None => 0,
+        }
+    }
+
+    pub fn deactivate(&mut self) {
+        if !self.active {
+            return;
+        }
+        unsafe {
+            let plugin = &*self.plugin;
+            if let Some(stop_processing) = plugin.stop_processing {
+                stop_processing(self.plugin);
+            }
+            if let Some(deactivate) = plugin.deactivate {
+                deactivate(self.plugin);
+            }
+        }
+        self.active = false;
+    }
+
+    /// Processes stereo input into output with the parameter changes applied, events have
+    /// to be in time order. Doesn't allocate for up to 1024 events.
+    pub fn process(
+        &mut self,
+        input: [&[f32]; 2],
+        output: [&mut [f32]; 2],
+        events: &[ParamValue],
+    ) -> io::Result<()> {
+        let frames = input[0].len();
+        assert!(input[1].len() == frames && output.iter().all(|o| o.len() == frames));
+        self.events.clear();
+        self.events
+            .extend(events.iter().map(|event| clap_event_param_value {
+                header: clap_event_header {
+                    size: std::mem::size_of::<clap_event_param_value>() as u32,
+                    time: event.time,
+                    space_id: CLAP_CORE_EVENT_SPACE_ID,
+                    type_: CLAP_EVENT_PARAM_VALUE,
+                    flags: 0,
+                },
+                param_id: event.id,
+                cookie: ptr::null_mut(),
+                note_id: -1,
+                port_index: -1,
+                channel: -1,
+                key: -1,
+                value: event.value,
+            }));
+        let in_events = clap_input_events {
+            ctx: &self.events as *const Vec<clap_event_param_value> as *mut c_void,
+            size: Some(events_size),
+            get: Some(events_get),
+        };
+        let out_events = clap_output_events {
+            ctx: ptr::null_mut(),
+            try_push: Some(events_try_push),
+        };
+        let [output_l, output_r] = output;
+        let mut input_channels = [input[0].as_ptr() as *mut f32, input[1].as_ptr() as *mut f32];
+        let mut output_channels = [output_l.as_mut_ptr(), output_r.as_mut_ptr()];
+        let audio_input = clap_audio_buffer {
+            data32: input_channels.as_mut_ptr(),
+            data64: ptr::null_mut(),
+            channel_count: 2,
+            latency: 0,
+            constant_mask: 0,
+        };
+        let mut audio_output = clap_audio_buffer {
+            data32: output_channels.as_mut_ptr(),
+            data64: ptr::null_mut(),
+            channel_count: 2,
+            latency: 0,
+            constant_mask: 0,
+        };
+        let process = clap_process {
+            steady_time: -1,
+            frames_count: frames as u32,
+            transport: ptr::null(),
+            audio_inputs: &audio_input,
+            audio_outputs: &mut audio_output,
+            audio_inputs_count: 1,
+            audio_outputs_count: 1,
+            in_events: &in_events,
+            out_events: &out_events,
+        };
+        unsafe {
+            let process_fn = (*self.plugin).process.ok_or_else(|| error("no process"))?;
+            if process_fn(self.plugin, &process) == CLAP_PROCESS_ERROR {
+                return Err(error("plugin failed to process"));
+            }
+        }
+        Ok(())
+    }
+}
+
+impl<'a> Drop for Instance<'a> {
+    fn drop(&mut self) {
+        self.deactivate();
+        unsafe {
+            if let Some(destroy) = (*self.plugin).destroy {
+                destroy(self.plugin);
+            }
+        }
+    }
+}
+
+unsafe extern "C" fn events_size(list: *const clap_input_events) -> u32 {
+    let list = &*((*list).ctx as *const Vec<clap_event_param_value>);
+    list.len() as u32
+}
+
+unsafe extern "C" fn events_get(
+    list: *const clap_input_events,
+    index: u32,
+) -> *const clap_event_header {
+    let list = &*((*list).ctx as *const Vec<clap_event_param_value>);
+    match list.get(index as usize) {
+        Some(event) => &event.header,
+        None => ptr::null(),
+    }
+}
+
+unsafe extern "C" fn events_try_push(
+    _list: *const clap_output_events,
+    _event: *const clap_event_header,
+) -> bool {
+    true
+}
+
+unsafe extern "C" fn stream_read(
+    stream: *const clap_istream,
+    buffer: *mut c_void,
+    size: u64,
+) -> i64 {
+    let input = &mut *((*stream).ctx as *mut &[u8]);
+    let size = (size as usize).min(input.len());
+    ptr::copy_nonoverlapping(input.as_ptr(), buffer as *mut u8, size);
+    *input = &input[size..];
+    size as i64
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const PLUGINS: [&str; 4] = ["gain", "dynsat", "varb", "onepole"];
+
+    // Where cargo puts a workspace plugin's library, next to the deps directory the test runs in
+    fn built(name: &str) -> PathBuf {
+        let exe = std::env::current_exe().unwrap();
+        exe.parent().unwrap().parent().unwrap().join(format!(
+            "{}{}{}",
+            std::env::consts::DLL_PREFIX,
+            name,
+            std::env::consts::DLL_SUFFIX
+        ))
+    }
+
+    fn noise(frames: usize, seed: &mut u32) -> Vec<f32> {
+        (0..frames)
+            .map(|_| {
+                *seed = seed.wrapping_mul(1664525).wrapping_add(1013904223);
+                (*seed as f32 / u32::MAX as f32) * 2.0 - 1.0
+            })
+            .collect()
+    }
+
+    // Loads each plugin's built library through clap_entry, the way a host loads the .clap,
+    // rather than calling into the crate. Needs the workspace built, e.g. cargo build --workspace.
+    #[test]
+    fn test_built_plugins() {
+        for name in PLUGINS {
+            let library = Library::open(&built(name))
+                .unwrap_or_else(|e| panic!("{}, cargo build --workspace builds it", e));
+            let mut plugin = library.create().unwrap();
+
+            // The parameters are the model's fields, with ids in their order
+            let params = plugin.params();
+            for (id, param) in params.iter().enumerate() {
+                assert_eq!(param.id, id as clap_id, "{} {}", name, param.name);
+                assert!(param.min <= param.default && param.default <= param.max);
+            }
+            for expected in ["Mix", "Trim", "Mix Law", "Bypass"] {
+                assert!(
+                    params.iter().any(|param| param.name == expected),
+                    "{} has no {}",
+                    name,
+                    expected
+                );
+            }
+
+            plugin
+                .load_state(br#"{"version": 1, "model": {}}"#)
+                .unwrap();
+            assert!(plugin.load_state(b"{ not state").is_err());
+            plugin.activate(48000.0, 512).unwrap();
+            assert_eq!(plugin.latency(), 0);
+            let mut seed = 1;
+            let input = [noise(512, &mut seed), noise(512, &mut seed)];
+            let mut output = [vec![0.0; 512], vec![0.0; 512]];
+            let [output_l, output_r] = &mut output;
+            let mix = ParamValue {
+                time: 256,
+                id: params.iter().find(|param| param.name == "Mix").unwrap().id,
+                value: 0.5,
+            };
+            plugin
+                .process([&input[0], &input[1]], [output_l, output_r], &[mix])
+                .unwrap();
+            assert!(output.iter().flatten().all(|x| x.is_finite()), "{}", name);
+            assert!(
+                output.iter().flatten().any(|x| *x != 0.0),
+                "{} is silent",
+                name
+            );
+            plugin.deactivate();
+        }
+    }
+
+    // Loads each plugin's built library and opens it through VSTPluginMain, the way a VST2 host
+    // loads it
+    #[test]
+    fn test_built_vst2_plugins() {
+        use plugin_common::vst2::host::Instance;
+        use plugin_common::vst2::sys::{AEffect, HostCallbackProc};
+
+        for name in PLUGINS {
+            let library = unsafe { libloading::Library::new(built(name)) }
+                .unwrap_or_else(|e| panic!("{}, cargo build --workspace builds it", e));
+            let main = unsafe {
+                *library
+                    .get::<extern "C" fn(HostCallbackProc) -> *mut AEffect>(b"VSTPluginMain\0")
+                    .unwrap()
+            };
+            let instance = Instance::new(main);
+            assert!(instance.effect().num_params > 0, "{}", name);
+
+            // A chunk saved before versioning loads, parameters it doesn't have take defaults
+            assert!(instance.load(b"{}"), "{}", name);
+            instance.resume();
+            let mut seed = 1;
+            let mut buffers = [noise(512, &mut seed), noise(512, &mut seed)];
+            instance.process(&mut buffers);
+            assert!(buffers.iter().flatten().all(|x| x.is_finite()), "{}", name);
+            assert!(
+                buffers.iter().flatten().any(|x| *x != 0.0),
+                "{} is silent",
+                name
+            );
+            drop(instance);
+        }
+    }
+
+    // Loads each plugin's built library and calls its VST3 entry points through the exported
+    // symbols, the way a host loads the .vst3
+    #[test]
+    fn test_built_vst3_plugins() {
+        use plugin_common::vst3::host::{Host, ParamEvent};
+        use plugin_common::vst3::sys::{ParameterInfo, AUDIO_EFFECT_CLASS};
+
+        for name in PLUGINS {
+            let library = unsafe { libloading::Library::new(built(name)) }
+                .unwrap_or_else(|e| panic!("{}, cargo build --workspace builds it", e));
+            unsafe {
+                #[cfg(target_os = "linux")]
+                {
+                    let entry = library
+                        .get::<extern "C" fn(*mut c_void) -> bool>(b"ModuleEntry\0")
+                        .unwrap();
+                    assert!(entry(ptr::null_mut()));
+                }
+                #[cfg(target_os = "macos")]
+                {
+                    let entry = library
+                        .get::<extern "C" fn(*mut c_void) -> bool>(b"bundleEntry\0")
+                        .unwrap();
+                    assert!(entry(ptr::null_mut()));
+                }
+                #[cfg(windows)]
+                {
+                    let entry = library
+                        .get::<extern "system" fn() -> bool>(b"InitDll\0")
+                        .unwrap();
+                    assert!(entry());
+                }
+
+                let get_factory = *library
+                    .get::<extern "system" fn() -> *mut c_void>(b"GetPluginFactory\0")
+                    .unwrap();
+                let host = Host::new(get_factory);
+                let info = host.class_info();
+                let category = CStr::from_ptr(info.category.as_ptr());
+                assert_eq!(category.to_str().unwrap(), AUDIO_EFFECT_CLASS, "{}", name);
+                let name_c = CStr::from_ptr(info.name.as_ptr());
+                assert!(!name_c.to_bytes().is_empty(), "{}", name);
+
+                let instance = host.create();
+                let params = instance.param_infos();
+                instance.activate();
+                let mut seed = 1;
+                let mut buffers = [noise(512, &mut seed), noise(512, &mut seed)];
+                let title = |info: &ParameterInfo| {
+                    let end = info.title.iter().position(|c| *c == 0).unwrap();
+                    String::from_utf16_lossy(&info.title[..end])
+                };
+                let mix = ParamEvent {
+                    offset: 256,
+                    id: params.iter().find(|info| title(info) == "Mix").unwrap().id,
+                    normalized: 0.5,
+                };
+                instance.process(&mut buffers, &[mix]);
+                assert!(buffers.iter().flatten().all(|x| x.is_finite()), "{}", name);
+                assert!(
+                    buffers.iter().flatten().any(|x| *x != 0.0),
+                    "{} is silent",
+                    name
+                );
+                instance.deactivate();
+                drop(instance);
+                drop(host);
+
+                #[cfg(target_os = "linux")]
+                {
+                    let exit = library
+                        .get::<extern "C" fn() -> bool>(b"ModuleExit\0")
+                        .unwrap();
+                    assert!(exit());
+                }
+                #[cfg(target_os = "macos")]
+                {
+                    let exit = library
+                        .get::<extern "C" fn() -> bool>(b"bundleExit\0")
+                        .unwrap();
+                    assert!(exit());
+                }
+                #[cfg(windows)]
+                {
+                    let exit = library
+                        .get::<extern "system" fn() -> bool>(b"ExitDll\0")
+                        .unwrap();
+                    assert!(exit());
+                }
+            }
+        }
+    }
+}
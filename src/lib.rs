@@ -1,3 +1,7 @@
+pub mod host;
+pub mod live;
+pub mod offline;
+
 #[cfg(test)]
 mod tests {
     #[test]
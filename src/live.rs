@@ -0,0 +1,502 @@
+// Runs a plugin in real time as a JACK client, with a stereo input and output and a MIDI input
+// whose control changes set parameters
+
+use std::fs;
+use std::io::{self, BufRead};
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Arc;
+
+use clap_sys::id::clap_id;
+use jack::{
+    AsyncClient, AudioIn, AudioOut, Client, ClientOptions, Control, MidiIn, Port, PortFlags,
+    PortSpec, ProcessHandler, ProcessScope,
+};
+
+use crate::host::{Instance, Library, ParamInfo, ParamValue};
+use crate::offline::{find_param, parse_override};
+
+/// Longest chunk passed to the plugin, longer JACK periods are split
+const MAX_FRAMES: usize = 4096;
+/// Parameter changes per period, more are dropped rather than allocating on JACK's thread
+const EVENT_CAPACITY: usize = 1024;
+/// Controller for the first parameter when none are given, 20-31 aren't assigned in the MIDI spec
+const FIRST_CC: u8 = 20;
+/// No change pending in a Remote slot, a NaN that f64 arithmetic doesn't produce
+const NO_CHANGE: u64 = u64::MAX;
+
+fn jack_error(error: jack::Error) -> io::Error {
+    io::Error::other(format!("JACK: {}", error))
+}
+
+/// A MIDI controller setting a parameter, 0-127 across the parameter's range
+#[derive(Clone, Debug, PartialEq)]
+pub struct CcMapping {
+    pub cc: u8,
+    pub param: String,
+    pub id: clap_id,
+    pub min: f64,
+    pub max: f64,
+}
+
+impl CcMapping {
+    fn new(cc: u8, param: &ParamInfo) -> CcMapping {
+        CcMapping {
+            cc,
+            param: param.name.clone(),
+            id: param.id,
+            min: param.min,
+            max: param.max,
+        }
+    }
+
+    pub fn value(&self, data: u8) -> f64 {
+        self.min + (self.max - self.min) * f64::from(data.min(127)) / 127.0
+    }
+}
+
+/// Controllers for the named parameters, or when none are named, 20 upwards for every parameter
+/// in order
+pub fn map_ccs(params: &[ParamInfo], ccs: &[(u8, String)]) -> io::Result<Vec<CcMapping>> {
+    if ccs.is_empty() {
+        return Ok(params
+            .iter()
+            .zip(FIRST_CC..=127)
+            .map(|(param, cc)| CcMapping::new(cc, param))
+            .collect());
+    }
+    ccs.iter()
+        .map(|(cc, name)| Ok(CcMapping::new(*cc, find_param(params, name)?)))
+        .collect()
+}
+
+/// Sets parameters from any thread, JACK's thread applies them at the start of its next period.
+/// Only the latest value set between two periods is applied.
+#[derive(Clone)]
+pub struct Remote {
+    params: Arc<[ParamInfo]>,
+    pending: Arc<[AtomicU64]>,
+}
+
+impl Remote {
+    fn new(params: Vec<ParamInfo>) -> Remote {
+        Remote {
+            pending: params.iter().map(|_| AtomicU64::new(NO_CHANGE)).collect(),
+            params: params.into(),
+        }
+    }
+
+    pub fn params(&self) -> &[ParamInfo] {
+        &self.params
+    }
+
+    /// Sets a parameter by name, in the units the plugin shows
+    pub fn set(&self, name: &str, value: f64) -> io::Result<()> {
+        if !value.is_finite() {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                format!("{} isn't a parameter value", value),
+            ));
+        }
+        let id = find_param(&self.params, name)?.id;
+        let index = self.params.iter().position(|param| param.id == id).unwrap();
+        self.pending[index].store(value.to_bits(), Ordering::Release);
+        Ok(())
+    }
+
+    fn take(&self, index: usize) -> Option<f64> {
+        match self.pending[index].swap(NO_CHANGE, Ordering::Acquire) {
+            NO_CHANGE => None,
+            bits => Some(f64::from_bits(bits)),
+        }
+    }
+}
+
+struct Live {
+    plugin: Instance<'static>,
+    inputs: [Port<AudioIn>; 2],
+    outputs: [Port<AudioOut>; 2],
+    midi: Port<MidiIn>,
+    ccs: Vec<CcMapping>,
+    remote: Remote,
+    // This period's changes, then the ones in the chunk being processed
+    events: Vec<ParamValue>,
+    chunk_events: Vec<ParamValue>,
+}
+
+fn push_event(events: &mut Vec<ParamValue>, event: ParamValue) {
+    if events.len() < events.capacity() {
+        events.push(event);
+    }
+}
+
+impl ProcessHandler for Live {
+    fn process(&mut self, _: &Client, ps: &ProcessScope) -> Control {
+        self.events.clear();
+        for (index, param) in self.remote.params.iter().enumerate() {
+            if let Some(value) = self.remote.take(index) {
+                let event = ParamValue {
+                    time: 0,
+                    id: param.id,
+                    value,
+                };
+                push_event(&mut self.events, event);
+            }
+        }
+        for midi in self.midi.iter(ps) {
+            if let [status, cc, data] = *midi.bytes {
+                if status & 0xf0 != 0xb0 {
+                    continue;
+                }
+                for mapping in self.ccs.iter().filter(|mapping| mapping.cc == cc) {
+                    let event = ParamValue {
+                        time: midi.time,
+                        id: mapping.id,
+                        value: mapping.value(data),
+                    };
+                    push_event(&mut self.events, event);
+                }
+            }
+        }
+
+        let frames = ps.n_frames() as usize;
+        let [input_l, input_r] = &self.inputs;
+        let [output_l, output_r] = &mut self.outputs;
+        let (input_l, input_r) = (input_l.as_slice(ps), input_r.as_slice(ps));
+        let (output_l, output_r) = (output_l.as_mut_slice(ps), output_r.as_mut_slice(ps));
+        for start in (0..frames).step_by(MAX_FRAMES) {
+            let end = (start + MAX_FRAMES).min(frames);
+            self.chunk_events.clear();
+            self.chunk_events.extend(
+                self.events
+                    .iter()
+                    .filter(|event| (start..end).contains(&(event.time as usize)))
+                    .map(|event| ParamValue {
+                        time: event.time - start as u32,
+                        ..*event
+                    }),
+            );
+            let processed = self.plugin.process(
+                [&input_l[start..end], &input_r[start..end]],
+                [&mut output_l[start..end], &mut output_r[start..end]],
+                &self.chunk_events,
+            );
+            if processed.is_err() {
+                output_l[start..end].fill(0.0);
+                output_r[start..end].fill(0.0);
+            }
+        }
+        Control::Continue
+    }
+}
+
+/// A plugin running as a JACK client until stopped
+pub struct Session {
+    client: AsyncClient<(), Live>,
+    remote: Remote,
+}
+
+fn register<P: PortSpec + Default>(client: &Client, name: &str) -> io::Result<Port<P>> {
+    client.register_port(name, P::default()).map_err(jack_error)
+}
+
+/// Starts an inactive plugin as a JACK client with in_l, in_r, out_l, out_r and midi_in ports,
+/// at the server's sample rate. Doesn't start a server if there's none running.
+pub fn start(
+    mut plugin: Instance<'static>,
+    name: &str,
+    ccs: Vec<CcMapping>,
+) -> io::Result<Session> {
+    let (client, _) = Client::new(name, ClientOptions::NO_START_SERVER).map_err(jack_error)?;
+    let inputs = [register(&client, "in_l")?, register(&client, "in_r")?];
+    let outputs = [register(&client, "out_l")?, register(&client, "out_r")?];
+    let midi = register(&client, "midi_in")?;
+    plugin.activate(client.sample_rate() as f64, MAX_FRAMES as u32)?;
+    let remote = Remote::new(plugin.params());
+    let live = Live {
+        plugin,
+        inputs,
+        outputs,
+        midi,
+        ccs,
+        remote: remote.clone(),
+        events: Vec::with_capacity(EVENT_CAPACITY),
+        chunk_events: Vec::with_capacity(EVENT_CAPACITY),
+    };
+    let client = client.activate_async((), live).map_err(jack_error)?;
+    Ok(Session { client, remote })
+}
+
+impl Session {
+    /// The client's name, JACK makes it unique if another client has the one asked for
+    pub fn name(&self) -> &str {
+        self.client.as_client().name()
+    }
+
+    pub fn remote(&self) -> &Remote {
+        &self.remote
+    }
+
+    pub fn connect(&self, source: &str, destination: &str) -> io::Result<()> {
+        self.client
+            .as_client()
+            .connect_ports_by_name(source, destination)
+            .map_err(jack_error)
+    }
+
+    /// Connects the first two physical inputs and outputs, e.g. system:capture_1 to in_l
+    pub fn connect_physical(&self) -> io::Result<()> {
+        let client = self.client.as_client();
+        let physical = |flags| client.ports(None, Some("audio"), PortFlags::IS_PHYSICAL | flags);
+        let captures = physical(PortFlags::IS_OUTPUT);
+        let playbacks = physical(PortFlags::IS_INPUT);
+        for (capture, input) in captures.iter().zip(["in_l", "in_r"].iter()) {
+            self.connect(capture, &format!("{}:{}", self.name(), input))?;
+        }
+        for (playback, output) in playbacks.iter().zip(["out_l", "out_r"].iter()) {
+            self.connect(&format!("{}:{}", self.name(), output), playback)?;
+        }
+        Ok(())
+    }
+
+    /// Closes the client, the plugin is deactivated and destroyed on this thread
+    pub fn stop(self) -> io::Result<()> {
+        let (client, (), live) = self.client.deactivate().map_err(jack_error)?;
+        drop(live);
+        drop(client);
+        Ok(())
+    }
+}
+
+/// How a plugin runs live, for `baseplug-tests live` and each plugin's own executable
+#[derive(clap::Args)]
+pub struct Options {
+    /// Preset or saved state to start from, JSON as the plugins save it
+    #[clap(long, value_parser)]
+    pub preset: Option<PathBuf>,
+    /// A MIDI controller for a parameter, e.g. --cc "74=Out Gain". Can be given more than once,
+    /// without any, controllers 20 upwards set every parameter in order.
+    #[clap(long = "cc", value_name = "CC=NAME", value_parser = parse_cc)]
+    pub ccs: Vec<(u8, String)>,
+    /// JACK client name, the plugin's by default
+    #[clap(long)]
+    pub name: Option<String>,
+    /// Connect to the first two physical inputs and outputs
+    #[clap(long)]
+    pub connect: bool,
+}
+
+fn parse_cc(arg: &str) -> Result<(u8, String), String> {
+    let (cc, name) = arg
+        .split_once('=')
+        .ok_or_else(|| format!("{:?} isn't CC=NAME", arg))?;
+    let cc = match cc.trim().parse() {
+        Ok(cc) if cc < 128 => cc,
+        _ => return Err(format!("{:?} isn't a controller, 0 to 127", cc)),
+    };
+    Ok((cc, name.trim().to_string()))
+}
+
+/// Runs plugin, a built plugin's name or a library's path, as a JACK client and sets the
+/// parameters typed in on stdin until an empty line
+pub fn run(plugin: &str, options: Options) -> io::Result<()> {
+    let stem = Path::new(plugin).file_stem().unwrap_or_default();
+    let stem = stem.to_string_lossy();
+    let name = match &options.name {
+        Some(name) => name,
+        None => stem
+            .strip_prefix(std::env::consts::DLL_PREFIX)
+            .unwrap_or(&stem),
+    };
+    // The plugin runs on JACK's thread until the end of the program
+    let library: &'static Library = Box::leak(Box::new(Library::find(plugin)?));
+    let mut instance = library.create()?;
+    if let Some(preset) = &options.preset {
+        instance.load_state(&fs::read(preset)?)?;
+    }
+    let ccs = map_ccs(&instance.params(), &options.ccs)?;
+    for mapping in &ccs {
+        println!("CC {}: {}", mapping.cc, mapping.param);
+    }
+    let session = start(instance, name, ccs)?;
+    if options.connect {
+        session.connect_physical()?;
+    }
+
+    println!(
+        "Running as JACK client {}, type NAME=VALUE to set a parameter, an empty line quits",
+        session.name()
+    );
+    for line in io::stdin().lock().lines() {
+        let line = line?;
+        if line.trim().is_empty() {
+            break;
+        }
+        let set = parse_override(&line)
+            .map_err(|e| io::Error::new(io::ErrorKind::InvalidInput, e))
+            .and_then(|(name, value)| session.remote().set(&name, value));
+        if let Err(e) = set {
+            eprintln!("error: {}", e);
+        }
+    }
+    session.stop()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use std::sync::atomic::AtomicU32;
+    use std::time::{Duration, Instant};
+
+    use jack::{ClosureProcessHandler, MidiOut, RawMidi};
+
+    fn params() -> Vec<ParamInfo> {
+        let param = |id, name: &str, min, max| ParamInfo {
+            id,
+            name: name.to_string(),
+            min,
+            max,
+            default: min,
+        };
+        vec![param(3, "Out Gain", -90.0, 6.0), param(7, "Mode", 1.0, 4.0)]
+    }
+
+    #[test]
+    fn test_cc_mapping() {
+        let params = params();
+        let ccs = map_ccs(&params, &[]).unwrap();
+        assert_eq!(
+            ccs.iter().map(|m| (m.cc, m.id)).collect::<Vec<_>>(),
+            [(20, 3), (21, 7)]
+        );
+        assert_eq!(ccs[0].value(0), -90.0);
+        assert_eq!(ccs[0].value(127), 6.0);
+        assert_eq!(ccs[1].value(127), 4.0);
+
+        let ccs = map_ccs(&params, &[(74, "out_gain".to_string())]).unwrap();
+        assert_eq!(ccs.len(), 1);
+        assert_eq!((ccs[0].cc, ccs[0].param.as_str()), (74, "Out Gain"));
+        assert!(map_ccs(&params, &[(1, "Gain".to_string())]).is_err());
+    }
+
+    #[test]
+    fn test_parse_cc() {
+        assert_eq!(
+            parse_cc("74=Out Gain").unwrap(),
+            (74, "Out Gain".to_string())
+        );
+        assert!(parse_cc("128=Gain").is_err());
+        assert!(parse_cc("Gain").is_err());
+    }
+
+    #[test]
+    fn test_remote() {
+        let remote = Remote::new(params());
+        assert_eq!(remote.take(1), None);
+        remote.set("mode", 2.0).unwrap();
+        remote.set("mode", 3.0).unwrap();
+        assert_eq!(remote.take(0), None);
+        assert_eq!(remote.take(1), Some(3.0));
+        assert_eq!(remote.take(1), None);
+        assert!(remote.set("Gain", 0.0).is_err());
+        assert!(remote.set("Mode", f64::NAN).is_err());
+    }
+
+    fn wait_for(what: &str, level: &AtomicU32, done: impl Fn(f32) -> bool) {
+        let deadline = Instant::now() + Duration::from_secs(2);
+        loop {
+            let level = f32::from_bits(level.load(Ordering::Relaxed));
+            if done(level) {
+                return;
+            }
+            assert!(Instant::now() < deadline, "{}, output is {}", what, level);
+            std::thread::sleep(Duration::from_millis(10));
+        }
+    }
+
+    // Plays a constant through Gain and changes its gain remotely and with a CC. Needs a JACK
+    // server, `jackd -d dummy` will do, and the workspace built for libgain. Skipped when there's
+    // no server unless BASEPLUG_TESTS_JACK is set, as it is on CI.
+    #[test]
+    fn test_jack() {
+        let client = match Client::new("baseplug-tests", ClientOptions::NO_START_SERVER) {
+            Ok((client, _)) => client,
+            Err(e) if std::env::var_os("BASEPLUG_TESTS_JACK").is_none() => {
+                eprintln!("skipping test_jack, no JACK server: {}", e);
+                return;
+            }
+            Err(e) => panic!("no JACK server: {}", e),
+        };
+        let target = std::env::current_exe().unwrap();
+        let gain = target.parent().unwrap().parent().unwrap().join(format!(
+            "{}gain{}",
+            std::env::consts::DLL_PREFIX,
+            std::env::consts::DLL_SUFFIX
+        ));
+        let library = Library::open(&gain)
+            .unwrap_or_else(|e| panic!("{}, cargo build --workspace builds it", e));
+        let library: &'static Library = Box::leak(Box::new(library));
+        let plugin = library.create().unwrap();
+        let ccs = map_ccs(&plugin.params(), &[]).unwrap();
+        let session = start(plugin, "baseplug-tests-gain", ccs).unwrap();
+
+        let mut outputs: [Port<AudioOut>; 2] = [
+            register(&client, "out_l").unwrap(),
+            register(&client, "out_r").unwrap(),
+        ];
+        let inputs: [Port<AudioIn>; 2] = [
+            register(&client, "in_l").unwrap(),
+            register(&client, "in_r").unwrap(),
+        ];
+        let mut midi: Port<MidiOut> = register(&client, "midi_out").unwrap();
+        let level = Arc::new(AtomicU32::new(0));
+        let send_cc = Arc::new(AtomicU32::new(u32::MAX));
+        let (level_out, send_cc_in) = (level.clone(), send_cc.clone());
+        let process = move |_: &Client, ps: &ProcessScope| {
+            for output in outputs.iter_mut() {
+                output.as_mut_slice(ps).fill(0.5);
+            }
+            let last = inputs[0].as_slice(ps).last().copied().unwrap_or(0.0);
+            let last_r = inputs[1].as_slice(ps).last().copied().unwrap_or(0.0);
+            // Both channels have to agree before the level counts
+            if last == last_r {
+                level_out.store(last.to_bits(), Ordering::Relaxed);
+            }
+            let cc = send_cc_in.swap(u32::MAX, Ordering::Relaxed);
+            if cc != u32::MAX {
+                let bytes = [0xb0, (cc >> 8) as u8, cc as u8];
+                let _ = midi.writer(ps).write(&RawMidi {
+                    time: 0,
+                    bytes: &bytes,
+                });
+            }
+            Control::Continue
+        };
+        let client = client
+            .activate_async((), ClosureProcessHandler::new(process))
+            .unwrap();
+        let (test, gain) = (client.as_client().name().to_string(), session.name());
+        for (source, destination) in [
+            (format!("{}:out_l", test), format!("{}:in_l", gain)),
+            (format!("{}:out_r", test), format!("{}:in_r", gain)),
+            (format!("{}:midi_out", test), format!("{}:midi_in", gain)),
+            (format!("{}:out_l", gain), format!("{}:in_l", test)),
+            (format!("{}:out_r", gain), format!("{}:in_r", test)),
+        ]
+        .iter()
+        {
+            session.connect(source, destination).unwrap();
+        }
+
+        wait_for("unity gain", &level, |level| (level - 0.5).abs() < 1e-3);
+        session.remote().set("Gain", -6.0).unwrap();
+        wait_for("-6 dB", &level, |level| (level - 0.2506).abs() < 1e-3);
+        // CC 20 is Gain, 0 is -90 dB
+        send_cc.store(20 << 8, Ordering::Relaxed);
+        wait_for("CC 20 at 0", &level, |level| level.abs() < 1e-3);
+
+        client.deactivate().unwrap();
+        session.stop().unwrap();
+    }
+}
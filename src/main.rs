@@ -0,0 +1,185 @@
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+
+use clap::Parser;
+
+use baseplug_tests::host::Library;
+use baseplug_tests::live;
+use baseplug_tests::offline::{parse_automation, parse_override, Render};
+
+/// Runs the plugins without a DAW
+#[derive(Parser)]
+#[clap(name = "baseplug-tests", version)]
+enum Command {
+    /// Processes a WAV file through a plugin, e.g.
+    /// baseplug-tests process --plugin dynsat --preset x.json in.wav out.wav
+    Process(Process),
+    /// Runs a plugin live as a JACK client, e.g. baseplug-tests live --plugin varb --connect.
+    /// Parameters are set by MIDI CC, or typed in as NAME=VALUE.
+    Live(Live),
+}
+
+#[derive(clap::Args)]
+struct Process {
+    /// dynsat, varb, onepole or gain, built next to this executable, or the path of any CLAP
+    /// plugin library
+    #[clap(long)]
+    plugin: String,
+    /// Preset or saved state to start from, JSON as the plugins save it
+    #[clap(long, value_parser)]
+    preset: Option<PathBuf>,
+    /// A parameter value for the whole file, in the units the plugin shows, e.g.
+    /// --set "Out Gain=-6". Can be given more than once.
+    #[clap(long = "set", value_name = "NAME=VALUE", value_parser = parse_override)]
+    overrides: Vec<(String, f64)>,
+    /// JSON parameter envelopes, {"Gain": [[seconds, value], ..], ..}
+    #[clap(long, value_parser)]
+    automation: Option<PathBuf>,
+    /// Seconds of silence to process after the input, e.g. for a reverb's tail
+    #[clap(long, value_parser, default_value = "0")]
+    tail: f64,
+    /// Output sample format, 16 or 24 bit integer or 32 bit float
+    #[clap(long, value_parser = parse_bits, default_value = "32")]
+    bits: u16,
+    #[clap(value_parser)]
+    input: PathBuf,
+    #[clap(value_parser)]
+    output: PathBuf,
+}
+
+#[derive(clap::Args)]
+struct Live {
+    /// dynsat, varb, onepole or gain, built next to this executable, or the path of any CLAP
+    /// plugin library
+    #[clap(long)]
+    plugin: String,
+    #[clap(flatten)]
+    options: live::Options,
+}
+
+fn parse_bits(arg: &str) -> Result<u16, String> {
+    match arg {
+        "16" => Ok(16),
+        "24" => Ok(24),
+        "32" => Ok(32),
+        _ => Err("has to be 16, 24 or 32".to_string()),
+    }
+}
+
+fn invalid_data<E: Into<Box<dyn std::error::Error + Send + Sync>>>(error: E) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, error)
+}
+
+// Channels of samples in -1..1, and the sample rate
+fn read_wav(path: &Path) -> io::Result<(Vec<Vec<f32>>, u32)> {
+    let mut reader = hound::WavReader::open(path).map_err(invalid_data)?;
+    let spec = reader.spec();
+    let samples: Vec<f32> = match spec.sample_format {
+        hound::SampleFormat::Float => reader.samples::<f32>().collect::<Result<_, _>>(),
+        hound::SampleFormat::Int => {
+            let scale = (1i64 << (spec.bits_per_sample - 1)) as f32;
+            reader
+                .samples::<i32>()
+                .map(|sample| sample.map(|sample| sample as f32 / scale))
+                .collect()
+        }
+    }
+    .map_err(invalid_data)?;
+    let channels = spec.channels as usize;
+    let channels = (0..channels)
+        .map(|channel| {
+            samples
+                .iter()
+                .skip(channel)
+                .step_by(channels)
+                .copied()
+                .collect()
+        })
+        .collect();
+    Ok((channels, spec.sample_rate))
+}
+
+fn write_wav(path: &Path, channels: &[Vec<f32>], sample_rate: u32, bits: u16) -> io::Result<()> {
+    let spec = hound::WavSpec {
+        channels: channels.len() as u16,
+        sample_rate,
+        bits_per_sample: bits,
+        sample_format: if bits == 32 {
+            hound::SampleFormat::Float
+        } else {
+            hound::SampleFormat::Int
+        },
+    };
+    let mut writer = hound::WavWriter::create(path, spec).map_err(invalid_data)?;
+    let scale = ((1i64 << (bits - 1)) - 1) as f32;
+    for frame in 0..channels[0].len() {
+        for channel in channels {
+            let sample = channel[frame];
+            let written = if bits == 32 {
+                writer.write_sample(sample)
+            } else {
+                writer.write_sample((sample.clamp(-1.0, 1.0) * scale).round() as i32)
+            };
+            written.map_err(invalid_data)?;
+        }
+    }
+    writer.finalize().map_err(invalid_data)
+}
+
+fn process(args: Process) -> io::Result<()> {
+    let library = Library::find(&args.plugin)?;
+    let mut plugin = library.create()?;
+    if let Some(preset) = &args.preset {
+        plugin.load_state(&fs::read(preset)?)?;
+    }
+    let render = Render {
+        overrides: args.overrides,
+        automation: match &args.automation {
+            Some(automation) => parse_automation(&fs::read_to_string(automation)?)?,
+            None => Vec::new(),
+        },
+        tail: args.tail,
+    };
+
+    let (input, sample_rate) = read_wav(&args.input)?;
+    let output = render.run(&mut plugin, &input, sample_rate as f64)?;
+    write_wav(&args.output, &output, sample_rate, args.bits)
+}
+
+fn main() {
+    let result = match Command::parse() {
+        Command::Process(args) => process(args),
+        Command::Live(args) => live::run(&args.plugin, args.options),
+    };
+    if let Err(e) = result {
+        eprintln!("error: {}", e);
+        std::process::exit(1);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_wav_round_trip() {
+        let path = std::env::temp_dir().join(format!("baseplug-tests-{}.wav", std::process::id()));
+        let channels = vec![vec![0.0, 0.5, -1.0, 1.5], vec![0.25, -0.25, 0.0, -1.5]];
+        for &bits in [16, 24, 32].iter() {
+            write_wav(&path, &channels, 48000, bits).unwrap();
+            let (read, sample_rate) = read_wav(&path).unwrap();
+            assert_eq!(sample_rate, 48000);
+            for (read, written) in read.iter().flatten().zip(channels.iter().flatten()) {
+                // Integer formats clip
+                let written = if bits == 32 {
+                    *written
+                } else {
+                    written.clamp(-1.0, 1.0)
+                };
+                assert!((read - written).abs() < 1e-4, "{} bits: {}", bits, read);
+            }
+        }
+        fs::remove_file(&path).unwrap();
+    }
+}
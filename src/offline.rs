@@ -0,0 +1,264 @@
+// Renders audio through a plugin faster than real time, with parameters set for the whole
+// render or automated along envelopes
+
+use std::io;
+
+use serde_json::Value;
+
+use crate::host::{Instance, ParamInfo, ParamValue};
+
+/// Frames per process call
+const BLOCK: usize = 1024;
+/// Frames between the automation values sent to the plugin, it smooths in between
+const AUTOMATION_STEP: usize = 32;
+
+fn invalid_input<E: Into<Box<dyn std::error::Error + Send + Sync>>>(error: E) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidInput, error)
+}
+
+/// A parameter's value over time, linear between points and constant before the first and
+/// after the last. Values are in the units the plugin shows, e.g. dB.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Envelope {
+    pub param: String,
+    /// (seconds, value) in time order
+    pub points: Vec<(f64, f64)>,
+}
+
+impl Envelope {
+    pub fn value_at(&self, seconds: f64) -> f64 {
+        let next = self.points.iter().position(|(time, _)| *time > seconds);
+        match next {
+            Some(0) => self.points[0].1,
+            Some(next) => {
+                let (t0, v0) = self.points[next - 1];
+                let (t1, v1) = self.points[next];
+                v0 + (v1 - v0) * (seconds - t0) / (t1 - t0)
+            }
+            None => self.points.last().map_or(0.0, |(_, value)| *value),
+        }
+    }
+}
+
+/// A parameter value given as NAME=VALUE, e.g. "Out Gain=-6"
+pub fn parse_override(arg: &str) -> Result<(String, f64), String> {
+    let (name, value) = arg
+        .split_once('=')
+        .ok_or_else(|| format!("{:?} isn't NAME=VALUE", arg))?;
+    let value = value
+        .trim()
+        .parse()
+        .map_err(|_| format!("{:?} isn't a number", value))?;
+    Ok((name.trim().to_string(), value))
+}
+
+/// Envelopes from JSON, {"Gain": [[0.0, 0.0], [4.0, 24.0]], ..} with [seconds, value] points
+pub fn parse_automation(json: &str) -> io::Result<Vec<Envelope>> {
+    let envelopes = match serde_json::from_str(json).map_err(invalid_input)? {
+        Value::Object(envelopes) => envelopes,
+        _ => return Err(invalid_input("automation is not an object of envelopes")),
+    };
+    envelopes
+        .into_iter()
+        .map(|(param, points)| {
+            let points = points
+                .as_array()
+                .filter(|points| !points.is_empty())
+                .ok_or_else(|| invalid_input(format!("{} has no points", param)))?;
+            let mut points = points
+                .iter()
+                .map(|point| match point.as_array().map(|point| &point[..]) {
+                    Some([time, value]) => match (time.as_f64(), value.as_f64()) {
+                        (Some(time), Some(value)) if time.is_finite() && value.is_finite() => {
+                            Ok((time, value))
+                        }
+                        _ => Err(()),
+                    },
+                    _ => Err(()),
+                })
+                .collect::<Result<Vec<(f64, f64)>, ()>>()
+                .map_err(|_| invalid_input(format!("{} points aren't [seconds, value]", param)))?;
+            points.sort_by(|a, b| a.0.partial_cmp(&b.0).unwrap());
+            Ok(Envelope { param, points })
+        })
+        .collect()
+}
+
+/// Parameter names match ignoring case, spaces and underscores, so "out_gain" finds "Out Gain"
+fn same_name(a: &str, b: &str) -> bool {
+    let key = |name: &str| -> String {
+        name.chars()
+            .filter(|c| c.is_alphanumeric())
+            .flat_map(char::to_lowercase)
+            .collect()
+    };
+    key(a) == key(b)
+}
+
+pub(crate) fn find_param<'a>(params: &'a [ParamInfo], name: &str) -> io::Result<&'a ParamInfo> {
+    params
+        .iter()
+        .find(|param| same_name(&param.name, name))
+        .ok_or_else(|| {
+            let names: Vec<&str> = params.iter().map(|param| param.name.as_str()).collect();
+            invalid_input(format!(
+                "no parameter {:?}, the plugin has {}",
+                name,
+                names.join(", ")
+            ))
+        })
+}
+
+/// What to change on top of the plugin's state for a render
+#[derive(Clone, Debug, Default)]
+pub struct Render {
+    /// Parameter values for the whole render, by name
+    pub overrides: Vec<(String, f64)>,
+    pub automation: Vec<Envelope>,
+    /// Seconds of silence to process after the input, e.g. for a reverb's tail
+    pub tail: f64,
+}
+
+impl Render {
+    /// Runs mono or stereo input through an inactive plugin, returns the stereo output
+    pub fn run(
+        &self,
+        plugin: &mut Instance<'_>,
+        input: &[Vec<f32>],
+        sample_rate: f64,
+    ) -> io::Result<[Vec<f32>; 2]> {
+        let (left, right) = match input {
+            [mono] => (mono, mono),
+            [left, right] => (left, right),
+            _ => return Err(invalid_input("input has to be mono or stereo")),
+        };
+        let params = plugin.params();
+        let overrides = self
+            .overrides
+            .iter()
+            .map(|(name, value)| Ok((find_param(&params, name)?.id, *value)))
+            .collect::<io::Result<Vec<_>>>()?;
+        let automation = self
+            .automation
+            .iter()
+            .map(|envelope| Ok((find_param(&params, &envelope.param)?.id, envelope)))
+            .collect::<io::Result<Vec<_>>>()?;
+
+        // The output lags the input by the plugin's latency, the render runs that much longer
+        // and drops the start so the output lines up with the input
+        plugin.activate(sample_rate, BLOCK as u32)?;
+        let latency = plugin.latency() as usize;
+        let tail = (self.tail.max(0.0) * sample_rate) as usize;
+        let frames = left.len() + tail + latency;
+        let mut output = [vec![0.0; frames], vec![0.0; frames]];
+        let silence = vec![0.0; BLOCK];
+        let input_block = |channel: &[f32], start: usize, end: usize| -> Vec<f32> {
+            let len = channel.len();
+            let mut block = channel[start.min(len)..end.min(len)].to_vec();
+            block.extend_from_slice(&silence[..end - start - block.len()]);
+            block
+        };
+
+        let mut events = Vec::new();
+        let mut sent: Vec<Option<f64>> = vec![None; automation.len()];
+        for start in (0..frames).step_by(BLOCK) {
+            let end = (start + BLOCK).min(frames);
+            events.clear();
+            if start == 0 {
+                events.extend(overrides.iter().map(|&(id, value)| ParamValue {
+                    time: 0,
+                    id,
+                    value,
+                }));
+            }
+            for frame in (start..end).step_by(AUTOMATION_STEP) {
+                let seconds = frame as f64 / sample_rate;
+                for ((id, envelope), sent) in automation.iter().zip(sent.iter_mut()) {
+                    let value = envelope.value_at(seconds);
+                    if *sent != Some(value) {
+                        *sent = Some(value);
+                        events.push(ParamValue {
+                            time: (frame - start) as u32,
+                            id: *id,
+                            value,
+                        });
+                    }
+                }
+            }
+            let [output_l, output_r] = &mut output;
+            plugin.process(
+                [
+                    &input_block(left, start, end),
+                    &input_block(right, start, end),
+                ],
+                [&mut output_l[start..end], &mut output_r[start..end]],
+                &events,
+            )?;
+        }
+        plugin.deactivate();
+        for channel in output.iter_mut() {
+            channel.drain(..latency);
+        }
+        Ok(output)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_override() {
+        assert_eq!(
+            parse_override("Out Gain=-6").unwrap(),
+            ("Out Gain".to_string(), -6.0)
+        );
+        assert_eq!(
+            parse_override("mode = 3").unwrap(),
+            ("mode".to_string(), 3.0)
+        );
+        assert!(parse_override("gain").is_err());
+        assert!(parse_override("gain=loud").is_err());
+    }
+
+    #[test]
+    fn test_envelope() {
+        let envelope = Envelope {
+            param: "Gain".to_string(),
+            points: vec![(1.0, 0.0), (3.0, 10.0), (3.0, -5.0), (4.0, -5.0)],
+        };
+        assert_eq!(envelope.value_at(0.0), 0.0);
+        assert_eq!(envelope.value_at(2.0), 5.0);
+        assert_eq!(envelope.value_at(3.0), -5.0);
+        assert_eq!(envelope.value_at(10.0), -5.0);
+    }
+
+    #[test]
+    fn test_parse_automation() {
+        let automation =
+            parse_automation(r#"{"Out Gain": [[2, -6.0], [0.0, 0]], "Mode": [[0, 3]]}"#).unwrap();
+        assert_eq!(automation.len(), 2);
+        let out_gain = automation.iter().find(|e| e.param == "Out Gain").unwrap();
+        assert_eq!(out_gain.points, vec![(0.0, 0.0), (2.0, -6.0)]);
+
+        assert!(parse_automation("[]").is_err());
+        assert!(parse_automation(r#"{"Gain": []}"#).is_err());
+        assert!(parse_automation(r#"{"Gain": [[0, "loud"]]}"#).is_err());
+        assert!(parse_automation(r#"{"Gain": [[0, 1, 2]]}"#).is_err());
+    }
+
+    #[test]
+    fn test_param_names() {
+        let params = vec![ParamInfo {
+            id: 1,
+            name: "Out Gain".to_string(),
+            min: -96.0,
+            max: 12.0,
+            default: 0.0,
+        }];
+        assert_eq!(find_param(&params, "out_gain").unwrap().id, 1);
+        assert_eq!(find_param(&params, "OUT GAIN").unwrap().id, 1);
+        let error = find_param(&params, "gain").unwrap_err();
+        assert!(error.to_string().contains("Out Gain"));
+    }
+}
@@ -0,0 +1,51 @@
+use criterion::{black_box, criterion_group, criterion_main, BenchmarkId, Criterion, Throughput};
+
+use plugin_common::bench::{assert_budget, bench_plugin, model_with, noise};
+
+use varb::{VerbPlug, VerbPlugModel, VerbUnit};
+
+// A single instance at 64 iterations has to leave room in each block for 3 more like it, so a
+// session can run a few at their worst
+const MAX_ITERATIONS_HEADROOM: f64 = 4.0;
+
+fn verb_unit_process(c: &mut Criterion) {
+    let input: Vec<f64> = noise(1024).iter().map(|x| *x as f64).collect();
+    let mut verb = VerbUnit::new();
+    let mut n = 0;
+
+    let mut group = c.benchmark_group("verb_unit_process");
+    group.throughput(Throughput::Elements(input.len() as u64));
+    for &iterations in [1, 4, 16, 32, 64].iter() {
+        group.bench_with_input(
+            BenchmarkId::from_parameter(iterations),
+            &iterations,
+            |b, &iterations| {
+                b.iter(|| {
+                    for x in input.iter() {
+                        black_box(verb.process(*x, 9600, 0.9, 0.9, 0.9, iterations, 0.0, n));
+                        n = (n + 1) % 960000;
+                    }
+                })
+            },
+        );
+    }
+    group.finish();
+}
+
+fn varb_process(c: &mut Criterion) {
+    bench_plugin::<VerbPlug>(c, "varb_process", VerbPlugModel::default);
+    // Maximum iterations, this is the worst case per sample cost
+    let max_iterations = || model_with::<VerbPlug>(&[("Iterations", 64.0)]);
+    bench_plugin::<VerbPlug>(c, "varb_process_64_iterations", max_iterations);
+    // A session runs many of these, one instance at its worst has to stay well under a core
+    assert_budget::<VerbPlug>(
+        "varb_process_64_iterations",
+        max_iterations,
+        48000.0,
+        256,
+        MAX_ITERATIONS_HEADROOM,
+    );
+}
+
+criterion_group!(benches, verb_unit_process, varb_process);
+criterion_main!(benches);
@@ -1,4 +1,4 @@
-use crate::units::Units;
+use plugin_common::units::Units;
 use std::f64::consts::PI;
 
 #[derive(Debug, Clone, Copy)]
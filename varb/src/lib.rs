@@ -1,34 +1,35 @@
 #![allow(incomplete_features)]
 #![feature(generic_associated_types)]
 
+use std::ffi::CStr;
+
 use serde::{Deserialize, Serialize};
+use serde_json::{Map, Value};
 
 use baseplug::{Plugin, ProcessContext};
-mod comp;
-mod svf;
-mod units;
-
-fn setup_logging() {
-    let log_folder = ::dirs::home_dir().unwrap().join("tmp");
-
-    let _ = ::std::fs::create_dir(log_folder.clone());
-
-    let log_file = ::std::fs::File::create(log_folder.join("DGVerb.log")).unwrap();
-
-    let log_config = ::simplelog::ConfigBuilder::new()
-        .set_time_to_local(true)
-        .build();
+use clap_sys::id::clap_id;
+use clap_sys::plugin_features::{
+    CLAP_PLUGIN_FEATURE_AUDIO_EFFECT, CLAP_PLUGIN_FEATURE_REVERB, CLAP_PLUGIN_FEATURE_STEREO,
+};
+use log::Level;
+use plugin_common::clap::{ClapExport, ClapParam};
+use plugin_common::logging;
+use plugin_common::mix::{MixLaw, MixStage};
+use plugin_common::presets::{FactoryPresets, Preset};
+use plugin_common::protect::{DenormalGuard, Sanitizer};
+use plugin_common::rtlog::RtLog;
+use plugin_common::smooth::{Curve, Smooth};
+use plugin_common::state::VersionedState;
+use plugin_common::units::Units;
+use plugin_common::vst3::sys::{uid, Tuid};
+use plugin_common::vst3::Vst3Export;
 
-    let _ = ::simplelog::WriteLogger::init(simplelog::LevelFilter::Info, log_config, log_file);
-
-    ::log_panics::init();
-
-    ::log::info!("init");
-}
+mod comp;
 
-baseplug::model! {
-    #[derive(Debug, Serialize, Deserialize)]
-    struct VerbPlugModel {
+plugin_common::model! {
+    #[derive(Debug, Clone, Serialize, Deserialize)]
+    #[serde(default)]
+    pub struct VerbPlugModel {
 
         #[model(min = 0.0, max = 1.0)]
         #[parameter(name = "Mix", unit = "Generic",
@@ -38,6 +39,7 @@ baseplug::model! {
         #[model(min = 0.0001, max = 1000.0)]
         #[parameter(name = "Delay Size", unit = "Generic",
             gradient = "Linear")]
+        #[export(label = "ms")]
         delay_size: f32,
 
         #[model(min = 0.0, max = 1.5)]
@@ -58,6 +60,7 @@ baseplug::model! {
         #[model(min = 0.0, max = 64.0)]
         #[parameter(name = "Iterations", unit = "Generic",
             gradient = "Linear")]
+        #[export(stepped)]
         iterations: f32,
 
         #[model(min = -48.0, max = 48.0)]
@@ -65,6 +68,35 @@ baseplug::model! {
             gradient = "Power(1.0)")]
         out_gain: f32,
 
+        #[model(min = 0.0, max = 1.0)]
+        #[parameter(name = "Freeze", unit = "Generic",
+            gradient = "Linear")]
+        #[export(stepped)]
+        freeze: f32,
+
+        #[model(min = -12.0, max = 12.0)]
+        #[parameter(name = "Trim", unit = "Decibels",
+            gradient = "Power(1.0)")]
+        trim: f32,
+
+        #[model(min = 0.0, max = 1.0)]
+        #[parameter(name = "Mix Law", unit = "Generic",
+            gradient = "Linear")]
+        #[export(stepped)]
+        mix_law: f32,
+
+        #[model(min = 0.0, max = 1.0)]
+        #[parameter(name = "Bypass", unit = "Generic",
+            gradient = "Linear")]
+        #[export(stepped)]
+        bypass: f32,
+
+        #[model(min = 0.0, max = 1.0)]
+        #[parameter(name = "Bypass Tails", unit = "Generic",
+            gradient = "Linear")]
+        #[export(stepped)]
+        bypass_tails: f32,
+
     }
 }
 
@@ -78,21 +110,118 @@ impl Default for VerbPlugModel {
             decay_delta: 0.9,
             iterations: 16.0,
             out_gain: 1.0,
+            freeze: 0.0,
+            trim: 1.0,
+            mix_law: 0.0,
+            bypass: 0.0,
+            // The reverb rings out when it's bypassed
+            bypass_tails: 1.0,
         }
     }
 }
 
+impl FactoryPresets for VerbPlugModel {
+    fn factory_presets() -> Vec<Preset<Self>> {
+        let preset = |name: &str, model: VerbPlugModel| Preset {
+            name: name.to_string(),
+            model,
+        };
+        vec![
+            preset("Default", VerbPlugModel::default()),
+            preset(
+                "Small Room",
+                VerbPlugModel {
+                    mix: 0.3,
+                    delay_size: 8.0,
+                    delay_delta: 0.8,
+                    decay_init: 0.6,
+                    decay_delta: 0.95,
+                    iterations: 12.0,
+                    ..VerbPlugModel::default()
+                },
+            ),
+            preset(
+                "Plate",
+                VerbPlugModel {
+                    mix: 0.4,
+                    delay_size: 20.0,
+                    delay_delta: 0.7,
+                    decay_init: 0.75,
+                    decay_delta: 0.97,
+                    iterations: 24.0,
+                    ..VerbPlugModel::default()
+                },
+            ),
+            preset(
+                "Cathedral",
+                VerbPlugModel {
+                    mix: 0.5,
+                    delay_size: 120.0,
+                    delay_delta: 0.85,
+                    decay_init: 0.85,
+                    decay_delta: 0.98,
+                    iterations: 32.0,
+                    out_gain: (-3.0f32).db_to_lin(),
+                    ..VerbPlugModel::default()
+                },
+            ),
+            preset(
+                "Frozen Pad",
+                VerbPlugModel {
+                    mix: 0.6,
+                    delay_size: 60.0,
+                    delay_delta: 0.9,
+                    decay_init: 0.9,
+                    decay_delta: 0.99,
+                    iterations: 32.0,
+                    out_gain: (-6.0f32).db_to_lin(),
+                    freeze: 1.0,
+                    ..VerbPlugModel::default()
+                },
+            ),
+        ]
+    }
+}
+
+impl VersionedState for VerbPlugModel {
+    const STATE_VERSION: u32 = 1;
+
+    fn migrate(_from_version: u32, _model: &mut Map<String, Value>) {
+        // Version 0 is the bare model, freeze and the mix and bypass parameters were added after
+        // it and take their defaults
+    }
+}
+
 const MAX_BUFFER_LENGTH: usize = 960000;
 const ITERATIONS: usize = 64;
+// Time constant for the freeze amount to ramp in and out
+const FREEZE_TIME_MS: f64 = 50.0;
+// Values written into or coming out of a line are soft limited above LINE_KNEE and never exceed
+// LINE_LIMIT, this keeps decay settings above 1.0 from growing without bound.
+const LINE_KNEE: f64 = 50.0;
+const LINE_LIMIT: f64 = 100.0;
 
 fn mix(x: f64, y: f64, a: f64) -> f64 {
     x * (1.0 - a) + y * a
 }
 
+fn soft_limit(x: f64) -> f64 {
+    let abs = x.abs();
+    if abs <= LINE_KNEE {
+        x
+    } else {
+        let range = LINE_LIMIT - LINE_KNEE;
+        (LINE_KNEE + range * ((abs - LINE_KNEE) / range).tanh()).copysign(x)
+    }
+}
+
 #[derive(Debug, Clone)]
 pub struct VerbUnit {
     buffers: Vec<Vec<f64>>,
     delay: usize,
+    limiting: bool,
+    pub resets: usize,
+    pub limiter_onsets: usize,
 }
 
 impl VerbUnit {
@@ -100,6 +229,9 @@ impl VerbUnit {
         VerbUnit {
             buffers: vec![vec![0.0f64; MAX_BUFFER_LENGTH]; ITERATIONS],
             delay: 1000,
+            limiting: false,
+            resets: 0,
+            limiter_onsets: 0,
         }
     }
 
@@ -111,19 +243,42 @@ impl VerbUnit {
         decay_init: f64,
         decay_delta: f64,
         iterations: usize,
+        freeze: f64,
         n: usize,
     ) -> f64 {
-        let mut x = x;
+        let mut x = if x.is_finite() { x } else { 0.0 };
         let mut decay = decay_init;
-        self.delay = delay_size;
+        self.delay = delay_size.min(MAX_BUFFER_LENGTH);
         for i in 0..iterations {
-            x = self.line1(x, i, n, decay);
+            x = self.line1(x, i, n, decay, freeze);
             decay *= decay_delta;
-            self.delay = (delay_delta * self.delay as f64) as usize;
+            self.delay = ((delay_delta * self.delay as f64) as usize).min(MAX_BUFFER_LENGTH);
         }
+        if !x.is_finite() {
+            self.resets += 1;
+            self.reset(delay_size, delay_delta, iterations);
+            return 0.0;
+        }
+        // Counts each time the limiter engages, not every limited sample
+        let limiting = x.abs() > LINE_KNEE;
+        if limiting && !self.limiting {
+            self.limiter_onsets += 1;
+        }
+        self.limiting = limiting;
         x
     }
 
+    /// Clears the part of each buffer that is in use for the given delay settings
+    pub fn reset(&mut self, delay_size: usize, delay_delta: f64, iterations: usize) {
+        let mut delay = delay_size.min(MAX_BUFFER_LENGTH);
+        for buffer in self.buffers.iter_mut().take(iterations) {
+            for v in buffer.iter_mut().take(delay.max(1)) {
+                *v = 0.0;
+            }
+            delay = ((delay_delta * delay as f64) as usize).min(MAX_BUFFER_LENGTH);
+        }
+    }
+
     pub fn get(&self, buffer_num: usize, position: usize) -> f64 {
         self.buffers[buffer_num][position % MAX_BUFFER_LENGTH.min(self.delay).max(1)]
     }
@@ -132,21 +287,31 @@ impl VerbUnit {
         self.buffers[buffer_num][position % MAX_BUFFER_LENGTH.min(self.delay).max(1)] = value;
     }
 
-    pub fn line1(&mut self, x: f64, buffer_idx: usize, n: usize, gain: f64) -> f64 {
-        let back = gain * self.get(buffer_idx, n + self.delay);
-        self.set(buffer_idx, n, x + back);
-        self.get(buffer_idx, n) + (-gain * x)
+    // As freeze goes to 1.0 the line stops taking in new input, neither into the buffer nor
+    // past it, and its feedback goes to unity, so whatever is currently in the buffer keeps
+    // recirculating without growing or decaying.
+    pub fn line1(&mut self, x: f64, buffer_idx: usize, n: usize, gain: f64, freeze: f64) -> f64 {
+        let back = mix(gain, 1.0, freeze) * self.get(buffer_idx, n + self.delay);
+        self.set(buffer_idx, n, soft_limit(x * (1.0 - freeze) + back));
+        soft_limit(self.get(buffer_idx, n) + (-gain * x) * (1.0 - freeze))
     }
 
-    pub fn line2(&mut self, x: f64, buffer_idx: usize, n: usize, gain: f64) -> f64 {
-        let back = gain * self.get(buffer_idx, n + self.delay);
-        self.set(buffer_idx, n, x + back);
-        (1.0 - gain * gain) * self.get(buffer_idx, n) + (-gain * x)
+    pub fn line2(&mut self, x: f64, buffer_idx: usize, n: usize, gain: f64, freeze: f64) -> f64 {
+        let back = mix(gain, 1.0, freeze) * self.get(buffer_idx, n + self.delay);
+        self.set(buffer_idx, n, soft_limit(x * (1.0 - freeze) + back));
+        soft_limit((1.0 - gain * gain) * self.get(buffer_idx, n) + (-gain * x) * (1.0 - freeze))
     }
 }
 
-struct VerbPlug {
+pub struct VerbPlug {
     verbs: [VerbUnit; 2],
+    freeze: Smooth,
+    mix: MixStage,
+    sanitizer: Sanitizer,
+    rt_log: RtLog,
+    // Counts from the verb units at the end of the last block
+    resets: usize,
+    limiter_onsets: usize,
     sample_rate: f64,
     n: usize,
 }
@@ -162,10 +327,25 @@ impl Plugin for VerbPlug {
     type Model = VerbPlugModel;
 
     #[inline]
-    fn new(sample_rate: f32, _model: &VerbPlugModel) -> Self {
-        setup_logging();
+    fn new(sample_rate: f32, model: &VerbPlugModel) -> Self {
+        let log_tag = logging::init(Self::NAME);
+        ::log::info!(target: &log_tag, "init");
+        let mut mix = MixStage::new(sample_rate);
+        mix.set_latency(Self::latency(model, sample_rate));
         VerbPlug {
             verbs: [VerbUnit::new(), VerbUnit::new()],
+            freeze: Smooth::new(
+                if model.freeze >= 0.5 { 1.0 } else { 0.0 },
+                Curve::Exponential {
+                    time_ms: FREEZE_TIME_MS,
+                },
+                sample_rate as f64,
+            ),
+            mix,
+            sanitizer: Sanitizer::new(),
+            rt_log: RtLog::new(log_tag),
+            resets: 0,
+            limiter_onsets: 0,
             sample_rate: sample_rate as f64,
             n: 0,
         }
@@ -173,6 +353,7 @@ impl Plugin for VerbPlug {
 
     #[inline]
     fn process(&mut self, model: &VerbPlugModelProcess, ctx: &mut ProcessContext<Self>) {
+        let _denormals = DenormalGuard::new();
         let input = &ctx.inputs[0].buffers;
         let output = &mut ctx.outputs[0].buffers;
         for i in 0..ctx.nframes {
@@ -183,39 +364,367 @@ impl Plugin for VerbPlug {
             let decay_delta = model.decay_delta[i] as f64;
             let iterations = model.iterations[i] as usize;
             let out_gain = model.out_gain[i] as f64;
+            let trim = model.trim[i] as f64;
+            let mix_law = MixLaw::from_param(model.mix_law[i]);
+            let bypass = model.bypass[i] >= 0.5;
+            let bypass_tails = model.bypass_tails[i] >= 0.5;
+            // Freeze is a toggle, the ramp in and out comes from the smoother
+            self.freeze
+                .set_target(if model.freeze[i] >= 0.5 { 1.0 } else { 0.0 });
+            let freeze = self.freeze.next();
             let in_l = input[0][i] as f64;
             let in_r = input[1][i] as f64;
+            let in_l = if in_l.is_finite() { in_l } else { 0.0 };
+            let in_r = if in_r.is_finite() { in_r } else { 0.0 };
+            let dry = [in_l, in_r];
+
+            self.mix.set_bypass(bypass, bypass_tails);
+            if !self.mix.processing() {
+                // Bypassed without tails, the delay lines hold their state
+                let [l, r] = self.mix.process(dry, [0.0; 2], mix_amnt, mix_law, trim);
+                output[0][i] = l as f32;
+                output[1][i] = r as f32;
+                continue;
+            }
+            let input_gain = self.mix.input_gain();
 
             let l = self.verbs[0].process(
-                in_l,
+                in_l * input_gain,
                 delay_size,
                 delay_delta,
                 decay_init,
                 decay_delta,
                 iterations,
+                freeze,
                 self.n,
             );
             let r = self.verbs[1].process(
-                in_r,
+                in_r * input_gain,
                 delay_size,
                 delay_delta,
                 decay_init,
                 decay_delta,
                 iterations,
+                freeze,
                 self.n,
             );
 
-            let l = l * out_gain;
-            let r = r * out_gain;
-
-            let l = mix(in_l, l, mix_amnt);
-            let r = mix(in_r, r, mix_amnt);
+            let wet = [l * out_gain, r * out_gain];
+            let [l, r] = self.mix.process(dry, wet, mix_amnt, mix_law, trim);
 
             output[0][i] = l as f32;
             output[1][i] = r as f32;
             self.n = (self.n + 1) % MAX_BUFFER_LENGTH;
         }
+
+        let resets: usize = self.verbs.iter().map(|verb| verb.resets).sum();
+        if resets != self.resets {
+            self.resets = resets;
+            self.rt_log.push(
+                Level::Warn,
+                "VerbUnit output was not finite, buffers cleared. Resets",
+                resets as f64,
+            );
+        }
+        let limiter_onsets: usize = self.verbs.iter().map(|verb| verb.limiter_onsets).sum();
+        if limiter_onsets != self.limiter_onsets {
+            self.limiter_onsets = limiter_onsets;
+            self.rt_log.push(
+                Level::Warn,
+                "VerbUnit feedback is running away, output is being limited. Times",
+                limiter_onsets as f64,
+            );
+        }
+        if self.sanitizer.process_buffers(output, ctx.nframes) {
+            self.rt_log.push(
+                Level::Warn,
+                "Replaced non-finite output samples. Total",
+                self.sanitizer.count as f64,
+            );
+        }
+    }
+}
+
+impl ClapExport for VerbPlug {
+    const CLAP_ID: &'static str = "net.dgdigital.varb";
+    const VERSION: &'static str = env!("CARGO_PKG_VERSION");
+    const CLAP_FEATURES: &'static [&'static CStr] = &[
+        CLAP_PLUGIN_FEATURE_AUDIO_EFFECT,
+        CLAP_PLUGIN_FEATURE_REVERB,
+        CLAP_PLUGIN_FEATURE_STEREO,
+    ];
+    const BYPASS_PARAM: Option<clap_id> = Some(10);
+
+    fn clap_params() -> Vec<ClapParam<VerbPlugModel>> {
+        VerbPlugModel::clap_params()
     }
 }
 
-baseplug::vst2!(VerbPlug, b"tAnF");
+impl Vst3Export for VerbPlug {
+    const VST3_CLASS_ID: Tuid = uid(0xF91F4468, 0x5F9843C4, 0xBC6F174F, 0x51D78383);
+    const VST3_CATEGORIES: &'static str = "Fx|Reverb";
+}
+
+plugin_common::vst2_export!(VerbPlug, b"tAnF");
+plugin_common::clap_export!(VerbPlug);
+plugin_common::vst3_export!(VerbPlug);
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use plugin_common::clap;
+    use plugin_common::fuzz;
+    use plugin_common::harness::Harness;
+    use plugin_common::presets::PresetBank;
+    #[cfg(feature = "rt-check")]
+    use plugin_common::rt_check;
+    use plugin_common::state;
+    use plugin_common::vst2;
+    use plugin_common::vst3;
+    use proptest::prelude::*;
+    use std::num::FpCategory;
+
+    // The lines soft limit to LINE_LIMIT, so that is the most the wet signal reaches before
+    // the out gain
+    fn max_output(models: &[VerbPlugModel]) -> f32 {
+        let wet = LINE_LIMIT as f32 * fuzz::peak(models, |model| model.out_gain);
+        fuzz::mixed_max_output(wet, fuzz::peak(models, |model| model.trim))
+    }
+
+    fn model() -> impl Strategy<Value = VerbPlugModel> {
+        (
+            (0.0f32..=1.0, 0.0001f32..=1000.0, 0.0f32..=1.5, 0.0f32..=1.5),
+            (0.0f32..=1.5, 0.0f32..=64.0, -48.0f32..=48.0, 0.0f32..=1.0),
+            (-12.0f32..=12.0, 0.0f32..=1.0, 0.0f32..=1.0, 0.0f32..=1.0),
+        )
+            .prop_map(
+                |(
+                    (mix, delay_size, delay_delta, decay_init),
+                    (decay_delta, iterations, out_gain, freeze),
+                    (trim, mix_law, bypass, bypass_tails),
+                )| VerbPlugModel {
+                    mix,
+                    delay_size,
+                    delay_delta,
+                    decay_init,
+                    decay_delta,
+                    iterations,
+                    out_gain: out_gain.db_to_lin(),
+                    freeze,
+                    trim: trim.db_to_lin(),
+                    mix_law,
+                    bypass,
+                    bypass_tails,
+                },
+            )
+    }
+
+    #[test]
+    fn test_feedback_heavy_patch_stays_clean() {
+        let sample_rate = 48000;
+
+        // (delay_size ms, delay_delta, decay_init, decay_delta, iterations)
+        let patches = [
+            (10.0, 1.5, 1.5, 1.5, ITERATIONS as f32),
+            (100.0, 1.0, 0.99, 1.0, ITERATIONS as f32),
+            (0.21, 0.5, 1.5, 0.5, 16.0),
+        ];
+        for &(delay_size, delay_delta, decay_init, decay_delta, iterations) in patches.iter() {
+            let model = VerbPlugModel {
+                delay_size,
+                delay_delta,
+                decay_init,
+                decay_delta,
+                iterations,
+                ..VerbPlugModel::default()
+            };
+            let mut harness = Harness::<VerbPlug>::new(|| model.clone(), sample_rate as f32, 128);
+            let mut seed = 1u32;
+            for block in 0..sample_rate * 2 / 128 {
+                // Half a second of full scale noise, then silence for the tail to decay into
+                let input: Vec<f32> = (0..128)
+                    .map(|_| {
+                        seed = seed.wrapping_mul(1664525).wrapping_add(1013904223);
+                        if block * 128 < sample_rate / 2 {
+                            (seed as f32 / u32::MAX as f32) * 2.0 - 1.0
+                        } else {
+                            0.0
+                        }
+                    })
+                    .collect();
+                let output = harness.process([&input, &input]);
+                for y in output[0].iter().chain(output[1]) {
+                    assert!(y.abs() <= LINE_LIMIT as f32);
+                    assert_ne!(y.classify(), FpCategory::Subnormal);
+                }
+            }
+            // The plugin's own checks never had to step in
+            assert_eq!(harness.plugin.sanitizer.count, 0);
+            assert_eq!(harness.plugin.resets, 0);
+        }
+    }
+
+    #[test]
+    fn test_freeze_holds_the_tail() {
+        let sample_rate = 48000;
+        let frames = 480;
+        // A 10 ms line throughout, so the held tail repeats every 480 samples
+        let mut model = VerbPlugModel {
+            delay_size: 10.0,
+            delay_delta: 1.0,
+            decay_init: 0.5,
+            decay_delta: 1.0,
+            iterations: 4.0,
+            ..VerbPlugModel::default()
+        };
+        let mut fed = Harness::<VerbPlug>::new(|| model.clone(), sample_rate as f32, frames);
+        let mut silent = Harness::<VerbPlug>::new(|| model.clone(), sample_rate as f32, frames);
+        let mut seed = 1u32;
+        let mut noise = || -> Vec<f32> {
+            (0..frames)
+                .map(|_| {
+                    seed = seed.wrapping_mul(1664525).wrapping_add(1013904223);
+                    (seed as f32 / u32::MAX as f32) * 2.0 - 1.0
+                })
+                .collect()
+        };
+        let silence = vec![0.0; frames];
+
+        // Fill the lines, then freeze and give the ramp 10 time constants to settle
+        for _ in 0..25 {
+            let input = noise();
+            fed.process([&input, &input]);
+            silent.process([&input, &input]);
+        }
+        model.freeze = 1.0;
+        fed.set_model(&model);
+        silent.set_model(&model);
+        let settle = (FREEZE_TIME_MS * 10.0 * sample_rate as f64 / 1000.0) as usize / frames;
+        for _ in 0..settle {
+            fed.process([&silence, &silence]);
+            silent.process([&silence, &silence]);
+        }
+
+        // Input fed to a frozen reverb doesn't reach its output, and the tail it holds keeps its
+        // level for a second
+        let rms = |x: &[f32]| (x.iter().map(|x| x * x).sum::<f32>() / x.len() as f32).sqrt();
+        let mut levels = Vec::new();
+        for _ in 0..100 {
+            let input = noise();
+            let held = fed.process([&input, &input])[0].to_vec();
+            let expected = silent.process([&silence, &silence])[0];
+            for (y, expected) in held.iter().zip(expected) {
+                assert!(
+                    (y - expected).abs() <= 1e-6,
+                    "{} instead of {}",
+                    y,
+                    expected
+                );
+            }
+            levels.push(rms(&held));
+        }
+        let first = levels[0];
+        assert!(first > 0.01, "nothing was held");
+        for level in levels {
+            assert!(
+                (level / first - 1.0).abs() < 0.01,
+                "{} then {}",
+                first,
+                level
+            );
+        }
+    }
+
+    #[test]
+    fn test_factory_presets_round_trip() {
+        let mut bank = PresetBank::<VerbPlugModel>::new();
+        assert!(bank.find("Small Room").is_some());
+        assert!(bank.find("Cathedral").is_some());
+        for i in 0..bank.count() {
+            let json = state::to_json(&bank.get(i).unwrap().model).unwrap();
+            let loaded: VerbPlugModel = state::from_json(&json).unwrap();
+            assert_eq!(state::to_json(&loaded).unwrap(), json);
+        }
+        let model = bank.set_program(0).unwrap();
+        assert_eq!(
+            serde_json::to_string(&model).unwrap(),
+            serde_json::to_string(&VerbPlugModel::default()).unwrap()
+        );
+    }
+
+    #[test]
+    fn test_state_v0_fixture() {
+        let model: VerbPlugModel =
+            state::from_json(include_str!("../fixtures/state_v0.json")).unwrap();
+        assert_eq!(model.mix, 0.5);
+        assert_eq!(model.delay_size, 120.0);
+        assert_eq!(model.iterations, 32.0);
+        assert_eq!(model.out_gain, 0.70794576);
+        // Saved before freeze existed
+        assert_eq!(model.freeze, 0.0);
+        assert_eq!(model.trim, 1.0);
+        assert_eq!(model.bypass_tails, 1.0);
+    }
+
+    #[cfg(feature = "rt-check")]
+    #[test]
+    fn test_process_is_realtime_safe() {
+        for preset in VerbPlugModel::factory_presets() {
+            rt_check::check_process::<VerbPlug>(|| preset.model.clone(), 48000.0, 256, 64);
+        }
+    }
+
+    proptest! {
+        // Each case makes a plugin with its full delay buffers, keep the count down
+        #![proptest_config(ProptestConfig::with_cases(16))]
+
+        #[test]
+        fn test_fuzz_process(
+            blocks in fuzz::blocks(model(), 1..16),
+            sample_rate in fuzz::sample_rate(),
+        ) {
+            fuzz::fuzz_process::<VerbPlug>(
+                &blocks,
+                VerbPlugModel::default(),
+                sample_rate,
+                256,
+                max_output,
+            )?;
+        }
+    }
+
+    #[test]
+    fn test_clap_export() {
+        clap::host::validate::<VerbPlug>(&crate::clap_entry);
+    }
+
+    #[test]
+    fn test_vst2_export() {
+        vst2::host::validate::<VerbPlug>(crate::VSTPluginMain);
+    }
+
+    #[test]
+    fn test_vst2_chunk_v0_fixture() {
+        // The chunk baseplug's VST2 wrapper saved, the bare model before freeze and the mix
+        // stage existed
+        let instance = vst2::host::Instance::new(crate::VSTPluginMain);
+        assert!(instance.load(include_bytes!("../fixtures/vst2_chunk_v0.json")));
+        let saved = String::from_utf8(instance.save()).unwrap();
+        let model: VerbPlugModel = state::from_json(&saved).unwrap();
+        assert_eq!(model.mix, 0.3);
+        assert_eq!(model.delay_size, 45.0);
+        assert_eq!(model.delay_delta, 0.7);
+        assert_eq!(model.decay_init, 0.8);
+        assert_eq!(model.decay_delta, 0.95);
+        assert_eq!(model.iterations, 24.0);
+        assert!((model.out_gain - 1.4125376).abs() < 1e-6);
+        assert_eq!(model.freeze, 0.0);
+        assert_eq!(model.trim, 1.0);
+        assert_eq!(model.bypass_tails, 1.0);
+    }
+
+    #[test]
+    fn test_vst3_export() {
+        vst3::host::validate::<VerbPlug>(crate::GetPluginFactory);
+    }
+}